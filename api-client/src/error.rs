@@ -0,0 +1,43 @@
+use shared::ContainerError;
+use thiserror::Error;
+
+/// Errors returned by `ApiClient`
+#[derive(Debug, Error)]
+pub enum ClientError {
+    /// The request could not be sent or its response not read
+    #[error("Request failed: {0}")]
+    Network(String),
+    /// The server answered with an error status; `message` is its `ErrorResponse`
+    /// text when it sent one
+    #[error("HTTP {status}: {message}")]
+    Status {
+        status: u16,
+        message: String,
+        /// Seconds to wait before retrying, when the server is busy
+        retry_after: Option<u64>,
+    },
+    /// The request needs more memory than the server's whole budget (507);
+    /// unlike a busy server, retrying cannot help
+    #[error("Too large for the server: {0}")]
+    TooLarge(String),
+    /// The response body is not what the route returns
+    #[error("Invalid response: {0}")]
+    Decode(String),
+    /// A volume data payload could not be decoded
+    #[error("{0}")]
+    Container(#[from] ContainerError),
+    /// The progress callback stopped a download
+    #[error("Download cancelled")]
+    Cancelled,
+}
+
+impl ClientError {
+    /// HTTP status of an error response
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            ClientError::Status { status, .. } => Some(*status),
+            ClientError::TooLarge(_) => Some(507),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,611 @@
+//! Typed async client of the 3DLab HTTP API, for native targets (reqwest, run
+//! it in a tokio runtime) and the browser (fetch)
+
+mod error;
+#[cfg(not(target_arch = "wasm32"))]
+mod native;
+#[cfg(target_arch = "wasm32")]
+mod web;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use shared::{
+    decode_f32, decode_u16, AdmissionStatus, Annotation, AnnotationListResponse, AnnotationRequest, CacheStats, CompareRequest,
+    CompareResponse, ContainerError, DeriveRequest, ErrorResponse, ExportQuery, FrameRangeQuery, HealthResponse, LabelListResponse,
+    LabelVolumeInfo, MeshQuery, ReadinessResponse, SegmentRequest, SegmentResponse, SpectrumRequest, SpectrumResponse,
+    VersionQuery, VolumeHeader, VolumeInfo,
+    VolumeListQuery, VolumeListResponse, VolumeMetadataResponse,
+};
+
+pub use error::ClientError;
+#[cfg(not(target_arch = "wasm32"))]
+use native::{Response, Transport};
+#[cfg(target_arch = "wasm32")]
+use web::{Response, Transport};
+
+/// Header identifying a client session; a heavy request cancels the one its
+/// session started before
+pub const SESSION_HEADER: &str = "x-session-id";
+
+/// Number of times an interrupted download is resumed with a `Range` request
+const MAX_DOWNLOAD_RETRIES: u32 = 3;
+
+enum Method {
+    Get,
+    Post,
+    Put,
+    Delete,
+}
+
+/// A request for the transport
+struct Request {
+    method: Method,
+    url: String,
+    headers: Vec<(&'static str, String)>,
+    /// JSON body
+    json: Option<String>,
+}
+
+/// Progress of a download
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Progress {
+    pub received: u64,
+    /// Size of the whole body, if the server sent it
+    pub total: Option<u64>,
+}
+
+/// A download across its resumed attempts
+#[derive(Debug, Default)]
+struct Download {
+    bytes: Vec<u8>,
+    /// Size of the whole body, if the server sent it
+    total: Option<u64>,
+    /// ETag of the content being downloaded, which a resumed request must still match
+    etag: Option<String>,
+}
+
+impl Download {
+    /// Headers asking for the rest of the content after the bytes received so
+    /// far. Without an ETag to send as `If-Range` the content might have
+    /// changed since, so it starts over.
+    fn resume_headers(&self) -> Vec<(&'static str, String)> {
+        match &self.etag {
+            Some(etag) if !self.bytes.is_empty() => vec![
+                ("range", format!("bytes={}-", self.bytes.len())),
+                ("if-range", etag.clone()),
+            ],
+            _ => Vec::new(),
+        }
+    }
+
+    /// Take in the status and headers of an attempt's response. Anything but
+    /// a 206 continuing the bytes so far is the whole (possibly changed)
+    /// content, so the download starts over.
+    fn start(&mut self, status: u16, header: impl Fn(&str) -> Option<String>) {
+        if status == 206 && !self.bytes.is_empty() {
+            return;
+        }
+        self.bytes.clear();
+        self.total = header("content-length").and_then(|v| v.parse().ok());
+        self.etag = header("etag");
+        if let Some(total) = self.total {
+            self.bytes.reserve(total as usize);
+        }
+    }
+
+    fn is_complete(&self) -> bool {
+        self.total.is_none_or(|t| self.bytes.len() as u64 >= t)
+    }
+}
+
+/// A volume data payload, split into its header and voxel bytes
+#[derive(Debug, Clone)]
+pub struct VolumePayload {
+    pub header: VolumeHeader,
+    pub voxels: Vec<u8>,
+}
+
+impl VolumePayload {
+    /// Check and split a payload, reusing its buffer for the voxels
+    pub fn decode(mut bytes: Vec<u8>) -> Result<Self, ContainerError> {
+        let (header, voxels) = VolumeHeader::decode(&bytes)?;
+        // Offset of the voxels in `bytes`
+        let start = voxels.as_ptr() as usize - bytes.as_ptr() as usize;
+        let end = start + voxels.len();
+        bytes.truncate(end);
+        bytes.drain(..start);
+        Ok(Self { header, voxels: bytes })
+    }
+
+    /// Voxels of an `F32` payload
+    pub fn f32s(&self) -> Vec<f32> {
+        decode_f32(&self.voxels)
+    }
+
+    /// Voxels of an `F32` payload, one vector per frame
+    pub fn f32_frames(&self) -> Vec<Vec<f32>> {
+        let voxels = self.header.dims.iter().map(|&d| d as usize).product::<usize>().max(1);
+        self.voxels.chunks_exact(voxels * 4).map(decode_f32).collect()
+    }
+
+    /// Voxels of a `U8` or `U16` (label) payload, widened to u16
+    pub fn u16s(&self) -> Vec<u16> {
+        decode_u16(&self.voxels, self.header.dtype)
+    }
+}
+
+/// Client of one server. Cheap to clone.
+#[derive(Clone)]
+pub struct ApiClient {
+    base: String,
+    session: Option<String>,
+    transport: Transport,
+}
+
+impl ApiClient {
+    /// Client of the server at `base_url`, e.g. `http://localhost:9000`; empty
+    /// in the browser for the origin of the page
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self {
+            base: base_url.into().trim_end_matches('/').to_string(),
+            session: None,
+            transport: Transport::new(),
+        }
+    }
+
+    /// The same client, sending `session` with every request
+    pub fn with_session(&self, session: impl Into<String>) -> Self {
+        Self {
+            session: Some(session.into()),
+            ..self.clone()
+        }
+    }
+
+    /// The same client without a session, for requests that must not cancel
+    /// (or be cancelled by) the session's others
+    pub fn without_session(&self) -> Self {
+        Self {
+            session: None,
+            ..self.clone()
+        }
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base
+    }
+
+    /// GET /api/health
+    pub async fn health(&self) -> Result<HealthResponse, ClientError> {
+        self.get_json(self.url("/health")).await
+    }
+
+    /// GET /api/ready; a server that is up but not ready answers with `ready: false`
+    pub async fn ready(&self) -> Result<ReadinessResponse, ClientError> {
+        let response = self.send(Method::Get, self.url("/ready"), Vec::new(), None).await?;
+        if response.status() != 503 {
+            return json(&check(response).await?.bytes().await?);
+        }
+        json(&response.bytes().await?)
+    }
+
+    /// GET /api/cache
+    pub async fn cache_stats(&self) -> Result<CacheStats, ClientError> {
+        self.get_json(self.url("/cache")).await
+    }
+
+    /// GET /api/status
+    pub async fn status(&self) -> Result<AdmissionStatus, ClientError> {
+        self.get_json(self.url("/status")).await
+    }
+
+    /// GET /metrics, in the Prometheus text format
+    pub async fn metrics(&self) -> Result<String, ClientError> {
+        let bytes = self.get_bytes(format!("{}/metrics", self.base)).await?;
+        String::from_utf8(bytes).map_err(|e| ClientError::Decode(e.to_string()))
+    }
+
+    /// GET /api/volumes
+    pub async fn list_volumes(&self, query: &VolumeListQuery) -> Result<VolumeListResponse, ClientError> {
+        self.get_json(with_query(self.url("/volumes"), query)?).await
+    }
+
+    /// GET /api/volumes/{id}/info
+    pub async fn volume_info(&self, id: &str) -> Result<VolumeInfo, ClientError> {
+        let response: VolumeMetadataResponse = self.get_json(self.volume_url(id, "/info")).await?;
+        Ok(response.info)
+    }
+
+    /// POST /api/volumes/{id}/derive; returns the derived volume
+    pub async fn derive(&self, id: &str, request: &DeriveRequest) -> Result<VolumeInfo, ClientError> {
+        let response: VolumeMetadataResponse = self.send_json(Method::Post, self.volume_url(id, "/derive"), request).await?;
+        Ok(response.info)
+    }
+
+    /// POST /api/volumes/{id}/compare
+    pub async fn compare(&self, id: &str, request: &CompareRequest) -> Result<CompareResponse, ClientError> {
+        self.send_json(Method::Post, self.volume_url(id, "/compare"), request).await
+    }
+
+    /// POST /api/volumes/{id}/spectrum
+    pub async fn spectrum(&self, id: &str, request: &SpectrumRequest) -> Result<SpectrumResponse, ClientError> {
+        self.send_json(Method::Post, self.volume_url(id, "/spectrum"), request).await
+    }
+
+    /// POST /api/volumes/{id}/segment
+    pub async fn segment(&self, id: &str, request: &SegmentRequest) -> Result<SegmentResponse, ClientError> {
+        self.send_json(Method::Post, self.volume_url(id, "/segment"), request).await
+    }
+
+    /// GET /api/volumes/{id}/labels
+    pub async fn volume_labels(&self, id: &str) -> Result<Vec<LabelVolumeInfo>, ClientError> {
+        let response: LabelListResponse = self.get_json(self.volume_url(id, "/labels")).await?;
+        Ok(response.labels)
+    }
+
+    /// GET /api/volumes/{id}/annotations
+    pub async fn annotations(&self, id: &str) -> Result<Vec<Annotation>, ClientError> {
+        let response: AnnotationListResponse = self.get_json(self.volume_url(id, "/annotations")).await?;
+        Ok(response.annotations)
+    }
+
+    /// POST /api/volumes/{id}/annotations
+    pub async fn create_annotation(&self, id: &str, request: &AnnotationRequest) -> Result<Annotation, ClientError> {
+        self.send_json(Method::Post, self.volume_url(id, "/annotations"), request).await
+    }
+
+    /// GET /api/volumes/{id}/annotations/{annotation_id}
+    pub async fn annotation(&self, id: &str, annotation_id: u64) -> Result<Annotation, ClientError> {
+        self.get_json(self.annotation_url(id, annotation_id)).await
+    }
+
+    /// PUT /api/volumes/{id}/annotations/{annotation_id}
+    pub async fn update_annotation(
+        &self,
+        id: &str,
+        annotation_id: u64,
+        request: &AnnotationRequest,
+    ) -> Result<Annotation, ClientError> {
+        self.send_json(Method::Put, self.annotation_url(id, annotation_id), request).await
+    }
+
+    /// DELETE /api/volumes/{id}/annotations/{annotation_id}
+    pub async fn delete_annotation(&self, id: &str, annotation_id: u64) -> Result<(), ClientError> {
+        let response = self.send(Method::Delete, self.annotation_url(id, annotation_id), Vec::new(), None).await?;
+        check(response).await.map(drop)
+    }
+
+    /// GET /api/labels/{id}/info
+    pub async fn label_info(&self, id: &str) -> Result<LabelVolumeInfo, ClientError> {
+        self.get_json(self.url(&format!("/labels/{}/info", encode_component(id)))).await
+    }
+
+    /// GET /api/labels/{id}/data
+    pub async fn label_data(&self, id: &str) -> Result<VolumePayload, ClientError> {
+        self.get_payload(self.url(&format!("/labels/{}/data", encode_component(id)))).await
+    }
+
+    /// URL of GET /api/volumes/{id}/mesh, e.g. for a download link
+    pub fn mesh_url(&self, id: &str, query: &MeshQuery) -> Result<String, ClientError> {
+        with_query(self.volume_url(id, "/mesh"), query)
+    }
+
+    /// GET /api/volumes/{id}/mesh; the surface file in `query.format`
+    pub async fn mesh(&self, id: &str, query: &MeshQuery) -> Result<Vec<u8>, ClientError> {
+        self.get_bytes(self.mesh_url(id, query)?).await
+    }
+
+    /// URL of GET /api/volumes/{id}/export, e.g. for a download link
+    pub fn export_url(&self, id: &str, query: &ExportQuery) -> Result<String, ClientError> {
+        with_query(self.volume_url(id, "/export"), query)
+    }
+
+    /// GET /api/volumes/{id}/export; the volume file in `query.format`
+    pub async fn export(&self, id: &str, query: &ExportQuery) -> Result<Vec<u8>, ClientError> {
+        self.get_bytes(self.export_url(id, query)?).await
+    }
+
+    /// GET /api/volumes/{id}/low. `version` is `VolumeInfo::version`, which
+    /// makes the response cacheable.
+    pub async fn low(&self, id: &str, version: Option<&str>) -> Result<VolumePayload, ClientError> {
+        self.get_payload(with_version(self.volume_url(id, "/low"), version)?).await
+    }
+
+    /// GET /api/volumes/{id}/full, streamed. `progress` is called after each
+    /// chunk and stops the download by returning false. Interrupted downloads
+    /// are resumed with `Range` requests, or restarted if the content changed.
+    pub async fn full(
+        &self,
+        id: &str,
+        version: Option<&str>,
+        mut progress: impl FnMut(Progress) -> bool,
+    ) -> Result<VolumePayload, ClientError> {
+        let url = with_version(self.volume_url(id, "/full"), version)?;
+        let mut download = Download::default();
+        let mut retries = 0;
+
+        loop {
+            match self.download(&url, &mut download, &mut progress).await {
+                Ok(()) if download.is_complete() => break,
+                Ok(()) | Err(ClientError::Network(_)) if retries < MAX_DOWNLOAD_RETRIES => {
+                    retries += 1;
+                    log::warn!("Download interrupted at {} bytes, resuming", download.bytes.len());
+                }
+                Ok(()) => return Err(ClientError::Network("Download ended early".to_string())),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(VolumePayload::decode(download.bytes)?)
+    }
+
+    /// GET /api/volumes/{id}/frames/{frame}
+    pub async fn frame(&self, id: &str, frame: u32, version: Option<&str>) -> Result<VolumePayload, ClientError> {
+        self.get_payload(with_version(self.volume_url(id, &format!("/frames/{}", frame)), version)?)
+            .await
+    }
+
+    /// GET /api/volumes/{id}/frames
+    pub async fn frames(&self, id: &str, range: &FrameRangeQuery, version: Option<&str>) -> Result<VolumePayload, ClientError> {
+        let url = with_query(self.volume_url(id, "/frames"), range)?;
+        self.get_payload(with_version(url, version)?).await
+    }
+
+    /// GET /api/volumes/{id}/at/{resolution}
+    pub async fn at_resolution(&self, id: &str, resolution: usize, version: Option<&str>) -> Result<VolumePayload, ClientError> {
+        self.get_payload(with_version(self.volume_url(id, &format!("/at/{}", resolution)), version)?)
+            .await
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}/api{}", self.base, path)
+    }
+
+    /// URL of a route below /api/volumes/{id}
+    fn volume_url(&self, id: &str, route: &str) -> String {
+        self.url(&format!("/volumes/{}{}", encode_component(id), route))
+    }
+
+    fn annotation_url(&self, id: &str, annotation_id: u64) -> String {
+        self.volume_url(id, &format!("/annotations/{}", annotation_id))
+    }
+
+    async fn send(
+        &self,
+        method: Method,
+        url: String,
+        mut headers: Vec<(&'static str, String)>,
+        json: Option<String>,
+    ) -> Result<Response, ClientError> {
+        if let Some(session) = &self.session {
+            headers.push((SESSION_HEADER, session.clone()));
+        }
+        self.transport.send(Request { method, url, headers, json }).await
+    }
+
+    async fn get_bytes(&self, url: String) -> Result<Vec<u8>, ClientError> {
+        let response = self.send(Method::Get, url, Vec::new(), None).await?;
+        check(response).await?.bytes().await
+    }
+
+    async fn get_json<T: DeserializeOwned>(&self, url: String) -> Result<T, ClientError> {
+        json(&self.get_bytes(url).await?)
+    }
+
+    async fn get_payload(&self, url: String) -> Result<VolumePayload, ClientError> {
+        Ok(VolumePayload::decode(self.get_bytes(url).await?)?)
+    }
+
+    async fn send_json<T: DeserializeOwned>(
+        &self,
+        method: Method,
+        url: String,
+        body: &impl Serialize,
+    ) -> Result<T, ClientError> {
+        let body = serde_json::to_string(body).map_err(|e| ClientError::Decode(e.to_string()))?;
+        let response = self.send(method, url, Vec::new(), Some(body)).await?;
+        json(&check(response).await?.bytes().await?)
+    }
+
+    /// One attempt at downloading `url`, resuming after what `download` already holds
+    async fn download(
+        &self,
+        url: &str,
+        download: &mut Download,
+        progress: &mut impl FnMut(Progress) -> bool,
+    ) -> Result<(), ClientError> {
+        let response = self
+            .send(Method::Get, url.to_string(), download.resume_headers(), None)
+            .await?;
+        let mut response = check(response).await?;
+        download.start(response.status(), |name| response.header(name));
+
+        while let Some(chunk) = response.chunk().await? {
+            download.bytes.extend_from_slice(&chunk);
+            let received = download.bytes.len() as u64;
+            if !progress(Progress {
+                received,
+                total: download.total,
+            }) {
+                return Err(ClientError::Cancelled);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The response if it has a success status, otherwise its error
+async fn check(response: Response) -> Result<Response, ClientError> {
+    let status = response.status();
+    if (200..300).contains(&status) {
+        return Ok(response);
+    }
+    let retry_after = response.header("retry-after").and_then(|v| v.parse().ok());
+    let body = response.bytes().await.unwrap_or_default();
+    Err(status_error(status, retry_after, &body))
+}
+
+/// Error of a response with a failure `status` and `body`
+fn status_error(status: u16, retry_after: Option<u64>, body: &[u8]) -> ClientError {
+    let message = match serde_json::from_slice::<ErrorResponse>(body) {
+        Ok(error) => error.error,
+        Err(_) => String::from_utf8_lossy(body).trim().to_string(),
+    };
+    if status == 507 {
+        return ClientError::TooLarge(message);
+    }
+    ClientError::Status {
+        status,
+        message,
+        retry_after,
+    }
+}
+
+fn json<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, ClientError> {
+    serde_json::from_slice(bytes).map_err(|e| ClientError::Decode(e.to_string()))
+}
+
+/// `url` with the query parameters of `query`, if it has any. Only flat
+/// structs of scalars can be query parameters.
+fn with_query(url: String, query: &impl Serialize) -> Result<String, ClientError> {
+    let query = serde_urlencoded::to_string(query).map_err(|e| ClientError::Decode(e.to_string()))?;
+    if query.is_empty() {
+        return Ok(url);
+    }
+    let separator = if url.contains('?') { '&' } else { '?' };
+    Ok(format!("{}{}{}", url, separator, query))
+}
+
+/// `url` pinned to a content version, when one is given
+fn with_version(url: String, version: Option<&str>) -> Result<String, ClientError> {
+    let query = VersionQuery {
+        v: version.filter(|v| !v.is_empty()).map(str::to_string),
+    };
+    with_query(url, &query)
+}
+
+/// Percent-encode text for use as one URL path segment. Ids of volumes in
+/// subfolders contain `/`.
+fn encode_component(text: &str) -> String {
+    let mut encoded = String::with_capacity(text.len());
+    for byte in text.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'(' | b')' | b',' | b'!' | b'*' | b'\'' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shared::VoxelDtype;
+    use std::collections::HashMap;
+
+    #[test]
+    fn encodes_ids_as_one_segment() {
+        assert_eq!(encode_component("target_087"), "target_087");
+        assert_eq!(encode_component("knee/case 01"), "knee%2Fcase%2001");
+        assert_eq!(encode_component("a~absdiff(b~gauss(1.5))"), "a~absdiff(b~gauss(1.5))");
+        assert_eq!(encode_component("é?#"), "%C3%A9%3F%23");
+
+        let client = ApiClient::new("http://localhost:9000/");
+        assert_eq!(
+            client.volume_url("phantoms/shepp-logan-128-f32", "/info"),
+            "http://localhost:9000/api/volumes/phantoms%2Fshepp-logan-128-f32/info"
+        );
+    }
+
+    #[test]
+    fn appends_query_parameters() {
+        let query = VolumeListQuery {
+            q: Some("knee case".to_string()),
+            min_dim: Some(64),
+            ..Default::default()
+        };
+        assert_eq!(with_query("/api/volumes".to_string(), &query).unwrap(), "/api/volumes?q=knee+case&min_dim=64");
+        assert_eq!(with_query("/api/volumes".to_string(), &VolumeListQuery::default()).unwrap(), "/api/volumes");
+        assert_eq!(with_version("/a?x=1".to_string(), Some("v1")).unwrap(), "/a?x=1&v=v1");
+        assert_eq!(with_version("/a".to_string(), Some("")).unwrap(), "/a");
+
+        // Nested values have no query form
+        let nested = HashMap::from([("dims", [1, 2])]);
+        assert!(matches!(with_query("/a".to_string(), &nested), Err(ClientError::Decode(_))));
+    }
+
+    #[test]
+    fn maps_error_statuses() {
+        let busy = status_error(503, Some(5), br#"{"error":"Server busy: queue full"}"#);
+        assert!(matches!(
+            &busy,
+            ClientError::Status { status: 503, message, retry_after: Some(5) } if message == "Server busy: queue full"
+        ));
+        let too_large = status_error(507, None, br#"{"error":"Not enough server memory"}"#);
+        assert!(matches!(&too_large, ClientError::TooLarge(message) if message == "Not enough server memory"));
+        assert_eq!(too_large.status(), Some(507));
+        // A body that is not an ErrorResponse is the message as it is
+        let plain = status_error(502, None, b"Bad Gateway\n");
+        assert!(matches!(&plain, ClientError::Status { message, .. } if message == "Bad Gateway"));
+    }
+
+    #[test]
+    fn decodes_payloads() {
+        let header = VolumeHeader::new([2, 1, 1], VoxelDtype::F32, [0.0, 1.0], [1.0; 3]);
+        let mut bytes = header.encode().to_vec();
+        bytes.extend([0.5f32, 1.0].iter().flat_map(|v| v.to_le_bytes()));
+        // Anything past the payload is dropped
+        bytes.extend([9, 9]);
+
+        let payload = VolumePayload::decode(bytes.clone()).unwrap();
+        assert_eq!(payload.header, header);
+        assert_eq!(payload.f32s(), vec![0.5, 1.0]);
+        assert_eq!(payload.f32_frames(), vec![vec![0.5, 1.0]]);
+
+        assert!(matches!(
+            VolumePayload::decode(bytes[..VolumeHeader::SIZE + 6].to_vec()),
+            Err(ContainerError::Truncated { .. })
+        ));
+    }
+
+    fn headers<'a>(pairs: &'a [(&str, &str)]) -> impl Fn(&str) -> Option<String> + 'a {
+        move |name| pairs.iter().find(|(n, _)| *n == name).map(|(_, v)| v.to_string())
+    }
+
+    #[test]
+    fn resumes_only_the_same_content() {
+        let mut download = Download::default();
+        assert!(download.resume_headers().is_empty());
+
+        download.start(200, headers(&[("content-length", "10"), ("etag", "\"v1\"")]));
+        download.bytes.extend([1; 4]);
+        assert!(!download.is_complete());
+        assert_eq!(
+            download.resume_headers(),
+            vec![("range", "bytes=4-".to_string()), ("if-range", "\"v1\"".to_string())]
+        );
+
+        // A 206 continues after the bytes so far
+        download.start(206, headers(&[("content-length", "6")]));
+        assert_eq!((download.bytes.len(), download.total), (4, Some(10)));
+        download.bytes.extend([1; 6]);
+        assert!(download.is_complete());
+    }
+
+    #[test]
+    fn restarts_when_the_content_changed() {
+        let mut download = Download::default();
+        download.start(200, headers(&[("content-length", "10"), ("etag", "\"v1\"")]));
+        download.bytes.extend([1; 4]);
+
+        // The ETag no longer matches, so the server sends the whole new content
+        download.start(200, headers(&[("content-length", "12"), ("etag", "\"v2\"")]));
+        assert!(download.bytes.is_empty());
+        assert_eq!((download.total, download.etag.as_deref()), (Some(12), Some("\"v2\"")));
+
+        // Without an ETag there is nothing to check a resume against
+        download.start(200, headers(&[("content-length", "10")]));
+        download.bytes.extend([1; 4]);
+        assert!(download.resume_headers().is_empty());
+    }
+}
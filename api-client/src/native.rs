@@ -0,0 +1,61 @@
+//! Transport on native targets, over reqwest
+
+use crate::{ClientError, Method, Request};
+
+#[derive(Clone)]
+pub struct Transport {
+    client: reqwest::Client,
+}
+
+impl Transport {
+    pub fn new() -> Self {
+        Self {
+            client: reqwest::Client::new(),
+        }
+    }
+
+    pub async fn send(&self, request: Request) -> Result<Response, ClientError> {
+        let method = match request.method {
+            Method::Get => reqwest::Method::GET,
+            Method::Post => reqwest::Method::POST,
+            Method::Put => reqwest::Method::PUT,
+            Method::Delete => reqwest::Method::DELETE,
+        };
+        let mut builder = self.client.request(method, &request.url);
+        for (name, value) in &request.headers {
+            builder = builder.header(*name, value);
+        }
+        if let Some(body) = request.json {
+            builder = builder.header("content-type", "application/json").body(body);
+        }
+        let inner = builder.send().await.map_err(network)?;
+        Ok(Response { inner })
+    }
+}
+
+pub struct Response {
+    inner: reqwest::Response,
+}
+
+impl Response {
+    pub fn status(&self) -> u16 {
+        self.inner.status().as_u16()
+    }
+
+    pub fn header(&self, name: &str) -> Option<String> {
+        self.inner.headers().get(name)?.to_str().ok().map(str::to_string)
+    }
+
+    /// Next chunk of the body, `None` at its end
+    pub async fn chunk(&mut self) -> Result<Option<Vec<u8>>, ClientError> {
+        Ok(self.inner.chunk().await.map_err(network)?.map(|chunk| chunk.to_vec()))
+    }
+
+    pub async fn bytes(self) -> Result<Vec<u8>, ClientError> {
+        Ok(self.inner.bytes().await.map_err(network)?.to_vec())
+    }
+}
+
+fn network(e: reqwest::Error) -> ClientError {
+    ClientError::Network(e.to_string())
+}
@@ -0,0 +1,98 @@
+//! Transport in the browser, over fetch()
+
+use gloo_net::http::Request as FetchRequest;
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::JsFuture;
+use web_sys::ReadableStreamDefaultReader;
+
+use crate::{ClientError, Method, Request};
+
+#[derive(Clone)]
+pub struct Transport;
+
+impl Transport {
+    pub fn new() -> Self {
+        Self
+    }
+
+    pub async fn send(&self, request: Request) -> Result<Response, ClientError> {
+        let mut builder = match request.method {
+            Method::Get => FetchRequest::get(&request.url),
+            Method::Post => FetchRequest::post(&request.url),
+            Method::Put => FetchRequest::put(&request.url),
+            Method::Delete => FetchRequest::delete(&request.url),
+        };
+        for (name, value) in &request.headers {
+            builder = builder.header(name, value);
+        }
+        let fetch = match request.json {
+            Some(body) => builder.header("content-type", "application/json").body(body),
+            None => builder.build(),
+        }
+        .map_err(network)?;
+        let inner = fetch.send().await.map_err(network)?;
+        Ok(Response { inner, reader: None })
+    }
+}
+
+pub struct Response {
+    inner: gloo_net::http::Response,
+    /// Reader of the body stream, once `chunk` has been called
+    reader: Option<ReadableStreamDefaultReader>,
+}
+
+impl Response {
+    pub fn status(&self) -> u16 {
+        self.inner.status()
+    }
+
+    pub fn header(&self, name: &str) -> Option<String> {
+        self.inner.headers().get(name)
+    }
+
+    /// Next chunk of the body, `None` at its end
+    pub async fn chunk(&mut self) -> Result<Option<Vec<u8>>, ClientError> {
+        if self.reader.is_none() {
+            let Some(body) = self.inner.body() else {
+                return Ok(None);
+            };
+            let reader = body
+                .get_reader()
+                .dyn_into()
+                .map_err(|_| ClientError::Network("Failed to get stream reader".to_string()))?;
+            self.reader = Some(reader);
+        }
+        let reader = self.reader.as_ref().unwrap();
+
+        let chunk = JsFuture::from(reader.read())
+            .await
+            .map_err(|e| ClientError::Network(format!("Failed to read body: {:?}", e)))?;
+        let done = js_sys::Reflect::get(&chunk, &"done".into())
+            .ok()
+            .and_then(|v| v.as_bool())
+            .unwrap_or(true);
+        if done {
+            return Ok(None);
+        }
+        let value = js_sys::Reflect::get(&chunk, &"value".into())
+            .map_err(|_| ClientError::Network("Malformed stream chunk".to_string()))?;
+        Ok(Some(js_sys::Uint8Array::new(&value).to_vec()))
+    }
+
+    pub async fn bytes(self) -> Result<Vec<u8>, ClientError> {
+        self.inner.binary().await.map_err(network)
+    }
+}
+
+impl Drop for Response {
+    /// Stop the browser downloading a body that is no longer read
+    fn drop(&mut self) {
+        if let Some(reader) = &self.reader {
+            let _ = reader.cancel();
+        }
+    }
+}
+
+fn network(e: gloo_net::Error) -> ClientError {
+    ClientError::Network(e.to_string())
+}
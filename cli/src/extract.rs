@@ -0,0 +1,149 @@
+use clap::Parser;
+use hdf5::File;
+use ndarray::{ArrayD, Axis, Slice};
+use server::hdf5_reader::{Complex32, HDF5Volume};
+use std::path::PathBuf;
+
+use crate::Result;
+
+#[derive(Parser)]
+pub struct Args {
+    input: PathBuf,
+    output: PathBuf,
+    /// Dataset to read
+    #[arg(long, default_value = "target")]
+    dataset: String,
+    /// numpy-style selection, one entry per axis: `:` (all), `N` (index) or
+    /// `A:B` (range); missing trailing axes are kept whole. E.g. `:,:,:,0,0`
+    #[arg(long)]
+    select: Option<String>,
+    /// Take the absolute value (required for complex data)
+    #[arg(long)]
+    magnitude: bool,
+    /// Keep every Nth voxel along each spatial axis
+    #[arg(long, default_value_t = 1)]
+    downsample: usize,
+}
+
+/// One entry of a `--select` expression
+enum Selection {
+    Index(usize),
+    Range(Option<usize>, Option<usize>),
+}
+
+fn parse_selection(spec: &str) -> Result<Vec<Selection>> {
+    spec.split(',')
+        .map(|entry| {
+            let entry = entry.trim();
+            let bound = |s: &str| -> Result<Option<usize>> {
+                Ok(if s.is_empty() { None } else { Some(s.parse()?) })
+            };
+            Ok(match entry.split_once(':') {
+                Some((start, end)) => Selection::Range(bound(start)?, bound(end)?),
+                None => Selection::Index(entry.parse().map_err(|_| format!("Invalid selection '{}'", entry))?),
+            })
+        })
+        .collect()
+}
+
+/// Read `args.dataset`, apply the selection, magnitude and downsampling, and
+/// write the result as a `target` dataset with the input's spacing
+pub fn run(args: &Args) -> Result<()> {
+    if args.downsample == 0 {
+        return Err("--downsample must be at least 1".into());
+    }
+
+    let file = File::open(&args.input)?;
+    let dataset = file.dataset(&args.dataset)?;
+    println!("Original shape: {:?}", dataset.shape());
+
+    // Whole-dataset read; the selection is applied in memory
+    let mut data: ArrayD<f32> = match dataset.read_dyn::<f32>() {
+        Ok(data) => data,
+        Err(_) => {
+            let complex = dataset.read_dyn::<Complex32>()?;
+            if !args.magnitude {
+                return Err(format!("Dataset '{}' is complex; pass --magnitude", args.dataset).into());
+            }
+            complex.mapv(Complex32::norm)
+        }
+    };
+    if args.magnitude {
+        data.mapv_inplace(f32::abs);
+    }
+
+    if let Some(spec) = &args.select {
+        let selection = parse_selection(spec)?;
+        if selection.len() > data.ndim() {
+            return Err(format!("Selection has {} axes, data has {}", selection.len(), data.ndim()).into());
+        }
+        // Ranges first so axis numbers stay valid, then indices from the last axis
+        for (axis, entry) in selection.iter().enumerate() {
+            if let Selection::Range(start, end) = *entry {
+                let len = data.len_of(Axis(axis));
+                let (start, end) = (start.unwrap_or(0), end.unwrap_or(len).min(len));
+                if start >= end {
+                    return Err(format!("Empty range {}:{} on axis {}", start, end, axis).into());
+                }
+                data.slice_axis_inplace(Axis(axis), Slice::from(start..end));
+            }
+        }
+        for (axis, entry) in selection.iter().enumerate().rev() {
+            if let Selection::Index(index) = *entry {
+                if index >= data.len_of(Axis(axis)) {
+                    return Err(format!("Index {} out of range on axis {}", index, axis).into());
+                }
+                data.index_axis_inplace(Axis(axis), index);
+            }
+        }
+        println!("After selection: {:?}", data.shape());
+    }
+
+    // The server reads 3D volumes and 4D time series with time first
+    let spatial_start = match data.ndim() {
+        3 => 0,
+        4 => 1,
+        n => return Err(format!("Result has {} dimensions; select down to 3 or 4", n).into()),
+    };
+
+    let mut spacing = HDF5Volume::read_spacing(&file, &dataset);
+    if args.downsample > 1 {
+        for axis in spatial_start..data.ndim() {
+            data.slice_axis_inplace(Axis(axis), Slice::new(0, None, args.downsample as isize));
+        }
+        spacing = spacing.map(|s| s * args.downsample as f32);
+        println!("After {}x downsample: {:?}", args.downsample, data.shape());
+    }
+
+    let data = data.as_standard_layout();
+    let output = File::create(&args.output)?;
+    let target = output.new_dataset_builder().with_data(&data).create("target")?;
+    target.new_attr_builder().with_data(&spacing[..]).create("spacing")?;
+
+    println!("\nSaved to: {}", args.output.display());
+    println!("Final shape: {:?}, dtype: float32, spacing: {:?} mm", data.shape(), spacing);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn selections_parse() {
+        let selection = parse_selection(":, 2:5 ,3,:4,1:").unwrap();
+        assert!(matches!(
+            selection[..],
+            [
+                Selection::Range(None, None),
+                Selection::Range(Some(2), Some(5)),
+                Selection::Index(3),
+                Selection::Range(None, Some(4)),
+                Selection::Range(Some(1), None),
+            ]
+        ));
+        assert!(parse_selection("x").is_err());
+        assert!(parse_selection("1:b").is_err());
+        assert!(parse_selection("0,,1").is_err());
+    }
+}
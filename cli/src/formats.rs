@@ -0,0 +1,373 @@
+use clap::Parser;
+use ndarray::{Array3, ShapeBuilder};
+use server::export::{self, Export, ExportFormat};
+use server::hdf5_reader::HDF5Volume;
+use server::phantom::Phantom;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use tokio_util::sync::CancellationToken;
+
+use crate::Result;
+
+/// NIfTI-1 header size, checked to tell the byte order
+const NIFTI_HEADER_SIZE: i32 = 348;
+
+#[derive(Parser)]
+pub struct ConvertArgs {
+    /// `.h5`, `.nii`, `.npy`, `.mha` or `.mhd`, or a generated `phantom:kind[:size[:dtype]]`
+    input: PathBuf,
+    /// `.h5`, `.nii`, `.npy`, `.mha` or `.mhd` (which also writes the `.raw` data file)
+    output: PathBuf,
+    /// Time frame of a time series
+    #[arg(long, default_value_t = 0)]
+    frame: u32,
+    /// Voxel box `x0:x1,y0:y1,z0:z1`, half-open
+    #[arg(long)]
+    crop: Option<String>,
+    /// Target size of the largest dimension after cropping
+    #[arg(long)]
+    resolution: Option<usize>,
+}
+
+/// A volume read from any supported file
+pub struct Volume {
+    pub data: Array3<f32>,
+    pub spacing: [f32; 3],
+}
+
+/// Read a 3D volume (or one frame of a time series) by file extension
+pub async fn load(path: &Path, frame: u32) -> Result<Volume> {
+    if let Some(spec) = path.to_str().and_then(|p| p.strip_prefix("phantom:")) {
+        let phantom: Phantom = spec.parse()?;
+        return Ok(Volume {
+            data: phantom.read_slab(0..phantom.dims[0] as usize),
+            spacing: [1.0; 3],
+        });
+    }
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_ascii_lowercase();
+    match extension.as_str() {
+        "h5" | "hdf5" => {
+            // The same reader the server uses, so the same datasets are found
+            let volume = HDF5Volume::open(path).await?;
+            if frame >= volume.info.frames {
+                return Err(format!("Frame {} out of range ({} frames)", frame, volume.info.frames).into());
+            }
+            let data = volume.read_frame(frame, CancellationToken::new()).await?;
+            Ok(Volume {
+                data,
+                spacing: volume.info.spacing,
+            })
+        }
+        "npy" => read_npy(&std::fs::read(path)?),
+        "nii" => read_nifti(&std::fs::read(path)?, frame),
+        "mha" | "mhd" => read_meta_image(path),
+        _ => Err(format!("Unsupported input format '{}'", path.display()).into()),
+    }
+}
+
+/// Convert between formats, cropping and downsampling on the way
+pub async fn convert(args: &ConvertArgs) -> Result<()> {
+    let format = args
+        .output
+        .extension()
+        .and_then(|e| e.to_str())
+        .and_then(ExportFormat::from_extension)
+        .ok_or_else(|| format!("Unsupported output format '{}'", args.output.display()))?;
+
+    let volume = load(&args.input, args.frame).await?;
+    let dims = volume.data.shape();
+    println!("Input: {:?}, spacing {:?} mm", dims, volume.spacing);
+
+    let crop = args
+        .crop
+        .as_deref()
+        .map(|crop| export::parse_crop(crop, [dims[0] as u32, dims[1] as u32, dims[2] as u32]))
+        .transpose()?;
+    let export = Export::prepare(
+        volume.data,
+        volume.spacing,
+        None,
+        crop,
+        args.resolution,
+        &CancellationToken::new(),
+    )?;
+
+    let stem = args.output.file_stem().and_then(|s| s.to_str()).unwrap_or("volume");
+    std::fs::write(&args.output, export.encode(format, stem)?)?;
+    if format == ExportFormat::Mhd {
+        std::fs::write(args.output.with_extension("raw"), export.encode(ExportFormat::Raw, stem)?)?;
+    }
+
+    println!(
+        "Saved to: {} ({:?}, spacing {:?} mm)",
+        args.output.display(),
+        export.dims(),
+        export.spacing()
+    );
+    Ok(())
+}
+
+/// Little-endian samples of one element type, widened to f32
+fn decode(bytes: &[u8], element: &str) -> Result<Vec<f32>> {
+    macro_rules! samples {
+        ($t:ty) => {
+            bytes
+                .chunks_exact(std::mem::size_of::<$t>())
+                .map(|c| <$t>::from_le_bytes(c.try_into().unwrap()) as f32)
+                .collect()
+        };
+    }
+    Ok(match element {
+        "u8" => bytes.iter().map(|&v| v as f32).collect(),
+        "i8" => bytes.iter().map(|&v| v as i8 as f32).collect(),
+        "i16" => samples!(i16),
+        "u16" => samples!(u16),
+        "i32" => samples!(i32),
+        "u32" => samples!(u32),
+        "f32" => samples!(f32),
+        "f64" => samples!(f64),
+        _ => return Err(format!("Unsupported element type {}", element).into()),
+    })
+}
+
+/// Shape the first `x * y * z` samples as [x, y, z], stored x-fastest
+fn from_fortran(values: Vec<f32>, dims: [usize; 3]) -> Result<Array3<f32>> {
+    let count = dims.iter().product::<usize>();
+    if values.len() < count {
+        return Err(format!("Expected {} voxels, file has {}", count, values.len()).into());
+    }
+    let values = values.into_iter().take(count).collect();
+    let data = Array3::from_shape_vec(dims.f(), values)?;
+    Ok(data.as_standard_layout().to_owned())
+}
+
+fn read_npy(bytes: &[u8]) -> Result<Volume> {
+    if bytes.len() < 10 || &bytes[..6] != b"\x93NUMPY" {
+        return Err("Not an NPY file".into());
+    }
+    let (header_len, header_start) = match bytes[6] {
+        1 => (u16::from_le_bytes([bytes[8], bytes[9]]) as usize, 10),
+        _ => (u32::from_le_bytes(bytes[8..12].try_into()?) as usize, 12),
+    };
+    let header = std::str::from_utf8(&bytes[header_start..header_start + header_len])?;
+    let field = |name: &str| -> Option<&str> {
+        let rest = &header[header.find(&format!("'{}':", name))? + name.len() + 3..];
+        Some(rest.trim_start())
+    };
+
+    let descr = field("descr").and_then(|d| d.split('\'').nth(1)).ok_or("NPY header has no descr")?;
+    let element = match descr {
+        "|u1" | "u1" => "u8",
+        "|i1" | "i1" => "i8",
+        "<i2" => "i16",
+        "<u2" => "u16",
+        "<i4" => "i32",
+        "<u4" => "u32",
+        "<f4" => "f32",
+        "<f8" => "f64",
+        _ => return Err(format!("Unsupported NPY dtype {}", descr).into()),
+    };
+    let fortran = field("fortran_order").is_some_and(|v| v.starts_with("True"));
+    let shape: Vec<usize> = field("shape")
+        .and_then(|s| s.strip_prefix('('))
+        .and_then(|s| s.split(')').next())
+        .ok_or("NPY header has no shape")?
+        .split(',')
+        .filter(|s| !s.trim().is_empty())
+        .map(|s| s.trim().parse())
+        .collect::<std::result::Result<_, _>>()?;
+    let [x, y, z] = shape[..] else {
+        return Err(format!("Expected a 3D array, got shape {:?}", shape).into());
+    };
+
+    let values = decode(&bytes[header_start + header_len..], element)?;
+    let data = if fortran {
+        from_fortran(values, [x, y, z])?
+    } else {
+        Array3::from_shape_vec((x, y, z), values)?
+    };
+    // NPY has no geometry
+    Ok(Volume {
+        data,
+        spacing: [1.0; 3],
+    })
+}
+
+fn read_nifti(bytes: &[u8], frame: u32) -> Result<Volume> {
+    if bytes.len() < 352 || i32::from_le_bytes(bytes[0..4].try_into()?) != NIFTI_HEADER_SIZE {
+        return Err("Not a little-endian NIfTI-1 file".into());
+    }
+    let i16_at = |offset: usize| i16::from_le_bytes([bytes[offset], bytes[offset + 1]]);
+    let f32_at = |offset: usize| f32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+
+    let ndim = i16_at(40);
+    let dims = [i16_at(42) as usize, i16_at(44) as usize, i16_at(46) as usize];
+    let frames = if ndim >= 4 { i16_at(48).max(1) as u32 } else { 1 };
+    if !(3..=4).contains(&ndim) || frame >= frames {
+        return Err(format!("Expected a 3D or 4D image with frame {}, got {} dimensions", frame, ndim).into());
+    }
+    let element = match i16_at(70) {
+        2 => "u8",
+        4 => "i16",
+        8 => "i32",
+        16 => "f32",
+        64 => "f64",
+        256 => "i8",
+        512 => "u16",
+        768 => "u32",
+        datatype => return Err(format!("Unsupported NIfTI datatype {}", datatype).into()),
+    };
+
+    let frame_bytes = dims.iter().product::<usize>() * (i16_at(72) as usize / 8);
+    let start = f32_at(108) as usize + frame as usize * frame_bytes;
+    let values = decode(bytes.get(start..start + frame_bytes).ok_or("NIfTI file is truncated")?, element)?;
+    let mut data = from_fortran(values, dims)?;
+
+    // A zero slope means the values are stored unscaled
+    let (slope, intercept) = (f32_at(112), f32_at(116));
+    if slope != 0.0 && (slope != 1.0 || intercept != 0.0) {
+        data.mapv_inplace(|v| v * slope + intercept);
+    }
+    let spacing = [f32_at(80), f32_at(84), f32_at(88)].map(|s| if s > 0.0 { s } else { 1.0 });
+    Ok(Volume { data, spacing })
+}
+
+fn read_meta_image(path: &Path) -> Result<Volume> {
+    let bytes = std::fs::read(path)?;
+
+    // `key = value` lines up to and including ElementDataFile
+    let mut fields = HashMap::new();
+    let mut offset = 0;
+    for line in bytes.split(|&b| b == b'\n') {
+        offset += line.len() + 1;
+        let line = String::from_utf8_lossy(line);
+        if let Some((key, value)) = line.split_once('=') {
+            fields.insert(key.trim().to_string(), value.trim().to_string());
+            if key.trim() == "ElementDataFile" {
+                break;
+            }
+        }
+    }
+    let field = |key: &str| fields.get(key).map(String::as_str);
+    let numbers = |key: &str| -> Vec<f32> {
+        field(key)
+            .map(|v| v.split_whitespace().filter_map(|n| n.parse().ok()).collect())
+            .unwrap_or_default()
+    };
+
+    if field("NDims") != Some("3") {
+        return Err("Only 3D MetaImages are supported".into());
+    }
+    if field("CompressedData").is_some_and(|v| v.eq_ignore_ascii_case("true"))
+        || field("BinaryDataByteOrderMSB").or(field("ElementByteOrderMSB")).is_some_and(|v| v.eq_ignore_ascii_case("true"))
+    {
+        return Err("Compressed or big-endian MetaImages are not supported".into());
+    }
+    let dims = numbers("DimSize");
+    let [x, y, z] = dims[..] else {
+        return Err("MetaImage has no DimSize".into());
+    };
+    let element = match field("ElementType") {
+        Some("MET_UCHAR") => "u8",
+        Some("MET_CHAR") => "i8",
+        Some("MET_SHORT") => "i16",
+        Some("MET_USHORT") => "u16",
+        Some("MET_INT") => "i32",
+        Some("MET_UINT") => "u32",
+        Some("MET_FLOAT") => "f32",
+        Some("MET_DOUBLE") => "f64",
+        other => return Err(format!("Unsupported MetaImage element type {:?}", other).into()),
+    };
+
+    let raw = match field("ElementDataFile") {
+        Some("LOCAL") => bytes[offset.min(bytes.len())..].to_vec(),
+        Some(file) => std::fs::read(path.with_file_name(file))?,
+        None => return Err("MetaImage has no ElementDataFile".into()),
+    };
+    let data = from_fortran(decode(&raw, element)?, [x as usize, y as usize, z as usize])?;
+    let spacing = numbers("ElementSpacing");
+    let spacing = if spacing.len() == 3 { [spacing[0], spacing[1], spacing[2]] } else { [1.0; 3] };
+    Ok(Volume { data, spacing })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh temporary directory for one test
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("3dlab-cli-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn convert_args(input: &str, output: PathBuf) -> ConvertArgs {
+        ConvertArgs {
+            input: PathBuf::from(input),
+            output,
+            frame: 0,
+            crop: None,
+            resolution: None,
+        }
+    }
+
+    async fn load_error(path: &str) -> String {
+        match load(Path::new(path), 0).await {
+            Ok(_) => panic!("{} loaded", path),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn phantom_inputs_are_generated() {
+        let volume = load(Path::new("phantom:checkerboard:4x6x8"), 0).await.unwrap();
+        assert_eq!(volume.data.shape(), [4, 6, 8]);
+        assert_eq!(volume.spacing, [1.0; 3]);
+        assert!(load_error("phantom:nothing").await.contains("Unknown phantom"));
+    }
+
+    #[tokio::test]
+    async fn unknown_formats_are_rejected() {
+        assert!(load_error("volume.tiff").await.contains("Unsupported input format"));
+        assert!(load_error("volume").await.contains("Unsupported input format"));
+
+        let dir = temp_dir("unknown");
+        let error = convert(&convert_args("phantom:checkerboard:4", dir.join("volume.tiff"))).await.unwrap_err();
+        assert!(error.to_string().contains("Unsupported output format"));
+        assert!(!dir.join("volume.tiff").exists());
+    }
+
+    #[tokio::test]
+    async fn converts_round_trip_through_each_format() {
+        let original = load(Path::new("phantom:shepp-logan:6x5x4"), 0).await.unwrap().data;
+        let dir = temp_dir("round-trip");
+        // Extensions are matched case-insensitively
+        for name in ["volume.npy", "volume.nii", "volume.mha", "VOLUME.MHD"] {
+            let output = dir.join(name);
+            convert(&convert_args("phantom:shepp-logan:6x5x4", output.clone())).await.unwrap();
+            assert_eq!(load(&output, 0).await.unwrap().data, original, "{}", name);
+        }
+        // The MetaImage header names its data file
+        assert!(dir.join("VOLUME.raw").exists());
+    }
+
+    #[tokio::test]
+    async fn crops_then_downsamples() {
+        let dir = temp_dir("crop");
+        let output = dir.join("volume.npy");
+        let args = ConvertArgs {
+            crop: Some("0:8,0:4,2:4".to_string()),
+            resolution: Some(4),
+            ..convert_args("phantom:gradient:8", output.clone())
+        };
+        convert(&args).await.unwrap();
+        assert_eq!(load(&output, 0).await.unwrap().data.shape(), [4, 2, 1]);
+
+        let outside = ConvertArgs {
+            crop: Some("0:9,0:4,0:4".to_string()),
+            ..convert_args("phantom:gradient:8", dir.join("outside.npy"))
+        };
+        assert!(convert(&outside).await.is_err());
+    }
+}
@@ -0,0 +1,102 @@
+use hdf5::types::VarLenUnicode;
+use hdf5::{Attribute, File, Group};
+use server::fastmri;
+use server::hdf5_reader::HDF5Volume;
+use std::path::Path;
+
+use crate::Result;
+
+/// Longest numeric attribute printed in full
+const MAX_ATTR_VALUES: usize = 8;
+
+/// Print the structure of an HDF5 file, then how the server would load it
+pub async fn run(path: &Path) -> Result<()> {
+    let file = File::open(path)?;
+
+    println!("\nExploring: {}", path.display());
+    println!("{}", "=".repeat(60));
+
+    let root_attrs = file.attr_names()?;
+    if !root_attrs.is_empty() {
+        println!("\nRoot attributes:");
+        for name in root_attrs {
+            println!("  @{}: {}", name, format_attr(&file.attr(&name)?));
+        }
+    }
+
+    println!("\nTop-level keys: {:?}", file.member_names()?);
+    println!("\nFull structure:");
+    println!("{}", "-".repeat(60));
+    visit(&file, "", 1)?;
+
+    println!();
+    let opened = match fastmri::is_fastmri(path) {
+        true => HDF5Volume::open_fastmri(path).await,
+        false => HDF5Volume::open(path).await.map(|volume| vec![volume]),
+    };
+    match opened {
+        Ok(volumes) => {
+            for volume in volumes {
+                let part = volume.part().map(|p| format!(" ({})", p)).unwrap_or_default();
+                let info = volume.info;
+                println!(
+                    "Server view{}: {}x{}x{} voxels, {} frame(s), spacing {:?} mm, values {} - {}",
+                    part,
+                    info.dimensions[0],
+                    info.dimensions[1],
+                    info.dimensions[2],
+                    info.frames,
+                    info.spacing,
+                    info.value_range[0],
+                    info.value_range[1]
+                );
+            }
+        }
+        Err(e) => println!("Server view: not loadable ({})", e),
+    }
+    Ok(())
+}
+
+/// Print the members of `group` (at `path`) and their attributes, recursively
+fn visit(group: &Group, path: &str, depth: usize) -> Result<()> {
+    let indent = "  ".repeat(depth);
+    for name in group.member_names()? {
+        let full_name = format!("{}{}", path, name);
+        if let Ok(dataset) = group.dataset(&name) {
+            let dtype = dataset
+                .dtype()
+                .and_then(|t| t.to_descriptor())
+                .map(|d| d.to_string())
+                .unwrap_or_else(|_| "unknown".to_string());
+            println!("{}{} [Dataset: shape={:?}, dtype={}]", indent, full_name, dataset.shape(), dtype);
+            for attr in dataset.attr_names()? {
+                println!("{}  @{}: {}", indent, attr, format_attr(&dataset.attr(&attr)?));
+            }
+        } else if let Ok(child) = group.group(&name) {
+            println!("{}{}/ [Group]", indent, full_name);
+            for attr in child.attr_names()? {
+                println!("{}  @{}: {}", indent, attr, format_attr(&child.attr(&attr)?));
+            }
+            visit(&child, &format!("{}/", full_name), depth + 1)?;
+        }
+    }
+    Ok(())
+}
+
+/// Numbers in full when short, strings as text, anything else by type
+fn format_attr(attr: &Attribute) -> String {
+    if let Ok(values) = attr.read_raw::<f64>() {
+        return match values.len() {
+            1 => values[0].to_string(),
+            n if n <= MAX_ATTR_VALUES => format!("{:?}", values),
+            n => format!("[{} values]", n),
+        };
+    }
+    if let Ok(text) = attr.read_scalar::<VarLenUnicode>() {
+        return format!("{:?}", text.as_str());
+    }
+    attr.dtype()
+        .and_then(|t| t.to_descriptor())
+        .map(|d| format!("<{}>", d))
+        .unwrap_or_else(|_| "<unreadable>".to_string())
+}
@@ -0,0 +1,48 @@
+mod extract;
+mod formats;
+mod inspect;
+mod slice;
+
+use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+
+/// Errors are reported to the user as text, so any error type will do
+pub type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+/// Inspect, extract, slice and convert volumes
+#[derive(Parser)]
+#[command(name = "3dlab", version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Print the groups, datasets, shapes, dtypes and attributes of an HDF5 file
+    Inspect {
+        file: PathBuf,
+    },
+    /// Write part of an N-D dataset to an HDF5 volume the server can load
+    Extract(extract::Args),
+    /// Save cross-sections of a volume as PNG
+    Slice(slice::Args),
+    /// Convert a volume between HDF5, NIfTI, NPY and MetaImage
+    Convert(formats::ConvertArgs),
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    let cli = Cli::parse();
+    let result = match cli.command {
+        Command::Inspect { file } => inspect::run(&file).await,
+        Command::Extract(args) => extract::run(&args),
+        Command::Slice(args) => slice::run(&args).await,
+        Command::Convert(args) => formats::convert(&args).await,
+    };
+
+    if let Err(e) = result {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,144 @@
+use clap::{Parser, ValueEnum};
+use ndarray::{Array2, Array3, Axis};
+use std::io::BufWriter;
+use std::path::PathBuf;
+
+use crate::formats;
+use crate::Result;
+
+/// Gap between panels when all three planes are saved
+const PANEL_GAP: usize = 8;
+
+#[derive(Parser)]
+pub struct Args {
+    /// Any volume `convert` can read
+    input: PathBuf,
+    /// Axis held fixed; all three middle planes side by side if omitted
+    #[arg(long, value_enum)]
+    axis: Option<SliceAxis>,
+    /// Slice index along `axis` (default: middle)
+    #[arg(long)]
+    index: Option<usize>,
+    /// Time frame of a time series
+    #[arg(long, default_value_t = 0)]
+    frame: u32,
+    /// Window minimum (default: volume minimum)
+    #[arg(long)]
+    min: Option<f32>,
+    /// Window maximum (default: volume maximum)
+    #[arg(long)]
+    max: Option<f32>,
+    #[arg(short, long, default_value = "cross_sections.png")]
+    output: PathBuf,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum SliceAxis {
+    X,
+    Y,
+    Z,
+}
+
+/// Save one or three cross-sections as an 8-bit grayscale PNG
+pub async fn run(args: &Args) -> Result<()> {
+    let volume = formats::load(&args.input, args.frame).await?;
+    let data = &volume.data;
+    println!("Shape: {:?}", data.shape());
+
+    let min = args.min.unwrap_or_else(|| data.iter().cloned().fold(f32::INFINITY, f32::min));
+    let max = args.max.unwrap_or_else(|| data.iter().cloned().fold(f32::NEG_INFINITY, f32::max));
+    println!("Window: {:.2} - {:.2}", min, max);
+
+    let panels = match args.axis {
+        Some(axis) => {
+            let axis = axis as usize;
+            let index = args.index.unwrap_or(data.len_of(Axis(axis)) / 2);
+            if index >= data.len_of(Axis(axis)) {
+                return Err(format!("Index {} out of range (size {})", index, data.len_of(Axis(axis))).into());
+            }
+            vec![plane(data, axis, index)]
+        }
+        None => (0..3).rev().map(|axis| plane(data, axis, data.len_of(Axis(axis)) / 2)).collect(),
+    };
+
+    let width = panels.iter().map(|p| p.ncols()).sum::<usize>() + PANEL_GAP * (panels.len() - 1);
+    let height = panels.iter().map(|p| p.nrows()).max().unwrap_or(0);
+    let mut pixels = vec![0u8; width * height];
+    let scale = if max > min { 255.0 / (max - min) } else { 0.0 };
+    let mut left = 0;
+    for panel in &panels {
+        for ((row, col), &value) in panel.indexed_iter() {
+            pixels[row * width + left + col] = ((value - min) * scale).clamp(0.0, 255.0) as u8;
+        }
+        left += panel.ncols() + PANEL_GAP;
+    }
+
+    let file = std::fs::File::create(&args.output)?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), width as u32, height as u32);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header()?.write_image_data(&pixels)?;
+
+    println!("Saved to: {}", args.output.display());
+    Ok(())
+}
+
+/// The plane at `index` along `axis`, as an image: columns follow the lower
+/// remaining axis, rows the higher one, increasing upwards
+fn plane(data: &Array3<f32>, axis: usize, index: usize) -> Array2<f32> {
+    let plane = data.index_axis(Axis(axis), index);
+    let mut image = plane.t().to_owned();
+    image.invert_axis(Axis(0));
+    image
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::BufReader;
+
+    fn args(axis: Option<SliceAxis>, index: Option<usize>, name: &str) -> Args {
+        let dir = std::env::temp_dir().join(format!("3dlab-cli-{}-slice", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        Args {
+            input: PathBuf::from("phantom:checkerboard:4x6x8"),
+            axis,
+            index,
+            frame: 0,
+            min: None,
+            max: None,
+            output: dir.join(name),
+        }
+    }
+
+    /// Width and height of a saved PNG
+    fn image_size(args: &Args) -> (u32, u32) {
+        let file = std::fs::File::open(&args.output).unwrap();
+        let reader = png::Decoder::new(BufReader::new(file)).read_info().unwrap();
+        (reader.info().width, reader.info().height)
+    }
+
+    #[tokio::test]
+    async fn saves_one_plane_or_all_three() {
+        // Side by side: z (4x6), y (4x8) and x (6x8), separated by gaps
+        let all = args(None, None, "all.png");
+        run(&all).await.unwrap();
+        assert_eq!(image_size(&all), (4 + 4 + 6 + 2 * PANEL_GAP as u32, 8));
+
+        let one = args(Some(SliceAxis::Z), Some(0), "z.png");
+        run(&one).await.unwrap();
+        assert_eq!(image_size(&one), (4, 6));
+
+        assert!(run(&args(Some(SliceAxis::X), Some(4), "outside.png")).await.is_err());
+    }
+
+    #[test]
+    fn planes_are_upright() {
+        let data = Array3::from_shape_fn((2, 3, 1), |(x, y, _)| (x * 10 + y) as f32);
+        let image = plane(&data, 2, 0);
+        assert_eq!(image.shape(), [3, 2]);
+        // Top left is the lowest x and the highest y
+        assert_eq!(image[[0, 0]], 2.0);
+        assert_eq!(image[[2, 1]], 10.0);
+    }
+}
@@ -1,16 +1,71 @@
 use eframe::egui;
 use eframe::glow;
 use glam::Vec3;
-use shared::{VolumeInfo, VolumeListResponse};
+use api_client::{ApiClient, ClientError, Progress};
+use shared::{
+    default_label_color, Annotation, AnnotationRequest, AnnotationShape, CompareRequest, CompareResponse, CatalogFolder, ExportFormat, ExportQuery,
+    FailedVolume, FrameRangeQuery, LabelVolumeInfo, MeshFormat, MeshQuery, QualityMetrics, SortOrder, SpectrumRequest, SpectrumResponse,
+    VolumeInfo, VolumeListQuery, VolumeListResponse, VolumeSort,
+};
+use std::collections::{BTreeSet, HashSet};
 use std::sync::{Arc, Mutex};
 
-use crate::renderer::{Camera, VolumeRenderer};
+use crate::renderer::{Camera, VolumeRenderer, FRAME_RING_SIZE};
+
+/// Frames of a time series fetched ahead of the current one. Leaves room in
+/// the GPU ring for the current frame and the one before it.
+const FRAME_PREFETCH: u32 = FRAME_RING_SIZE as u32 - 2;
+
+/// Rows fetched per page of the catalog table
+const CATALOG_PAGE_SIZE: usize = 100;
+
+/// Run a request in the background, on a shared tokio runtime natively and
+/// on the browser's event loop in wasm
+#[cfg(not(target_arch = "wasm32"))]
+fn spawn(request: impl std::future::Future<Output = ()> + Send + 'static) {
+    static RUNTIME: std::sync::OnceLock<tokio::runtime::Runtime> = std::sync::OnceLock::new();
+    RUNTIME
+        .get_or_init(|| {
+            tokio::runtime::Builder::new_multi_thread()
+                .worker_threads(2)
+                .enable_all()
+                .build()
+                .expect("Failed to start the request runtime")
+        })
+        .spawn(request);
+}
+
+#[cfg(target_arch = "wasm32")]
+fn spawn(request: impl std::future::Future<Output = ()> + 'static) {
+    wasm_bindgen_futures::spawn_local(request);
+}
 
 /// Shared state for async operations
 #[derive(Default)]
 struct AsyncState {
-    volumes: Option<Result<Vec<VolumeInfo>, String>>,
+    volumes: Option<Result<VolumeListResponse, String>>,
     volume_data: Option<Result<VolumeData, String>>,
+    label_data: Option<Result<LabelData, String>>,
+    mesh_data: Option<Result<MeshData, String>>,
+    comparison: Option<Result<CompareResponse, String>>,
+    spectrum: Option<Result<SpectrumResponse, String>>,
+    frame_data: Option<Result<FrameBatch, String>>,
+    /// Annotations of a volume, by volume id
+    annotations: Option<Result<(String, Vec<Annotation>), String>>,
+    catalog_page: Option<CatalogPage>,
+    /// Progress of the volume download in flight
+    download_progress: Progress,
+    /// Incremented for each download; older downloads stop when they see a newer value
+    download_generation: u64,
+}
+
+/// A page of the catalog table
+struct CatalogPage {
+    /// Request number, so answers to outdated searches are dropped
+    request: u64,
+    /// Whether the rows follow the ones already shown
+    append: bool,
+    result: Result<VolumeListResponse, String>,
 }
 
 /// Loaded volume data ready for GPU upload
@@ -19,6 +74,138 @@ struct VolumeData {
     data: Vec<f32>,
     dims: [u32; 3],
     value_range: [f32; 2],
+    /// Number of time frames; `data` is the first
+    frames: u32,
+}
+
+/// Consecutive time frames of a volume, ready for GPU upload
+struct FrameBatch {
+    volume_id: String,
+    start: u32,
+    frames: Vec<Vec<f32>>,
+}
+
+/// Label volume for the overlay, with values widened to u16
+#[derive(Clone)]
+struct LabelData {
+    info: LabelVolumeInfo,
+    values: Vec<u16>,
+}
+
+/// Surface mesh ready for GPU upload
+#[derive(Clone)]
+struct MeshData {
+    volume_id: String,
+    /// Interleaved position + normal per vertex, in the volume's [-0.5, 0.5] model space
+    vertices: Vec<f32>,
+    indices: Vec<u32>,
+}
+
+/// Which surface to extract
+#[derive(Clone, Copy, PartialEq)]
+enum SurfaceSource {
+    /// Isosurface of the intensity
+    Intensity,
+    /// Boundary of one label value
+    Label(u32),
+}
+
+/// Metric plotted in the comparison panel
+#[derive(Clone, Copy, PartialEq)]
+enum Metric {
+    Psnr,
+    Ssim,
+    Nmse,
+    Mae,
+}
+
+impl Metric {
+    const ALL: [Metric; 4] = [Metric::Psnr, Metric::Ssim, Metric::Nmse, Metric::Mae];
+
+    fn name(self) -> &'static str {
+        match self {
+            Metric::Psnr => "PSNR",
+            Metric::Ssim => "SSIM",
+            Metric::Nmse => "NMSE",
+            Metric::Mae => "MAE",
+        }
+    }
+
+    /// Value of this metric, if it is defined
+    fn value(self, metrics: &QualityMetrics) -> Option<f64> {
+        match self {
+            Metric::Psnr => metrics.psnr,
+            Metric::Ssim => Some(metrics.ssim),
+            Metric::Nmse => metrics.nmse,
+            Metric::Mae => Some(metrics.mae),
+        }
+    }
+
+    fn format(self, value: Option<f64>) -> String {
+        match (self, value) {
+            (_, None) => "--".to_string(),
+            (Metric::Psnr, Some(v)) => format!("{:.2} dB", v),
+            (Metric::Nmse, Some(v)) => format!("{:.3e}", v),
+            (_, Some(v)) => format!("{:.4}", v),
+        }
+    }
+}
+
+/// Colour and opacity mapping of the volume rendering
+#[derive(Clone, Copy, PartialEq)]
+enum TransferPreset {
+    /// Grey levels, opacity rising with intensity
+    Grayscale,
+    /// For log-magnitude spectra: the noise floor is hidden and the rest runs
+    /// from dark red to white, so the centre of k-space and its spokes stand out
+    LogKSpace,
+}
+
+impl TransferPreset {
+    const ALL: [TransferPreset; 2] = [TransferPreset::Grayscale, TransferPreset::LogKSpace];
+
+    fn name(self) -> &'static str {
+        match self {
+            TransferPreset::Grayscale => "Grayscale",
+            TransferPreset::LogKSpace => "k-space (log)",
+        }
+    }
+
+    /// Preset for a volume: log-scaled k-space for `~fft(logmag)` and
+    /// `~fft2(logmag,axis)` spectra, grayscale otherwise
+    fn for_volume(id: &str) -> Self {
+        match id.rsplit_once('~') {
+            Some((_, op)) if op.starts_with("fft(logmag") || op.starts_with("fft2(logmag") => TransferPreset::LogKSpace,
+            _ => TransferPreset::Grayscale,
+        }
+    }
+}
+
+/// Formats offered by /volumes/{id}/export, as (query value, display name)
+const EXPORT_FORMATS: [(ExportFormat, &str); 6] = [
+    (ExportFormat::Nifti, "NIfTI"),
+    (ExportFormat::Npy, "NPY"),
+    (ExportFormat::Mha, "MetaImage"),
+    (ExportFormat::Mhd, "MHD header"),
+    (ExportFormat::Raw, "MHD data"),
+    (ExportFormat::Hdf5, "HDF5"),
+];
+
+/// Change to the annotation store, followed by a refresh of the list
+enum AnnotationAction {
+    Create(AnnotationRequest),
+    Update(u64, AnnotationRequest),
+    Delete(u64),
+}
+
+/// What clicking the viewport places
+#[derive(Clone, Copy, PartialEq)]
+enum AnnotationTool {
+    Point,
+    /// Two clicks: start and end
+    Line,
+    /// Two clicks: opposite corners
+    Box,
 }
 
 /// Info about a point in the volume (for hover display)
@@ -34,6 +221,8 @@ struct HoverInfo {
     normalized: f32,
     /// Voxel coordinates
     voxel: [u32; 3],
+    /// Label value at the voxel (0 = none)
+    label: u16,
 }
 
 /// Render state that can be shared across threads (no GL types)
@@ -48,6 +237,13 @@ struct RenderParams {
     volume_rotation: glam::Mat4,
     show_axes: bool,
     opacity: f32,
+    transfer_preset: TransferPreset,
+    /// Label overlay opacity (0.0 hides the overlay)
+    label_opacity: f32,
+    show_mesh: bool,
+    mesh_opacity: f32,
+    /// Time frame to render (kept at the previous one until it is uploaded)
+    frame: u32,
 }
 
 impl Default for RenderParams {
@@ -62,6 +258,11 @@ impl Default for RenderParams {
             volume_rotation: glam::Mat4::IDENTITY,
             show_axes: true,
             opacity: 1.0,
+            transfer_preset: TransferPreset::Grayscale,
+            label_opacity: 0.0,
+            show_mesh: true,
+            mesh_opacity: 1.0,
+            frame: 0,
         }
     }
 }
@@ -70,6 +271,14 @@ impl Default for RenderParams {
 struct SharedRenderState {
     params: RenderParams,
     pending_volume: Option<VolumeData>,
+    pending_labels: Option<LabelData>,
+    /// Label colour table to upload (RGBA per label value)
+    pending_label_colors: Option<Vec<[u8; 4]>>,
+    pending_mesh: Option<MeshData>,
+    /// Time frames to add to the GPU ring buffer
+    pending_frames: Vec<(u32, Vec<f32>)>,
+    /// Frames in the GPU ring buffer, as of the last paint
+    resident_frames: Vec<u32>,
 }
 
 impl Default for SharedRenderState {
@@ -77,6 +286,11 @@ impl Default for SharedRenderState {
         Self {
             params: RenderParams::default(),
             pending_volume: None,
+            pending_labels: None,
+            pending_label_colors: None,
+            pending_mesh: None,
+            pending_frames: Vec::new(),
+            resident_frames: Vec::new(),
         }
     }
 }
@@ -84,12 +298,38 @@ impl Default for SharedRenderState {
 /// Main application state
 pub struct App {
     volumes: Vec<VolumeInfo>,
+    /// Files the server found but could not load, shown disabled in the picker
+    failed_volumes: Vec<FailedVolume>,
+    /// File volumes grouped by folder, as listed by the server
+    catalog: CatalogFolder,
+    /// Every tag used by a volume
+    tags: Vec<String>,
+    /// Only show volumes with this tag in the picker
+    tag_filter: Option<String>,
+    /// Whether the catalog table window is open
+    show_catalog: bool,
+    /// Search text and filters of the catalog table
+    catalog_search: String,
+    catalog_tag: Option<String>,
+    catalog_dtype: Option<String>,
+    catalog_sort: VolumeSort,
+    catalog_order: SortOrder,
+    /// Rows of the catalog table fetched so far
+    catalog_rows: Vec<VolumeInfo>,
+    /// Volumes matching the catalog filters, across all pages
+    catalog_total: usize,
+    /// Cursor of the next page of the catalog table, if there is one
+    catalog_cursor: Option<String>,
+    /// Number of the latest catalog request
+    catalog_request: u64,
+    loading_catalog: bool,
+    catalog_error: Option<String>,
     selected_volume: Option<String>,
     loaded_volume: Option<String>,
     loading: bool,
     loading_volume: bool,
     error: Option<String>,
-    api_base: String,
+    api: ApiClient,
     async_state: Arc<Mutex<AsyncState>>,
     /// Shared render state (no GL objects)
     shared_render_state: Arc<Mutex<SharedRenderState>>,
@@ -107,10 +347,81 @@ pub struct App {
     render_quality: f32,
     /// Volume opacity (0.0 = transparent, 1.0 = opaque)
     opacity: f32,
+    /// Transfer function, chosen for each volume as it loads
+    transfer_preset: TransferPreset,
     /// CPU copy of volume data for hover raycasting
     cpu_volume_data: Option<VolumeData>,
     /// Current hover info
     hover_info: HoverInfo,
+    /// Time (egui seconds) when the current download started
+    download_started: f64,
+    /// Label volume chosen for the overlay
+    selected_labels: Option<String>,
+    /// CPU copy of the overlay labels for the legend and hover display
+    labels: Option<LabelData>,
+    loading_labels: bool,
+    label_error: Option<String>,
+    show_labels: bool,
+    label_opacity: f32,
+    /// Label values hidden from the overlay
+    hidden_labels: HashSet<u32>,
+    /// Surface extraction settings
+    surface_source: SurfaceSource,
+    surface_iso: f32,
+    surface_smooth: u32,
+    surface_decimate: u32,
+    loading_mesh: bool,
+    mesh_error: Option<String>,
+    /// Whether a surface for the loaded volume has been uploaded
+    has_mesh: bool,
+    show_mesh: bool,
+    mesh_opacity: f32,
+    /// Reference volume to compare the loaded volume against
+    compare_reference: Option<String>,
+    /// Axis of the per-slice metrics
+    compare_axis: usize,
+    comparing: bool,
+    compare_error: Option<String>,
+    comparison: Option<CompareResponse>,
+    /// Slice axis of the 2D spectrum (`None` = 3D)
+    spectrum_axis: Option<usize>,
+    computing_spectrum: bool,
+    spectrum_error: Option<String>,
+    /// Volume to load on the next frame, e.g. a spectrum just registered
+    volume_to_load: Option<String>,
+    /// Metric shown in the comparison plot
+    plot_metric: Metric,
+    /// Time frame shown for time series
+    current_frame: u32,
+    playing: bool,
+    loop_playback: bool,
+    /// Playback speed in frames per second
+    playback_fps: f32,
+    /// Time (egui seconds) the current frame was first shown
+    frame_shown_at: f64,
+    loading_frames: bool,
+    frame_error: Option<String>,
+    /// CPU copies of the frames on the GPU (other than the first) for hover display
+    cpu_frames: Vec<(u32, Vec<f32>)>,
+    /// Annotations of the loaded volume
+    annotations: Vec<Annotation>,
+    loading_annotations: bool,
+    annotation_error: Option<String>,
+    show_annotations: bool,
+    /// Tool used by viewport clicks (`None` = clicks do nothing)
+    annotation_tool: Option<AnnotationTool>,
+    /// First click of a line or box still waiting for its second one
+    annotation_anchor: Option<[f32; 3]>,
+    /// Annotation whose label and note are being edited
+    selected_annotation: Option<u64>,
+    /// Label and note for new annotations, or of the selected one
+    annotation_label: String,
+    annotation_note: String,
+    /// Export settings: format, voxel box, largest dimension and label mask
+    export_format: ExportFormat,
+    export_crop: Option<[[u32; 2]; 3]>,
+    export_resolution: Option<usize>,
+    export_label: Option<u32>,
 }
 
 impl App {
@@ -189,12 +500,29 @@ impl App {
 
         let mut app = Self {
             volumes: Vec::new(),
+            failed_volumes: Vec::new(),
+            catalog: CatalogFolder::default(),
+            tags: Vec::new(),
+            tag_filter: None,
+            show_catalog: false,
+            catalog_search: String::new(),
+            catalog_tag: None,
+            catalog_dtype: None,
+            catalog_sort: VolumeSort::default(),
+            catalog_order: SortOrder::default(),
+            catalog_rows: Vec::new(),
+            catalog_total: 0,
+            catalog_cursor: None,
+            catalog_request: 0,
+            loading_catalog: false,
+            catalog_error: None,
             selected_volume: None,
             loaded_volume: None,
             loading: true,
             loading_volume: false,
             error: None,
-            api_base,
+            // Requests of one session cancel each other's heavy work on the server
+            api: ApiClient::new(api_base).with_session(Self::new_session_id()),
             async_state,
             shared_render_state,
             camera: Camera::default(),
@@ -204,163 +532,617 @@ impl App {
             show_axes: true,
             render_quality: 0.5,  // Default to medium quality
             opacity: 1.0,  // Default to fully opaque
+            transfer_preset: TransferPreset::Grayscale,
             cpu_volume_data: None,
             hover_info: HoverInfo::default(),
+            download_started: 0.0,
+            selected_labels: None,
+            labels: None,
+            loading_labels: false,
+            label_error: None,
+            show_labels: true,
+            label_opacity: 0.6,
+            hidden_labels: HashSet::new(),
+            surface_source: SurfaceSource::Intensity,
+            surface_iso: 0.0,
+            surface_smooth: 5,
+            surface_decimate: 1,
+            loading_mesh: false,
+            mesh_error: None,
+            has_mesh: false,
+            show_mesh: true,
+            mesh_opacity: 1.0,
+            compare_reference: None,
+            compare_axis: 0,
+            comparing: false,
+            compare_error: None,
+            comparison: None,
+            spectrum_axis: None,
+            computing_spectrum: false,
+            spectrum_error: None,
+            volume_to_load: None,
+            plot_metric: Metric::Psnr,
+            current_frame: 0,
+            playing: false,
+            loop_playback: true,
+            playback_fps: 10.0,
+            frame_shown_at: 0.0,
+            loading_frames: false,
+            frame_error: None,
+            cpu_frames: Vec::new(),
+            annotations: Vec::new(),
+            loading_annotations: false,
+            annotation_error: None,
+            show_annotations: true,
+            annotation_tool: None,
+            annotation_anchor: None,
+            selected_annotation: None,
+            annotation_label: String::new(),
+            annotation_note: String::new(),
+            export_format: EXPORT_FORMATS[0].0,
+            export_crop: None,
+            export_resolution: None,
+            export_label: None,
         };
 
         app.fetch_volumes();
         app
     }
 
+    fn new_session_id() -> String {
+        #[cfg(not(target_arch = "wasm32"))]
+        let seed = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0)
+            ^ std::process::id() as u64;
+        #[cfg(target_arch = "wasm32")]
+        let seed = (js_sys::Math::random() * u64::MAX as f64) as u64;
+
+        format!("{:016x}", seed)
+    }
+
     fn fetch_volumes(&mut self) {
         self.loading = true;
         self.error = None;
 
-        #[cfg(not(target_arch = "wasm32"))]
-        {
-            let state = self.async_state.clone();
-            let url = format!("{}/api/volumes", self.api_base);
-
-            // Spawn background thread to avoid blocking render loop
-            std::thread::spawn(move || {
-                let result = match reqwest::blocking::get(&url) {
-                    Ok(response) => match response.json::<VolumeListResponse>() {
-                        Ok(data) => Ok(data.volumes),
-                        Err(e) => Err(format!("Failed to parse response: {}", e)),
-                    },
-                    Err(e) => Err(format!("Failed to fetch volumes: {}", e)),
-                };
-
-                if let Ok(mut state) = state.lock() {
-                    state.volumes = Some(result);
-                }
-            });
-        }
+        let state = self.async_state.clone();
+        let api = self.api.clone();
+        let query = VolumeListQuery {
+            include: Some("failed".to_string()),
+            ..Default::default()
+        };
 
-        #[cfg(target_arch = "wasm32")]
-        {
-            use gloo_net::http::Request;
+        spawn(async move {
+            let result = api
+                .list_volumes(&query)
+                .await
+                .map_err(|e| format!("Failed to fetch volumes: {}", e));
 
-            let state = self.async_state.clone();
-            let url = format!("{}/api/volumes", self.api_base);
+            if let Ok(mut state) = state.lock() {
+                state.volumes = Some(result);
+            }
+        });
+    }
 
-            wasm_bindgen_futures::spawn_local(async move {
-                let result = async {
-                    let response = Request::get(&url)
-                        .send()
-                        .await
-                        .map_err(|e| format!("Request failed: {}", e))?;
+    /// Fetch the first page of the catalog table, or with `append` the page after the rows shown
+    fn fetch_catalog(&mut self, append: bool) {
+        self.loading_catalog = true;
+        self.catalog_error = None;
+        self.catalog_request += 1;
+        let request = self.catalog_request;
+
+        let search = self.catalog_search.trim();
+        let query = VolumeListQuery {
+            q: Some(search.to_string()).filter(|_| !search.is_empty()),
+            tags: self.catalog_tag.clone(),
+            dtype: self.catalog_dtype.clone(),
+            sort: Some(self.catalog_sort),
+            order: Some(self.catalog_order),
+            limit: Some(CATALOG_PAGE_SIZE),
+            cursor: self.catalog_cursor.clone().filter(|_| append),
+            ..Default::default()
+        };
 
-                    let data: VolumeListResponse = response
-                        .json()
-                        .await
-                        .map_err(|e| format!("Parse failed: {}", e))?;
+        let state = self.async_state.clone();
+        let api = self.api.clone();
 
-                    Ok::<_, String>(data.volumes)
-                }
-                .await;
+        spawn(async move {
+            let result = api
+                .list_volumes(&query)
+                .await
+                .map_err(|e| format!("Failed to fetch catalog: {}", e));
 
-                if let Ok(mut state) = state.lock() {
-                    state.volumes = Some(result);
-                }
-            });
-        }
+            if let Ok(mut state) = state.lock() {
+                state.catalog_page = Some(CatalogPage { request, append, result });
+            }
+        });
     }
 
     fn fetch_volume_data(&mut self, volume_id: &str) {
         self.loading_volume = true;
 
         let volume_info = self.volumes.iter().find(|v| v.id == volume_id).cloned();
+        let frames = volume_info.as_ref().map_or(1, |info| info.frames);
+        // Pinning the content version lets the browser cache the payload
+        let version = volume_info.map(|info| info.version);
+
+        let generation = match self.async_state.lock() {
+            Ok(mut state) => {
+                state.download_progress = Progress::default();
+                state.download_generation += 1;
+                state.download_generation
+            }
+            Err(_) => 0,
+        };
 
-        #[cfg(not(target_arch = "wasm32"))]
-        {
-            let state = self.async_state.clone();
-            let url = format!("{}/api/volumes/{}/full", self.api_base, volume_id);
-
-            // Spawn background thread to avoid blocking render loop
-            std::thread::spawn(move || {
-                let result = match reqwest::blocking::get(&url) {
-                    Ok(response) => match response.bytes() {
-                        Ok(bytes) => {
-                            if let Some(info) = volume_info {
-                                let data: Vec<f32> = bytes
-                                    .chunks_exact(4)
-                                    .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
-                                    .collect();
-
-                                Ok(VolumeData {
-                                    data,
-                                    dims: info.dimensions,
-                                    value_range: info.value_range,
-                                })
-                            } else {
-                                Err("Volume info not found".to_string())
-                            }
-                        }
-                        Err(e) => Err(format!("Failed to read volume data: {}", e)),
-                    },
-                    Err(e) => Err(format!("Failed to fetch volume: {}", e)),
-                };
+        let state = self.async_state.clone();
+        let api = self.api.clone();
+        let volume_id = volume_id.to_string();
 
-                if let Ok(mut state) = state.lock() {
+        spawn(async move {
+            let progress_state = state.clone();
+            let result = api
+                .full(&volume_id, version.as_deref(), move |progress| {
+                    let Ok(mut state) = progress_state.lock() else {
+                        return true;
+                    };
+                    // A newer download replaced this one
+                    if state.download_generation != generation {
+                        return false;
+                    }
+                    state.download_progress = progress;
+                    true
+                })
+                .await
+                .map(|payload| VolumeData {
+                    data: payload.f32s(),
+                    dims: payload.header.dims,
+                    value_range: payload.header.value_range,
+                    frames,
+                })
+                .map_err(|e| format!("Failed to fetch volume: {}", e));
+
+            if let Ok(mut state) = state.lock() {
+                // Results of superseded downloads are dropped
+                if state.download_generation == generation {
                     state.volume_data = Some(result);
                 }
-            });
+            }
+        });
+    }
+
+    /// Fetch a label volume and its label table for the overlay
+    fn fetch_labels(&mut self, label_id: &str) {
+        self.loading_labels = true;
+        self.label_error = None;
+
+        let state = self.async_state.clone();
+        let api = self.api.clone();
+        let label_id = label_id.to_string();
+
+        spawn(async move {
+            let result = async {
+                let info = api.label_info(&label_id).await?;
+                let payload = api.label_data(&label_id).await?;
+                Ok::<_, ClientError>(LabelData {
+                    values: payload.u16s(),
+                    info,
+                })
+            }
+            .await
+            .map_err(|e| format!("Failed to fetch labels: {}", e));
+
+            if let Ok(mut state) = state.lock() {
+                state.label_data = Some(result);
+            }
+        });
+    }
+
+    /// Colour of a label value from the label table, or the default palette
+    fn label_color(info: &LabelVolumeInfo, value: u32) -> [u8; 3] {
+        info.table
+            .iter()
+            .find(|entry| entry.value == value)
+            .map(|entry| entry.color())
+            .unwrap_or_else(|| default_label_color(value))
+    }
+
+    fn label_name(info: &LabelVolumeInfo, value: u32) -> String {
+        info.table
+            .iter()
+            .find(|entry| entry.value == value)
+            .map(|entry| entry.name.clone())
+            .unwrap_or_else(|| format!("Label {}", value))
+    }
+
+    /// RGBA colour table for the overlay, with hidden labels transparent
+    fn label_colors(labels: &LabelData, hidden: &HashSet<u32>) -> Vec<[u8; 4]> {
+        let max = labels.values.iter().copied().max().unwrap_or(0) as u32;
+        (0..=max)
+            .map(|value| {
+                let [r, g, b] = Self::label_color(&labels.info, value);
+                let visible = value > 0 && !hidden.contains(&value);
+                [r, g, b, if visible { 255 } else { 0 }]
+            })
+            .collect()
+    }
+
+    /// Send the current label colours to the renderer
+    fn update_label_colors(&self) {
+        if let Some(labels) = &self.labels {
+            if let Ok(mut render_state) = self.shared_render_state.lock() {
+                render_state.pending_label_colors = Some(Self::label_colors(labels, &self.hidden_labels));
+            }
         }
+    }
 
-        #[cfg(target_arch = "wasm32")]
-        {
-            use gloo_net::http::Request;
-
-            let state = self.async_state.clone();
-            let url = format!("{}/api/volumes/{}/full", self.api_base, volume_id);
-
-            wasm_bindgen_futures::spawn_local(async move {
-                let result = async {
-                    let response = Request::get(&url)
-                        .send()
-                        .await
-                        .map_err(|e| format!("Request failed: {}", e))?;
-
-                    let bytes = response
-                        .binary()
-                        .await
-                        .map_err(|e| format!("Failed to read bytes: {}", e))?;
-
-                    let data: Vec<f32> = bytes
-                        .chunks_exact(4)
-                        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
-                        .collect();
+    /// Query of the selected surface of the loaded volume in `format`
+    fn mesh_query(&self, format: MeshFormat) -> MeshQuery {
+        let mut query = MeshQuery {
+            format,
+            smooth: self.surface_smooth,
+            decimate: self.surface_decimate,
+            ..Default::default()
+        };
+        match self.surface_source {
+            SurfaceSource::Intensity => query.iso = Some(self.surface_iso),
+            SurfaceSource::Label(value) => {
+                query.label = Some(value);
+                query.labels = self.selected_labels.clone();
+            }
+        }
+        query
+    }
 
-                    let (dims, value_range) = if let Some(info) = volume_info {
-                        (info.dimensions, info.value_range)
-                    } else {
-                        // Fallback: try to infer cubic dimensions from data length
-                        let side = (data.len() as f32).cbrt().round() as u32;
-                        ([side, side, side], [0.0, 1.0])
-                    };
+    /// Query for downloading the loaded volume with the export settings and current frame
+    fn export_query(&self, volume: &VolumeInfo) -> ExportQuery {
+        ExportQuery {
+            format: self.export_format,
+            frame: if volume.frames > 1 { self.current_frame } else { 0 },
+            crop: self
+                .export_crop
+                .map(|[x, y, z]| format!("{}:{},{}:{},{}:{}", x[0], x[1], y[0], y[1], z[0], z[1])),
+            resolution: self.export_resolution,
+            label: self.export_label,
+            labels: self.export_label.and(self.selected_labels.clone()),
+        }
+    }
+
+    /// Extract the selected surface on the server and fetch it for display
+    fn fetch_mesh(&mut self, volume: &VolumeInfo) {
+        self.loading_mesh = true;
+        self.mesh_error = None;
+
+        let state = self.async_state.clone();
+        let api = self.api.clone();
+        let query = self.mesh_query(MeshFormat::Obj);
+        let volume = volume.clone();
+
+        spawn(async move {
+            let result = api
+                .mesh(&volume.id, &query)
+                .await
+                .map_err(|e| format!("Failed to fetch surface: {}", e))
+                .and_then(|obj| Self::parse_obj(&String::from_utf8_lossy(&obj), &volume));
+
+            if let Ok(mut state) = state.lock() {
+                state.mesh_data = Some(result);
+            }
+        });
+    }
+
+    /// Parse a triangle OBJ in millimetres into the volume's model space
+    fn parse_obj(obj: &str, volume: &VolumeInfo) -> Result<MeshData, String> {
+        let parse = |parts: std::str::SplitWhitespace| -> Result<[f32; 3], String> {
+            let values: Vec<f32> = parts
+                .map(|p| p.parse().map_err(|_| format!("Invalid number '{}'", p)))
+                .collect::<Result<_, _>>()?;
+            values
+                .get(..3)
+                .map(|v| [v[0], v[1], v[2]])
+                .ok_or_else(|| "Expected 3 coordinates".to_string())
+        };
 
-                    Ok::<_, String>(VolumeData {
-                        data,
-                        dims,
-                        value_range,
-                    })
+        let mut positions = Vec::new();
+        let mut normals = Vec::new();
+        let mut indices = Vec::new();
+        for line in obj.lines() {
+            let mut parts = line.split_whitespace();
+            match parts.next() {
+                Some("v") => positions.push(parse(parts)?),
+                Some("vn") => normals.push(parse(parts)?),
+                Some("f") => {
+                    // Indices are 1-based, or negative counting back from the last vertex
+                    let face = parts
+                        .map(|vertex| {
+                            let index: i64 = vertex.split('/').next()?.parse().ok()?;
+                            let index = if index < 0 { positions.len() as i64 + index } else { index - 1 };
+                            (0..positions.len() as i64).contains(&index).then_some(index as u32)
+                        })
+                        .collect::<Option<Vec<u32>>>()
+                        .filter(|face| face.len() >= 3)
+                        .ok_or_else(|| format!("Invalid face '{}'", line))?;
+                    // Polygons become triangle fans
+                    for i in 1..face.len() - 1 {
+                        indices.extend_from_slice(&[face[0], face[i], face[i + 1]]);
+                    }
                 }
-                .await;
+                _ => {}
+            }
+        }
 
-                if let Ok(mut state) = state.lock() {
-                    state.volume_data = Some(result);
+        // Millimetres -> voxels -> texture coordinates -> centred unit cube
+        let scale: [f32; 3] = std::array::from_fn(|i| volume.spacing[i] * volume.dimensions[i] as f32);
+        let mut vertices = Vec::with_capacity(positions.len() * 6);
+        for (i, p) in positions.iter().enumerate() {
+            for (k, &c) in p.iter().enumerate() {
+                vertices.push((c / volume.spacing[k] + 0.5) / volume.dimensions[k] as f32 - 0.5);
+            }
+            // Normals transform with the inverse scale
+            let n = normals.get(i).copied().unwrap_or([0.0, 0.0, 1.0]);
+            let n = Vec3::new(n[0] * scale[0], n[1] * scale[1], n[2] * scale[2]).normalize_or_zero();
+            vertices.extend_from_slice(&[n.x, n.y, n.z]);
+        }
+
+        Ok(MeshData {
+            volume_id: volume.id.clone(),
+            vertices,
+            indices,
+        })
+    }
+
+    /// Compare the loaded volume against the selected reference
+    fn fetch_comparison(&mut self, volume_id: &str, reference: &str) {
+        self.comparing = true;
+        self.compare_error = None;
+
+        let state = self.async_state.clone();
+        let api = self.api.clone();
+        let volume_id = volume_id.to_string();
+        let request = CompareRequest {
+            reference: reference.to_string(),
+            axis: self.compare_axis,
+        };
+
+        spawn(async move {
+            let result = api
+                .compare(&volume_id, &request)
+                .await
+                .map_err(|e| format!("Failed to compare: {}", e));
+
+            if let Ok(mut state) = state.lock() {
+                state.comparison = Some(result);
+            }
+        });
+    }
+
+    /// Register the spectrum of a volume, to be loaded as its log-magnitude
+    fn fetch_spectrum(&mut self, volume_id: &str) {
+        self.computing_spectrum = true;
+        self.spectrum_error = None;
+
+        let state = self.async_state.clone();
+        let api = self.api.clone();
+        let volume_id = volume_id.to_string();
+        let request = SpectrumRequest {
+            slice_axis: self.spectrum_axis,
+        };
+
+        spawn(async move {
+            let result = api
+                .spectrum(&volume_id, &request)
+                .await
+                .map_err(|e| format!("Failed to compute spectrum: {}", e));
+
+            if let Ok(mut state) = state.lock() {
+                state.spectrum = Some(result);
+            }
+        });
+    }
+
+    /// Apply `action` (if any) to the annotations of a volume, then fetch the list
+    fn sync_annotations(&mut self, volume_id: &str, action: Option<AnnotationAction>) {
+        self.loading_annotations = true;
+        self.annotation_error = None;
+
+        let state = self.async_state.clone();
+        let api = self.api.clone();
+        let volume_id = volume_id.to_string();
+
+        spawn(async move {
+            let result = async move {
+                let saved = match action {
+                    Some(AnnotationAction::Create(request)) => api.create_annotation(&volume_id, &request).await.map(drop),
+                    Some(AnnotationAction::Update(id, request)) => {
+                        api.update_annotation(&volume_id, id, &request).await.map(drop)
+                    }
+                    Some(AnnotationAction::Delete(id)) => api.delete_annotation(&volume_id, id).await,
+                    None => Ok(()),
+                };
+                saved.map_err(|e| format!("Failed to save annotation: {}", e))?;
+                let annotations = api
+                    .annotations(&volume_id)
+                    .await
+                    .map_err(|e| format!("Failed to fetch annotations: {}", e))?;
+                Ok((volume_id, annotations))
+            }
+            .await;
+
+            if let Ok(mut state) = state.lock() {
+                state.annotations = Some(result);
+            }
+        });
+    }
+
+    /// Handle a viewport click at `voxel` with the current annotation tool
+    fn place_annotation(&mut self, voxel: [f32; 3]) {
+        let (Some(tool), Some(volume_id)) = (self.annotation_tool, self.loaded_volume.clone()) else {
+            return;
+        };
+
+        let shape = match (tool, self.annotation_anchor.take()) {
+            (AnnotationTool::Point, _) => AnnotationShape::Point { position: voxel },
+            (AnnotationTool::Line, Some(start)) => AnnotationShape::Line { start, end: voxel },
+            (AnnotationTool::Box, Some(corner)) => AnnotationShape::Box { min: corner, max: voxel },
+            (_, None) => {
+                self.annotation_anchor = Some(voxel);
+                return;
+            }
+        };
+
+        let request = AnnotationRequest {
+            shape,
+            units: Default::default(),
+            label: self.annotation_label.clone(),
+            text: std::mem::take(&mut self.annotation_note),
+        };
+        self.sync_annotations(&volume_id, Some(AnnotationAction::Create(request)));
+    }
+
+    /// Fetch `count` consecutive frames of a time series starting at `start`.
+    /// Sent without the session so prefetching never cancels the volume download.
+    fn fetch_frames(&mut self, volume: &VolumeInfo, start: u32, count: u32) {
+        self.loading_frames = true;
+        self.frame_error = None;
+
+        let state = self.async_state.clone();
+        let api = self.api.without_session();
+        let volume_id = volume.id.clone();
+        let version = volume.version.clone();
+
+        spawn(async move {
+            let result = api
+                .frames(&volume_id, &FrameRangeQuery { start, count }, Some(&version))
+                .await
+                .map(|payload| FrameBatch {
+                    start: payload.header.frame,
+                    frames: payload.f32_frames(),
+                    volume_id,
+                })
+                .map_err(|e| format!("Failed to fetch frames: {}", e));
+
+            if let Ok(mut state) = state.lock() {
+                state.frame_data = Some(result);
+            }
+        });
+    }
+
+    /// Info of the loaded volume if it is a time series
+    fn loaded_time_series(&self) -> Option<&VolumeInfo> {
+        self.loaded_volume
+            .as_ref()
+            .and_then(|id| self.volumes.iter().find(|v| &v.id == id))
+            .filter(|v| v.frames > 1)
+    }
+
+    /// CPU copy of the frame being shown, if it has arrived
+    fn current_frame_values(&self) -> Option<&[f32]> {
+        if self.current_frame == 0 {
+            return self.cpu_volume_data.as_ref().map(|v| v.data.as_slice());
+        }
+        self.cpu_frames
+            .iter()
+            .find(|(frame, _)| *frame == self.current_frame)
+            .map(|(_, values)| values.as_slice())
+    }
+
+    /// Advance playback once the next frame is on the GPU, and prefetch the
+    /// frames after the current one
+    fn update_playback(&mut self, now: f64) {
+        let Some(volume) = self.loaded_time_series().cloned() else {
+            return;
+        };
+        let frames = volume.frames;
+
+        // Frames on the GPU or waiting to be uploaded
+        let mut available = match self.shared_render_state.lock() {
+            Ok(state) => {
+                let mut available = state.resident_frames.clone();
+                available.extend(state.pending_frames.iter().map(|(frame, _)| *frame));
+                available
+            }
+            Err(_) => return,
+        };
+        available.push(0);
+        self.cpu_frames.retain(|(frame, _)| available.contains(frame));
+
+        let next = |frame: u32| match frame + 1 {
+            next if next < frames => Some(next),
+            _ if self.loop_playback => Some(0),
+            _ => None,
+        };
+
+        if self.playing && now - self.frame_shown_at >= 1.0 / self.playback_fps as f64 {
+            match next(self.current_frame) {
+                // Otherwise wait for the frame to arrive (buffering)
+                Some(frame) if available.contains(&frame) => {
+                    self.current_frame = frame;
+                    self.frame_shown_at = now;
                 }
-            });
+                Some(_) => {}
+                None => self.playing = false,
+            }
+        }
+
+        if self.loading_frames || self.frame_error.is_some() {
+            return;
         }
+
+        // The current frame (after scrubbing) and the ones playback reaches next
+        let mut upcoming = vec![self.current_frame];
+        for _ in 0..FRAME_PREFETCH {
+            match upcoming.last().and_then(|&frame| next(frame)) {
+                Some(frame) if !upcoming.contains(&frame) => upcoming.push(frame),
+                _ => break,
+            }
+        }
+
+        // One request for the first run of consecutive missing frames
+        let Some(first) = upcoming.iter().position(|frame| !available.contains(frame)) else {
+            return;
+        };
+        let start = upcoming[first];
+        let count = upcoming[first..]
+            .iter()
+            .enumerate()
+            .take_while(|(i, frame)| **frame == start + *i as u32 && !available.contains(frame))
+            .count() as u32;
+        self.fetch_frames(&volume, start, count);
     }
 
     fn poll_async_state(&mut self) {
+        let mut labels_to_fetch = None;
+        let mut annotations_to_fetch = None;
+
         if let Ok(mut state) = self.async_state.lock() {
+            // Answers to outdated catalog requests are dropped
+            if let Some(page) = state.catalog_page.take().filter(|p| p.request == self.catalog_request) {
+                self.loading_catalog = false;
+                match page.result {
+                    Ok(list) => {
+                        if page.append {
+                            self.catalog_rows.extend(list.volumes);
+                        } else {
+                            self.catalog_rows = list.volumes;
+                        }
+                        self.catalog_total = list.total;
+                        self.catalog_cursor = list.next_cursor;
+                    }
+                    Err(e) => self.catalog_error = Some(e),
+                }
+            }
+
             if let Some(result) = state.volumes.take() {
                 match result {
-                    Ok(volumes) => {
-                        self.volumes = volumes;
+                    Ok(list) => {
+                        self.volumes = list.volumes;
+                        self.failed_volumes = list.failed;
+                        self.catalog = list.tree;
+                        if self.tag_filter.as_ref().is_some_and(|tag| !list.tags.contains(tag)) {
+                            self.tag_filter = None;
+                        }
+                        self.tags = list.tags;
                         self.loading = false;
                         self.error = None;
                     }
@@ -375,15 +1157,54 @@ impl App {
                 match result {
                     Ok(data) => {
                         self.loading_volume = false;
+                        // Surfaces belong to the previous volume
+                        self.has_mesh = false;
+                        self.surface_source = SurfaceSource::Intensity;
+                        self.surface_iso = (data.value_range[0] + data.value_range[1]) / 2.0;
+
+                        // Time series start paused on the first frame
+                        self.current_frame = 0;
+                        self.playing = false;
+                        self.frame_error = None;
+                        self.cpu_frames.clear();
+                        self.playback_fps = self
+                            .selected_volume
+                            .as_ref()
+                            .and_then(|id| self.volumes.iter().find(|v| &v.id == id))
+                            .and_then(|v| v.frame_interval)
+                            .map_or(10.0, |interval| (1.0 / interval).clamp(1.0, 60.0));
+
                         // Keep a CPU copy for hover raycasting
                         self.cpu_volume_data = Some(data.clone());
                         // Store pending volume in shared state for callback to pick up
                         if let Ok(mut render_state) = self.shared_render_state.lock() {
                             render_state.params.value_range = data.value_range;
                             render_state.pending_volume = Some(data);
+                            render_state.pending_frames.clear();
+                            render_state.resident_frames.clear();
                         }
                         self.has_volume = true;
                         self.loaded_volume = self.selected_volume.clone();
+                        self.transfer_preset = TransferPreset::for_volume(self.loaded_volume.as_deref().unwrap_or_default());
+
+                        // Overlay the first linked label volume, if any
+                        self.labels = None;
+                        self.selected_labels = self
+                            .loaded_volume
+                            .as_ref()
+                            .and_then(|id| self.volumes.iter().find(|v| &v.id == id))
+                            .and_then(|v| v.labels.first().cloned());
+                        labels_to_fetch = self.selected_labels.clone();
+
+                        // Export settings refer to the previous volume's voxels and labels
+                        self.export_crop = None;
+                        self.export_label = None;
+
+                        self.annotations.clear();
+                        self.annotation_anchor = None;
+                        self.selected_annotation = None;
+                        self.annotation_error = None;
+                        annotations_to_fetch = self.loaded_volume.clone();
                     }
                     Err(e) => {
                         self.error = Some(e);
@@ -391,12 +1212,118 @@ impl App {
                     }
                 }
             }
+
+            if let Some(result) = state.label_data.take() {
+                self.loading_labels = false;
+                match result {
+                    // Labels of a volume that is no longer shown are dropped
+                    Ok(labels) if self.loaded_volume.as_ref() == Some(&labels.info.volume_id) => {
+                        self.hidden_labels.clear();
+                        if let Ok(mut render_state) = self.shared_render_state.lock() {
+                            render_state.pending_label_colors =
+                                Some(Self::label_colors(&labels, &self.hidden_labels));
+                            render_state.pending_labels = Some(labels.clone());
+                        }
+                        self.labels = Some(labels);
+                    }
+                    Ok(_) => {}
+                    Err(e) => self.label_error = Some(e),
+                }
+            }
+
+            if let Some(result) = state.mesh_data.take() {
+                self.loading_mesh = false;
+                match result {
+                    Ok(mesh) if self.loaded_volume.as_ref() == Some(&mesh.volume_id) => {
+                        if let Ok(mut render_state) = self.shared_render_state.lock() {
+                            render_state.pending_mesh = Some(mesh);
+                        }
+                        self.has_mesh = true;
+                    }
+                    Ok(_) => {}
+                    Err(e) => self.mesh_error = Some(e),
+                }
+            }
+
+            if let Some(result) = state.frame_data.take() {
+                self.loading_frames = false;
+                match result {
+                    // Frames of a volume that is no longer shown are dropped
+                    Ok(batch) if self.loaded_volume.as_ref() == Some(&batch.volume_id) => {
+                        let frames: Vec<(u32, Vec<f32>)> = (batch.start..)
+                            .zip(batch.frames)
+                            .collect();
+                        if let Ok(mut render_state) = self.shared_render_state.lock() {
+                            render_state.pending_frames.extend(frames.iter().cloned());
+                        }
+                        self.cpu_frames.extend(frames);
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        self.frame_error = Some(e);
+                        self.playing = false;
+                    }
+                }
+            }
+
+            if let Some(result) = state.annotations.take() {
+                self.loading_annotations = false;
+                match result {
+                    Ok((volume_id, annotations)) if self.loaded_volume.as_ref() == Some(&volume_id) => {
+                        if !annotations.iter().any(|a| Some(a.id) == self.selected_annotation) {
+                            self.selected_annotation = None;
+                        }
+                        self.annotations = annotations;
+                    }
+                    Ok(_) => {}
+                    Err(e) => self.annotation_error = Some(e),
+                }
+            }
+
+            if let Some(result) = state.comparison.take() {
+                self.comparing = false;
+                match result {
+                    Ok(comparison) => {
+                        // Make the difference volume selectable
+                        if !self.volumes.iter().any(|v| v.id == comparison.difference.id) {
+                            self.volumes.push(comparison.difference.clone());
+                        }
+                        self.comparison = Some(comparison);
+                    }
+                    Err(e) => self.compare_error = Some(e),
+                }
+            }
+
+            if let Some(result) = state.spectrum.take() {
+                self.computing_spectrum = false;
+                match result {
+                    Ok(spectrum) => {
+                        for info in [&spectrum.log_magnitude, &spectrum.phase] {
+                            if !self.volumes.iter().any(|v| v.id == info.id) {
+                                self.volumes.push(info.clone());
+                            }
+                        }
+                        self.selected_volume = Some(spectrum.log_magnitude.id.clone());
+                        self.volume_to_load = Some(spectrum.log_magnitude.id);
+                    }
+                    Err(e) => self.spectrum_error = Some(e),
+                }
+            }
+        }
+
+        if let Some(label_id) = labels_to_fetch {
+            self.fetch_labels(&label_id);
+        }
+        // Derived volumes (`source~op`) are computed, so annotations live on their source
+        if let Some(volume_id) = annotations_to_fetch.filter(|id| !id.contains('~')) {
+            self.sync_annotations(&volume_id, None);
         }
     }
 
     /// Raycast into volume to find first significant voxel
     fn raycast_volume(&self, ray_origin: Vec3, ray_dir: Vec3) -> Option<HoverInfo> {
         let vol_data = self.cpu_volume_data.as_ref()?;
+        let values = self.current_frame_values()?;
         let dims = vol_data.dims;
         let value_range = vol_data.value_range;
 
@@ -438,17 +1365,23 @@ impl App {
                 let vz = ((rotated_pos.z * dims[2] as f32) as u32).min(dims[2] - 1);
 
                 let idx = (vx * dims[1] * dims[2] + vy * dims[2] + vz) as usize;
-                if idx < vol_data.data.len() {
-                    let value = vol_data.data[idx];
+                if idx < values.len() {
+                    let value = values[idx];
                     let normalized = (value - value_range[0]) / (value_range[1] - value_range[0]);
 
                     if normalized > threshold {
+                        let label = self
+                            .labels
+                            .as_ref()
+                            .and_then(|labels| labels.values.get(idx).copied())
+                            .unwrap_or(0);
                         return Some(HoverInfo {
                             valid: true,
                             position: [rotated_pos.x, rotated_pos.y, rotated_pos.z],
                             value,
                             normalized,
                             voxel: [vx, vy, vz],
+                            label,
                         });
                     }
                 }
@@ -460,49 +1393,296 @@ impl App {
         None
     }
 
-    fn render_sidebar(&mut self, ui: &mut egui::Ui) -> Option<String> {
-        let mut volume_changed = None;
-
-        ui.heading("3DLab");
-        ui.separator();
-
-        ui.label("Select Volume:");
-        if self.loading {
-            ui.spinner();
-        } else if let Some(error) = &self.error {
-            ui.colored_label(egui::Color32::RED, error);
-            if ui.button("Retry").clicked() {
-                self.fetch_volumes();
+    /// One level of the volume picker: subfolders as collapsible headers, then
+    /// the volumes and the files that failed to load in this folder
+    fn render_catalog_folder(
+        ui: &mut egui::Ui,
+        folder: &CatalogFolder,
+        volumes: &[VolumeInfo],
+        failed: &[FailedVolume],
+        tag_filter: Option<&str>,
+        selected: &mut Option<String>,
+    ) {
+        for child in &folder.folders {
+            if tag_filter.is_some_and(|tag| !Self::folder_has_tag(child, volumes, tag)) {
+                continue;
             }
-        } else {
-            let previous_selection = self.selected_volume.clone();
-
-            egui::ComboBox::from_label("")
-                .selected_text(
-                    self.selected_volume
-                        .as_ref()
-                        .and_then(|id| self.volumes.iter().find(|v| &v.id == id))
-                        .map(|v| v.name.as_str())
-                        .unwrap_or("Select..."),
-                )
-                .show_ui(ui, |ui| {
-                    for volume in &self.volumes {
-                        ui.selectable_value(
-                            &mut self.selected_volume,
-                            Some(volume.id.clone()),
-                            &volume.name,
-                        );
-                    }
+            let contains_selection = selected
+                .as_ref()
+                .is_some_and(|id| id.starts_with(&format!("{}/", child.path)));
+            egui::CollapsingHeader::new(&child.name)
+                .id_salt(&child.path)
+                .default_open(contains_selection)
+                .show(ui, |ui| {
+                    Self::render_catalog_folder(ui, child, volumes, failed, tag_filter, selected);
                 });
+        }
 
-            if self.selected_volume != previous_selection {
-                volume_changed = self.selected_volume.clone();
+        for id in &folder.volumes {
+            let Some(volume) = volumes.iter().find(|v| &v.id == id) else {
+                continue;
+            };
+            if tag_filter.is_some_and(|tag| !volume.tags.iter().any(|t| t == tag)) {
+                continue;
+            }
+            let response = ui.selectable_value(selected, Some(volume.id.clone()), &volume.name);
+            if !volume.tags.is_empty() {
+                response.on_hover_text(volume.tags.join(", "));
             }
         }
 
-        ui.separator();
-
-        if let Some(volume) = self
+        if tag_filter.is_none() {
+            for entry in failed.iter().filter(|f| Self::parent_folder(&f.id) == folder.path) {
+                let name = entry.id.rsplit('/').next().unwrap_or(&entry.id);
+                ui.add_enabled(false, egui::SelectableLabel::new(false, name))
+                    .on_disabled_hover_text(format!("{} failed to load: {}", entry.file, entry.error));
+            }
+        }
+    }
+
+    /// Folder part of a volume id ("" at the top level)
+    fn parent_folder(id: &str) -> &str {
+        id.rsplit_once('/').map_or("", |(folder, _)| folder)
+    }
+
+    fn has_folder(folder: &CatalogFolder, path: &str) -> bool {
+        folder.path == path || folder.folders.iter().any(|f| Self::has_folder(f, path))
+    }
+
+    /// Whether any volume in `folder` or below has `tag`
+    fn folder_has_tag(folder: &CatalogFolder, volumes: &[VolumeInfo], tag: &str) -> bool {
+        folder.volumes.iter().any(|id| {
+            volumes
+                .iter()
+                .any(|v| &v.id == id && v.tags.iter().any(|t| t == tag))
+        }) || folder.folders.iter().any(|f| Self::folder_has_tag(f, volumes, tag))
+    }
+
+    fn collect_catalog_ids<'a>(folder: &'a CatalogFolder, ids: &mut HashSet<&'a str>) {
+        ids.extend(folder.volumes.iter().map(String::as_str));
+        for child in &folder.folders {
+            Self::collect_catalog_ids(child, ids);
+        }
+    }
+
+    /// Searchable, sortable table of the catalog, fetched a page at a time.
+    /// Returns the id of the volume clicked, if any.
+    fn render_catalog_window(&mut self, ctx: &egui::Context) -> Option<String> {
+        let mut open = self.show_catalog;
+        let mut chosen = None;
+        let mut refetch = false;
+        let mut load_more = false;
+
+        egui::Window::new("Catalog")
+            .open(&mut open)
+            .default_size([640.0, 420.0])
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    refetch |= ui
+                        .add(egui::TextEdit::singleline(&mut self.catalog_search).hint_text("Search names, tags, attributes"))
+                        .changed();
+
+                    egui::ComboBox::from_id_salt("catalog_tag")
+                        .selected_text(self.catalog_tag.as_deref().unwrap_or("Any tag"))
+                        .show_ui(ui, |ui| {
+                            refetch |= ui.selectable_value(&mut self.catalog_tag, None, "Any tag").changed();
+                            for tag in &self.tags {
+                                refetch |= ui.selectable_value(&mut self.catalog_tag, Some(tag.clone()), tag).changed();
+                            }
+                        });
+
+                    let dtypes: BTreeSet<&str> = self
+                        .volumes
+                        .iter()
+                        .map(|v| v.dtype.as_str())
+                        .filter(|d| !d.is_empty())
+                        .collect();
+                    egui::ComboBox::from_id_salt("catalog_dtype")
+                        .selected_text(self.catalog_dtype.as_deref().unwrap_or("Any type"))
+                        .show_ui(ui, |ui| {
+                            refetch |= ui.selectable_value(&mut self.catalog_dtype, None, "Any type").changed();
+                            for dtype in dtypes {
+                                refetch |= ui
+                                    .selectable_value(&mut self.catalog_dtype, Some(dtype.to_string()), dtype)
+                                    .changed();
+                            }
+                        });
+                });
+
+                ui.horizontal(|ui| {
+                    ui.label(format!("{} of {} volumes", self.catalog_rows.len(), self.catalog_total));
+                    if self.loading_catalog {
+                        ui.spinner();
+                    }
+                    if let Some(error) = &self.catalog_error {
+                        ui.colored_label(egui::Color32::RED, error);
+                    }
+                });
+                ui.separator();
+
+                egui::ScrollArea::both().auto_shrink([false, false]).show(ui, |ui| {
+                    egui::Grid::new("catalog_table").striped(true).num_columns(7).show(ui, |ui| {
+                        for (label, key) in [
+                            ("Name", Some(VolumeSort::Name)),
+                            ("Folder", Some(VolumeSort::Folder)),
+                            ("Dimensions", Some(VolumeSort::Voxels)),
+                            ("Frames", Some(VolumeSort::Frames)),
+                            ("Type", Some(VolumeSort::Dtype)),
+                            ("Size", Some(VolumeSort::Size)),
+                            ("Tags", None),
+                        ] {
+                            let Some(key) = key else {
+                                ui.strong(label);
+                                continue;
+                            };
+                            let arrow = match (self.catalog_sort == key, self.catalog_order) {
+                                (true, SortOrder::Asc) => " ⬆",
+                                (true, SortOrder::Desc) => " ⬇",
+                                (false, _) => "",
+                            };
+                            if ui.small_button(format!("{}{}", label, arrow)).clicked() {
+                                if self.catalog_sort == key {
+                                    self.catalog_order = match self.catalog_order {
+                                        SortOrder::Asc => SortOrder::Desc,
+                                        SortOrder::Desc => SortOrder::Asc,
+                                    };
+                                } else {
+                                    self.catalog_sort = key;
+                                    self.catalog_order = SortOrder::Asc;
+                                }
+                                refetch = true;
+                            }
+                        }
+                        ui.end_row();
+
+                        for volume in &self.catalog_rows {
+                            let selected = self.selected_volume.as_ref() == Some(&volume.id);
+                            if ui.selectable_label(selected, &volume.name).on_hover_text(&volume.id).clicked() {
+                                chosen = Some(volume.id.clone());
+                            }
+                            ui.label(&volume.folder);
+                            let [x, y, z] = volume.dimensions;
+                            ui.label(format!("{}x{}x{}", x, y, z));
+                            ui.label(volume.frames.to_string());
+                            ui.label(&volume.dtype);
+                            ui.label(format!("{:.1} MB", volume.full_res_size as f64 / (1024.0 * 1024.0)));
+                            ui.label(volume.tags.join(", "));
+                            ui.end_row();
+                        }
+                    });
+
+                    if self.catalog_cursor.is_some() && !self.loading_catalog && ui.button("Load More").clicked() {
+                        load_more = true;
+                    }
+                });
+            });
+
+        self.show_catalog = open;
+        if refetch {
+            self.fetch_catalog(false);
+        } else if load_more {
+            self.fetch_catalog(true);
+        }
+        chosen
+    }
+
+    fn render_sidebar(&mut self, ui: &mut egui::Ui) -> Option<String> {
+        let mut volume_changed = None;
+
+        ui.heading("3DLab");
+        ui.separator();
+
+        ui.label("Select Volume:");
+        if self.loading {
+            ui.spinner();
+        } else if let Some(error) = &self.error {
+            ui.colored_label(egui::Color32::RED, error);
+            if ui.button("Retry").clicked() {
+                self.fetch_volumes();
+            }
+        } else {
+            let previous_selection = self.selected_volume.clone();
+
+            if !self.tags.is_empty() {
+                ui.horizontal_wrapped(|ui| {
+                    for tag in &self.tags {
+                        let active = self.tag_filter.as_ref() == Some(tag);
+                        if ui.selectable_label(active, tag).on_hover_text("Filter by tag").clicked() {
+                            self.tag_filter = if active { None } else { Some(tag.clone()) };
+                        }
+                    }
+                });
+            }
+
+            egui::ScrollArea::vertical()
+                .id_salt("volume_tree")
+                .max_height(260.0)
+                .show(ui, |ui| {
+                    let tag_filter = self.tag_filter.as_deref();
+                    Self::render_catalog_folder(
+                        ui,
+                        &self.catalog,
+                        &self.volumes,
+                        &self.failed_volumes,
+                        tag_filter,
+                        &mut self.selected_volume,
+                    );
+
+                    // Failed files in folders where nothing loaded
+                    if tag_filter.is_none() {
+                        for failed in self
+                            .failed_volumes
+                            .iter()
+                            .filter(|f| !Self::has_folder(&self.catalog, Self::parent_folder(&f.id)))
+                        {
+                            ui.add_enabled(false, egui::SelectableLabel::new(false, &failed.id))
+                                .on_disabled_hover_text(format!("{} failed to load: {}", failed.file, failed.error));
+                        }
+                    }
+
+                    // Derived volumes are not part of the catalog tree
+                    let mut listed = HashSet::new();
+                    Self::collect_catalog_ids(&self.catalog, &mut listed);
+                    let others: Vec<&VolumeInfo> = self
+                        .volumes
+                        .iter()
+                        .filter(|v| !listed.contains(v.id.as_str()))
+                        .filter(|v| tag_filter.is_none_or(|tag| v.tags.iter().any(|t| t == tag)))
+                        .collect();
+                    if !others.is_empty() {
+                        ui.separator();
+                        for volume in others {
+                            ui.selectable_value(&mut self.selected_volume, Some(volume.id.clone()), &volume.name);
+                        }
+                    }
+                });
+
+            if !self.failed_volumes.is_empty() {
+                ui.small(format!("{} file(s) failed to load", self.failed_volumes.len()))
+                    .on_hover_text(
+                        self.failed_volumes
+                            .iter()
+                            .map(|f| format!("{}: {}", f.file, f.error))
+                            .collect::<Vec<_>>()
+                            .join("\n"),
+                    );
+            }
+
+            if ui.button("Browse Catalog").clicked() {
+                self.show_catalog = true;
+                if self.catalog_rows.is_empty() {
+                    self.fetch_catalog(false);
+                }
+            }
+
+            if self.selected_volume != previous_selection {
+                volume_changed = self.selected_volume.clone();
+            }
+        }
+
+        ui.separator();
+
+        if let Some(volume) = self
             .selected_volume
             .as_ref()
             .and_then(|id| self.volumes.iter().find(|v| &v.id == id))
@@ -518,13 +1698,17 @@ impl App {
             ));
 
             if self.loading_volume {
-                ui.horizontal(|ui| {
-                    ui.spinner();
-                    ui.label("Loading volume...");
-                });
+                self.render_download_progress(ui);
             }
         }
 
+        self.render_label_controls(ui);
+        self.render_surface_controls(ui);
+        self.render_export_controls(ui);
+        self.render_compare_controls(ui);
+        self.render_spectrum_controls(ui);
+        self.render_annotation_controls(ui);
+
         ui.separator();
 
         // Rotation controls (Euler angles synced with quaternion)
@@ -577,11 +1761,770 @@ impl App {
         ui.label("Opacity:");
         ui.add(egui::Slider::new(&mut self.opacity, 0.0..=1.0).text(""));
 
+        ui.label("Transfer Function:");
+        egui::ComboBox::from_id_salt("transfer_preset")
+            .selected_text(self.transfer_preset.name())
+            .show_ui(ui, |ui| {
+                for preset in TransferPreset::ALL {
+                    ui.selectable_value(&mut self.transfer_preset, preset, preset.name());
+                }
+            });
+
         ui.separator();
 
         volume_changed
     }
 
+    /// Label volume selection, overlay settings and legend
+    fn render_label_controls(&mut self, ui: &mut egui::Ui) {
+        let label_ids = match self
+            .loaded_volume
+            .as_ref()
+            .and_then(|id| self.volumes.iter().find(|v| &v.id == id))
+        {
+            Some(volume) if !volume.labels.is_empty() => volume.labels.clone(),
+            _ => return,
+        };
+
+        ui.separator();
+        ui.label("Labels:");
+
+        let previous_selection = self.selected_labels.clone();
+        egui::ComboBox::from_id_salt("labels")
+            .selected_text(self.selected_labels.as_deref().unwrap_or("None"))
+            .show_ui(ui, |ui| {
+                ui.selectable_value(&mut self.selected_labels, None, "None");
+                for id in &label_ids {
+                    ui.selectable_value(&mut self.selected_labels, Some(id.clone()), id);
+                }
+            });
+        if self.selected_labels != previous_selection {
+            self.labels = None;
+            if let Some(id) = self.selected_labels.clone() {
+                self.fetch_labels(&id);
+            }
+        }
+
+        ui.checkbox(&mut self.show_labels, "Show Labels");
+        ui.label("Label Opacity:");
+        ui.add(egui::Slider::new(&mut self.label_opacity, 0.0..=1.0).text(""));
+
+        if self.loading_labels {
+            ui.spinner();
+        } else if let Some(error) = &self.label_error {
+            ui.colored_label(egui::Color32::RED, error);
+        }
+
+        // Legend with per-label visibility
+        let mut colors_changed = false;
+        if let Some(labels) = &self.labels {
+            egui::ScrollArea::vertical()
+                .id_salt("label_legend")
+                .max_height(160.0)
+                .show(ui, |ui| {
+                    for component in &labels.info.components {
+                        let value = component.label;
+                        let [r, g, b] = Self::label_color(&labels.info, value);
+                        let mut visible = !self.hidden_labels.contains(&value);
+
+                        ui.horizontal(|ui| {
+                            if ui.checkbox(&mut visible, "").changed() {
+                                if visible {
+                                    self.hidden_labels.remove(&value);
+                                } else {
+                                    self.hidden_labels.insert(value);
+                                }
+                                colors_changed = true;
+                            }
+                            let (swatch, _) = ui.allocate_exact_size(egui::vec2(10.0, 10.0), egui::Sense::hover());
+                            ui.painter().rect_filled(swatch, 0.0, egui::Color32::from_rgb(r, g, b));
+                            ui.label(Self::label_name(&labels.info, value));
+                        })
+                        .response
+                        .on_hover_text(format!(
+                            "{} voxels, {:.1} mm³",
+                            component.voxel_count, component.physical_volume
+                        ));
+                    }
+                });
+        }
+        if colors_changed {
+            self.update_label_colors();
+        }
+    }
+
+    /// Surface extraction settings, display options and downloads
+    fn render_surface_controls(&mut self, ui: &mut egui::Ui) {
+        let Some(volume) = self
+            .loaded_volume
+            .as_ref()
+            .and_then(|id| self.volumes.iter().find(|v| &v.id == id))
+            .cloned()
+        else {
+            return;
+        };
+
+        ui.separator();
+        ui.label("Surface:");
+
+        let source_name = |source: SurfaceSource, labels: &Option<LabelData>| match (source, labels) {
+            (SurfaceSource::Intensity, _) => "Intensity".to_string(),
+            (SurfaceSource::Label(value), Some(labels)) => Self::label_name(&labels.info, value),
+            (SurfaceSource::Label(value), None) => format!("Label {}", value),
+        };
+        egui::ComboBox::from_id_salt("surface_source")
+            .selected_text(source_name(self.surface_source, &self.labels))
+            .show_ui(ui, |ui| {
+                ui.selectable_value(&mut self.surface_source, SurfaceSource::Intensity, "Intensity");
+                if let Some(labels) = &self.labels {
+                    for component in labels.info.components.iter().take(256) {
+                        let source = SurfaceSource::Label(component.label);
+                        ui.selectable_value(&mut self.surface_source, source, source_name(source, &self.labels));
+                    }
+                }
+            });
+
+        if self.surface_source == SurfaceSource::Intensity {
+            ui.horizontal(|ui| {
+                ui.label("Iso:");
+                ui.add(egui::Slider::new(
+                    &mut self.surface_iso,
+                    volume.value_range[0]..=volume.value_range[1],
+                ));
+            });
+        }
+        ui.horizontal(|ui| {
+            ui.label("Smooth:");
+            ui.add(egui::DragValue::new(&mut self.surface_smooth).range(0..=50));
+            ui.label("Decimate:");
+            ui.add(egui::DragValue::new(&mut self.surface_decimate).range(1..=16));
+        });
+
+        ui.horizontal(|ui| {
+            if ui.add_enabled(!self.loading_mesh, egui::Button::new("Extract")).clicked() {
+                self.fetch_mesh(&volume);
+            }
+            if self.loading_mesh {
+                ui.spinner();
+            }
+        });
+        if let Some(error) = &self.mesh_error {
+            ui.colored_label(egui::Color32::RED, error);
+        }
+
+        if self.has_mesh {
+            ui.checkbox(&mut self.show_mesh, "Show Surface");
+            ui.label("Surface Opacity:");
+            ui.add(egui::Slider::new(&mut self.mesh_opacity, 0.0..=1.0).text(""));
+        }
+
+        ui.horizontal(|ui| {
+            ui.label("Download:");
+            for format in [MeshFormat::Glb, MeshFormat::Obj, MeshFormat::Stl] {
+                if let Ok(url) = self.api.mesh_url(&volume.id, &self.mesh_query(format)) {
+                    ui.hyperlink_to(format.name().to_uppercase(), url);
+                }
+            }
+        });
+    }
+
+    /// Format, crop, resolution and label mask for downloading the loaded volume
+    fn render_export_controls(&mut self, ui: &mut egui::Ui) {
+        let Some(volume) = self
+            .loaded_volume
+            .as_ref()
+            .and_then(|id| self.volumes.iter().find(|v| &v.id == id))
+            .cloned()
+        else {
+            return;
+        };
+
+        ui.separator();
+        ui.label("Export:");
+
+        egui::ComboBox::from_id_salt("export_format")
+            .selected_text(
+                EXPORT_FORMATS
+                    .iter()
+                    .find(|(value, _)| *value == self.export_format)
+                    .map_or("", |(_, name)| *name),
+            )
+            .show_ui(ui, |ui| {
+                for (value, name) in EXPORT_FORMATS {
+                    ui.selectable_value(&mut self.export_format, value, name);
+                }
+            });
+
+        let mut crop = self.export_crop.is_some();
+        if ui.checkbox(&mut crop, "Crop").changed() {
+            self.export_crop = crop.then(|| volume.dimensions.map(|d| [0, d]));
+        }
+        if let Some(ranges) = &mut self.export_crop {
+            for (axis, name) in ["X", "Y", "Z"].iter().enumerate() {
+                let dim = volume.dimensions[axis];
+                let [start, end] = &mut ranges[axis];
+                ui.horizontal(|ui| {
+                    ui.label(format!("{}:", name));
+                    ui.add(egui::DragValue::new(start).range(0..=dim - 1));
+                    ui.label("to");
+                    ui.add(egui::DragValue::new(end).range(1..=dim));
+                });
+                *end = (*end).max(*start + 1);
+            }
+        }
+
+        ui.horizontal(|ui| {
+            ui.label("Resolution:");
+            egui::ComboBox::from_id_salt("export_resolution")
+                .selected_text(self.export_resolution.map_or("Full".to_string(), |r| r.to_string()))
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut self.export_resolution, None, "Full");
+                    for resolution in [256, 128, 64] {
+                        ui.selectable_value(&mut self.export_resolution, Some(resolution), resolution.to_string());
+                    }
+                });
+        });
+
+        if let Some(labels) = &self.labels {
+            ui.horizontal(|ui| {
+                ui.label("Only label:");
+                egui::ComboBox::from_id_salt("export_label")
+                    .selected_text(
+                        self.export_label
+                            .map_or("All".to_string(), |value| Self::label_name(&labels.info, value)),
+                    )
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(&mut self.export_label, None, "All");
+                        for component in labels.info.components.iter().take(256) {
+                            ui.selectable_value(
+                                &mut self.export_label,
+                                Some(component.label),
+                                Self::label_name(&labels.info, component.label),
+                            );
+                        }
+                    });
+            });
+        }
+
+        if let Ok(url) = self.api.export_url(&volume.id, &self.export_query(&volume)) {
+            ui.hyperlink_to("Download", url);
+        }
+    }
+
+    /// Reference selection for comparing the loaded volume
+    fn render_compare_controls(&mut self, ui: &mut egui::Ui) {
+        let Some(volume) = self
+            .loaded_volume
+            .as_ref()
+            .and_then(|id| self.volumes.iter().find(|v| &v.id == id))
+            .cloned()
+        else {
+            return;
+        };
+
+        ui.separator();
+        ui.label("Compare Against:");
+
+        // Only volumes of the same shape can be compared
+        let candidates: Vec<&VolumeInfo> = self
+            .volumes
+            .iter()
+            .filter(|v| v.id != volume.id && v.dimensions == volume.dimensions)
+            .collect();
+        if candidates.is_empty() {
+            ui.label(egui::RichText::new("(no volumes of the same size)").small().weak());
+            return;
+        }
+
+        egui::ComboBox::from_id_salt("compare_reference")
+            .selected_text(
+                self.compare_reference
+                    .as_ref()
+                    .and_then(|id| candidates.iter().find(|v| &v.id == id))
+                    .map(|v| v.name.as_str())
+                    .unwrap_or("Reference..."),
+            )
+            .show_ui(ui, |ui| {
+                for candidate in &candidates {
+                    ui.selectable_value(
+                        &mut self.compare_reference,
+                        Some(candidate.id.clone()),
+                        &candidate.name,
+                    );
+                }
+            });
+
+        ui.horizontal(|ui| {
+            ui.label("Slices along:");
+            for (axis, name) in ["X", "Y", "Z"].iter().enumerate() {
+                ui.radio_value(&mut self.compare_axis, axis, *name);
+            }
+        });
+
+        ui.horizontal(|ui| {
+            let reference = self.compare_reference.clone();
+            let enabled = !self.comparing && reference.is_some();
+            if ui.add_enabled(enabled, egui::Button::new("Compare")).clicked() {
+                if let Some(reference) = reference {
+                    self.fetch_comparison(&volume.id, &reference);
+                }
+            }
+            if self.comparing {
+                ui.spinner();
+            }
+        });
+        if let Some(error) = &self.compare_error {
+            ui.colored_label(egui::Color32::RED, error);
+        }
+    }
+
+    /// Centred FFT of the loaded volume, in 3D or per slice
+    fn render_spectrum_controls(&mut self, ui: &mut egui::Ui) {
+        let Some(volume_id) = self.loaded_volume.clone() else {
+            return;
+        };
+
+        ui.separator();
+        ui.label("Spectrum (FFT):");
+        ui.horizontal(|ui| {
+            ui.radio_value(&mut self.spectrum_axis, None, "3D");
+            for (axis, name) in ["X", "Y", "Z"].iter().enumerate() {
+                ui.radio_value(&mut self.spectrum_axis, Some(axis), format!("2D {}", name))
+                    .on_hover_text(format!("Transform each slice along {}", name));
+            }
+        });
+
+        ui.horizontal(|ui| {
+            if ui.add_enabled(!self.computing_spectrum, egui::Button::new("Show Spectrum")).clicked() {
+                self.fetch_spectrum(&volume_id);
+            }
+            if self.computing_spectrum {
+                ui.spinner();
+            }
+        });
+        if let Some(error) = &self.spectrum_error {
+            ui.colored_label(egui::Color32::RED, error);
+        }
+    }
+
+    /// Annotation tools, the label/note form and the list of stored annotations
+    fn render_annotation_controls(&mut self, ui: &mut egui::Ui) {
+        let Some(volume_id) = self.loaded_volume.clone() else {
+            return;
+        };
+
+        ui.separator();
+        ui.horizontal(|ui| {
+            ui.label("Annotations:");
+            ui.checkbox(&mut self.show_annotations, "Show");
+            if self.loading_annotations {
+                ui.spinner();
+            }
+        });
+        if volume_id.contains('~') {
+            ui.label(egui::RichText::new("(annotate the source volume)").small().weak());
+            return;
+        }
+
+        let previous_tool = self.annotation_tool;
+        ui.horizontal(|ui| {
+            ui.radio_value(&mut self.annotation_tool, None, "Off");
+            ui.radio_value(&mut self.annotation_tool, Some(AnnotationTool::Point), "Point");
+            ui.radio_value(&mut self.annotation_tool, Some(AnnotationTool::Line), "Line");
+            ui.radio_value(&mut self.annotation_tool, Some(AnnotationTool::Box), "Box");
+        });
+        if self.annotation_tool != previous_tool {
+            self.annotation_anchor = None;
+            self.selected_annotation = None;
+        }
+        if self.annotation_tool.is_some() {
+            let hint = if self.annotation_anchor.is_some() {
+                "Click the second point"
+            } else {
+                "Click the volume to place"
+            };
+            ui.label(egui::RichText::new(hint).small().weak());
+        }
+
+        ui.horizontal(|ui| {
+            ui.label("Label:");
+            ui.text_edit_singleline(&mut self.annotation_label);
+        });
+        ui.add(
+            egui::TextEdit::multiline(&mut self.annotation_note)
+                .hint_text("Note")
+                .desired_rows(2),
+        );
+
+        let request = |shape| AnnotationRequest {
+            shape,
+            units: Default::default(),
+            label: self.annotation_label.clone(),
+            text: self.annotation_note.clone(),
+        };
+        let mut action = None;
+        ui.horizontal(|ui| {
+            match self
+                .selected_annotation
+                .and_then(|id| self.annotations.iter().find(|a| a.id == id))
+            {
+                Some(selected) => {
+                    if ui.button("Save").clicked() {
+                        action = Some(AnnotationAction::Update(selected.id, request(selected.voxel)));
+                    }
+                    if ui.button("Delete").clicked() {
+                        action = Some(AnnotationAction::Delete(selected.id));
+                    }
+                    if ui.button("Deselect").clicked() {
+                        self.selected_annotation = None;
+                    }
+                }
+                None => {
+                    if ui.button("Add Note").clicked() {
+                        action = Some(AnnotationAction::Create(request(AnnotationShape::Text { position: None })));
+                    }
+                }
+            }
+        });
+        if let Some(action) = action {
+            if !matches!(action, AnnotationAction::Update(..)) {
+                self.selected_annotation = None;
+                self.annotation_note.clear();
+            }
+            self.sync_annotations(&volume_id, Some(action));
+        }
+        if let Some(error) = &self.annotation_error {
+            ui.colored_label(egui::Color32::RED, error);
+        }
+
+        let mut clicked = None;
+        for annotation in &self.annotations {
+            let measurement = match (&annotation.voxel, annotation.measurement) {
+                (AnnotationShape::Line { .. }, Some(length)) => format!(" {:.1} mm", length),
+                (AnnotationShape::Box { .. }, Some(volume)) => format!(" {:.0} mm³", volume),
+                _ => String::new(),
+            };
+            let text = format!(
+                "#{} {} {}{}",
+                annotation.id,
+                annotation.voxel.kind(),
+                annotation.label,
+                measurement
+            );
+            let selected = self.selected_annotation == Some(annotation.id);
+            let response = ui.selectable_label(selected, text);
+            let response = if annotation.text.is_empty() {
+                response
+            } else {
+                response.on_hover_text(&annotation.text)
+            };
+            if response.clicked() {
+                clicked = Some(annotation.clone());
+            }
+        }
+        if let Some(annotation) = clicked {
+            self.selected_annotation = Some(annotation.id);
+            self.annotation_label = annotation.label;
+            self.annotation_note = annotation.text;
+            self.annotation_tool = None;
+            self.annotation_anchor = None;
+        }
+    }
+
+    /// Screen position of a voxel coordinate, or `None` behind the camera
+    fn project_voxel(
+        voxel: [f32; 3],
+        dims: [u32; 3],
+        view_proj: glam::Mat4,
+        rotation: glam::Mat4,
+        rect: egui::Rect,
+    ) -> Option<egui::Pos2> {
+        // Voxel centres in the volume's [-0.5, 0.5] model space, as the raycaster sees them
+        let model = Vec3::from(std::array::from_fn(|i| (voxel[i] + 0.5) / dims[i] as f32)) - Vec3::splat(0.5);
+        let clip = view_proj * rotation.transform_point3(model).extend(1.0);
+        if clip.w <= 0.0 {
+            return None;
+        }
+        let ndc = clip.truncate() / clip.w;
+        Some(egui::pos2(
+            rect.center().x + ndc.x * rect.width() * 0.5,
+            rect.center().y - ndc.y * rect.height() * 0.5,
+        ))
+    }
+
+    /// Draw the annotations of the loaded volume over the rendered volume
+    fn paint_annotations(&self, painter: &egui::Painter, rect: egui::Rect, view_proj: glam::Mat4) {
+        let Some(dims) = self.cpu_volume_data.as_ref().map(|v| v.dims) else {
+            return;
+        };
+        let rotation = glam::Mat4::from_quat(self.volume_rotation);
+        let project = |voxel: [f32; 3]| Self::project_voxel(voxel, dims, view_proj, rotation, rect);
+        let font = egui::FontId::monospace(11.0);
+
+        for annotation in &self.annotations {
+            let color = if self.selected_annotation == Some(annotation.id) {
+                egui::Color32::YELLOW
+            } else {
+                egui::Color32::from_rgb(80, 200, 255)
+            };
+            let stroke = egui::Stroke::new(1.5, color);
+
+            let (anchor, caption) = match annotation.voxel {
+                AnnotationShape::Point { position } => {
+                    let Some(p) = project(position) else { continue };
+                    painter.circle_stroke(p, 5.0, stroke);
+                    (p, annotation.label.clone())
+                }
+                AnnotationShape::Line { start, end } => {
+                    let (Some(a), Some(b)) = (project(start), project(end)) else { continue };
+                    painter.line_segment([a, b], stroke);
+                    painter.circle_filled(a, 2.5, color);
+                    painter.circle_filled(b, 2.5, color);
+                    let length = annotation.measurement.map(|l| format!(" {:.1} mm", l)).unwrap_or_default();
+                    (a.lerp(b, 0.5), format!("{}{}", annotation.label, length))
+                }
+                AnnotationShape::Box { min, max } => {
+                    let corners: Option<Vec<egui::Pos2>> = (0..8)
+                        .map(|c| project(std::array::from_fn(|i| if c & (1 << i) != 0 { max[i] } else { min[i] })))
+                        .collect();
+                    let Some(corners) = corners else { continue };
+                    // Corners differing in exactly one bit share an edge
+                    for a in 0..8 {
+                        for bit in [1, 2, 4] {
+                            if a & bit == 0 {
+                                painter.line_segment([corners[a], corners[a | bit]], stroke);
+                            }
+                        }
+                    }
+                    (corners[7], annotation.label.clone())
+                }
+                AnnotationShape::Text { position } => {
+                    let Some(p) = position.and_then(project) else { continue };
+                    painter.rect_stroke(egui::Rect::from_center_size(p, egui::vec2(8.0, 8.0)), 0.0, stroke);
+                    let caption = if annotation.label.is_empty() { &annotation.text } else { &annotation.label };
+                    (p, caption.clone())
+                }
+            };
+
+            if !caption.is_empty() {
+                painter.text(anchor + egui::vec2(7.0, -7.0), egui::Align2::LEFT_BOTTOM, caption, font.clone(), color);
+            }
+        }
+
+        if let Some(p) = self.annotation_anchor.and_then(project) {
+            painter.circle_stroke(p, 4.0, egui::Stroke::new(1.5, egui::Color32::from_rgb(255, 160, 60)));
+        }
+    }
+
+    /// Global metrics and per-slice curve of the last comparison. Returns the
+    /// difference volume's id if the user asked to view it.
+    fn render_comparison_panel(&mut self, ui: &mut egui::Ui) -> Option<String> {
+        let comparison = self.comparison.as_ref()?;
+        let mut view_difference = None;
+        let mut close = false;
+
+        let name = |id: &str| {
+            self.volumes
+                .iter()
+                .find(|v| v.id == id)
+                .map(|v| v.name.clone())
+                .unwrap_or_else(|| id.to_string())
+        };
+
+        ui.horizontal(|ui| {
+            ui.label(format!(
+                "{} vs {}",
+                name(&comparison.volume_id),
+                name(&comparison.reference_id)
+            ));
+            ui.separator();
+            for metric in Metric::ALL {
+                ui.label(format!(
+                    "{}: {}",
+                    metric.name(),
+                    metric.format(metric.value(&comparison.global))
+                ));
+            }
+            ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                if ui.button("Close").clicked() {
+                    close = true;
+                }
+                if ui.button("View Difference").clicked() {
+                    view_difference = Some(comparison.difference.id.clone());
+                }
+            });
+        });
+
+        ui.horizontal(|ui| {
+            ui.label("Per slice:");
+            for metric in Metric::ALL {
+                ui.selectable_value(&mut self.plot_metric, metric, metric.name());
+            }
+        });
+
+        let metric = self.plot_metric;
+        let axis = ["X", "Y", "Z"][comparison.axis.min(2)];
+        let values: Vec<Option<f64>> = comparison.slices.iter().map(|s| metric.value(s)).collect();
+        Self::render_metric_plot(ui, &values, metric, axis);
+
+        if close {
+            self.comparison = None;
+        }
+        view_difference
+    }
+
+    /// Line plot of one value per slice, with the hovered slice's value
+    fn render_metric_plot(ui: &mut egui::Ui, values: &[Option<f64>], metric: Metric, axis: &str) {
+        let size = egui::vec2(ui.available_width(), ui.available_height().max(80.0));
+        let (rect, response) = ui.allocate_exact_size(size, egui::Sense::hover());
+        let painter = ui.painter_at(rect);
+        painter.rect_filled(rect, 0.0, egui::Color32::from_rgb(30, 30, 30));
+
+        let defined = values.iter().flatten();
+        let min = defined.clone().cloned().fold(f64::INFINITY, f64::min);
+        let max = defined.cloned().fold(f64::NEG_INFINITY, f64::max);
+        if values.len() < 2 || !min.is_finite() {
+            return;
+        }
+        let span = if max > min { max - min } else { 1.0 };
+
+        let plot = rect.shrink2(egui::vec2(8.0, 14.0));
+        let x_of = |i: usize| plot.left() + plot.width() * i as f32 / (values.len() - 1) as f32;
+        let y_of = |v: f64| plot.bottom() - plot.height() * ((v - min) / span) as f32;
+
+        // Undefined values (e.g. PSNR of an exact slice) break the line
+        let stroke = egui::Stroke::new(1.5, egui::Color32::from_rgb(100, 160, 230));
+        let mut segment = Vec::new();
+        for (i, value) in values.iter().enumerate() {
+            match value {
+                Some(v) => segment.push(egui::pos2(x_of(i), y_of(*v))),
+                None => {
+                    painter.add(egui::Shape::line(std::mem::take(&mut segment), stroke));
+                }
+            }
+        }
+        painter.add(egui::Shape::line(segment, stroke));
+
+        let font = egui::FontId::monospace(10.0);
+        let text_color = egui::Color32::from_rgb(150, 150, 150);
+        painter.text(
+            rect.left_top() + egui::vec2(4.0, 2.0),
+            egui::Align2::LEFT_TOP,
+            metric.format(Some(max)),
+            font.clone(),
+            text_color,
+        );
+        painter.text(
+            rect.left_bottom() + egui::vec2(4.0, -2.0),
+            egui::Align2::LEFT_BOTTOM,
+            metric.format(Some(min)),
+            font.clone(),
+            text_color,
+        );
+
+        if let Some(pointer) = response.hover_pos() {
+            let fraction = ((pointer.x - plot.left()) / plot.width()).clamp(0.0, 1.0);
+            let index = (fraction * (values.len() - 1) as f32).round() as usize;
+            let x = x_of(index);
+            painter.line_segment(
+                [egui::pos2(x, rect.top()), egui::pos2(x, rect.bottom())],
+                egui::Stroke::new(1.0, egui::Color32::from_rgb(90, 90, 90)),
+            );
+            painter.text(
+                rect.right_top() + egui::vec2(-4.0, 2.0),
+                egui::Align2::RIGHT_TOP,
+                format!("{} slice {}: {}", axis, index, metric.format(values[index])),
+                font,
+                egui::Color32::WHITE,
+            );
+        }
+    }
+
+    /// Play/pause, frame slider, speed and loop controls for time series
+    fn render_timeline(&mut self, ui: &mut egui::Ui, now: f64) {
+        let Some(volume) = self.loaded_time_series().cloned() else {
+            return;
+        };
+        let last = volume.frames - 1;
+
+        ui.horizontal(|ui| {
+            let label = if self.playing { "Pause" } else { "Play" };
+            if ui.button(label).clicked() {
+                self.playing = !self.playing;
+                if self.playing && self.current_frame == last && !self.loop_playback {
+                    self.current_frame = 0;
+                }
+                self.frame_shown_at = now;
+            }
+
+            let slider = egui::Slider::new(&mut self.current_frame, 0..=last).text("frame");
+            if ui.add(slider).changed() {
+                self.frame_shown_at = now;
+            }
+            if let Some(interval) = volume.frame_interval {
+                ui.label(format!("t = {:.2} s", self.current_frame as f32 * interval));
+            }
+
+            ui.separator();
+            ui.label("Speed:");
+            ui.add(
+                egui::DragValue::new(&mut self.playback_fps)
+                    .range(1.0..=60.0)
+                    .speed(0.2)
+                    .suffix(" fps"),
+            );
+            ui.checkbox(&mut self.loop_playback, "Loop");
+
+            if self.loading_frames {
+                ui.spinner();
+                ui.label(egui::RichText::new("buffering").small().weak());
+            }
+            if let Some(error) = &self.frame_error {
+                ui.colored_label(egui::Color32::RED, error);
+                if ui.button("Retry").clicked() {
+                    self.frame_error = None;
+                }
+            }
+        });
+    }
+
+    /// Progress bar with bytes received, transfer rate and ETA
+    fn render_download_progress(&self, ui: &mut egui::Ui) {
+        let progress = self
+            .async_state
+            .lock()
+            .map(|state| state.download_progress)
+            .unwrap_or_default();
+
+        let elapsed = ui.input(|i| i.time) - self.download_started;
+        let rate = if elapsed > 0.0 { progress.received as f64 / elapsed } else { 0.0 };
+        let mb = |bytes: f64| bytes / (1024.0 * 1024.0);
+
+        match progress.total {
+            Some(total) if total > 0 => {
+                let fraction = progress.received as f32 / total as f32;
+                ui.add(egui::ProgressBar::new(fraction).show_percentage());
+                ui.label(format!(
+                    "{:.1} / {:.1} MB",
+                    mb(progress.received as f64),
+                    mb(total as f64)
+                ));
+                let eta = if rate > 0.0 {
+                    format!("{:.0}s", (total - progress.received.min(total)) as f64 / rate)
+                } else {
+                    "--".to_string()
+                };
+                ui.label(format!("{:.1} MB/s, ETA {}", mb(rate), eta));
+            }
+            _ => {
+                ui.horizontal(|ui| {
+                    ui.spinner();
+                    ui.label(format!("Loading volume... {:.1} MB", mb(progress.received as f64)));
+                });
+            }
+        }
+    }
+
     fn render_footer(&self, ui: &mut egui::Ui) {
         let fps = 1.0 / ui.ctx().input(|i| i.stable_dt).max(0.001);
         ui.label(format!("{:.0} fps", fps));
@@ -648,6 +2591,12 @@ impl App {
             self.hover_info.valid = false;
         }
 
+        // Place annotations on the voxel under the pointer
+        if response.clicked() && self.hover_info.valid {
+            let voxel = self.hover_info.voxel.map(|v| v as f32);
+            self.place_annotation(voxel);
+        }
+
         // Build volume rotation matrix from quaternion
         let volume_rotation = glam::Mat4::from_quat(self.volume_rotation);
 
@@ -665,6 +2614,15 @@ impl App {
             state.params.show_axes = self.show_axes;
             state.params.step_size = step_size;
             state.params.opacity = self.opacity;
+            state.params.transfer_preset = self.transfer_preset;
+            state.params.label_opacity = if self.show_labels && self.labels.is_some() {
+                self.label_opacity
+            } else {
+                0.0
+            };
+            state.params.show_mesh = self.show_mesh && self.has_mesh;
+            state.params.mesh_opacity = self.mesh_opacity;
+            state.params.frame = self.current_frame;
         }
 
         if !self.has_volume {
@@ -717,8 +2675,24 @@ impl App {
                                         &vol_data.data,
                                         vol_data.dims,
                                         vol_data.value_range,
+                                        vol_data.frames,
                                     );
                                 }
+                                renderer.set_frame(state.params.frame);
+                                for (frame, values) in std::mem::take(&mut state.pending_frames) {
+                                    renderer.upload_frame(painter.gl(), frame, &values);
+                                }
+                                state.resident_frames = renderer.resident_frames();
+
+                                if let Some(labels) = state.pending_labels.take() {
+                                    renderer.upload_labels(painter.gl(), &labels.values, labels.info.dimensions);
+                                }
+                                if let Some(colors) = state.pending_label_colors.take() {
+                                    renderer.upload_label_colors(painter.gl(), &colors);
+                                }
+                                if let Some(mesh) = state.pending_mesh.take() {
+                                    renderer.upload_mesh(painter.gl(), &mesh.vertices, &mesh.indices);
+                                }
 
                                 // Render if we have a volume
                                 if state.params.has_volume && renderer.has_volume() {
@@ -730,8 +2704,20 @@ impl App {
                                         state.params.value_range,
                                         &state.params.volume_rotation,
                                         state.params.opacity,
+                                        state.params.transfer_preset as i32,
+                                        state.params.label_opacity,
                                     );
 
+                                    if state.params.show_mesh && renderer.has_mesh() {
+                                        renderer.render_mesh(
+                                            painter.gl(),
+                                            &state.params.view_proj_matrix,
+                                            &state.params.volume_rotation,
+                                            [0.9, 0.85, 0.75],
+                                            state.params.mesh_opacity,
+                                        );
+                                    }
+
                                     // Render axes if enabled
                                     if state.params.show_axes {
                                         renderer.render_axes(
@@ -748,6 +2734,10 @@ impl App {
             };
             ui.painter().add(callback);
 
+            if self.show_annotations {
+                self.paint_annotations(ui.painter(), rect, self.camera.view_projection_matrix(aspect_ratio));
+            }
+
             // Show hover info overlay
             if self.hover_info.valid {
                 let info = &self.hover_info;
@@ -755,9 +2745,10 @@ impl App {
                 // Position the info panel in the top-left corner of the viewport
                 let panel_pos = rect.left_top() + egui::vec2(10.0, 10.0);
 
+                let show_label = info.label > 0 && self.show_labels && self.labels.is_some();
                 let panel_rect = egui::Rect::from_min_size(
                     panel_pos,
-                    egui::vec2(160.0, 80.0),
+                    egui::vec2(160.0, if show_label { 96.0 } else { 80.0 }),
                 );
 
                 // Draw background
@@ -802,6 +2793,17 @@ impl App {
                     egui::FontId::monospace(12.0),
                     egui::Color32::GRAY,
                 );
+
+                if let Some(labels) = self.labels.as_ref().filter(|_| show_label) {
+                    let [r, g, b] = Self::label_color(&labels.info, info.label as u32);
+                    ui.painter().text(
+                        text_pos + egui::vec2(0.0, line_height * 4.0),
+                        egui::Align2::LEFT_TOP,
+                        format!("Label: {}", Self::label_name(&labels.info, info.label as u32)),
+                        egui::FontId::monospace(12.0),
+                        egui::Color32::from_rgb(r, g, b),
+                    );
+                }
             }
         }
 
@@ -815,15 +2817,24 @@ impl eframe::App for App {
         ctx.set_style(Self::flat_style());
 
         self.poll_async_state();
-
-        if self.loading || self.loading_volume {
+        let now = ctx.input(|i| i.time);
+        self.update_playback(now);
+
+        if self.loading
+            || self.loading_volume
+            || self.comparing
+            || self.playing
+            || self.loading_frames
+            || self.loading_annotations
+            || self.loading_catalog
+        {
             ctx.request_repaint();
         }
 
         // Get GL context from frame
         let gl = frame.gl().cloned();
 
-        let mut volume_to_fetch: Option<String> = None;
+        let mut volume_to_fetch: Option<String> = self.volume_to_load.take();
 
         egui::SidePanel::right("sidebar")
             .resizable(true)
@@ -837,6 +2848,31 @@ impl eframe::App for App {
                 });
             });
 
+        if self.loaded_time_series().is_some() {
+            egui::TopBottomPanel::bottom("timeline").show(ctx, |ui| {
+                self.render_timeline(ui, now);
+            });
+        }
+
+        if self.comparison.is_some() {
+            egui::TopBottomPanel::bottom("comparison")
+                .resizable(true)
+                .default_height(160.0)
+                .show(ctx, |ui| {
+                    if let Some(difference) = self.render_comparison_panel(ui) {
+                        self.selected_volume = Some(difference.clone());
+                        volume_to_fetch = Some(difference);
+                    }
+                });
+        }
+
+        if self.show_catalog {
+            if let Some(volume_id) = self.render_catalog_window(ctx) {
+                self.selected_volume = Some(volume_id.clone());
+                volume_to_fetch = Some(volume_id);
+            }
+        }
+
         egui::CentralPanel::default().show(ctx, |ui| {
             if let Some(ref gl) = gl {
                 self.render_viewport(ui, gl);
@@ -857,6 +2893,7 @@ impl eframe::App for App {
         if let Some(volume_id) = volume_to_fetch {
             if self.loaded_volume.as_ref() != Some(&volume_id) {
                 // Always load at full resolution
+                self.download_started = ctx.input(|i| i.time);
                 self.fetch_volume_data(&volume_id);
             }
         }
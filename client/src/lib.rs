@@ -52,7 +52,11 @@ pub fn start() -> Result<(), JsValue> {
         .expect("Failed to append canvas");
 
     // Start the app with glow (OpenGL/WebGL2)
-    let web_options = eframe::WebOptions::default();
+    let web_options = eframe::WebOptions {
+        // Needed to draw surface meshes
+        depth_buffer: 24,
+        ..Default::default()
+    };
 
     wasm_bindgen_futures::spawn_local(async move {
         log::info!("Starting eframe...");
@@ -11,6 +11,8 @@ fn main() -> eframe::Result<()> {
             .with_inner_size([1280.0, 720.0])
             .with_min_inner_size([800.0, 600.0])
             .with_title("3DLab - MRI Volume Viewer"),
+        // Needed to draw surface meshes
+        depth_buffer: 24,
         ..Default::default()
     };
 
@@ -2,4 +2,4 @@ mod camera;
 mod volume_renderer;
 
 pub use camera::Camera;
-pub use volume_renderer::VolumeRenderer;
+pub use volume_renderer::{VolumeRenderer, FRAME_RING_SIZE};
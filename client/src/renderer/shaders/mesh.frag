@@ -0,0 +1,15 @@
+#version 300 es
+precision highp float;
+
+uniform vec3 u_color;
+uniform float u_opacity;
+
+in vec3 v_normal;
+out vec4 out_color;
+
+void main() {
+    // Two-sided diffuse lighting from a fixed direction towards the viewer
+    vec3 light_dir = normalize(vec3(0.3, 0.5, 1.0));
+    float diffuse = abs(dot(normalize(v_normal), light_dir));
+    out_color = vec4(u_color * (0.25 + 0.75 * diffuse), u_opacity);
+}
@@ -0,0 +1,15 @@
+#version 300 es
+precision highp float;
+
+uniform mat4 u_view_proj;
+uniform mat4 u_model;
+
+layout(location = 0) in vec3 a_position;
+layout(location = 1) in vec3 a_normal;
+
+out vec3 v_normal;
+
+void main() {
+    v_normal = mat3(u_model) * a_normal;
+    gl_Position = u_view_proj * u_model * vec4(a_position, 1.0);
+}
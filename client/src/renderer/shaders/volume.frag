@@ -1,6 +1,7 @@
 #version 300 es
 precision highp float;
 precision highp sampler3D;
+precision highp usampler3D;
 
 uniform vec3 u_camera_pos;
 uniform float u_step_size;
@@ -11,6 +12,11 @@ uniform mat4 u_volume_rotation;
 uniform sampler3D u_occupancy;
 uniform float u_occupancy_size;  // Grid size (e.g., 16.0)
 uniform float u_opacity;  // Overall opacity multiplier (0.0 - 1.0)
+uniform int u_transfer_preset;  // 0 = grayscale, 1 = log-scaled k-space
+uniform usampler3D u_labels;  // Integer label values (nearest sampling only)
+uniform sampler2D u_label_colors;  // RGBA per label value, 256 values per row
+uniform float u_label_opacity;
+uniform bool u_show_labels;
 
 in vec3 v_world_pos;
 out vec4 out_color;
@@ -27,11 +33,20 @@ vec2 intersect_box(vec3 ray_origin, vec3 ray_dir) {
     return vec2(max(t_near, 0.0), t_far);
 }
 
-// Simple grayscale transfer function
+// Grayscale transfer function, or the k-space one for log-magnitude spectra
 vec4 transfer_function(float density) {
     // Normalize density to [0,1] based on value range
     float normalized = clamp((density - u_value_min) / (u_value_max - u_value_min), 0.0, 1.0);
 
+    if (u_transfer_preset == 1) {
+        // Most of a log-magnitude spectrum is a noise floor in the lower part
+        // of the range; hide it, then ramp from dark red through yellow to
+        // white so the centre of k-space and its spokes stand out
+        float t = clamp((normalized - 0.35) / 0.65, 0.0, 1.0);
+        vec3 hot = clamp(vec3(3.0 * t, 3.0 * t - 1.0, 3.0 * t - 2.0), 0.0, 1.0);
+        return vec4(hot, t * t * 0.3 * u_opacity);
+    }
+
     // Simple opacity curve - higher values are more opaque
     // Scale by u_opacity for user control
     float alpha = pow(normalized, 2.0) * 0.5 * u_opacity;
@@ -83,13 +98,28 @@ void main() {
             continue;
         }
 
+        // Label overlay takes precedence over the intensity
+        if (u_show_labels) {
+            uint label = texture(u_labels, tex_coord).r;
+            if (label > 0u) {
+                vec4 label_color = texelFetch(u_label_colors, ivec2(int(label % 256u), int(label / 256u)), 0);
+                if (label_color.a > 0.0) {
+                    float alpha = label_color.a * u_label_opacity;
+                    color.rgb += (1.0 - color.a) * alpha * label_color.rgb;
+                    color.a += (1.0 - color.a) * alpha;
+                    t_current += u_step_size;
+                    continue;
+                }
+            }
+        }
+
         // Check occupancy grid first (cheaper than volume sample)
         // Occupancy uses same coordinate layout as volume
         vec3 occ_coord = vec3(rotated_pos.z, rotated_pos.y, rotated_pos.x);
         float occupied = texture(u_occupancy, occ_coord).r;
 
-        // If cell is empty, skip ahead by cell size
-        if (occupied < 0.5) {
+        // If cell is empty, skip ahead by cell size (labels may lie in empty cells)
+        if (occupied < 0.5 && !u_show_labels) {
             // Skip to next cell (cell size = 1/occupancy_size)
             t_current += 1.0 / u_occupancy_size;
             continue;
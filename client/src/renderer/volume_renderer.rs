@@ -6,16 +6,43 @@ const VERTEX_SHADER: &str = include_str!("shaders/volume.vert");
 const FRAGMENT_SHADER: &str = include_str!("shaders/volume.frag");
 const AXES_VERTEX_SHADER: &str = include_str!("shaders/axes.vert");
 const AXES_FRAGMENT_SHADER: &str = include_str!("shaders/axes.frag");
+const MESH_VERTEX_SHADER: &str = include_str!("shaders/mesh.vert");
+const MESH_FRAGMENT_SHADER: &str = include_str!("shaders/mesh.frag");
 
 /// Size of the occupancy grid (cells per dimension)
 const OCCUPANCY_GRID_SIZE: u32 = 16;
 
+/// Number of time frames kept on the GPU for playback
+pub const FRAME_RING_SIZE: usize = 8;
+
+/// Width of the label colour table texture (label value = row * width + column)
+const LABEL_COLOR_TABLE_WIDTH: usize = 256;
+
+/// Textures of one uploaded time frame
+struct FrameSlot {
+    frame: u32,
+    texture: glow::Texture,
+    occupancy: glow::Texture,
+}
+
 /// Volume renderer using OpenGL ray marching
 pub struct VolumeRenderer {
     program: glow::Program,
     vao: glow::VertexArray,
+    /// Textures of the frame being rendered (owned by `frames`)
     volume_texture: Option<glow::Texture>,
     occupancy_texture: Option<glow::Texture>,
+    /// Ring buffer of uploaded frames, at most `FRAME_RING_SIZE`
+    frames: Vec<FrameSlot>,
+    /// Frame being rendered
+    current_frame: u32,
+    /// Frame requested by `set_frame`, shown once it is uploaded
+    wanted_frame: u32,
+    frame_count: u32,
+    /// Integer label values, same dimensions as the volume
+    label_texture: Option<glow::Texture>,
+    /// RGBA colour per label value
+    label_color_texture: Option<glow::Texture>,
     pub camera: Camera,
     volume_dims: [u32; 3],
     value_range: [f32; 2],
@@ -30,12 +57,28 @@ pub struct VolumeRenderer {
     u_occupancy: Option<glow::UniformLocation>,
     u_occupancy_size: Option<glow::UniformLocation>,
     u_opacity: Option<glow::UniformLocation>,
+    u_transfer_preset: Option<glow::UniformLocation>,
+    u_labels: Option<glow::UniformLocation>,
+    u_label_colors: Option<glow::UniformLocation>,
+    u_label_opacity: Option<glow::UniformLocation>,
+    u_show_labels: Option<glow::UniformLocation>,
     // Axes rendering
     axes_program: glow::Program,
     axes_vao: glow::VertexArray,
     axes_vbo: glow::Buffer,
     axes_u_view_proj: Option<glow::UniformLocation>,
     axes_u_model: Option<glow::UniformLocation>,
+    // Surface mesh rendering
+    mesh_program: glow::Program,
+    mesh_vao: glow::VertexArray,
+    mesh_vbo: glow::Buffer,
+    mesh_ebo: glow::Buffer,
+    /// Number of indices in the uploaded mesh (0 = no mesh)
+    mesh_index_count: i32,
+    mesh_u_view_proj: Option<glow::UniformLocation>,
+    mesh_u_model: Option<glow::UniformLocation>,
+    mesh_u_color: Option<glow::UniformLocation>,
+    mesh_u_opacity: Option<glow::UniformLocation>,
 }
 
 impl VolumeRenderer {
@@ -80,6 +123,11 @@ impl VolumeRenderer {
             let u_occupancy = gl.get_uniform_location(program, "u_occupancy");
             let u_occupancy_size = gl.get_uniform_location(program, "u_occupancy_size");
             let u_opacity = gl.get_uniform_location(program, "u_opacity");
+            let u_transfer_preset = gl.get_uniform_location(program, "u_transfer_preset");
+            let u_labels = gl.get_uniform_location(program, "u_labels");
+            let u_label_colors = gl.get_uniform_location(program, "u_label_colors");
+            let u_label_opacity = gl.get_uniform_location(program, "u_label_opacity");
+            let u_show_labels = gl.get_uniform_location(program, "u_show_labels");
 
             // Create VAO (required for WebGL2/OpenGL ES 3.0)
             let vao = gl.create_vertex_array().unwrap();
@@ -151,11 +199,63 @@ impl VolumeRenderer {
             gl.bind_vertex_array(None);
             gl.bind_buffer(glow::ARRAY_BUFFER, None);
 
+            // === Mesh shader setup ===
+            let mesh_vs = gl.create_shader(glow::VERTEX_SHADER).unwrap();
+            gl.shader_source(mesh_vs, MESH_VERTEX_SHADER);
+            gl.compile_shader(mesh_vs);
+            if !gl.get_shader_compile_status(mesh_vs) {
+                panic!("Mesh vertex shader error: {}", gl.get_shader_info_log(mesh_vs));
+            }
+
+            let mesh_fs = gl.create_shader(glow::FRAGMENT_SHADER).unwrap();
+            gl.shader_source(mesh_fs, MESH_FRAGMENT_SHADER);
+            gl.compile_shader(mesh_fs);
+            if !gl.get_shader_compile_status(mesh_fs) {
+                panic!("Mesh fragment shader error: {}", gl.get_shader_info_log(mesh_fs));
+            }
+
+            let mesh_program = gl.create_program().unwrap();
+            gl.attach_shader(mesh_program, mesh_vs);
+            gl.attach_shader(mesh_program, mesh_fs);
+            gl.link_program(mesh_program);
+            if !gl.get_program_link_status(mesh_program) {
+                panic!("Mesh program link error: {}", gl.get_program_info_log(mesh_program));
+            }
+
+            gl.delete_shader(mesh_vs);
+            gl.delete_shader(mesh_fs);
+
+            let mesh_u_view_proj = gl.get_uniform_location(mesh_program, "u_view_proj");
+            let mesh_u_model = gl.get_uniform_location(mesh_program, "u_model");
+            let mesh_u_color = gl.get_uniform_location(mesh_program, "u_color");
+            let mesh_u_opacity = gl.get_uniform_location(mesh_program, "u_opacity");
+
+            // Interleaved position + normal (6 floats per vertex), filled by upload_mesh
+            let mesh_vao = gl.create_vertex_array().unwrap();
+            let mesh_vbo = gl.create_buffer().unwrap();
+            let mesh_ebo = gl.create_buffer().unwrap();
+
+            gl.bind_vertex_array(Some(mesh_vao));
+            gl.bind_buffer(glow::ARRAY_BUFFER, Some(mesh_vbo));
+            gl.bind_buffer(glow::ELEMENT_ARRAY_BUFFER, Some(mesh_ebo));
+            gl.enable_vertex_attrib_array(0);
+            gl.vertex_attrib_pointer_f32(0, 3, glow::FLOAT, false, 24, 0);
+            gl.enable_vertex_attrib_array(1);
+            gl.vertex_attrib_pointer_f32(1, 3, glow::FLOAT, false, 24, 12);
+            gl.bind_vertex_array(None);
+            gl.bind_buffer(glow::ARRAY_BUFFER, None);
+
             Self {
                 program,
                 vao,
                 volume_texture: None,
                 occupancy_texture: None,
+                frames: Vec::new(),
+                current_frame: 0,
+                wanted_frame: 0,
+                frame_count: 1,
+                label_texture: None,
+                label_color_texture: None,
                 camera: Camera::default(),
                 volume_dims: [1, 1, 1],
                 value_range: [0.0, 1.0],
@@ -169,36 +269,74 @@ impl VolumeRenderer {
                 u_occupancy,
                 u_occupancy_size,
                 u_opacity,
+                u_transfer_preset,
+                u_labels,
+                u_label_colors,
+                u_label_opacity,
+                u_show_labels,
                 axes_program,
                 axes_vao,
                 axes_vbo,
                 axes_u_view_proj,
                 axes_u_model,
+                mesh_program,
+                mesh_vao,
+                mesh_vbo,
+                mesh_ebo,
+                mesh_index_count: 0,
+                mesh_u_view_proj,
+                mesh_u_model,
+                mesh_u_color,
+                mesh_u_opacity,
             }
         }
     }
 
-    /// Upload volume data as a 3D texture
+    /// Upload volume data as a 3D texture. For time series this is one of
+    /// `frames` frames; the others are added with `upload_frame`.
     pub fn upload_volume(
         &mut self,
         gl: &glow::Context,
         data: &[f32],
         dims: [u32; 3],
         value_range: [f32; 2],
+        frames: u32,
     ) {
         self.volume_dims = dims;
         self.value_range = value_range;
+        self.frame_count = frames.max(1);
+
+        // Labels, surfaces and frames belong to the previous volume
+        self.clear_labels(gl);
+        self.mesh_index_count = 0;
+        self.clear_frames(gl);
+
+        self.wanted_frame = 0;
+        self.upload_frame(gl, 0, data);
+    }
+
+    /// Add a time frame to the ring buffer. When it is full, the frame that
+    /// playback reaches last (the one just behind the wanted frame) is
+    /// evicted; the frame on screen is kept.
+    pub fn upload_frame(&mut self, gl: &glow::Context, frame: u32, data: &[f32]) {
+        if self.has_frame(frame) {
+            return;
+        }
 
         unsafe {
-            // Delete old textures if they exist
-            if let Some(tex) = self.volume_texture.take() {
-                gl.delete_texture(tex);
-            }
-            if let Some(tex) = self.occupancy_texture.take() {
-                gl.delete_texture(tex);
+            if self.frames.len() >= FRAME_RING_SIZE {
+                let (wanted, count) = (self.wanted_frame, self.frame_count);
+                let evict = (0..self.frames.len())
+                    .filter(|&i| self.frames[i].frame != self.current_frame)
+                    .max_by_key(|&i| (self.frames[i].frame + count - wanted) % count);
+                if let Some(i) = evict {
+                    let slot = self.frames.swap_remove(i);
+                    gl.delete_texture(slot.texture);
+                    gl.delete_texture(slot.occupancy);
+                }
             }
 
-            // Create 3D texture for volume
+            // Create 3D texture for the frame
             let texture = gl.create_texture().unwrap();
             gl.bind_texture(glow::TEXTURE_3D, Some(texture));
 
@@ -219,9 +357,9 @@ impl VolumeRenderer {
                 glow::TEXTURE_3D,
                 0,
                 glow::R32F as i32,
-                dims[2] as i32,  // width = Z (fastest varying in memory)
-                dims[1] as i32,  // height = Y
-                dims[0] as i32,  // depth = X (slowest varying in memory)
+                self.volume_dims[2] as i32,  // width = Z (fastest varying in memory)
+                self.volume_dims[1] as i32,  // height = Y
+                self.volume_dims[0] as i32,  // depth = X (slowest varying in memory)
                 0,
                 glow::RED,
                 glow::FLOAT,
@@ -229,10 +367,9 @@ impl VolumeRenderer {
             );
 
             gl.bind_texture(glow::TEXTURE_3D, None);
-            self.volume_texture = Some(texture);
 
             // Compute and upload occupancy grid
-            let occupancy = Self::compute_occupancy_grid(data, dims, value_range);
+            let occupancy = Self::compute_occupancy_grid(data, self.volume_dims, self.value_range);
             let occ_texture = gl.create_texture().unwrap();
             gl.bind_texture(glow::TEXTURE_3D, Some(occ_texture));
 
@@ -257,10 +394,156 @@ impl VolumeRenderer {
             );
 
             gl.bind_texture(glow::TEXTURE_3D, None);
-            self.occupancy_texture = Some(occ_texture);
+            self.frames.push(FrameSlot {
+                frame,
+                texture,
+                occupancy: occ_texture,
+            });
+        }
+        self.show_wanted_frame();
+    }
+
+    /// Whether `frame` is on the GPU
+    pub fn has_frame(&self, frame: u32) -> bool {
+        self.frames.iter().any(|slot| slot.frame == frame)
+    }
+
+    /// Frames currently on the GPU
+    pub fn resident_frames(&self) -> Vec<u32> {
+        self.frames.iter().map(|slot| slot.frame).collect()
+    }
+
+    /// Render `frame` from now on. Until it is uploaded the current frame stays
+    /// on screen.
+    pub fn set_frame(&mut self, frame: u32) {
+        self.wanted_frame = frame;
+        self.show_wanted_frame();
+    }
+
+    fn show_wanted_frame(&mut self) {
+        if let Some(slot) = self.frames.iter().find(|slot| slot.frame == self.wanted_frame) {
+            self.volume_texture = Some(slot.texture);
+            self.occupancy_texture = Some(slot.occupancy);
+            self.current_frame = self.wanted_frame;
+        }
+    }
+
+    fn clear_frames(&mut self, gl: &glow::Context) {
+        unsafe {
+            for slot in self.frames.drain(..) {
+                gl.delete_texture(slot.texture);
+                gl.delete_texture(slot.occupancy);
+            }
+        }
+        self.volume_texture = None;
+        self.occupancy_texture = None;
+    }
+
+    /// Upload label values as an integer 3D texture. Integer textures are
+    /// always sampled with nearest filtering, so labels are never blended.
+    pub fn upload_labels(&mut self, gl: &glow::Context, labels: &[u16], dims: [u32; 3]) {
+        self.clear_labels(gl);
+
+        unsafe {
+            let texture = gl.create_texture().unwrap();
+            gl.bind_texture(glow::TEXTURE_3D, Some(texture));
+
+            gl.tex_parameter_i32(glow::TEXTURE_3D, glow::TEXTURE_WRAP_S, glow::CLAMP_TO_EDGE as i32);
+            gl.tex_parameter_i32(glow::TEXTURE_3D, glow::TEXTURE_WRAP_T, glow::CLAMP_TO_EDGE as i32);
+            gl.tex_parameter_i32(glow::TEXTURE_3D, glow::TEXTURE_WRAP_R, glow::CLAMP_TO_EDGE as i32);
+            gl.tex_parameter_i32(glow::TEXTURE_3D, glow::TEXTURE_MIN_FILTER, glow::NEAREST as i32);
+            gl.tex_parameter_i32(glow::TEXTURE_3D, glow::TEXTURE_MAG_FILTER, glow::NEAREST as i32);
+
+            // Rows of u16 are not always 4-byte aligned
+            gl.pixel_store_i32(glow::UNPACK_ALIGNMENT, 1);
+            gl.tex_image_3d(
+                glow::TEXTURE_3D,
+                0,
+                glow::R16UI as i32,
+                dims[2] as i32,  // Same axis order as the volume texture
+                dims[1] as i32,
+                dims[0] as i32,
+                0,
+                glow::RED_INTEGER,
+                glow::UNSIGNED_SHORT,
+                Some(bytemuck::cast_slice(labels)),
+            );
+            gl.pixel_store_i32(glow::UNPACK_ALIGNMENT, 4);
+
+            gl.bind_texture(glow::TEXTURE_3D, None);
+            self.label_texture = Some(texture);
+        }
+    }
+
+    /// Upload the RGBA colour of each label value (index 0 is background).
+    /// A zero alpha hides that label.
+    pub fn upload_label_colors(&mut self, gl: &glow::Context, colors: &[[u8; 4]]) {
+        let rows = colors.len().div_ceil(LABEL_COLOR_TABLE_WIDTH).max(1);
+        let mut table = vec![[0u8; 4]; rows * LABEL_COLOR_TABLE_WIDTH];
+        table[..colors.len()].copy_from_slice(colors);
+
+        unsafe {
+            if let Some(tex) = self.label_color_texture.take() {
+                gl.delete_texture(tex);
+            }
+
+            let texture = gl.create_texture().unwrap();
+            gl.bind_texture(glow::TEXTURE_2D, Some(texture));
+            gl.tex_parameter_i32(glow::TEXTURE_2D, glow::TEXTURE_WRAP_S, glow::CLAMP_TO_EDGE as i32);
+            gl.tex_parameter_i32(glow::TEXTURE_2D, glow::TEXTURE_WRAP_T, glow::CLAMP_TO_EDGE as i32);
+            gl.tex_parameter_i32(glow::TEXTURE_2D, glow::TEXTURE_MIN_FILTER, glow::NEAREST as i32);
+            gl.tex_parameter_i32(glow::TEXTURE_2D, glow::TEXTURE_MAG_FILTER, glow::NEAREST as i32);
+            gl.tex_image_2d(
+                glow::TEXTURE_2D,
+                0,
+                glow::RGBA8 as i32,
+                LABEL_COLOR_TABLE_WIDTH as i32,
+                rows as i32,
+                0,
+                glow::RGBA,
+                glow::UNSIGNED_BYTE,
+                Some(table.as_flattened()),
+            );
+            gl.bind_texture(glow::TEXTURE_2D, None);
+            self.label_color_texture = Some(texture);
+        }
+    }
+
+    /// Remove the label overlay
+    pub fn clear_labels(&mut self, gl: &glow::Context) {
+        unsafe {
+            if let Some(tex) = self.label_texture.take() {
+                gl.delete_texture(tex);
+            }
+            if let Some(tex) = self.label_color_texture.take() {
+                gl.delete_texture(tex);
+            }
         }
     }
 
+    /// Upload a surface mesh: interleaved position + normal per vertex in the
+    /// volume's [-0.5, 0.5] model space, and triangle indices
+    pub fn upload_mesh(&mut self, gl: &glow::Context, vertices: &[f32], indices: &[u32]) {
+        unsafe {
+            gl.bind_vertex_array(Some(self.mesh_vao));
+            gl.bind_buffer(glow::ARRAY_BUFFER, Some(self.mesh_vbo));
+            gl.buffer_data_u8_slice(glow::ARRAY_BUFFER, bytemuck::cast_slice(vertices), glow::STATIC_DRAW);
+            gl.buffer_data_u8_slice(
+                glow::ELEMENT_ARRAY_BUFFER,
+                bytemuck::cast_slice(indices),
+                glow::STATIC_DRAW,
+            );
+            gl.bind_vertex_array(None);
+            gl.bind_buffer(glow::ARRAY_BUFFER, None);
+        }
+        self.mesh_index_count = indices.len() as i32;
+    }
+
+    /// Check if a surface mesh is loaded
+    pub fn has_mesh(&self) -> bool {
+        self.mesh_index_count > 0
+    }
+
     /// Compute occupancy grid from volume data
     /// Returns a 3D grid where each cell is 1.0 if that region has data above threshold, 0.0 otherwise
     fn compute_occupancy_grid(data: &[f32], dims: [u32; 3], value_range: [f32; 2]) -> Vec<f32> {
@@ -312,10 +595,11 @@ impl VolumeRenderer {
     pub fn render(&self, gl: &glow::Context, aspect_ratio: f32) {
         let view_proj = self.camera.view_projection_matrix(aspect_ratio);
         let camera_pos = self.camera.position();
-        self.render_with_params(gl, &view_proj, &camera_pos, 0.005, self.value_range, &glam::Mat4::IDENTITY, 1.0);
+        self.render_with_params(gl, &view_proj, &camera_pos, 0.005, self.value_range, &glam::Mat4::IDENTITY, 1.0, 0, 0.0);
     }
 
-    /// Render the volume with explicit parameters
+    /// Render the volume with explicit parameters. `transfer_preset` is the
+    /// shader's transfer function: 0 for grayscale, 1 for log-scaled k-space.
     pub fn render_with_params(
         &self,
         gl: &glow::Context,
@@ -325,6 +609,8 @@ impl VolumeRenderer {
         value_range: [f32; 2],
         volume_rotation: &glam::Mat4,
         opacity: f32,
+        transfer_preset: i32,
+        label_opacity: f32,
     ) {
         if self.volume_texture.is_none() {
             return;
@@ -367,10 +653,13 @@ impl VolumeRenderer {
                 gl.uniform_matrix_4_f32_slice(Some(loc), false, &volume_rotation.to_cols_array());
             }
 
-            // Set opacity
+            // Set opacity and transfer function
             if let Some(loc) = &self.u_opacity {
                 gl.uniform_1_f32(Some(loc), opacity);
             }
+            if let Some(loc) = &self.u_transfer_preset {
+                gl.uniform_1_i32(Some(loc), transfer_preset);
+            }
 
             // Bind volume texture
             gl.active_texture(glow::TEXTURE0);
@@ -389,10 +678,35 @@ impl VolumeRenderer {
                 gl.uniform_1_f32(Some(loc), OCCUPANCY_GRID_SIZE as f32);
             }
 
+            // Bind label textures; the overlay is off without them
+            let show_labels = label_opacity > 0.0
+                && self.label_texture.is_some()
+                && self.label_color_texture.is_some();
+            gl.active_texture(glow::TEXTURE2);
+            gl.bind_texture(glow::TEXTURE_3D, self.label_texture);
+            if let Some(loc) = &self.u_labels {
+                gl.uniform_1_i32(Some(loc), 2);
+            }
+            gl.active_texture(glow::TEXTURE3);
+            gl.bind_texture(glow::TEXTURE_2D, self.label_color_texture);
+            if let Some(loc) = &self.u_label_colors {
+                gl.uniform_1_i32(Some(loc), 3);
+            }
+            if let Some(loc) = &self.u_label_opacity {
+                gl.uniform_1_f32(Some(loc), label_opacity);
+            }
+            if let Some(loc) = &self.u_show_labels {
+                gl.uniform_1_i32(Some(loc), show_labels as i32);
+            }
+
             // Draw cube (36 vertices)
             gl.draw_arrays(glow::TRIANGLES, 0, 36);
 
             // Clean up state
+            gl.active_texture(glow::TEXTURE3);
+            gl.bind_texture(glow::TEXTURE_2D, None);
+            gl.active_texture(glow::TEXTURE2);
+            gl.bind_texture(glow::TEXTURE_3D, None);
             gl.active_texture(glow::TEXTURE1);
             gl.bind_texture(glow::TEXTURE_3D, None);
             gl.active_texture(glow::TEXTURE0);
@@ -404,6 +718,52 @@ impl VolumeRenderer {
         }
     }
 
+    /// Render the surface mesh over the volume, rotated with it
+    pub fn render_mesh(
+        &self,
+        gl: &glow::Context,
+        view_proj: &glam::Mat4,
+        volume_rotation: &glam::Mat4,
+        color: [f32; 3],
+        opacity: f32,
+    ) {
+        if self.mesh_index_count == 0 {
+            return;
+        }
+
+        unsafe {
+            // Depth only orders the mesh against itself; the volume writes none
+            gl.enable(glow::DEPTH_TEST);
+            gl.depth_func(glow::LESS);
+            gl.clear(glow::DEPTH_BUFFER_BIT);
+            gl.enable(glow::BLEND);
+            gl.blend_func(glow::SRC_ALPHA, glow::ONE_MINUS_SRC_ALPHA);
+
+            gl.use_program(Some(self.mesh_program));
+            gl.bind_vertex_array(Some(self.mesh_vao));
+
+            if let Some(loc) = &self.mesh_u_view_proj {
+                gl.uniform_matrix_4_f32_slice(Some(loc), false, &view_proj.to_cols_array());
+            }
+            if let Some(loc) = &self.mesh_u_model {
+                gl.uniform_matrix_4_f32_slice(Some(loc), false, &volume_rotation.to_cols_array());
+            }
+            if let Some(loc) = &self.mesh_u_color {
+                gl.uniform_3_f32(Some(loc), color[0], color[1], color[2]);
+            }
+            if let Some(loc) = &self.mesh_u_opacity {
+                gl.uniform_1_f32(Some(loc), opacity);
+            }
+
+            gl.draw_elements(glow::TRIANGLES, self.mesh_index_count, glow::UNSIGNED_INT, 0);
+
+            gl.bind_vertex_array(None);
+            gl.use_program(None);
+            gl.disable(glow::BLEND);
+            gl.disable(glow::DEPTH_TEST);
+        }
+    }
+
     /// Render coordinate axes
     pub fn render_axes(
         &self,
@@ -445,15 +805,23 @@ impl VolumeRenderer {
         unsafe {
             gl.delete_program(self.program);
             gl.delete_vertex_array(self.vao);
-            if let Some(tex) = self.volume_texture {
+            for slot in &self.frames {
+                gl.delete_texture(slot.texture);
+                gl.delete_texture(slot.occupancy);
+            }
+            if let Some(tex) = self.label_texture {
                 gl.delete_texture(tex);
             }
-            if let Some(tex) = self.occupancy_texture {
+            if let Some(tex) = self.label_color_texture {
                 gl.delete_texture(tex);
             }
             gl.delete_program(self.axes_program);
             gl.delete_vertex_array(self.axes_vao);
             gl.delete_buffer(self.axes_vbo);
+            gl.delete_program(self.mesh_program);
+            gl.delete_vertex_array(self.mesh_vao);
+            gl.delete_buffer(self.mesh_vbo);
+            gl.delete_buffer(self.mesh_ebo);
         }
     }
 }
@@ -1,6 +0,0 @@
-def main():
-    print("Hello from 3dlab!")
-
-
-if __name__ == "__main__":
-    main()
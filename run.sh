@@ -9,6 +9,9 @@ NC='\033[0m' # No Color
 # Set HDF5 path for macOS
 export HDF5_DIR=/opt/homebrew/opt/hdf5
 
+# Serve the phantoms too, so there is something to show without data files
+export PHANTOMS="${PHANTOMS-all}"
+
 # Kill any existing processes
 pkill -f "target/.*/server" 2>/dev/null
 pkill -f "trunk serve" 2>/dev/null
@@ -0,0 +1,295 @@
+use shared::{AdmissionStatus, RouteStatus};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{Notify, OwnedSemaphorePermit, Semaphore};
+
+/// How long a request may wait for a slot before it is rejected
+const QUEUE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Maximum number of requests waiting per route before new ones are rejected
+const MAX_QUEUE_DEPTH: usize = 8;
+
+/// Seconds clients are asked to wait before retrying a rejected request
+pub const RETRY_AFTER_SECS: u64 = 5;
+
+/// Memory budget of the heavy routes when `ADMISSION_BUDGET_MB` is not set
+pub const DEFAULT_BUDGET_MB: u64 = 512;
+
+/// Heavy routes with their own concurrency limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeavyRoute {
+    /// /volumes/{id}/full
+    Full,
+    /// /volumes/{id}/at/{resolution}
+    Resample,
+    /// Computing a derived (filtered) volume
+    Filter,
+    /// /volumes/{id}/mesh
+    Mesh,
+}
+
+impl HeavyRoute {
+    pub const ALL: [HeavyRoute; 4] = [
+        HeavyRoute::Full,
+        HeavyRoute::Resample,
+        HeavyRoute::Filter,
+        HeavyRoute::Mesh,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            HeavyRoute::Full => "full",
+            HeavyRoute::Resample => "resample",
+            HeavyRoute::Filter => "filter",
+            HeavyRoute::Mesh => "mesh",
+        }
+    }
+
+    /// Environment variable overriding the concurrency limit
+    fn env_var(self) -> &'static str {
+        match self {
+            HeavyRoute::Full => "FULL_CONCURRENCY",
+            HeavyRoute::Resample => "RESAMPLE_CONCURRENCY",
+            HeavyRoute::Filter => "FILTER_CONCURRENCY",
+            HeavyRoute::Mesh => "MESH_CONCURRENCY",
+        }
+    }
+}
+
+/// Why a request was not admitted
+#[derive(Debug, Clone, Copy)]
+pub enum Rejection {
+    /// Too many requests already waiting for this route
+    QueueFull,
+    /// Waited too long for a concurrency slot or memory
+    Timeout,
+    /// The request alone needs more memory than the whole budget
+    TooLarge,
+}
+
+impl std::fmt::Display for Rejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Rejection::QueueFull => write!(f, "too many queued requests"),
+            Rejection::Timeout => write!(f, "timed out waiting for capacity"),
+            Rejection::TooLarge => write!(f, "request exceeds the memory budget"),
+        }
+    }
+}
+
+struct RouteLimit {
+    route: HeavyRoute,
+    limit: usize,
+    semaphore: Arc<Semaphore>,
+    queued: AtomicUsize,
+}
+
+/// Admits heavy requests subject to per-route concurrency limits and a
+/// global budget on the memory their in-flight work is expected to use
+pub struct AdmissionController {
+    routes: Vec<RouteLimit>,
+    memory_budget: u64,
+    in_flight_bytes: Mutex<u64>,
+    /// Woken whenever memory is released
+    released: Notify,
+    rejected: AtomicU64,
+}
+
+/// Held while a request runs; releases its slot and memory on drop
+pub struct Permit {
+    controller: Arc<AdmissionController>,
+    bytes: u64,
+    _slot: OwnedSemaphorePermit,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        *self.controller.in_flight_bytes.lock().unwrap() -= self.bytes;
+        self.controller.released.notify_waiters();
+    }
+}
+
+impl AdmissionController {
+    /// Create a controller from `FULL_CONCURRENCY`, `RESAMPLE_CONCURRENCY`,
+    /// `FILTER_CONCURRENCY`, `MESH_CONCURRENCY` and `ADMISSION_BUDGET_MB`
+    pub fn from_env() -> Self {
+        let env = |name: &str, default: u64| {
+            std::env::var(name)
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(default)
+        };
+
+        Self::with_limits(
+            |route| env(route.env_var(), 2).max(1) as usize,
+            env("ADMISSION_BUDGET_MB", DEFAULT_BUDGET_MB) * 1024 * 1024,
+        )
+    }
+
+    /// Create a controller with `limit(route)` concurrent requests per route
+    /// and `memory_budget` bytes in flight
+    pub fn with_limits(limit: impl Fn(HeavyRoute) -> usize, memory_budget: u64) -> Self {
+        let routes = HeavyRoute::ALL
+            .iter()
+            .map(|&route| {
+                let limit = limit(route).max(1);
+                RouteLimit {
+                    route,
+                    limit,
+                    semaphore: Arc::new(Semaphore::new(limit)),
+                    queued: AtomicUsize::new(0),
+                }
+            })
+            .collect();
+
+        Self {
+            routes,
+            memory_budget,
+            in_flight_bytes: Mutex::new(0),
+            released: Notify::new(),
+            rejected: AtomicU64::new(0),
+        }
+    }
+
+    fn route(&self, route: HeavyRoute) -> &RouteLimit {
+        self.routes.iter().find(|r| r.route == route).unwrap()
+    }
+
+    /// Wait for a slot on `route` and `bytes` of memory, or reject the request
+    pub async fn admit(self: &Arc<Self>, route: HeavyRoute, bytes: u64) -> Result<Permit, Rejection> {
+        let result = self.try_admit(route, bytes).await;
+        if let Err(rejection) = &result {
+            self.rejected.fetch_add(1, Ordering::Relaxed);
+            tracing::warn!(route = route.name(), bytes, reason = %rejection, "Rejected request");
+        }
+        result
+    }
+
+    async fn try_admit(self: &Arc<Self>, route: HeavyRoute, bytes: u64) -> Result<Permit, Rejection> {
+        if bytes > self.memory_budget {
+            return Err(Rejection::TooLarge);
+        }
+
+        let limit = self.route(route);
+        if limit.queued.fetch_add(1, Ordering::SeqCst) >= MAX_QUEUE_DEPTH {
+            limit.queued.fetch_sub(1, Ordering::SeqCst);
+            return Err(Rejection::QueueFull);
+        }
+
+        let waited = tokio::time::timeout(QUEUE_TIMEOUT, async {
+            let slot = limit.semaphore.clone().acquire_owned().await.unwrap();
+            loop {
+                // Register for wakeups before checking so a release is not missed
+                let released = self.released.notified();
+                {
+                    let mut in_flight = self.in_flight_bytes.lock().unwrap();
+                    if *in_flight + bytes <= self.memory_budget {
+                        *in_flight += bytes;
+                        break;
+                    }
+                }
+                released.await;
+            }
+            slot
+        })
+        .await;
+
+        limit.queued.fetch_sub(1, Ordering::SeqCst);
+
+        match waited {
+            Ok(slot) => Ok(Permit {
+                controller: self.clone(),
+                bytes,
+                _slot: slot,
+            }),
+            Err(_) => Err(Rejection::Timeout),
+        }
+    }
+
+    pub fn status(&self) -> AdmissionStatus {
+        AdmissionStatus {
+            routes: self
+                .routes
+                .iter()
+                .map(|r| RouteStatus {
+                    route: r.route.name().to_string(),
+                    limit: r.limit,
+                    in_flight: r.limit - r.semaphore.available_permits(),
+                    queued: r.queued.load(Ordering::SeqCst),
+                })
+                .collect(),
+            in_flight_bytes: *self.in_flight_bytes.lock().unwrap(),
+            memory_budget_bytes: self.memory_budget,
+            rejected: self.rejected.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn controller(memory_budget: u64) -> Arc<AdmissionController> {
+        Arc::new(AdmissionController::with_limits(|_| 2, memory_budget))
+    }
+
+    #[tokio::test]
+    async fn rejects_requests_over_the_budget() {
+        let admission = controller(100);
+        assert!(matches!(
+            admission.admit(HeavyRoute::Full, 101).await,
+            Err(Rejection::TooLarge)
+        ));
+        assert!(admission.admit(HeavyRoute::Full, 100).await.is_ok());
+        assert_eq!(admission.status().rejected, 1);
+    }
+
+    #[tokio::test]
+    async fn permits_release_their_memory_on_drop() {
+        let admission = controller(100);
+        let permit = admission.admit(HeavyRoute::Full, 60).await.unwrap();
+        assert_eq!(admission.status().in_flight_bytes, 60);
+        drop(permit);
+        assert_eq!(admission.status().in_flight_bytes, 0);
+    }
+
+    #[tokio::test]
+    async fn waits_for_memory_to_be_released() {
+        let admission = controller(100);
+        let first = admission.admit(HeavyRoute::Full, 60).await.unwrap();
+
+        let waiting = tokio::spawn({
+            let admission = admission.clone();
+            async move { admission.admit(HeavyRoute::Resample, 60).await.map(|_| ()) }
+        });
+        tokio::task::yield_now().await;
+        assert!(!waiting.is_finished());
+
+        drop(first);
+        assert!(waiting.await.unwrap().is_ok());
+    }
+
+    #[tokio::test]
+    async fn limits_concurrency_per_route() {
+        let admission = controller(100);
+        let _a = admission.admit(HeavyRoute::Mesh, 1).await.unwrap();
+        let _b = admission.admit(HeavyRoute::Mesh, 1).await.unwrap();
+        let status = admission.status();
+        let mesh = status.routes.iter().find(|r| r.route == "mesh").unwrap();
+        assert_eq!((mesh.limit, mesh.in_flight), (2, 2));
+
+        // Other routes have their own slots
+        assert!(admission.admit(HeavyRoute::Full, 1).await.is_ok());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn times_out_without_a_slot() {
+        let admission = Arc::new(AdmissionController::with_limits(|_| 1, 100));
+        let _held = admission.admit(HeavyRoute::Full, 1).await.unwrap();
+        assert!(matches!(
+            admission.admit(HeavyRoute::Full, 1).await,
+            Err(Rejection::Timeout)
+        ));
+    }
+}
@@ -0,0 +1,381 @@
+use serde::{Deserialize, Serialize};
+use shared::{Annotation, AnnotationRequest, AnnotationShape, CoordinateUnits, VolumeInfo};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+use tokio::sync::{Mutex, OwnedMutexGuard};
+
+use crate::error::ApiError;
+
+/// Extension of annotation sidecars, e.g. `case_01.annotations.json`
+const ANNOTATION_EXTENSION: &str = "annotations.json";
+
+/// Longest accepted label or note, in bytes
+const MAX_TEXT_LEN: usize = 4096;
+
+/// Contents of an annotation sidecar
+#[derive(Default, Serialize, Deserialize)]
+struct AnnotationFile {
+    next_id: u64,
+    annotations: Vec<StoredAnnotation>,
+}
+
+/// An annotation as written to disk; the physical shape and measurement
+/// are recomputed from the volume spacing when it is served
+#[derive(Clone, Serialize, Deserialize)]
+struct StoredAnnotation {
+    id: u64,
+    #[serde(default)]
+    label: String,
+    #[serde(default)]
+    text: String,
+    /// Shape in voxel coordinates
+    shape: AnnotationShape,
+    created: u64,
+    modified: u64,
+}
+
+/// Annotations of the file volumes, kept in JSON sidecars next to the HDF5
+/// files and loaded on first use
+#[derive(Default)]
+pub struct AnnotationStore {
+    /// Sidecar contents by sidecar path, each behind its own lock, which keeps
+    /// every read-modify-write of a file atomic without serialising the others
+    files: std::sync::Mutex<HashMap<PathBuf, Arc<Mutex<Option<AnnotationFile>>>>>,
+}
+
+impl AnnotationStore {
+    /// Exclusive access to the contents of one sidecar, not read yet if `None`
+    async fn lock(&self, sidecar: &Path) -> OwnedMutexGuard<Option<AnnotationFile>> {
+        let file = self
+            .files
+            .lock()
+            .unwrap()
+            .entry(sidecar.to_path_buf())
+            .or_default()
+            .clone();
+        file.lock_owned().await
+    }
+
+    /// All annotations of the volume stored at `path`, oldest first
+    pub async fn list(&self, path: &Path, volume: &VolumeInfo) -> Result<Vec<Annotation>, ApiError> {
+        let sidecar = sidecar_path(path);
+        let mut slot = self.lock(&sidecar).await;
+        let file = load(&mut slot, &sidecar).await?;
+        Ok(file.annotations.iter().map(|a| to_annotation(a, volume)).collect())
+    }
+
+    /// One annotation by id
+    pub async fn get(&self, path: &Path, volume: &VolumeInfo, id: u64) -> Result<Annotation, ApiError> {
+        let sidecar = sidecar_path(path);
+        let mut slot = self.lock(&sidecar).await;
+        let file = load(&mut slot, &sidecar).await?;
+        let stored = file
+            .annotations
+            .iter()
+            .find(|a| a.id == id)
+            .ok_or(ApiError::AnnotationNotFound(id))?;
+        Ok(to_annotation(stored, volume))
+    }
+
+    /// Add an annotation and write the sidecar
+    pub async fn create(
+        &self,
+        path: &Path,
+        volume: &VolumeInfo,
+        request: &AnnotationRequest,
+    ) -> Result<Annotation, ApiError> {
+        let shape = validate(request, volume).map_err(ApiError::BadRequest)?;
+        let sidecar = sidecar_path(path);
+        let mut slot = self.lock(&sidecar).await;
+        let file = load(&mut slot, &sidecar).await?;
+
+        let now = now();
+        file.next_id += 1;
+        let stored = StoredAnnotation {
+            id: file.next_id,
+            label: request.label.clone(),
+            text: request.text.clone(),
+            shape,
+            created: now,
+            modified: now,
+        };
+        file.annotations.push(stored.clone());
+        save(&sidecar, file).await?;
+        Ok(to_annotation(&stored, volume))
+    }
+
+    /// Replace the shape, label and note of an annotation
+    pub async fn update(
+        &self,
+        path: &Path,
+        volume: &VolumeInfo,
+        id: u64,
+        request: &AnnotationRequest,
+    ) -> Result<Annotation, ApiError> {
+        let shape = validate(request, volume).map_err(ApiError::BadRequest)?;
+        let sidecar = sidecar_path(path);
+        let mut slot = self.lock(&sidecar).await;
+        let file = load(&mut slot, &sidecar).await?;
+
+        let stored = file
+            .annotations
+            .iter_mut()
+            .find(|a| a.id == id)
+            .ok_or(ApiError::AnnotationNotFound(id))?;
+        stored.label = request.label.clone();
+        stored.text = request.text.clone();
+        stored.shape = shape;
+        stored.modified = now();
+        let stored = stored.clone();
+
+        save(&sidecar, file).await?;
+        Ok(to_annotation(&stored, volume))
+    }
+
+    /// Remove an annotation. Ids are never reused.
+    pub async fn delete(&self, path: &Path, id: u64) -> Result<(), ApiError> {
+        let sidecar = sidecar_path(path);
+        let mut slot = self.lock(&sidecar).await;
+        let file = load(&mut slot, &sidecar).await?;
+
+        let index = file
+            .annotations
+            .iter()
+            .position(|a| a.id == id)
+            .ok_or(ApiError::AnnotationNotFound(id))?;
+        file.annotations.remove(index);
+        save(&sidecar, file).await
+    }
+}
+
+/// `{stem}.annotations.json` next to the volume file
+fn sidecar_path(path: &Path) -> PathBuf {
+    path.with_extension(ANNOTATION_EXTENSION)
+}
+
+/// The cached sidecar contents, read from disk on first use
+async fn load<'a>(slot: &'a mut Option<AnnotationFile>, sidecar: &Path) -> Result<&'a mut AnnotationFile, ApiError> {
+    let file = match slot.take() {
+        Some(file) => file,
+        None => match tokio::fs::read_to_string(sidecar).await {
+            // Refuse to go on with an unreadable file rather than overwrite it
+            Ok(json) => serde_json::from_str(&json).map_err(|e| {
+                ApiError::Storage(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("{}: {}", sidecar.display(), e),
+                ))
+            })?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => AnnotationFile::default(),
+            Err(e) => return Err(ApiError::Storage(e)),
+        },
+    };
+    Ok(slot.insert(file))
+}
+
+/// Write the sidecar through a temporary file so a crash never leaves it half-written
+async fn save(sidecar: &Path, file: &AnnotationFile) -> Result<(), ApiError> {
+    let json = serde_json::to_string_pretty(file).map_err(|e| ApiError::Storage(e.into()))?;
+    let temp = sidecar.with_extension(format!("{}.tmp", ANNOTATION_EXTENSION));
+    tokio::fs::write(&temp, json).await.map_err(ApiError::Storage)?;
+    tokio::fs::rename(&temp, sidecar).await.map_err(ApiError::Storage)
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// The request's shape in voxel coordinates, checked against the volume bounds.
+/// Voxel coordinates are (fractional) indices, so they run from 0 to dim - 1.
+fn validate(request: &AnnotationRequest, volume: &VolumeInfo) -> Result<AnnotationShape, String> {
+    if request.label.len() > MAX_TEXT_LEN || request.text.len() > MAX_TEXT_LEN {
+        return Err(format!("Labels and notes are limited to {} bytes", MAX_TEXT_LEN));
+    }
+
+    let spacing = volume.spacing;
+    let shape = match request.units {
+        CoordinateUnits::Voxel => request.shape,
+        CoordinateUnits::Mm => request.shape.map(|p| std::array::from_fn(|i| p[i] / spacing[i])),
+    };
+
+    for position in shape.positions() {
+        for (axis, (&c, &dim)) in position.iter().zip(&volume.dimensions).enumerate() {
+            if !c.is_finite() || c < 0.0 || c > dim.saturating_sub(1) as f32 {
+                return Err(format!(
+                    "Coordinate {} on axis {} is outside the volume (0..{})",
+                    c,
+                    axis,
+                    dim.saturating_sub(1)
+                ));
+            }
+        }
+    }
+
+    // Store boxes with min <= max on every axis
+    Ok(match shape {
+        AnnotationShape::Box { min, max } => AnnotationShape::Box {
+            min: std::array::from_fn(|i| min[i].min(max[i])),
+            max: std::array::from_fn(|i| min[i].max(max[i])),
+        },
+        shape => shape,
+    })
+}
+
+fn to_annotation(stored: &StoredAnnotation, volume: &VolumeInfo) -> Annotation {
+    let spacing = volume.spacing;
+    let physical = stored.shape.map(|p| std::array::from_fn(|i| p[i] * spacing[i]));
+    let measurement = match physical {
+        AnnotationShape::Line { start, end } => Some(
+            (0..3)
+                .map(|i| ((end[i] - start[i]) as f64).powi(2))
+                .sum::<f64>()
+                .sqrt(),
+        ),
+        AnnotationShape::Box { min, max } => Some((0..3).map(|i| (max[i] - min[i]) as f64).product()),
+        _ => None,
+    };
+
+    Annotation {
+        id: stored.id,
+        volume_id: volume.id.clone(),
+        label: stored.label.clone(),
+        text: stored.text.clone(),
+        voxel: stored.shape,
+        physical,
+        measurement,
+        created: stored.created,
+        modified: stored.modified,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn volume() -> VolumeInfo {
+        VolumeInfo {
+            id: "case".to_string(),
+            dimensions: [10, 20, 30],
+            spacing: [0.5, 1.0, 2.0],
+            version: "v1".to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn request(shape: AnnotationShape, units: CoordinateUnits) -> AnnotationRequest {
+        AnnotationRequest {
+            shape,
+            units,
+            label: "lesion".to_string(),
+            text: String::new(),
+        }
+    }
+
+    /// A volume path in a fresh temporary directory
+    fn volume_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("annotations-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir.join("case.h5")
+    }
+
+    #[test]
+    fn millimetres_become_voxels_and_boxes_are_ordered() {
+        let shape = AnnotationShape::Box {
+            min: [4.0, 10.0, 20.0],
+            max: [1.0, 0.0, 4.0],
+        };
+        let stored = validate(&request(shape, CoordinateUnits::Mm), &volume()).unwrap();
+        assert_eq!(
+            stored,
+            AnnotationShape::Box {
+                min: [2.0, 0.0, 2.0],
+                max: [8.0, 10.0, 10.0]
+            }
+        );
+    }
+
+    #[test]
+    fn coordinates_outside_the_volume_are_rejected() {
+        let outside = AnnotationShape::Point { position: [9.5, 0.0, 0.0] };
+        assert!(validate(&request(outside, CoordinateUnits::Voxel), &volume()).is_err());
+        let nan = AnnotationShape::Point { position: [f32::NAN, 0.0, 0.0] };
+        assert!(validate(&request(nan, CoordinateUnits::Voxel), &volume()).is_err());
+        let mut long = request(AnnotationShape::Text { position: None }, CoordinateUnits::Voxel);
+        long.text = "x".repeat(MAX_TEXT_LEN + 1);
+        assert!(validate(&long, &volume()).is_err());
+    }
+
+    #[test]
+    fn lines_measure_physical_length() {
+        let stored = StoredAnnotation {
+            id: 1,
+            label: String::new(),
+            text: String::new(),
+            shape: AnnotationShape::Line {
+                start: [0.0, 0.0, 0.0],
+                end: [6.0, 0.0, 2.0],
+            },
+            created: 0,
+            modified: 0,
+        };
+        let annotation = to_annotation(&stored, &volume());
+        assert_eq!(annotation.measurement, Some(5.0));
+    }
+
+    #[tokio::test]
+    async fn annotations_persist_in_the_sidecar() {
+        let path = volume_path("persist");
+        let store = AnnotationStore::default();
+        let point = request(AnnotationShape::Point { position: [1.0, 2.0, 3.0] }, CoordinateUnits::Voxel);
+        let first = store.create(&path, &volume(), &point).await.unwrap();
+        let second = store.create(&path, &volume(), &point).await.unwrap();
+        store.delete(&path, first.id).await.unwrap();
+        assert!(matches!(
+            store.delete(&path, first.id).await,
+            Err(ApiError::AnnotationNotFound(_))
+        ));
+
+        // A new store reads the sidecar back; ids are not reused
+        let store = AnnotationStore::default();
+        let listed = store.list(&path, &volume()).await.unwrap();
+        assert_eq!(listed.iter().map(|a| a.id).collect::<Vec<_>>(), vec![second.id]);
+        let third = store.create(&path, &volume(), &point).await.unwrap();
+        assert_eq!(third.id, 3);
+    }
+
+    #[tokio::test]
+    async fn concurrent_creates_each_get_an_id() {
+        let (a, b) = (volume_path("concurrent-a"), volume_path("concurrent-b"));
+        let store = Arc::new(AnnotationStore::default());
+        let point = request(AnnotationShape::Point { position: [1.0, 2.0, 3.0] }, CoordinateUnits::Voxel);
+        let tasks: Vec<_> = (0..8)
+            .map(|i| {
+                let (store, point) = (store.clone(), point.clone());
+                let path = if i % 2 == 0 { a.clone() } else { b.clone() };
+                tokio::spawn(async move { store.create(&path, &volume(), &point).await.unwrap().id })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+        for path in [&a, &b] {
+            let ids: Vec<u64> = store.list(path, &volume()).await.unwrap().iter().map(|a| a.id).collect();
+            assert_eq!(ids.len(), 4);
+        }
+    }
+
+    #[tokio::test]
+    async fn unreadable_sidecars_are_not_overwritten() {
+        let path = volume_path("corrupt");
+        std::fs::write(sidecar_path(&path), "not json").unwrap();
+        let store = AnnotationStore::default();
+        let point = request(AnnotationShape::Point { position: [1.0, 2.0, 3.0] }, CoordinateUnits::Voxel);
+        assert!(matches!(store.create(&path, &volume(), &point).await, Err(ApiError::Storage(_))));
+        assert_eq!(std::fs::read_to_string(sidecar_path(&path)).unwrap(), "not json");
+    }
+}
@@ -0,0 +1,118 @@
+//! Route table of the HTTP API, and the OpenAPI document generated from it
+
+use std::sync::Arc;
+use utoipa::OpenApi;
+use utoipa_axum::{router::OpenApiRouter, routes};
+
+use crate::routes;
+use crate::state::AppState;
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "3DLab",
+        license(name = "MIT"),
+        description = "Volume data is sent as a 64-byte header (`shared::VolumeHeader`: magic `3DLV`, \
+            version, dimensions, dtype, encoding, value range, spacing, brick origin and frames) \
+            followed by the little-endian voxels in C order ([x, y, z], z fastest). \
+            Requests with the same `x-session-id` header cancel each other's heavy work."
+    ),
+    tags(
+        (name = "volumes", description = "Volume metadata, data and processing"),
+        (name = "labels", description = "Label volumes and segmentation"),
+        (name = "annotations", description = "Points, lines, boxes and notes stored per volume"),
+        (name = "server", description = "Health and load"),
+    )
+)]
+struct ApiDoc;
+
+/// Every `/api` route plus `/metrics`, documented by its handler's `#[utoipa::path]`
+pub fn router() -> OpenApiRouter<Arc<AppState>> {
+    let api = OpenApiRouter::new()
+        .routes(routes!(routes::health))
+        .routes(routes!(routes::ready))
+        .routes(routes!(routes::cache_stats))
+        .routes(routes!(routes::status))
+        .routes(routes!(routes::list_volumes))
+        .routes(routes!(routes::get_volume_info))
+        .routes(routes!(routes::derive_volume))
+        .routes(routes!(routes::compare_volume))
+        .routes(routes!(routes::spectrum_volume))
+        .routes(routes!(routes::segment_volume))
+        .routes(routes!(routes::list_volume_labels))
+        .routes(routes!(routes::list_annotations, routes::create_annotation))
+        .routes(routes!(
+            routes::get_annotation,
+            routes::update_annotation,
+            routes::delete_annotation
+        ))
+        .routes(routes!(routes::get_volume_mesh))
+        .routes(routes!(routes::export_volume))
+        .routes(routes!(routes::get_volume_low))
+        .routes(routes!(routes::get_volume_full))
+        .routes(routes!(routes::get_volume_frames))
+        .routes(routes!(routes::get_volume_frame))
+        .routes(routes!(routes::get_volume_at_resolution))
+        .routes(routes!(routes::get_label_info))
+        .routes(routes!(routes::get_label_data));
+
+    OpenApiRouter::with_openapi(ApiDoc::openapi())
+        .routes(routes!(routes::metrics))
+        .nest("/api", api)
+}
+
+/// The OpenAPI document served at /api/openapi.json
+pub fn openapi() -> utoipa::openapi::OpenApi {
+    router().into_openapi()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::{Method, Request, StatusCode};
+    use tower::ServiceExt;
+
+    #[test]
+    fn checked_in_document_is_current() {
+        // Regenerate with `cargo run -p server --bin openapi > openapi.json`
+        let generated = format!("{}\n", openapi().to_pretty_json().unwrap());
+        assert!(generated == include_str!("../../openapi.json"), "openapi.json is out of date");
+    }
+
+    #[test]
+    fn data_routes_document_busy_and_too_large() {
+        let doc = openapi();
+        for path in ["/low", "/full", "/frames", "/frames/{frame}", "/at/{resolution}"] {
+            let item = &doc.paths.paths[&format!("/api/volumes/{{id}}{}", path)];
+            let responses = &item.get.as_ref().unwrap().responses.responses;
+            for status in ["200", "304", "404", "503", "507"] {
+                assert!(responses.contains_key(status), "{} does not document {}", path, status);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn documented_routes_are_served() {
+        let state = Arc::new(AppState::new(&[], &["checkerboard:4".parse().unwrap()]).await);
+        let (router, doc) = router().split_for_parts();
+        let router = router.with_state(state);
+
+        let request = |method, uri: &str| Request::builder().method(method).uri(uri).body(Body::empty()).unwrap();
+        let id = "phantoms%2Fcheckerboard-4-f32";
+        for (method, uri) in [
+            (Method::GET, "/api/health".to_string()),
+            (Method::GET, "/metrics".to_string()),
+            (Method::GET, format!("/api/volumes/{}/info", id)),
+            (Method::GET, format!("/api/volumes/{}/frames/0", id)),
+        ] {
+            let response = router.clone().oneshot(request(method.clone(), &uri)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK, "{} {}", method, uri);
+        }
+
+        // A documented path with an undocumented method is not routed
+        assert!(doc.paths.paths["/api/volumes/{id}/info"].post.is_none());
+        let response = router.oneshot(request(Method::POST, &format!("/api/volumes/{}/info", id))).await.unwrap();
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+    }
+}
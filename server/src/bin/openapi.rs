@@ -0,0 +1,7 @@
+//! Print the OpenAPI document of the HTTP API. CI compares it with the
+//! checked-in `openapi.json`; regenerate that with
+//! `cargo run -p server --bin openapi > openapi.json`
+
+fn main() {
+    println!("{}", server::api::openapi().to_pretty_json().unwrap());
+}
@@ -0,0 +1,454 @@
+use axum::body::Bytes;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use shared::CacheStats;
+use tokio::sync::OnceCell;
+use tokio_util::sync::CancellationToken;
+
+use crate::hdf5_reader::HDF5Error;
+
+/// Default cache budget when `CACHE_BUDGET_MB` is not set
+const DEFAULT_BUDGET_MB: u64 = 256;
+
+/// Which resolution of a volume an entry holds
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum CacheLevel {
+    /// Full-resolution data
+    Full,
+    /// Resampled so the largest dimension is at most this size
+    Resolution(usize),
+    /// Voxels of a label volume, in its own dtype
+    Labels,
+    /// Surface extracted from the volume, in a mesh file format
+    Mesh,
+}
+
+/// Identifies one processed representation of a volume
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    pub volume: String,
+    pub level: CacheLevel,
+    /// Component of multi-component data (e.g. "magnitude")
+    pub component: String,
+    /// Byte encoding of the payload (e.g. "f32le")
+    pub encoding: String,
+}
+
+impl CacheKey {
+    /// Key for the default component and encoding of a volume
+    pub fn new(volume: &str, level: CacheLevel) -> Self {
+        Self {
+            volume: volume.to_string(),
+            level,
+            component: "magnitude".to_string(),
+            encoding: "f32le".to_string(),
+        }
+    }
+
+    /// Key for the voxels of a label volume
+    pub fn labels(id: &str) -> Self {
+        Self {
+            volume: id.to_string(),
+            level: CacheLevel::Labels,
+            component: "labels".to_string(),
+            encoding: "le".to_string(),
+        }
+    }
+}
+
+/// Decoded volume payload held by the cache
+#[derive(Debug, Clone)]
+pub struct CachedVolume {
+    pub bytes: Bytes,
+    /// Dimensions [x, y, z] of the payload
+    pub dims: [u32; 3],
+}
+
+struct Entry {
+    value: CachedVolume,
+    /// Tick of the last access, used for LRU eviction
+    last_used: u64,
+}
+
+/// A computation in progress, shared by identical concurrent requests
+#[derive(Default)]
+struct InFlight {
+    cell: OnceCell<CachedVolume>,
+    /// Passed to the computation; cancelled once no request waits for it
+    cancel: CancellationToken,
+    /// Requests still waiting for the value, changed under the cache lock
+    waiters: AtomicUsize,
+}
+
+/// One request waiting for an in-flight computation. The last to leave
+/// cancels it, so no single requester's cancellation stops shared work.
+struct Waiter<'a> {
+    cache: &'a VolumeCache,
+    key: &'a CacheKey,
+    in_flight: Arc<InFlight>,
+    left: bool,
+}
+
+impl<'a> Waiter<'a> {
+    fn join(cache: &'a VolumeCache, key: &'a CacheKey) -> Self {
+        let mut inner = cache.inner.lock().unwrap();
+        let in_flight = inner.in_flight.entry(key.clone()).or_default().clone();
+        in_flight.waiters.fetch_add(1, Ordering::Relaxed);
+        Self {
+            cache,
+            key,
+            in_flight,
+            left: false,
+        }
+    }
+
+    fn leave(&mut self) {
+        if std::mem::replace(&mut self.left, true) {
+            return;
+        }
+        let mut inner = self.cache.inner.lock().unwrap();
+        if self.in_flight.waiters.fetch_sub(1, Ordering::Relaxed) == 1 {
+            self.in_flight.cancel.cancel();
+            // Later requests start afresh rather than join a cancelled computation
+            if inner.in_flight.get(self.key).is_some_and(|f| Arc::ptr_eq(f, &self.in_flight)) {
+                inner.in_flight.remove(self.key);
+            }
+        }
+    }
+}
+
+impl Drop for Waiter<'_> {
+    fn drop(&mut self) {
+        self.leave();
+    }
+}
+
+#[derive(Default)]
+struct CacheInner {
+    entries: HashMap<CacheKey, Entry>,
+    /// Computations in progress, by key
+    in_flight: HashMap<CacheKey, Arc<InFlight>>,
+    used_bytes: u64,
+    tick: u64,
+}
+
+/// Memory-budgeted LRU cache of decoded and resampled volumes
+pub struct VolumeCache {
+    budget_bytes: u64,
+    inner: Mutex<CacheInner>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    coalesced: AtomicU64,
+}
+
+impl VolumeCache {
+    pub fn new(budget_bytes: u64) -> Self {
+        Self {
+            budget_bytes,
+            inner: Mutex::new(CacheInner::default()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            coalesced: AtomicU64::new(0),
+        }
+    }
+
+    /// Create a cache with the budget from `CACHE_BUDGET_MB`
+    pub fn from_env() -> Self {
+        let budget_mb = std::env::var("CACHE_BUDGET_MB")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_BUDGET_MB);
+        Self::new(budget_mb * 1024 * 1024)
+    }
+
+    pub fn budget_bytes(&self) -> u64 {
+        self.budget_bytes
+    }
+
+    /// Look up an entry without computing it, counting a hit or a miss
+    pub fn get(&self, key: &CacheKey) -> Option<CachedVolume> {
+        let value = self.find(key);
+        let counter = if value.is_some() { &self.hits } else { &self.misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+        value
+    }
+
+    /// Look up an entry and mark it as just used
+    fn find(&self, key: &CacheKey) -> Option<CachedVolume> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.tick += 1;
+        let tick = inner.tick;
+        let entry = inner.entries.get_mut(key)?;
+        entry.last_used = tick;
+        Some(entry.value.clone())
+    }
+
+    /// Return the cached value for `key`, or run `compute` to produce it.
+    /// Concurrent callers with the same key share a single computation, which
+    /// gets its own token: it is cancelled once every caller's `cancel` has
+    /// fired (or the caller has gone), not when the first caller's does. A
+    /// caller that is running the computation keeps driving it for the others.
+    pub async fn get_or_compute<F, Fut, E>(
+        &self,
+        key: CacheKey,
+        cancel: &CancellationToken,
+        compute: F,
+    ) -> Result<CachedVolume, E>
+    where
+        F: FnOnce(CancellationToken) -> Fut,
+        Fut: Future<Output = Result<CachedVolume, E>>,
+        E: From<HDF5Error>,
+    {
+        if let Some(value) = self.find(&key) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(value);
+        }
+
+        let mut waiter = Waiter::join(self, &key);
+        let in_flight = waiter.in_flight.clone();
+        let computed = AtomicBool::new(false);
+        let init = in_flight.cell.get_or_try_init(|| async {
+            computed.store(true, Ordering::Relaxed);
+            self.misses.fetch_add(1, Ordering::Relaxed);
+            let result = compute(in_flight.cancel.clone()).await;
+
+            // Publish the value and retire the in-flight computation in one step
+            let mut inner = self.inner.lock().unwrap();
+            if inner.in_flight.get(&key).is_some_and(|f| Arc::ptr_eq(f, &in_flight)) {
+                inner.in_flight.remove(&key);
+            }
+            if let Ok(value) = &result {
+                self.insert(&mut inner, key.clone(), value.clone());
+            }
+            result
+        });
+        tokio::pin!(init);
+
+        let result = tokio::select! {
+            result = &mut init => result.cloned(),
+            _ = cancel.cancelled() => {
+                waiter.leave();
+                if computed.load(Ordering::Relaxed) {
+                    (&mut init).await.cloned()
+                } else {
+                    Err(HDF5Error::Cancelled.into())
+                }
+            }
+        };
+
+        if !computed.load(Ordering::Relaxed) {
+            self.coalesced.fetch_add(1, Ordering::Relaxed);
+        }
+
+        result
+    }
+
+    /// Store a value computed outside `get_or_compute`
+    pub fn put(&self, key: CacheKey, value: CachedVolume) {
+        let mut inner = self.inner.lock().unwrap();
+        self.insert(&mut inner, key, value);
+    }
+
+    /// Insert a value, evicting least recently used entries to stay in budget.
+    /// Values larger than the whole budget are not stored.
+    fn insert(&self, inner: &mut CacheInner, key: CacheKey, value: CachedVolume) {
+        let size = value.bytes.len() as u64;
+        if size > self.budget_bytes {
+            return;
+        }
+
+        while inner.used_bytes + size > self.budget_bytes {
+            let Some(oldest) = inner
+                .entries
+                .iter()
+                .min_by_key(|(_, e)| e.last_used)
+                .map(|(k, _)| k.clone())
+            else {
+                break;
+            };
+            if let Some(evicted) = inner.entries.remove(&oldest) {
+                inner.used_bytes -= evicted.value.bytes.len() as u64;
+                tracing::debug!(key = ?oldest, "Evicted from cache");
+            }
+        }
+
+        inner.tick += 1;
+        let last_used = inner.tick;
+        if let Some(old) = inner.entries.insert(key, Entry { value, last_used }) {
+            inner.used_bytes -= old.value.bytes.len() as u64;
+        }
+        inner.used_bytes += size;
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let inner = self.inner.lock().unwrap();
+        CacheStats {
+            entries: inner.entries.len(),
+            used_bytes: inner.used_bytes,
+            budget_bytes: self.budget_bytes,
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            coalesced: self.coalesced.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::Notify;
+
+    fn volume(size: usize) -> CachedVolume {
+        CachedVolume {
+            bytes: Bytes::from(vec![0u8; size]),
+            dims: [size as u32, 1, 1],
+        }
+    }
+
+    async fn fill(cache: &VolumeCache, name: &str, size: usize) {
+        let key = CacheKey::new(name, CacheLevel::Full);
+        cache
+            .get_or_compute(key, &CancellationToken::new(), |_| async { Ok::<_, HDF5Error>(volume(size)) })
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn evicts_least_recently_used() {
+        let cache = VolumeCache::new(100);
+        fill(&cache, "a", 40).await;
+        fill(&cache, "b", 40).await;
+        // Touch a, so b is the oldest when c needs room
+        assert!(cache.get(&CacheKey::new("a", CacheLevel::Full)).is_some());
+        fill(&cache, "c", 40).await;
+
+        assert!(cache.get(&CacheKey::new("a", CacheLevel::Full)).is_some());
+        assert!(cache.get(&CacheKey::new("b", CacheLevel::Full)).is_none());
+        assert!(cache.get(&CacheKey::new("c", CacheLevel::Full)).is_some());
+        assert_eq!(cache.stats().used_bytes, 80);
+    }
+
+    #[tokio::test]
+    async fn does_not_store_values_over_budget() {
+        let cache = VolumeCache::new(100);
+        fill(&cache, "a", 40).await;
+        fill(&cache, "huge", 200).await;
+        assert!(cache.get(&CacheKey::new("huge", CacheLevel::Full)).is_none());
+        // Nothing was evicted to make room for it
+        assert!(cache.get(&CacheKey::new("a", CacheLevel::Full)).is_some());
+    }
+
+    #[tokio::test]
+    async fn lookups_count_hits_and_misses() {
+        let cache = VolumeCache::new(100);
+        assert!(cache.get(&CacheKey::new("a", CacheLevel::Full)).is_none());
+        fill(&cache, "a", 10).await;
+        fill(&cache, "a", 10).await;
+        assert!(cache.get(&CacheKey::new("a", CacheLevel::Full)).is_some());
+        let stats = cache.stats();
+        // Computing it was the second miss, the repeated fill and the get hit
+        assert_eq!((stats.hits, stats.misses), (2, 2));
+    }
+
+    #[tokio::test]
+    async fn levels_are_separate_entries() {
+        let cache = VolumeCache::new(100);
+        fill(&cache, "a", 10).await;
+        assert!(cache.get(&CacheKey::new("a", CacheLevel::Resolution(64))).is_none());
+    }
+
+    #[tokio::test]
+    async fn errors_are_not_cached() {
+        let cache = VolumeCache::new(100);
+        let (key, cancel) = (CacheKey::new("a", CacheLevel::Full), CancellationToken::new());
+        let failed = cache
+            .get_or_compute(key.clone(), &cancel, |_| async {
+                Err::<CachedVolume, _>(HDF5Error::Format("read failed".to_string()))
+            })
+            .await;
+        assert!(failed.is_err());
+        let retried = cache
+            .get_or_compute(key, &cancel, |_| async { Ok::<_, HDF5Error>(volume(10)) })
+            .await;
+        assert_eq!(retried.unwrap().bytes.len(), 10);
+    }
+
+    #[tokio::test]
+    async fn concurrent_requests_share_one_computation() {
+        let cache = VolumeCache::new(100);
+        let runs = AtomicUsize::new(0);
+        let compute = |_| async {
+            runs.fetch_add(1, Ordering::SeqCst);
+            tokio::task::yield_now().await;
+            Ok::<_, HDF5Error>(volume(10))
+        };
+        let (key, cancel) = (CacheKey::new("a", CacheLevel::Full), CancellationToken::new());
+        let (a, b) = tokio::join!(
+            cache.get_or_compute(key.clone(), &cancel, compute),
+            cache.get_or_compute(key.clone(), &cancel, compute)
+        );
+        assert!(a.is_ok() && b.is_ok());
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        assert_eq!(cache.stats().coalesced, 1);
+    }
+
+    /// Computes a value once `release` is notified, or fails once its token is cancelled
+    async fn slow(release: &Notify, cancel: CancellationToken) -> Result<CachedVolume, HDF5Error> {
+        tokio::select! {
+            _ = release.notified() => Ok(volume(10)),
+            _ = cancel.cancelled() => Err(HDF5Error::Cancelled),
+        }
+    }
+
+    #[tokio::test]
+    async fn first_caller_cancelling_does_not_cancel_the_others() {
+        let cache = VolumeCache::new(100);
+        let release = Notify::new();
+        let key = CacheKey::new("a", CacheLevel::Full);
+        let (first, second) = (CancellationToken::new(), CancellationToken::new());
+
+        let (a, b, _) = tokio::join!(
+            cache.get_or_compute(key.clone(), &first, |token| slow(&release, token)),
+            cache.get_or_compute(key.clone(), &second, |token| slow(&release, token)),
+            async {
+                tokio::task::yield_now().await;
+                first.cancel();
+                tokio::task::yield_now().await;
+                release.notify_waiters();
+            }
+        );
+        // The first caller kept driving the computation for the second
+        assert!(a.is_ok());
+        assert!(b.is_ok());
+        assert_eq!(cache.stats().misses, 1);
+    }
+
+    #[tokio::test]
+    async fn cancelled_once_every_caller_has_left() {
+        let cache = VolumeCache::new(100);
+        let release = Notify::new();
+        let key = CacheKey::new("a", CacheLevel::Full);
+        let (first, second) = (CancellationToken::new(), CancellationToken::new());
+
+        let (a, b, _) = tokio::join!(
+            cache.get_or_compute(key.clone(), &first, |token| slow(&release, token)),
+            cache.get_or_compute(key.clone(), &second, |token| slow(&release, token)),
+            async {
+                tokio::task::yield_now().await;
+                first.cancel();
+                second.cancel();
+            }
+        );
+        assert!(matches!(a, Err(HDF5Error::Cancelled)));
+        assert!(matches!(b, Err(HDF5Error::Cancelled)));
+        assert!(cache.get(&key).is_none());
+
+        // A later request starts a fresh computation
+        let retried = cache
+            .get_or_compute(key, &CancellationToken::new(), |_| async { Ok::<_, HDF5Error>(volume(10)) })
+            .await;
+        assert!(retried.is_ok());
+    }
+}
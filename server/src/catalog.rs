@@ -0,0 +1,559 @@
+//! Discovery of volume files below the data roots (folders or buckets),
+//! their ids and tags, and the folder tree, filters, sorting and pages of the volume list
+
+use object_store::path::Path as ObjectPath;
+use serde::Deserialize;
+use shared::{CatalogFolder, SortOrder, VolumeInfo, VolumeSort};
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::labels;
+use crate::storage::{self, Bucket};
+
+/// Data root scanned when `DATA_ROOTS` is not set
+const DEFAULT_DATA_ROOT: &str = "samples";
+
+/// Extensions of the metadata sidecars, e.g. `case_01.meta.json`
+const META_EXTENSIONS: [&str; 3] = ["meta.json", "meta.yaml", "meta.yml"];
+
+/// Largest page of the volume list
+pub const MAX_PAGE_SIZE: usize = 1000;
+
+/// Where a volume file is stored
+#[derive(Clone)]
+pub enum Location {
+    /// A file on local disk
+    File(PathBuf),
+    /// An object in a bucket
+    Object { bucket: Arc<Bucket>, key: ObjectPath },
+}
+
+impl Location {
+    /// Extension of the file or object, lowercased
+    pub fn extension(&self) -> String {
+        let extension = match self {
+            Location::File(path) => path.extension().and_then(|e| e.to_str()),
+            Location::Object { key, .. } => key.extension(),
+        };
+        extension.unwrap_or_default().to_ascii_lowercase()
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Location::File(path) => write!(f, "{}", path.display()),
+            Location::Object { bucket, key } => write!(f, "{}", bucket.object_url(key)),
+        }
+    }
+}
+
+/// A volume file found below a data root
+pub struct CatalogEntry {
+    pub location: Location,
+    /// Path relative to the data root without the extension, e.g. `brain/case_01`
+    pub id: String,
+    /// Folder part of the id ("" at the top level)
+    pub folder: String,
+    /// Path relative to the data root, e.g. `brain/case_01.h5`
+    pub file: String,
+}
+
+/// Contents of a `.meta.json` or `.meta.yaml` sidecar
+#[derive(Deserialize, Default)]
+pub struct Sidecar {
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Free-form values, searchable like the HDF5 attributes
+    #[serde(default)]
+    attributes: BTreeMap<String, serde_json::Value>,
+    /// Exact `[min, max]` of the values, for objects whose range is otherwise
+    /// estimated from samples
+    #[serde(default, deserialize_with = "value_range")]
+    pub value_range: Option<[f32; 2]>,
+}
+
+/// A `[min, max]` pair with `min <= max`
+fn value_range<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Option<[f32; 2]>, D::Error> {
+    match Option::<[f32; 2]>::deserialize(deserializer)? {
+        Some([min, max]) if min.is_nan() || max.is_nan() || min > max => {
+            Err(serde::de::Error::custom("value_range needs min <= max"))
+        }
+        range => Ok(range),
+    }
+}
+
+impl Sidecar {
+    /// Attribute values as text, without quotes around strings
+    pub fn attributes(&self) -> impl Iterator<Item = (String, String)> + '_ {
+        self.attributes.iter().map(|(name, value)| {
+            let text = match value {
+                serde_json::Value::String(text) => text.clone(),
+                other => other.to_string(),
+            };
+            (name.clone(), text)
+        })
+    }
+}
+
+/// Short name of a dtype as in `VolumeInfo::dtype`, e.g. `f32` for HDF5's
+/// `float32` or `u16` for `uint16`. Names without a short form are kept.
+pub fn dtype_name(name: &str) -> String {
+    let name = name.trim().to_lowercase();
+    let short = match name.as_str() {
+        "int8" => "i8",
+        "int16" => "i16",
+        "int32" => "i32",
+        "int64" => "i64",
+        "uint8" => "u8",
+        "uint16" => "u16",
+        "uint32" => "u32",
+        "uint64" => "u64",
+        "float32" => "f32",
+        "float64" => "f64",
+        _ => return name,
+    };
+    short.to_string()
+}
+
+/// Which volumes the volume list returns
+#[derive(Default)]
+pub struct Filter {
+    /// Case-insensitive text matched against id, name, tags and attribute names and values
+    pub text: Option<String>,
+    /// Volumes must have all of these tags
+    pub tags: Vec<String>,
+    /// Volumes must have one of these dtypes (any when empty), named as by `dtype_name`
+    pub dtypes: Vec<String>,
+    /// Volumes must be in this folder or below it
+    pub folder: Option<String>,
+    /// Every dimension must be at least this
+    pub min_dim: Option<u32>,
+    /// Every dimension must be at most this
+    pub max_dim: Option<u32>,
+}
+
+impl Filter {
+    pub fn matches(&self, info: &VolumeInfo) -> bool {
+        if let Some(text) = &self.text {
+            let text = text.to_lowercase();
+            let found = [&info.id, &info.name]
+                .into_iter()
+                .chain(&info.tags)
+                .chain(info.attributes.iter().flat_map(|(name, value)| [name, value]))
+                .any(|field| field.to_lowercase().contains(&text));
+            if !found {
+                return false;
+            }
+        }
+        if !self.tags.iter().all(|tag| info.tags.contains(tag)) {
+            return false;
+        }
+        if !self.dtypes.is_empty() && !self.dtypes.contains(&info.dtype) {
+            return false;
+        }
+        if let Some(folder) = self.folder.as_deref().map(|f| f.trim_matches('/')).filter(|f| !f.is_empty()) {
+            let inside = info.folder == folder
+                || info.folder.strip_prefix(folder).is_some_and(|rest| rest.starts_with('/'));
+            if !inside {
+                return false;
+            }
+        }
+        let dims = info.dimensions;
+        self.min_dim.is_none_or(|min| dims.iter().all(|&d| d >= min))
+            && self.max_dim.is_none_or(|max| dims.iter().all(|&d| d <= max))
+    }
+}
+
+/// Sort by `key`, then by id so the order (and so the cursors) are stable
+pub fn sort(volumes: &mut [VolumeInfo], key: VolumeSort, order: SortOrder) {
+    let voxels = |v: &VolumeInfo| v.dimensions.iter().map(|&d| d as u64).product::<u64>();
+    volumes.sort_by(|a, b| {
+        let ordering = match key {
+            VolumeSort::Id => Ordering::Equal,
+            VolumeSort::Name => a.name.to_lowercase().cmp(&b.name.to_lowercase()),
+            VolumeSort::Folder => a.folder.cmp(&b.folder),
+            VolumeSort::Size => a.full_res_size.cmp(&b.full_res_size),
+            VolumeSort::Voxels => voxels(a).cmp(&voxels(b)),
+            VolumeSort::Frames => a.frames.cmp(&b.frames),
+            VolumeSort::Dtype => a.dtype.cmp(&b.dtype),
+        }
+        .then_with(|| a.id.cmp(&b.id));
+        match order {
+            SortOrder::Asc => ordering,
+            SortOrder::Desc => ordering.reverse(),
+        }
+    });
+}
+
+/// The volumes after the one with id `cursor` (all when `None`), at most
+/// `limit` of them, and the cursor of the next page if there is one.
+/// Returns `None` when `cursor` is not in `volumes`.
+pub fn page(
+    mut volumes: Vec<VolumeInfo>,
+    cursor: Option<&str>,
+    limit: Option<usize>,
+) -> Option<(Vec<VolumeInfo>, Option<String>)> {
+    if let Some(cursor) = cursor {
+        let position = volumes.iter().position(|v| v.id == cursor)?;
+        volumes.drain(..=position);
+    }
+    let limit = limit.unwrap_or(usize::MAX);
+    let next_cursor = (volumes.len() > limit).then(|| volumes[limit - 1].id.clone());
+    volumes.truncate(limit);
+    Some((volumes, next_cursor))
+}
+
+/// Data roots from `DATA_ROOTS`, a comma-separated list of folders and
+/// `s3://bucket/prefix` URLs
+pub fn data_roots() -> Vec<String> {
+    match std::env::var("DATA_ROOTS") {
+        Ok(roots) => roots
+            .split(',')
+            .map(str::trim)
+            .filter(|r| !r.is_empty())
+            .map(str::to_string)
+            .collect(),
+        Err(_) => vec![DEFAULT_DATA_ROOT.to_string()],
+    }
+}
+
+/// Every volume file below `roots`, in path order. With more than one root,
+/// ids start with the root's directory (or bucket prefix) name so files in
+/// different roots do not collide.
+pub async fn discover(roots: &[String]) -> Vec<CatalogEntry> {
+    let mut entries = Vec::new();
+    for root in roots {
+        let listed = if root.starts_with(storage::S3_SCHEME) {
+            list_bucket(root).await
+        } else {
+            list_folder(Path::new(root))
+        };
+        let Some(listed) = listed else { continue };
+        let prefix = (roots.len() > 1).then_some(listed.name).flatten();
+
+        for (location, file) in listed.files {
+            let mut parts: Vec<String> = prefix.iter().cloned().collect();
+            parts.extend(Path::new(&file).with_extension("").iter().map(|p| p.to_string_lossy().into_owned()));
+            let id = parts.join("/");
+            let folder = parts[..parts.len() - 1].join("/");
+            entries.push(CatalogEntry { location, id, folder, file });
+        }
+    }
+    entries
+}
+
+/// The volume files of one data root
+struct RootListing {
+    /// Directory or bucket prefix name, which prefixes ids with several roots
+    name: Option<String>,
+    /// Each file with its path relative to the root
+    files: Vec<(Location, String)>,
+}
+
+/// Name and volume files of a folder root, with paths relative to it
+fn list_folder(root: &Path) -> Option<RootListing> {
+    if !root.is_dir() {
+        tracing::warn!(path = ?root, "Data root not found");
+        return None;
+    }
+    let name = root
+        .canonicalize()
+        .ok()
+        .and_then(|r| r.file_name().map(|n| n.to_string_lossy().into_owned()));
+
+    let mut paths = Vec::new();
+    walk(root, &mut paths);
+    let files = paths
+        .into_iter()
+        .filter_map(|path| {
+            let relative = path.strip_prefix(root).ok()?;
+            let file = relative.iter().map(|p| p.to_string_lossy()).collect::<Vec<_>>().join("/");
+            Some((Location::File(path), file))
+        })
+        .collect();
+    Some(RootListing { name, files })
+}
+
+/// Name and volume objects of a bucket root, with keys relative to its prefix.
+/// `.h5` objects are kept so they are reported as failed rather than ignored.
+async fn list_bucket(url: &str) -> Option<RootListing> {
+    let bucket = match Bucket::from_url(url) {
+        Ok(bucket) => Arc::new(bucket),
+        Err(e) => {
+            tracing::warn!(root = %url, error = %e, "Invalid bucket data root");
+            return None;
+        }
+    };
+    let objects = match bucket.list().await {
+        Ok(objects) => objects,
+        Err(e) => {
+            tracing::warn!(root = %url, error = %e, "Could not list bucket");
+            return None;
+        }
+    };
+
+    let files = objects
+        .into_iter()
+        .filter(|(file, _)| {
+            let path = Path::new(file);
+            !file.split('/').any(|part| part.starts_with('.'))
+                && path.extension().is_some_and(|e| e == "npy" || e == "h5")
+                && !labels::is_label_file(path)
+        })
+        .map(|(file, key)| {
+            let location = Location::Object {
+                bucket: bucket.clone(),
+                key,
+            };
+            (location, file)
+        })
+        .collect();
+    Some(RootListing {
+        name: Some(bucket.name()),
+        files,
+    })
+}
+
+/// Collect the volume files below `dir`, skipping hidden entries and symlinked folders
+fn walk(dir: &Path, files: &mut Vec<PathBuf>) {
+    let Ok(read) = std::fs::read_dir(dir) else {
+        tracing::warn!(path = ?dir, "Could not read folder");
+        return;
+    };
+    let mut children: Vec<_> = read
+        .flatten()
+        .filter(|e| !e.file_name().to_string_lossy().starts_with('.'))
+        .collect();
+    children.sort_by_key(|e| e.file_name());
+
+    for child in children {
+        let path = child.path();
+        if child.file_type().is_ok_and(|t| t.is_dir()) {
+            walk(&path, files);
+        } else if path.extension().is_some_and(|e| e == "h5") && !labels::is_label_file(&path) {
+            files.push(path);
+        }
+    }
+}
+
+/// The first `{stem}.meta.json`, `.meta.yaml` or `.meta.yml` next to the volume file or object
+pub async fn read_sidecar(location: &Location) -> Sidecar {
+    for extension in META_EXTENSIONS {
+        let (sidecar, text) = match location {
+            Location::File(path) => {
+                let sidecar = path.with_extension(extension);
+                let text = std::fs::read_to_string(&sidecar).ok();
+                (sidecar.display().to_string(), text)
+            }
+            Location::Object { bucket, key } => {
+                let name = key.as_ref();
+                let stem = key.extension().map_or(name, |e| &name[..name.len() - e.len() - 1]);
+                let sidecar = ObjectPath::from(format!("{}.{}", stem, extension));
+                // Only objects seen in the listing, to save a GET per missing sidecar
+                let text = match bucket.is_listed(&sidecar) {
+                    true => bucket.get(&sidecar).await.ok().map(|b| String::from_utf8_lossy(&b).into_owned()),
+                    false => None,
+                };
+                (bucket.object_url(&sidecar), text)
+            }
+        };
+        let Some(text) = text else { continue };
+        let parsed = if extension == "meta.json" {
+            serde_json::from_str::<Sidecar>(&text).map_err(|e| e.to_string())
+        } else {
+            serde_yaml::from_str::<Sidecar>(&text).map_err(|e| e.to_string())
+        };
+        match parsed {
+            Ok(parsed) => return parsed,
+            Err(e) => tracing::warn!(sidecar = %sidecar, error = %e, "Invalid metadata sidecar"),
+        }
+    }
+    Sidecar::default()
+}
+
+/// Group volumes into folders by `VolumeInfo::folder`
+pub fn tree<'a>(volumes: impl IntoIterator<Item = &'a VolumeInfo>) -> CatalogFolder {
+    let mut root = CatalogFolder::default();
+    for info in volumes {
+        let mut folder = &mut root;
+        for name in info.folder.split('/').filter(|n| !n.is_empty()) {
+            let index = match folder.folders.iter().position(|f| f.name == name) {
+                Some(index) => index,
+                None => {
+                    let path = if folder.path.is_empty() {
+                        name.to_string()
+                    } else {
+                        format!("{}/{}", folder.path, name)
+                    };
+                    folder.folders.push(CatalogFolder {
+                        name: name.to_string(),
+                        path,
+                        ..Default::default()
+                    });
+                    folder.folders.len() - 1
+                }
+            };
+            folder = &mut folder.folders[index];
+        }
+        folder.volumes.push(info.id.clone());
+    }
+    sort_folder(&mut root);
+    root
+}
+
+fn sort_folder(folder: &mut CatalogFolder) {
+    folder.volumes.sort();
+    folder.folders.sort_by(|a, b| a.name.cmp(&b.name));
+    folder.folders.iter_mut().for_each(sort_folder);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn volume(id: &str, folder: &str, dims: [u32; 3], dtype: &str, tags: &[&str]) -> VolumeInfo {
+        VolumeInfo {
+            id: id.to_string(),
+            name: id.rsplit('/').next().unwrap().to_string(),
+            dimensions: dims,
+            full_res_size: dims.iter().map(|&d| d as u64).product::<u64>() * 4,
+            folder: folder.to_string(),
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+            dtype: dtype.to_string(),
+            attributes: BTreeMap::from([("scanner".to_string(), "Prisma".to_string())]),
+            ..Default::default()
+        }
+    }
+
+    fn catalog() -> Vec<VolumeInfo> {
+        vec![
+            volume("knee/case_01", "knee", [64, 64, 32], "f32", &["mri", "knee"]),
+            volume("knee/deep/case_02", "knee/deep", [128, 128, 128], "u16", &["mri"]),
+            volume("kneecap", "", [16, 16, 16], "u8", &["ct"]),
+            volume("brain", "", [256, 256, 256], "f32", &[]),
+        ]
+    }
+
+    fn ids(volumes: &[VolumeInfo]) -> Vec<&str> {
+        volumes.iter().map(|v| v.id.as_str()).collect()
+    }
+
+    fn matching(filter: &Filter) -> Vec<String> {
+        catalog().into_iter().filter(|v| filter.matches(v)).map(|v| v.id).collect()
+    }
+
+    #[test]
+    fn shortens_dtype_names() {
+        assert_eq!(dtype_name("float32"), "f32");
+        assert_eq!(dtype_name("UInt16"), "u16");
+        assert_eq!(dtype_name("int8"), "i8");
+        assert_eq!(dtype_name("f32"), "f32");
+        assert_eq!(dtype_name("bool"), "bool");
+    }
+
+    #[test]
+    fn filters_by_text_tags_and_dtype() {
+        let text = |q: &str| Filter {
+            text: Some(q.to_string()),
+            ..Default::default()
+        };
+        assert_eq!(matching(&text("CASE_0")), ["knee/case_01", "knee/deep/case_02"]);
+        // Tags and attribute values count as text
+        assert_eq!(matching(&text("ct")), ["kneecap"]);
+        assert_eq!(matching(&text("prisma")).len(), 4);
+
+        let tags = Filter {
+            tags: vec!["mri".to_string(), "knee".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(matching(&tags), ["knee/case_01"]);
+
+        let dtypes = Filter {
+            dtypes: vec!["u16".to_string(), "u8".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(matching(&dtypes), ["knee/deep/case_02", "kneecap"]);
+    }
+
+    #[test]
+    fn filters_by_folder_and_dimensions() {
+        let folder = |f: &str| Filter {
+            folder: Some(f.to_string()),
+            ..Default::default()
+        };
+        assert_eq!(matching(&folder("knee")), ["knee/case_01", "knee/deep/case_02"]);
+        assert_eq!(matching(&folder("/knee/deep/")), ["knee/deep/case_02"]);
+        // A folder only matches whole path segments
+        assert!(matching(&folder("kne")).is_empty());
+        assert_eq!(matching(&folder("/")).len(), 4);
+
+        let dims = Filter {
+            min_dim: Some(32),
+            max_dim: Some(128),
+            ..Default::default()
+        };
+        assert_eq!(matching(&dims), ["knee/case_01", "knee/deep/case_02"]);
+    }
+
+    #[test]
+    fn sorts_with_ties_broken_by_id() {
+        let mut volumes = catalog();
+        sort(&mut volumes, VolumeSort::Dtype, SortOrder::Asc);
+        assert_eq!(ids(&volumes), ["brain", "knee/case_01", "knee/deep/case_02", "kneecap"]);
+
+        sort(&mut volumes, VolumeSort::Voxels, SortOrder::Desc);
+        assert_eq!(ids(&volumes), ["brain", "knee/deep/case_02", "knee/case_01", "kneecap"]);
+
+        sort(&mut volumes, VolumeSort::Folder, SortOrder::Asc);
+        assert_eq!(ids(&volumes), ["brain", "kneecap", "knee/case_01", "knee/deep/case_02"]);
+    }
+
+    #[test]
+    fn pages_with_cursors() {
+        let mut volumes = catalog();
+        sort(&mut volumes, VolumeSort::Id, SortOrder::Asc);
+
+        let (first, cursor) = page(volumes.clone(), None, Some(3)).unwrap();
+        assert_eq!(ids(&first), ["brain", "knee/case_01", "knee/deep/case_02"]);
+        assert_eq!(cursor.as_deref(), Some("knee/deep/case_02"));
+
+        let (second, cursor) = page(volumes.clone(), cursor.as_deref(), Some(3)).unwrap();
+        assert_eq!(ids(&second), ["kneecap"]);
+        assert_eq!(cursor, None);
+
+        // A page that ends exactly at the last volume has no next cursor
+        let (all, cursor) = page(volumes.clone(), None, Some(4)).unwrap();
+        assert_eq!(all.len(), 4);
+        assert_eq!(cursor, None);
+
+        let (rest, _) = page(volumes.clone(), Some("kneecap"), None).unwrap();
+        assert!(rest.is_empty());
+        assert!(page(volumes, Some("missing"), None).is_none());
+    }
+
+    #[test]
+    fn reads_sidecar_value_ranges() {
+        let sidecar: Sidecar = serde_yaml::from_str("tags: [knee]\nvalue_range: [-1, 4.5]").unwrap();
+        assert_eq!(sidecar.value_range, Some([-1.0, 4.5]));
+        let sidecar: Sidecar = serde_json::from_str(r#"{"tags": ["knee"]}"#).unwrap();
+        assert_eq!(sidecar.value_range, None);
+        assert!(serde_json::from_str::<Sidecar>(r#"{"value_range": [2, 1]}"#).is_err());
+    }
+
+    #[test]
+    fn builds_the_folder_tree() {
+        let volumes = catalog();
+        let root = tree(&volumes);
+        assert_eq!(root.volumes, ["brain", "kneecap"]);
+        assert_eq!(root.folders.len(), 1);
+        let knee = &root.folders[0];
+        assert_eq!((knee.name.as_str(), knee.path.as_str()), ("knee", "knee"));
+        assert_eq!(knee.volumes, ["knee/case_01"]);
+        assert_eq!(knee.folders[0].path, "knee/deep");
+        assert_eq!(knee.folders[0].volumes, ["knee/deep/case_02"]);
+    }
+}
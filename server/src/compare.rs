@@ -0,0 +1,221 @@
+use ndarray::{Array3, ArrayView2, Axis, Zip};
+use shared::QualityMetrics;
+use tokio_util::sync::CancellationToken;
+
+use crate::filters::check;
+use crate::hdf5_reader::HDF5Error;
+
+/// Side of the square SSIM window, as used by scikit-image and fastMRI
+const SSIM_WINDOW: usize = 7;
+
+/// SSIM stabilising constants, relative to the data range
+const SSIM_K1: f64 = 0.01;
+const SSIM_K2: f64 = 0.03;
+
+/// Metrics of a volume against its reference
+pub struct Comparison {
+    /// Reference max - min, used for PSNR and SSIM
+    pub data_range: f32,
+    pub global: QualityMetrics,
+    /// One entry per slice along the comparison axis
+    pub slices: Vec<QualityMetrics>,
+    /// Value range of the absolute difference
+    pub difference_range: [f32; 2],
+}
+
+/// Running sums for PSNR, NMSE and MAE
+#[derive(Default, Clone, Copy)]
+struct ErrorSums {
+    squared_error: f64,
+    squared_reference: f64,
+    absolute_error: f64,
+    count: u64,
+}
+
+impl ErrorSums {
+    fn add(&mut self, other: &ErrorSums) {
+        self.squared_error += other.squared_error;
+        self.squared_reference += other.squared_reference;
+        self.absolute_error += other.absolute_error;
+        self.count += other.count;
+    }
+
+    fn metrics(&self, data_range: f64, ssim: f64) -> QualityMetrics {
+        let n = self.count.max(1) as f64;
+        let mse = self.squared_error / n;
+        QualityMetrics {
+            psnr: (mse > 0.0).then(|| 10.0 * (data_range * data_range / mse).log10()),
+            ssim,
+            nmse: (self.squared_reference > 0.0).then(|| self.squared_error / self.squared_reference),
+            mae: self.absolute_error / n,
+        }
+    }
+}
+
+/// Compare `data` against `reference` (same shape) globally and per slice
+/// along `axis`. As in fastMRI, SSIM is computed on 2D slices and the
+/// global value is their mean.
+pub fn compare(
+    data: &Array3<f32>,
+    reference: &Array3<f32>,
+    axis: usize,
+    cancel: &CancellationToken,
+) -> Result<Comparison, HDF5Error> {
+    let min = reference.iter().cloned().fold(f32::INFINITY, f32::min);
+    let max = reference.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+    // A constant reference has no range; fall back to 1 so the metrics stay finite
+    let data_range = if max > min { max - min } else { 1.0 };
+
+    let mut total = ErrorSums::default();
+    let mut ssim_sum = 0.0;
+    let mut slices = Vec::with_capacity(data.len_of(Axis(axis)));
+    let mut difference_range = [f32::INFINITY, f32::NEG_INFINITY];
+
+    for (slice, reference_slice) in data.axis_iter(Axis(axis)).zip(reference.axis_iter(Axis(axis))) {
+        check(cancel)?;
+
+        let mut sums = ErrorSums::default();
+        Zip::from(&slice).and(&reference_slice).for_each(|&v, &r| {
+            let error = (v - r).abs();
+            difference_range[0] = difference_range[0].min(error);
+            difference_range[1] = difference_range[1].max(error);
+            sums.squared_error += (error as f64).powi(2);
+            sums.squared_reference += (r as f64).powi(2);
+            sums.absolute_error += error as f64;
+            sums.count += 1;
+        });
+
+        let ssim = ssim(slice, reference_slice, data_range as f64);
+        slices.push(sums.metrics(data_range as f64, ssim));
+        total.add(&sums);
+        ssim_sum += ssim;
+    }
+
+    let mean_ssim = ssim_sum / slices.len().max(1) as f64;
+    Ok(Comparison {
+        data_range,
+        global: total.metrics(data_range as f64, mean_ssim),
+        slices,
+        difference_range,
+    })
+}
+
+/// Mean SSIM over all windows that fit inside the slice, with the sample
+/// covariance correction scikit-image applies by default
+fn ssim(x: ArrayView2<f32>, y: ArrayView2<f32>, data_range: f64) -> f64 {
+    let (h, w) = x.dim();
+    let window = SSIM_WINDOW.min(h).min(w);
+    if window == 0 {
+        return 1.0;
+    }
+
+    // Summed-area tables of x, y, x², y² and xy, with a leading zero row and column
+    let mut tables = vec![[0.0f64; 5]; (h + 1) * (w + 1)];
+    let at = |r: usize, c: usize| r * (w + 1) + c;
+    for r in 0..h {
+        let mut row = [0.0f64; 5];
+        for c in 0..w {
+            let (a, b) = (x[[r, c]] as f64, y[[r, c]] as f64);
+            for (sum, value) in row.iter_mut().zip([a, b, a * a, b * b, a * b]) {
+                *sum += value;
+            }
+            let above = tables[at(r, c + 1)];
+            tables[at(r + 1, c + 1)] = std::array::from_fn(|k| above[k] + row[k]);
+        }
+    }
+
+    let n = (window * window) as f64;
+    let covariance_norm = if n > 1.0 { n / (n - 1.0) } else { 1.0 };
+    let c1 = (SSIM_K1 * data_range).powi(2);
+    let c2 = (SSIM_K2 * data_range).powi(2);
+
+    let mut total = 0.0;
+    let mut count = 0usize;
+    for r in 0..=h - window {
+        for c in 0..=w - window {
+            let (r_end, c_end) = (r + window, c + window);
+            let s: [f64; 5] = std::array::from_fn(|k| {
+                tables[at(r_end, c_end)][k] - tables[at(r, c_end)][k] - tables[at(r_end, c)][k] + tables[at(r, c)][k]
+            });
+            let (mx, my) = (s[0] / n, s[1] / n);
+            let vx = (s[2] / n - mx * mx) * covariance_norm;
+            let vy = (s[3] / n - my * my) * covariance_norm;
+            let vxy = (s[4] / n - mx * my) * covariance_norm;
+
+            total += ((2.0 * mx * my + c1) * (2.0 * vxy + c2)) / ((mx * mx + my * my + c1) * (vx + vy + c2));
+            count += 1;
+        }
+    }
+    total / count as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::{s, Array2};
+
+    fn ramp(n: usize) -> Array3<f32> {
+        Array3::from_shape_fn((n, n, n), |(x, y, z)| ((x * 7 + y * 3 + z * 5) % 11) as f32)
+    }
+
+    /// SSIM straight from the definition, one window at a time
+    fn naive_ssim(x: ArrayView2<f32>, y: ArrayView2<f32>, data_range: f64) -> f64 {
+        let (h, w) = x.dim();
+        let n = (SSIM_WINDOW * SSIM_WINDOW) as f64;
+        let (c1, c2) = ((SSIM_K1 * data_range).powi(2), (SSIM_K2 * data_range).powi(2));
+        let mut values = Vec::new();
+        for r in 0..=h - SSIM_WINDOW {
+            for c in 0..=w - SSIM_WINDOW {
+                let wx = x.slice(s![r..r + SSIM_WINDOW, c..c + SSIM_WINDOW]).mapv(|v| v as f64);
+                let wy = y.slice(s![r..r + SSIM_WINDOW, c..c + SSIM_WINDOW]).mapv(|v| v as f64);
+                let (mx, my) = (wx.mean().unwrap(), wy.mean().unwrap());
+                let vx = wx.mapv(|v| (v - mx).powi(2)).sum() / (n - 1.0);
+                let vy = wy.mapv(|v| (v - my).powi(2)).sum() / (n - 1.0);
+                let vxy = ((&wx - mx) * (&wy - my)).sum() / (n - 1.0);
+                values.push(((2.0 * mx * my + c1) * (2.0 * vxy + c2)) / ((mx * mx + my * my + c1) * (vx + vy + c2)));
+            }
+        }
+        values.iter().sum::<f64>() / values.len() as f64
+    }
+
+    #[test]
+    fn identical_volumes_match_perfectly() {
+        let data = ramp(8);
+        let result = compare(&data, &data, 0, &CancellationToken::new()).unwrap();
+        assert_eq!(result.data_range, 10.0);
+        assert_eq!(result.global.psnr, None);
+        assert_eq!(result.global.mae, 0.0);
+        assert_eq!(result.global.nmse, Some(0.0));
+        assert!((result.global.ssim - 1.0).abs() < 1e-9);
+        assert_eq!(result.slices.len(), 8);
+        assert_eq!(result.difference_range, [0.0, 0.0]);
+    }
+
+    #[test]
+    fn constant_offset_gives_known_errors() {
+        let reference = ramp(8);
+        let data = &reference + 0.5;
+        let result = compare(&data, &reference, 2, &CancellationToken::new()).unwrap();
+        assert!((result.global.mae - 0.5).abs() < 1e-9);
+        let psnr = 10.0 * (100.0f64 / 0.25).log10();
+        assert!((result.global.psnr.unwrap() - psnr).abs() < 1e-6);
+        assert_eq!(result.difference_range, [0.5, 0.5]);
+    }
+
+    #[test]
+    fn summed_area_ssim_matches_the_definition() {
+        let x = Array2::from_shape_fn((12, 10), |(r, c)| ((r * 13 + c * 7) % 17) as f32);
+        let y = Array2::from_shape_fn((12, 10), |(r, c)| ((r * 5 + c * 11) % 19) as f32 * 0.8 + 1.0);
+        let fast = ssim(x.view(), y.view(), 17.0);
+        let naive = naive_ssim(x.view(), y.view(), 17.0);
+        assert!((fast - naive).abs() < 1e-9, "{} vs {}", fast, naive);
+        assert!(fast < 1.0);
+    }
+
+    #[test]
+    fn slices_smaller_than_the_window_use_a_smaller_one() {
+        let x = Array2::from_shape_fn((3, 4), |(r, c)| (r + c) as f32);
+        assert!((ssim(x.view(), x.view(), 5.0) - 1.0).abs() < 1e-9);
+        assert_eq!(ssim(Array2::<f32>::zeros((0, 4)).view(), Array2::<f32>::zeros((0, 4)).view(), 1.0), 1.0);
+    }
+}
@@ -0,0 +1,404 @@
+use shared::VolumeInfo;
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::SystemTime;
+
+use crate::filters::Filter;
+use crate::hdf5_reader::{version_hash, HDF5Volume};
+use crate::spectrum::Spectrum;
+
+/// Separator between a source id and a filter in derived volume ids
+pub const DERIVED_SEPARATOR: char = '~';
+
+/// How a derived volume is computed from its source
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operation {
+    /// Image filter applied to the source
+    Filter(Filter),
+    /// Voxel-wise absolute difference between the source and the volume with this id
+    AbsDiff(String),
+    /// Log-magnitude or phase of the source's centred FFT
+    Spectrum(Spectrum),
+}
+
+impl fmt::Display for Operation {
+    /// Canonical form used in derived volume ids, e.g. `gauss(1.5)`,
+    /// `absdiff(target_087)` or `fft(logmag)`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Operation::Filter(filter) => write!(f, "{}", filter),
+            Operation::AbsDiff(reference) => write!(f, "absdiff({})", reference),
+            Operation::Spectrum(spectrum) => write!(f, "{}", spectrum),
+        }
+    }
+}
+
+impl FromStr for Operation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Some(reference) = s.strip_prefix("absdiff(").and_then(|r| r.strip_suffix(')')) {
+            return Ok(Operation::AbsDiff(reference.to_string()));
+        }
+        if s.starts_with("fft(") || s.starts_with("fft2(") {
+            return s.parse().map(Operation::Spectrum);
+        }
+        s.parse().map(Operation::Filter)
+    }
+}
+
+impl Operation {
+    /// Memory held while computing the result for a volume described by
+    /// `info`, on top of the inputs
+    pub fn footprint(&self, info: &VolumeInfo) -> u64 {
+        let size = info.full_res_size;
+        match self {
+            // The result and its encoded bytes
+            Operation::Filter(_) | Operation::AbsDiff(_) => 2 * size,
+            // The complex transform, twice the size. The part is written over
+            // the source, and the transform shrinks to half before the
+            // sibling is encoded next to it.
+            Operation::Spectrum(Spectrum { slice_axis: None, .. }) => 2 * size,
+            // The encoded sibling and one slice's complex transform
+            Operation::Spectrum(Spectrum {
+                slice_axis: Some(axis), ..
+            }) => size + 2 * size / info.dimensions[*axis].max(1) as u64,
+        }
+    }
+}
+
+/// A volume computed on demand from another (file or derived) volume.
+/// Its id is `{source}~{operation}`, e.g. `target_087~gauss(1.5)`.
+pub struct DerivedVolume {
+    /// Id of the volume the operation is applied to
+    pub source: String,
+    pub operation: Operation,
+    /// Modification time of the newest underlying file
+    pub modified: SystemTime,
+    info: VolumeInfo,
+    /// Value range of the filtered data, known once it has been computed
+    value_range: OnceLock<[f32; 2]>,
+}
+
+impl DerivedVolume {
+    /// `reference` is the second input of a difference, whose version is
+    /// part of the derived volume's version
+    pub fn new(
+        source: &VolumeInfo,
+        modified: SystemTime,
+        operation: Operation,
+        reference: Option<&VolumeInfo>,
+    ) -> Self {
+        let id = format!("{}{}{}", source.id, DERIVED_SEPARATOR, operation);
+        let reference_version = reference.map(|r| r.version.as_str()).unwrap_or_default();
+        let low_res_dimensions = HDF5Volume::downsampled_dims(source.dimensions, 64);
+        let low_res_voxels: u64 = low_res_dimensions.iter().map(|&d| d as u64).product();
+
+        let info = VolumeInfo {
+            name: format!("{} {} {}", source.name, DERIVED_SEPARATOR, operation),
+            dimensions: source.dimensions,
+            low_res_dimensions,
+            low_res_size: low_res_voxels * std::mem::size_of::<f32>() as u64,
+            full_res_size: source.full_res_size,
+            // Until the derived data has been computed: the spectrum's known
+            // range, or the source's for the others
+            value_range: match &operation {
+                Operation::Spectrum(spectrum) => spectrum.value_range(source),
+                Operation::Filter(_) | Operation::AbsDiff(_) => source.value_range,
+            },
+            spacing: source.spacing,
+            labels: Vec::new(),
+            // Computed from the first frame of time series
+            frames: 1,
+            frame_interval: None,
+            folder: source.folder.clone(),
+            tags: source.tags.clone(),
+            dtype: "f32".to_string(),
+            attributes: source.attributes.clone(),
+            version: version_hash(&format!(
+                "{}{}{}{}",
+                source.version, DERIVED_SEPARATOR, operation, reference_version
+            )),
+            id,
+        };
+
+        Self {
+            source: source.id.clone(),
+            operation,
+            modified,
+            info,
+            value_range: OnceLock::new(),
+        }
+    }
+
+    pub fn id(&self) -> &str {
+        &self.info.id
+    }
+
+    /// Current metadata, with the real value range once it is known
+    pub fn info(&self) -> VolumeInfo {
+        let mut info = self.info.clone();
+        if let Some(range) = self.value_range.get() {
+            info.value_range = *range;
+        }
+        info
+    }
+
+    pub fn set_value_range(&self, range: [f32; 2]) {
+        let _ = self.value_range.set(range);
+    }
+}
+
+/// Derived volumes registered so far, at most `capacity` of them. Any id a
+/// client asks for is registered, so the least recently used are forgotten;
+/// they are registered again (and their data recomputed) when next asked for.
+pub struct DerivedRegistry {
+    capacity: usize,
+    inner: Mutex<RegistryInner>,
+}
+
+#[derive(Default)]
+struct RegistryInner {
+    /// Each volume with the tick of its last use
+    volumes: HashMap<String, (Arc<DerivedVolume>, u64)>,
+    tick: u64,
+}
+
+impl DerivedRegistry {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            inner: Mutex::new(RegistryInner::default()),
+        }
+    }
+
+    pub fn get(&self, id: &str) -> Option<Arc<DerivedVolume>> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.tick += 1;
+        let tick = inner.tick;
+        let (volume, last_used) = inner.volumes.get_mut(id)?;
+        *last_used = tick;
+        Some(volume.clone())
+    }
+
+    /// Register `volume`, or return the one already registered with its id
+    pub fn insert(&self, volume: DerivedVolume) -> Arc<DerivedVolume> {
+        if let Some(existing) = self.get(volume.id()) {
+            return existing;
+        }
+
+        let mut inner = self.inner.lock().unwrap();
+        if inner.volumes.len() >= self.capacity {
+            let oldest = inner
+                .volumes
+                .iter()
+                .min_by_key(|(_, (_, last_used))| *last_used)
+                .map(|(id, _)| id.clone());
+            if let Some(oldest) = oldest {
+                inner.volumes.remove(&oldest);
+                tracing::debug!(volume = %oldest, "Forgot derived volume");
+            }
+        }
+        inner.tick += 1;
+        let tick = inner.tick;
+        tracing::info!(volume = volume.id(), "Registered derived volume");
+        let volume = Arc::new(volume);
+        inner.volumes.insert(volume.id().to_string(), (volume.clone(), tick));
+        volume
+    }
+
+    /// Current metadata of every registered volume
+    pub fn infos(&self) -> Vec<VolumeInfo> {
+        let inner = self.inner.lock().unwrap();
+        inner.volumes.values().map(|(volume, _)| volume.info()).collect()
+    }
+}
+
+/// Most operations in one derived id, counting those inside `absdiff` arguments
+pub const MAX_OPERATIONS: usize = 8;
+
+/// Deepest nesting of `absdiff` arguments in one derived id
+pub const MAX_ABSDIFF_NESTING: usize = 2;
+
+/// Reject ids with more operations or deeper `absdiff` nesting than the
+/// limits. Resolving an id recurses once per operation, so without a cap a
+/// single long id could overflow the stack.
+pub fn check_depth(id: &str) -> Result<(), String> {
+    let operations = id.matches(DERIVED_SEPARATOR).count();
+    if operations > MAX_OPERATIONS {
+        return Err(format!(
+            "Derived id has {} operations (at most {})",
+            operations, MAX_OPERATIONS
+        ));
+    }
+
+    // Whether each open parenthesis belongs to an `absdiff`
+    let mut open = Vec::new();
+    let (mut depth, mut nesting) = (0, 0);
+    for (i, c) in id.char_indices() {
+        match c {
+            '(' => {
+                let absdiff = id[..i].ends_with("absdiff");
+                open.push(absdiff);
+                depth += absdiff as usize;
+                nesting = nesting.max(depth);
+            }
+            ')' => depth -= open.pop().unwrap_or(false) as usize,
+            _ => {}
+        }
+    }
+    if nesting > MAX_ABSDIFF_NESTING {
+        return Err(format!(
+            "Derived id nests absdiff {} deep (at most {})",
+            nesting, MAX_ABSDIFF_NESTING
+        ));
+    }
+    Ok(())
+}
+
+/// Split a derived id into its source id and operation, if it names one.
+/// Ids over the limits of `check_depth` have an invalid operation.
+pub fn parse_derived_id(id: &str) -> Option<(&str, Result<Operation, String>)> {
+    // Arguments may themselves be derived ids, e.g. `a~absdiff(b~gauss(1))`,
+    // so split before the operation whose parentheses close the id
+    let end = if id.ends_with(')') {
+        matching_paren(id)?
+    } else {
+        id.len()
+    };
+    let (source, _) = id[..end].rsplit_once(DERIVED_SEPARATOR)?;
+    Some((source, check_depth(id).and_then(|_| id[source.len() + 1..].parse())))
+}
+
+/// Byte index of the `(` matching the final `)` of `s`
+fn matching_paren(s: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in s.char_indices().rev() {
+        match c {
+            ')' => depth += 1,
+            '(' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source(id: &str) -> VolumeInfo {
+        VolumeInfo {
+            id: id.to_string(),
+            dimensions: [4, 4, 4],
+            full_res_size: 256,
+            value_range: [0.0, 1.0],
+            version: "v1".to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn derived(id: &str) -> DerivedVolume {
+        DerivedVolume::new(&source(id), SystemTime::UNIX_EPOCH, Operation::Filter(Filter::Laplacian), None)
+    }
+
+    #[test]
+    fn splits_derived_ids() {
+        let (source, operation) = parse_derived_id("target_087~gauss(1.5)").unwrap();
+        assert_eq!(source, "target_087");
+        assert_eq!(operation, Ok(Operation::Filter(Filter::Gaussian(1.5))));
+
+        // Chains split at the last operation
+        let (source, operation) = parse_derived_id("a~gauss(1)~laplacian").unwrap();
+        assert_eq!(source, "a~gauss(1)");
+        assert_eq!(operation, Ok(Operation::Filter(Filter::Laplacian)));
+
+        // Arguments may be derived ids themselves
+        let (source, operation) = parse_derived_id("a~absdiff(b~gauss(1))").unwrap();
+        assert_eq!(source, "a");
+        assert_eq!(operation, Ok(Operation::AbsDiff("b~gauss(1)".to_string())));
+
+        assert!(parse_derived_id("knee/case_01").is_none());
+        assert!(parse_derived_id("a~sharpen").unwrap().1.is_err());
+    }
+
+    #[test]
+    fn long_ids_are_rejected() {
+        let chain = |n| format!("a{}", "~laplacian".repeat(n));
+        assert!(check_depth(&chain(MAX_OPERATIONS)).is_ok());
+        assert!(check_depth(&chain(MAX_OPERATIONS + 1)).is_err());
+        assert!(parse_derived_id(&chain(MAX_OPERATIONS + 1)).unwrap().1.is_err());
+        // Long enough to overflow the stack if it were resolved
+        assert!(parse_derived_id(&chain(100_000)).unwrap().1.is_err());
+
+        let nested = |n| (0..n).fold("b".to_string(), |inner, _| format!("a~absdiff({})", inner));
+        assert!(check_depth(&nested(MAX_ABSDIFF_NESTING)).is_ok());
+        assert!(check_depth(&nested(MAX_ABSDIFF_NESTING + 1)).is_err());
+        // Other parentheses do not count as nesting
+        assert!(check_depth("a~absdiff(b~gauss(1)~median(1))").is_ok());
+    }
+
+    #[test]
+    fn ids_and_versions_follow_the_operation() {
+        let a = derived("a");
+        assert_eq!(a.id(), "a~laplacian");
+        let b = DerivedVolume::new(&source("a"), SystemTime::UNIX_EPOCH, Operation::Filter(Filter::Median(1)), None);
+        assert_ne!(a.info().version, b.info().version);
+    }
+
+    #[test]
+    fn value_range_is_the_sources_until_known() {
+        let volume = derived("a");
+        assert_eq!(volume.info().value_range, [0.0, 1.0]);
+        volume.set_value_range([-2.0, 3.0]);
+        assert_eq!(volume.info().value_range, [-2.0, 3.0]);
+
+        // Spectra know theirs up front
+        let phase = Operation::Spectrum("fft(phase)".parse().unwrap());
+        let volume = DerivedVolume::new(&source("a"), SystemTime::UNIX_EPOCH, phase, None);
+        assert_eq!(volume.info().value_range, [-std::f32::consts::PI, std::f32::consts::PI]);
+    }
+
+    #[test]
+    fn spectra_fit_the_default_budget() {
+        // A full-size sample scan, 512 x 512 x 160 f32
+        let scan = VolumeInfo {
+            dimensions: [512, 512, 160],
+            full_res_size: 512 * 512 * 160 * 4,
+            ..Default::default()
+        };
+        let footprint = |spec: &str| Operation::Spectrum(spec.parse().unwrap()).footprint(&scan);
+        let budget = crate::admission::DEFAULT_BUDGET_MB * 1024 * 1024;
+        assert_eq!(footprint("fft(logmag)"), 2 * scan.full_res_size);
+        assert!(footprint("fft(phase)") <= budget);
+        // Slice by slice holds one slice's transform instead of the volume's
+        assert_eq!(footprint("fft2(logmag,2)"), scan.full_res_size + 2 * 512 * 512 * 4);
+        assert!(footprint("fft2(phase,0)") < footprint("fft(phase)"));
+        assert_eq!(Operation::Filter(Filter::Laplacian).footprint(&scan), 2 * scan.full_res_size);
+    }
+
+    #[test]
+    fn registry_forgets_the_least_recently_used() {
+        let registry = DerivedRegistry::new(2);
+        let first = registry.insert(derived("a"));
+        // Registering the same id again returns the existing volume
+        assert!(Arc::ptr_eq(&first, &registry.insert(derived("a"))));
+
+        registry.insert(derived("b"));
+        registry.get("a~laplacian");
+        registry.insert(derived("c"));
+
+        assert!(registry.get("a~laplacian").is_some());
+        assert!(registry.get("b~laplacian").is_none());
+        assert!(registry.get("c~laplacian").is_some());
+        assert_eq!(registry.infos().len(), 2);
+    }
+}
@@ -0,0 +1,70 @@
+use axum::{
+    http::{header, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use shared::ErrorResponse;
+
+use crate::admission::{Rejection, RETRY_AFTER_SECS};
+use crate::hdf5_reader::HDF5Error;
+
+/// Errors returned by the API routes, rendered as `ErrorResponse` JSON
+#[derive(Debug)]
+pub enum ApiError {
+    /// No volume with this id
+    NotFound(String),
+    /// The request parameters are invalid
+    BadRequest(String),
+    /// Reading or processing the volume failed
+    Read(HDF5Error),
+    /// The server is at capacity; the client should retry later, unless the
+    /// request alone is larger than the memory budget
+    Overloaded(Rejection),
+    /// No annotation with this id on the volume
+    AnnotationNotFound(u64),
+    /// Reading or writing the annotation store failed
+    Storage(std::io::Error),
+}
+
+impl From<HDF5Error> for ApiError {
+    fn from(e: HDF5Error) -> Self {
+        ApiError::Read(e)
+    }
+}
+
+impl From<Rejection> for ApiError {
+    fn from(r: Rejection) -> Self {
+        ApiError::Overloaded(r)
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let (status, error) = match &self {
+            ApiError::NotFound(id) => (StatusCode::NOT_FOUND, format!("Volume '{}' not found", id)),
+            ApiError::BadRequest(message) => (StatusCode::BAD_REQUEST, message.clone()),
+            // Only seen by a client whose newer request replaced this one
+            ApiError::Read(HDF5Error::Cancelled) => (StatusCode::CONFLICT, "Request was superseded".to_string()),
+            ApiError::Read(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to read volume: {}", e)),
+            // Retrying cannot help a request larger than the whole budget
+            ApiError::Overloaded(Rejection::TooLarge) => (
+                StatusCode::INSUFFICIENT_STORAGE,
+                format!("Not enough server memory: {}", Rejection::TooLarge),
+            ),
+            ApiError::Overloaded(r) => (StatusCode::SERVICE_UNAVAILABLE, format!("Server busy: {}", r)),
+            ApiError::AnnotationNotFound(id) => (StatusCode::NOT_FOUND, format!("Annotation {} not found", id)),
+            ApiError::Storage(e) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to access annotations: {}", e),
+            ),
+        };
+
+        let mut response = (status, Json(ErrorResponse { error })).into_response();
+        if status == StatusCode::SERVICE_UNAVAILABLE {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, HeaderValue::from(RETRY_AFTER_SECS));
+        }
+        response
+    }
+}
@@ -0,0 +1,328 @@
+use ndarray::{s, Array3, Zip};
+use std::ops::Range;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio_util::sync::CancellationToken;
+
+use crate::filters::check;
+use crate::hdf5_reader::{HDF5Error, HDF5Volume};
+
+pub use shared::ExportFormat;
+
+/// Largest dimension a NIfTI-1 header can hold
+const NIFTI_MAX_DIM: u32 = i16::MAX as u32;
+
+/// Parse a voxel box `x0:x1,y0:y1,z0:z1` (half-open, full-res coordinates)
+pub fn parse_crop(crop: &str, dims: [u32; 3]) -> Result<[Range<usize>; 3], String> {
+    let ranges: Vec<&str> = crop.split(',').collect();
+    if ranges.len() != 3 {
+        return Err(format!("crop must be x0:x1,y0:y1,z0:z1, got '{}'", crop));
+    }
+
+    let mut result: [Range<usize>; 3] = Default::default();
+    for (axis, range) in ranges.iter().enumerate() {
+        let (start, end) = range
+            .split_once(':')
+            .and_then(|(a, b)| Some((a.trim().parse::<usize>().ok()?, b.trim().parse::<usize>().ok()?)))
+            .ok_or_else(|| format!("Invalid crop range '{}'", range))?;
+        if start >= end || end > dims[axis] as usize {
+            return Err(format!(
+                "Crop range {}..{} on axis {} must be non-empty and within 0..{}",
+                start, end, axis, dims[axis]
+            ));
+        }
+        result[axis] = start..end;
+    }
+    Ok(result)
+}
+
+/// Dimensions `Export::prepare` produces from a volume of `dims`
+pub fn output_dims(dims: [u32; 3], crop: Option<&[Range<usize>; 3]>, resolution: Option<usize>) -> [u32; 3] {
+    let dims = match crop {
+        Some(crop) => std::array::from_fn(|axis| crop[axis].len() as u32),
+        None => dims,
+    };
+    match resolution {
+        Some(resolution) => HDF5Volume::downsampled_dims(dims, resolution),
+        None => dims,
+    }
+}
+
+/// Check that `format` can hold a volume of `dims`
+pub fn check_dims(format: ExportFormat, dims: [u32; 3]) -> Result<(), String> {
+    if format == ExportFormat::Nifti && dims.iter().any(|&d| d > NIFTI_MAX_DIM) {
+        return Err(format!(
+            "NIfTI dimensions are limited to {}, got {:?}; crop or pass a resolution",
+            NIFTI_MAX_DIM, dims
+        ));
+    }
+    Ok(())
+}
+
+/// A volume ready to be written, with its geometry in millimetres
+pub struct Export {
+    data: Array3<f32>,
+    spacing: [f32; 3],
+    /// Position of the first voxel; non-zero after cropping
+    origin: [f32; 3],
+}
+
+impl Export {
+    /// Mask, crop and downsample `data` in that order. Voxels whose label is
+    /// not `mask.1` are set to zero. `resolution` is the target size of the
+    /// largest dimension, as for `/at/{resolution}`.
+    pub fn prepare(
+        mut data: Array3<f32>,
+        spacing: [f32; 3],
+        mask: Option<(Array3<u16>, u32)>,
+        crop: Option<[Range<usize>; 3]>,
+        resolution: Option<usize>,
+        cancel: &CancellationToken,
+    ) -> Result<Self, HDF5Error> {
+        if let Some((labels, value)) = mask {
+            check(cancel)?;
+            Zip::from(&mut data).and(&labels).for_each(|v, &label| {
+                if label as u32 != value {
+                    *v = 0.0;
+                }
+            });
+        }
+
+        let mut origin = [0.0; 3];
+        if let Some([x, y, z]) = crop {
+            origin = [x.start as f32 * spacing[0], y.start as f32 * spacing[1], z.start as f32 * spacing[2]];
+            data = data.slice(s![x, y, z]).to_owned();
+        }
+
+        let mut spacing = spacing;
+        if let Some(resolution) = resolution {
+            // Downsampling keeps every factor-th voxel on every axis
+            let factor = HDF5Volume::downsample_factor(data.shape(), resolution) as f32;
+            data = HDF5Volume::downsample(&data, resolution, cancel)?;
+            spacing = spacing.map(|s| s * factor);
+        }
+
+        Ok(Self { data, spacing, origin })
+    }
+
+    pub fn dims(&self) -> [u32; 3] {
+        let (x, y, z) = self.data.dim();
+        [x as u32, y as u32, z as u32]
+    }
+
+    pub fn spacing(&self) -> [f32; 3] {
+        self.spacing
+    }
+
+    /// Encode in `format`. `name` is the file stem, used by the MHD header
+    /// to refer to its data file.
+    pub fn encode(&self, format: ExportFormat, name: &str) -> Result<Vec<u8>, HDF5Error> {
+        Ok(match format {
+            ExportFormat::Nifti => self.to_nifti()?,
+            ExportFormat::Npy => self.to_npy(),
+            ExportFormat::Mha => {
+                let mut out = self.meta_header("LOCAL").into_bytes();
+                out.extend(self.fortran_bytes());
+                out
+            }
+            ExportFormat::Mhd => self.meta_header(&format!("{}.raw", name)).into_bytes(),
+            ExportFormat::Raw => self.fortran_bytes(),
+            ExportFormat::Hdf5 => self.to_hdf5()?,
+        })
+    }
+
+    /// Voxels with x varying fastest, as NIfTI and MetaImage store them
+    fn fortran_bytes(&self) -> Vec<u8> {
+        self.data.t().iter().flat_map(|v| v.to_le_bytes()).collect()
+    }
+
+    fn to_nifti(&self) -> Result<Vec<u8>, HDF5Error> {
+        const HEADER_SIZE: usize = 348;
+        // Header plus the 4-byte extension flag
+        const VOX_OFFSET: usize = HEADER_SIZE + 4;
+
+        let mut header = vec![0u8; VOX_OFFSET];
+        let mut put = |offset: usize, bytes: &[u8]| header[offset..offset + bytes.len()].copy_from_slice(bytes);
+        check_dims(ExportFormat::Nifti, self.dims()).map_err(HDF5Error::Format)?;
+        let [x, y, z] = self.dims().map(|d| d as i16);
+        let [sx, sy, sz] = self.spacing;
+        let [ox, oy, oz] = self.origin;
+
+        put(0, &(HEADER_SIZE as i32).to_le_bytes());
+        put(38, b"r");
+        for (i, dim) in [3, x, y, z, 1, 1, 1, 1].iter().enumerate() {
+            put(40 + 2 * i, &dim.to_le_bytes());
+        }
+        put(70, &16i16.to_le_bytes()); // DT_FLOAT32
+        put(72, &32i16.to_le_bytes()); // bitpix
+        for (i, pixdim) in [1.0f32, sx, sy, sz].iter().enumerate() {
+            put(76 + 4 * i, &pixdim.to_le_bytes());
+        }
+        put(108, &(VOX_OFFSET as f32).to_le_bytes());
+        put(112, &1.0f32.to_le_bytes()); // scl_slope
+        put(123, &[2]); // xyzt_units: millimetres
+        put(148, b"3DLab export");
+
+        // Axis-aligned qform and sform with the spacing and crop origin
+        put(252, &1i16.to_le_bytes());
+        put(254, &1i16.to_le_bytes());
+        for (i, offset) in [ox, oy, oz].iter().enumerate() {
+            put(268 + 4 * i, &offset.to_le_bytes());
+        }
+        let srows = [[sx, 0.0, 0.0, ox], [0.0, sy, 0.0, oy], [0.0, 0.0, sz, oz]];
+        for (row, values) in srows.iter().enumerate() {
+            for (i, value) in values.iter().enumerate() {
+                put(280 + 16 * row + 4 * i, &value.to_le_bytes());
+            }
+        }
+        put(344, b"n+1\0");
+
+        header.extend(self.fortran_bytes());
+        Ok(header)
+    }
+
+    fn to_npy(&self) -> Vec<u8> {
+        let [x, y, z] = self.dims();
+        let mut dict = format!(
+            "{{'descr': '<f4', 'fortran_order': False, 'shape': ({}, {}, {}), }}",
+            x, y, z
+        );
+        // Magic, version and length take 10 bytes; the header ends in a newline
+        // and the data starts on a 64-byte boundary
+        let padded = (10 + dict.len() + 1).next_multiple_of(64) - 10 - 1;
+        dict.push_str(&" ".repeat(padded - dict.len()));
+        dict.push('\n');
+
+        let mut out = Vec::with_capacity(10 + dict.len() + self.data.len() * 4);
+        out.extend_from_slice(b"\x93NUMPY\x01\x00");
+        out.extend_from_slice(&(dict.len() as u16).to_le_bytes());
+        out.extend_from_slice(dict.as_bytes());
+        out.extend(HDF5Volume::to_bytes(&self.data));
+        out
+    }
+
+    fn meta_header(&self, data_file: &str) -> String {
+        let [x, y, z] = self.dims();
+        let [sx, sy, sz] = self.spacing;
+        let [ox, oy, oz] = self.origin;
+        format!(
+            "ObjectType = Image\n\
+             NDims = 3\n\
+             BinaryData = True\n\
+             BinaryDataByteOrderMSB = False\n\
+             CompressedData = False\n\
+             TransformMatrix = 1 0 0 0 1 0 0 0 1\n\
+             Offset = {} {} {}\n\
+             AnatomicalOrientation = RAI\n\
+             ElementSpacing = {} {} {}\n\
+             DimSize = {} {} {}\n\
+             ElementType = MET_FLOAT\n\
+             ElementDataFile = {}\n",
+            ox, oy, oz, sx, sy, sz, x, y, z, data_file
+        )
+    }
+
+    /// The HDF5 library only writes to files, so go through a temporary one
+    fn to_hdf5(&self) -> Result<Vec<u8>, HDF5Error> {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "3dlab-export-{}-{}.h5",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+
+        let written = (|| {
+            let file = hdf5::File::create(&path)?;
+            let dataset = file.new_dataset_builder().with_data(&self.data).create("data")?;
+            dataset.new_attr_builder().with_data(&self.spacing[..]).create("spacing")?;
+            dataset.new_attr_builder().with_data(&self.origin[..]).create("origin")?;
+            Ok::<_, hdf5::Error>(())
+        })();
+        let result = written
+            .map_err(HDF5Error::from)
+            .and_then(|_| Ok(std::fs::read(&path)?));
+        let _ = std::fs::remove_file(&path);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ramp(dims: (usize, usize, usize)) -> Array3<f32> {
+        Array3::from_shape_fn(dims, |(x, y, z)| (x * 100 + y * 10 + z) as f32)
+    }
+
+    #[test]
+    fn crops_parse_and_are_checked() {
+        assert_eq!(parse_crop("1:3, 0:4 ,2:5", [4, 4, 8]).unwrap(), [1..3, 0..4, 2..5]);
+        assert!(parse_crop("1:3,0:4", [4, 4, 8]).is_err());
+        assert!(parse_crop("3:3,0:4,0:1", [4, 4, 8]).is_err());
+        assert!(parse_crop("0:5,0:4,0:1", [4, 4, 8]).is_err());
+        assert!(parse_crop("a:1,0:4,0:1", [4, 4, 8]).is_err());
+    }
+
+    #[test]
+    fn mask_then_crop_sets_the_origin() {
+        let labels = Array3::from_shape_fn((4, 4, 4), |(x, _, _)| if x >= 2 { 1u16 } else { 2 });
+        let (mask, crop) = (Some((labels, 1)), Some([1..3, 0..2, 2..4]));
+        let cancel = CancellationToken::new();
+        let export = Export::prepare(ramp((4, 4, 4)), [0.5, 1.0, 2.0], mask, crop, None, &cancel).unwrap();
+        assert_eq!(export.dims(), [2, 2, 2]);
+        assert_eq!(export.origin, [0.5, 0.0, 4.0]);
+        assert_eq!(export.data[[0, 0, 0]], 0.0);
+        assert_eq!(export.data[[1, 1, 1]], 213.0);
+    }
+
+    #[test]
+    fn downsampling_scales_every_axis_by_the_same_factor() {
+        // The factor comes from the largest axis, not from the first
+        let dims = (6, 16, 16);
+        let export = Export::prepare(ramp(dims), [1.0, 1.0, 2.0], None, None, Some(4), &CancellationToken::new()).unwrap();
+        assert_eq!(export.dims(), [1, 4, 4]);
+        assert_eq!(export.spacing(), [4.0, 4.0, 8.0]);
+        assert_eq!(output_dims([6, 16, 16], None, Some(4)), export.dims());
+        assert_eq!(output_dims([6, 16, 16], Some(&[0..2, 0..8, 0..3]), None), [2, 8, 3]);
+    }
+
+    #[test]
+    fn nifti_header_holds_the_geometry() {
+        let export = Export::prepare(ramp((2, 3, 4)), [0.5, 1.0, 2.0], None, None, None, &CancellationToken::new()).unwrap();
+        let nifti = export.encode(ExportFormat::Nifti, "case").unwrap();
+        let i16_at = |o: usize| i16::from_le_bytes([nifti[o], nifti[o + 1]]);
+        let f32_at = |o: usize| f32::from_le_bytes(nifti[o..o + 4].try_into().unwrap());
+        assert_eq!([i16_at(40), i16_at(42), i16_at(44), i16_at(46)], [3, 2, 3, 4]);
+        assert_eq!([f32_at(80), f32_at(84), f32_at(88)], [0.5, 1.0, 2.0]);
+        assert_eq!(&nifti[344..348], b"n+1\0");
+        assert_eq!(nifti.len(), 352 + 24 * 4);
+        // x varies fastest: the second voxel is x = 1
+        assert_eq!(f32_at(356), 100.0);
+    }
+
+    #[test]
+    fn nifti_rejects_dimensions_its_header_cannot_hold() {
+        assert!(check_dims(ExportFormat::Nifti, [32768, 1, 1]).is_err());
+        assert!(check_dims(ExportFormat::Nifti, [32767, 1, 1]).is_ok());
+        assert!(check_dims(ExportFormat::Npy, [40000, 1, 1]).is_ok());
+    }
+
+    #[test]
+    fn npy_data_starts_on_a_64_byte_boundary() {
+        let export = Export::prepare(ramp((2, 3, 4)), [1.0; 3], None, None, None, &CancellationToken::new()).unwrap();
+        let npy = export.encode(ExportFormat::Npy, "case").unwrap();
+        assert_eq!(&npy[..8], b"\x93NUMPY\x01\x00");
+        let header_len = u16::from_le_bytes([npy[8], npy[9]]) as usize;
+        assert_eq!((10 + header_len) % 64, 0);
+        assert!(std::str::from_utf8(&npy[10..10 + header_len]).unwrap().contains("'shape': (2, 3, 4)"));
+        assert_eq!(npy.len(), 10 + header_len + 24 * 4);
+    }
+
+    #[test]
+    fn meta_image_header_names_its_data_file() {
+        let export = Export::prepare(ramp((2, 3, 4)), [1.0; 3], None, None, None, &CancellationToken::new()).unwrap();
+        let mhd = String::from_utf8(export.encode(ExportFormat::Mhd, "case").unwrap()).unwrap();
+        assert!(mhd.contains("DimSize = 2 3 4\n"));
+        assert!(mhd.ends_with("ElementDataFile = case.raw\n"));
+        assert_eq!(export.encode(ExportFormat::Raw, "case").unwrap().len(), 24 * 4);
+    }
+}
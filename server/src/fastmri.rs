@@ -0,0 +1,168 @@
+//! fastMRI raw data: multi- or single-coil `kspace`, the stored
+//! `reconstruction_rss` (or `reconstruction_esc`) and an `ismrmrd_header`.
+//! Each file is served as its stored reconstruction and a zero-filled one
+//! computed from the k-space.
+
+use hdf5::types::{FixedAscii, VarLenAscii, VarLenUnicode};
+use hdf5::{Dataset, File};
+use ndarray::{s, Array3, Axis, Ix3};
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::path::Path;
+
+use crate::fft;
+use crate::hdf5_reader::{Complex32, HDF5Error};
+
+/// Complex k-space, (slices, coils, kx, ky) or (slices, kx, ky) for single-coil data
+pub const KSPACE: &str = "kspace";
+
+/// Stored reconstructions: root-sum-of-squares of multi-coil data, or the
+/// emulated single-coil one
+pub const STORED: [&str; 2] = ["reconstruction_rss", "reconstruction_esc"];
+
+/// ISMRMRD XML header
+const HEADER: &str = "ismrmrd_header";
+
+/// Longest header read from a fixed-length string dataset
+const MAX_HEADER_BYTES: usize = 1 << 16;
+
+/// Header fields copied into the volume attributes, by element path
+const HEADER_FIELDS: [(&str, &[&str]); 8] = [
+    ("protocol_name", &["measurementInformation", "protocolName"]),
+    ("system_model", &["acquisitionSystemInformation", "systemModel"]),
+    ("field_strength_t", &["acquisitionSystemInformation", "systemFieldStrength_T"]),
+    ("receiver_channels", &["acquisitionSystemInformation", "receiverChannels"]),
+    ("tr_ms", &["sequenceParameters", "TR"]),
+    ("te_ms", &["sequenceParameters", "TE"]),
+    ("ti_ms", &["sequenceParameters", "TI"]),
+    ("flip_angle_deg", &["sequenceParameters", "flipAngle_deg"]),
+];
+
+/// Which reconstruction of a fastMRI file a volume shows
+#[derive(Debug, Clone, PartialEq)]
+pub enum Reconstruction {
+    /// A reconstruction dataset stored in the file
+    Stored(String),
+    /// Centred inverse 2D FFT of each coil's k-space, combined by
+    /// root-sum-of-squares and cropped to `crop` (x, y) about the centre
+    ZeroFilled { crop: [usize; 2] },
+}
+
+impl Reconstruction {
+    /// Name of the volume within its file, the last part of its id
+    pub fn name(&self) -> &str {
+        match self {
+            Reconstruction::Stored(dataset) => dataset,
+            Reconstruction::ZeroFilled { .. } => "zero_filled",
+        }
+    }
+}
+
+/// Whether the HDF5 file at `path` has the fastMRI layout
+pub fn is_fastmri(path: &Path) -> bool {
+    File::open(path).is_ok_and(|file| file.dataset(KSPACE).is_ok())
+}
+
+/// The ISMRMRD header of a file, searched by element path
+pub struct Header {
+    xml: String,
+}
+
+impl Header {
+    /// The header of a file, stored as a variable- or fixed-length string
+    pub fn read(file: &File) -> Option<Self> {
+        let dataset = file.dataset(HEADER).ok()?;
+        let xml = dataset
+            .read_scalar::<VarLenUnicode>()
+            .map(|v| v.as_str().to_string())
+            .or_else(|_| dataset.read_scalar::<VarLenAscii>().map(|v| v.as_str().to_string()))
+            .or_else(|_| dataset.read_scalar::<FixedAscii<MAX_HEADER_BYTES>>().map(|v| v.as_str().to_string()))
+            .ok()?;
+        Some(Self { xml })
+    }
+
+    /// Text of the first element at `path`, each element searched within the previous one
+    fn text(&self, path: &[&str]) -> Option<&str> {
+        let mut text = self.xml.as_str();
+        for name in path {
+            let open = format!("<{}>", name);
+            let close = format!("</{}>", name);
+            let start = text.find(&open)? + open.len();
+            let end = start + text[start..].find(&close)?;
+            text = &text[start..end];
+        }
+        Some(text.trim())
+    }
+
+    /// The x, y and z of a `matrixSize` or `fieldOfView_mm` of the first encoding
+    fn vector(&self, space: &str, field: &str) -> Option<[f64; 3]> {
+        let component = |axis| self.text(&["encoding", space, field, axis])?.parse().ok();
+        Some([component("x")?, component("y")?, component("z")?])
+    }
+
+    /// Reconstructed matrix size in x (readout) and y (phase encoding)
+    pub fn recon_matrix(&self) -> Option<[usize; 2]> {
+        let [x, y, _] = self.vector("reconSpace", "matrixSize")?;
+        (x >= 1.0 && y >= 1.0).then_some([x as usize, y as usize])
+    }
+
+    /// Voxel spacing along the volume axes (slice, x, y): the reconstructed
+    /// field of view over the matrix size, the slice thickness along the slices
+    pub fn spacing(&self) -> Option<[f32; 3]> {
+        let fov = self.vector("reconSpace", "fieldOfView_mm")?;
+        let matrix = self.vector("reconSpace", "matrixSize")?;
+        let spacing = [2, 0, 1].map(|axis| (fov[axis] / matrix[axis]) as f32);
+        spacing.iter().all(|s| s.is_finite() && *s > 0.0).then_some(spacing)
+    }
+
+    /// Matrix sizes, fields of view and acquisition parameters as volume attributes
+    pub fn attributes(&self) -> BTreeMap<String, String> {
+        let mut attributes = BTreeMap::new();
+        let vectors = [
+            ("matrix_size", "reconSpace", "matrixSize"),
+            ("fov_mm", "reconSpace", "fieldOfView_mm"),
+            ("encoded_matrix_size", "encodedSpace", "matrixSize"),
+            ("encoded_fov_mm", "encodedSpace", "fieldOfView_mm"),
+        ];
+        for (key, space, field) in vectors {
+            if let Some(vector) = self.vector(space, field) {
+                attributes.insert(key.to_string(), vector.map(|v| v.to_string()).join("x"));
+            }
+        }
+        for (key, path) in HEADER_FIELDS {
+            if let Some(text) = self.text(path).filter(|t| !t.is_empty()) {
+                attributes.insert(key.to_string(), text.to_string());
+            }
+        }
+        attributes
+    }
+}
+
+/// Zero-filled reconstruction of `slices`, read from the k-space one slice at a time
+pub fn zero_filled(kspace: &Dataset, slices: Range<usize>, crop: [usize; 2]) -> Result<Array3<f32>, HDF5Error> {
+    let mut result = Array3::zeros([slices.len(), crop[0], crop[1]]);
+    for (i, slice) in slices.enumerate() {
+        let coils: Array3<Complex32> = match kspace.shape().len() {
+            4 => kspace.read_slice(s![slice, .., .., ..])?,
+            _ => kspace.read_slice::<Complex32, _, Ix3>(s![slice..slice + 1, .., ..])?,
+        };
+        let mut image = coils.mapv(num_complex::Complex32::from);
+        let [_, rows, cols] = [image.shape()[0], image.shape()[1], image.shape()[2]];
+
+        // Crop the rows before the second transform, which then has less to do
+        fft::centered_axis(&mut image, 1, true);
+        let row = (rows - crop[0]) / 2;
+        let mut image = image.slice(s![.., row..row + crop[0], ..]).to_owned();
+        fft::centered_axis(&mut image, 2, true);
+        let col = (cols - crop[1]) / 2;
+
+        let sum_of_squares = image
+            .slice(s![.., .., col..col + crop[1]])
+            .mapv(|v| v.norm_sqr())
+            .sum_axis(Axis(0));
+        result
+            .index_axis_mut(Axis(0), i)
+            .assign(&sum_of_squares.mapv(f32::sqrt));
+    }
+    Ok(result)
+}
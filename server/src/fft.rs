@@ -0,0 +1,120 @@
+//! Centred, orthonormal discrete Fourier transforms along one axis of an
+//! array, planned by `rustfft` for any length
+
+use ndarray::{ArrayBase, Axis, DataMut, Dimension};
+use num_complex::Complex32;
+use rustfft::FftPlanner;
+
+/// Centred orthonormal transform of every lane of `data` along `axis`, the
+/// inverse transform when `inverse`. The zero frequency is in the middle of
+/// both input and output, as numpy's `fftshift(fft(ifftshift(x)))`, and both
+/// directions scale by 1/sqrt(n).
+pub fn centered_axis<S, D>(data: &mut ArrayBase<S, D>, axis: usize, inverse: bool)
+where
+    S: DataMut<Elem = Complex32>,
+    D: Dimension,
+{
+    let len = data.len_of(Axis(axis));
+    if len == 0 {
+        return;
+    }
+    let mut planner = FftPlanner::new();
+    let fft = if inverse {
+        planner.plan_fft_inverse(len)
+    } else {
+        planner.plan_fft_forward(len)
+    };
+    let mut buffer = vec![Complex32::default(); len];
+    let mut scratch = vec![Complex32::default(); fft.get_inplace_scratch_len()];
+    let scale = 1.0 / (len as f32).sqrt();
+    for mut lane in data.lanes_mut(Axis(axis)) {
+        buffer.iter_mut().zip(lane.iter()).for_each(|(b, v)| *b = *v);
+        buffer.rotate_left(len / 2);
+        fft.process_with_scratch(&mut buffer, &mut scratch);
+        buffer.rotate_right(len / 2);
+        lane.iter_mut().zip(&buffer).for_each(|(v, b)| *v = b * scale);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::{Array1, Array2};
+    use std::f64::consts::PI;
+
+    /// Some values without structure, the same on every run
+    fn signal(len: usize) -> Vec<Complex32> {
+        (0..len)
+            .map(|i| Complex32::new((i as f32 * 0.37).sin() + 0.5, (i as f32 * 1.3).cos() - 0.25 * (i % 3) as f32))
+            .collect()
+    }
+
+    /// Orthonormal DFT straight from the definition, with input and output
+    /// indices offset by `shift` (len / 2 for the centred transform)
+    fn naive(data: &[Complex32], inverse: bool, shift: usize) -> Vec<Complex32> {
+        let n = data.len() as i64;
+        let sign = if inverse { 1.0 } else { -1.0 };
+        (0..n)
+            .map(|k| {
+                let (mut re, mut im) = (0.0f64, 0.0f64);
+                for (j, v) in data.iter().enumerate() {
+                    let angle = sign * 2.0 * PI * ((j as i64 - shift as i64) * (k - shift as i64)) as f64 / n as f64;
+                    re += v.re as f64 * angle.cos() - v.im as f64 * angle.sin();
+                    im += v.re as f64 * angle.sin() + v.im as f64 * angle.cos();
+                }
+                let scale = 1.0 / (n as f64).sqrt();
+                Complex32::new((re * scale) as f32, (im * scale) as f32)
+            })
+            .collect()
+    }
+
+    fn assert_close(actual: &[Complex32], expected: &[Complex32]) {
+        assert_eq!(actual.len(), expected.len());
+        for (i, (a, e)) in actual.iter().zip(expected).enumerate() {
+            assert!((a - e).norm() < 1e-4, "element {}: {} != {}", i, a, e);
+        }
+    }
+
+    /// Centred transform of one vector
+    fn centered(data: &[Complex32], inverse: bool) -> Vec<Complex32> {
+        let mut data = Array1::from(data.to_vec());
+        centered_axis(&mut data, 0, inverse);
+        data.to_vec()
+    }
+
+    const LENGTHS: [usize; 10] = [1, 2, 3, 5, 8, 12, 64, 100, 128, 317];
+
+    #[test]
+    fn matches_the_definition() {
+        for len in LENGTHS {
+            for inverse in [false, true] {
+                assert_close(&centered(&signal(len), inverse), &naive(&signal(len), inverse, len / 2));
+            }
+        }
+    }
+
+    #[test]
+    fn round_trips() {
+        for len in LENGTHS {
+            assert_close(&centered(&centered(&signal(len), false), true), &signal(len));
+        }
+    }
+
+    #[test]
+    fn centres_zero_frequency() {
+        // A constant has all its energy at the centre
+        let data = centered(&[Complex32::new(1.0, 0.0); 9], false);
+        assert!((data[4].re - 3.0).abs() < 1e-5);
+        assert!(data.iter().enumerate().all(|(i, v)| i == 4 || v.norm() < 1e-5));
+    }
+
+    #[test]
+    fn transforms_each_lane() {
+        let rows: Vec<Vec<Complex32>> = (0..3).map(|r| signal(6 + r).into_iter().skip(r).collect()).collect();
+        let mut data = Array2::from_shape_fn((3, 6), |(r, c)| rows[r][c]);
+        centered_axis(&mut data, 1, true);
+        for (r, row) in rows.iter().enumerate() {
+            assert_close(data.row(r).as_slice().unwrap(), &naive(row, true, 3));
+        }
+    }
+}
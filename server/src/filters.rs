@@ -0,0 +1,344 @@
+use ndarray::{Array3, Axis};
+use std::fmt;
+use std::str::FromStr;
+use tokio_util::sync::CancellationToken;
+
+use crate::hdf5_reader::HDF5Error;
+
+/// Largest median radius accepted (the window is (2r+1)^3 voxels)
+const MAX_MEDIAN_RADIUS: usize = 3;
+
+/// Largest Gaussian sigma accepted, in voxels
+const MAX_GAUSSIAN_SIGMA: f32 = 16.0;
+
+/// Image filter producing a derived volume of the same dimensions
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Filter {
+    /// Separable Gaussian blur with the given sigma (voxels)
+    Gaussian(f32),
+    /// Median over a cube of the given radius
+    Median(usize),
+    /// Perona-Malik anisotropic diffusion. `kappa` is the edge threshold as
+    /// a fraction of the value range.
+    Diffusion { iterations: usize, kappa: f32 },
+    /// Central-difference gradient magnitude
+    GradientMagnitude,
+    /// 6-neighbour discrete Laplacian
+    Laplacian,
+}
+
+impl fmt::Display for Filter {
+    /// Canonical form used in derived volume ids, e.g. `gauss(1.5)`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Filter::Gaussian(sigma) => write!(f, "gauss({})", sigma),
+            Filter::Median(radius) => write!(f, "median({})", radius),
+            Filter::Diffusion { iterations, kappa } => write!(f, "diffusion({},{})", iterations, kappa),
+            Filter::GradientMagnitude => write!(f, "gradmag"),
+            Filter::Laplacian => write!(f, "laplacian"),
+        }
+    }
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (name, args) = match s.split_once('(') {
+            Some((name, rest)) => {
+                let args = rest
+                    .strip_suffix(')')
+                    .ok_or_else(|| format!("Missing ')' in filter '{}'", s))?;
+                (name, args.split(',').map(str::trim).collect::<Vec<_>>())
+            }
+            None => (s, Vec::new()),
+        };
+
+        // Missing or empty arguments fall back to their defaults
+        let arg = |i: usize, default: &str| -> String {
+            args.get(i)
+                .copied()
+                .filter(|a| !a.is_empty())
+                .unwrap_or(default)
+                .to_string()
+        };
+        let bad = |a: &str| format!("Invalid argument '{}' for filter '{}'", a, name);
+
+        let filter = match name {
+            "gauss" | "gaussian" => {
+                let a = arg(0, "1");
+                let sigma: f32 = a.parse().map_err(|_| bad(&a))?;
+                if !(sigma > 0.0 && sigma <= MAX_GAUSSIAN_SIGMA) {
+                    return Err(format!("Sigma must be in (0, {}]", MAX_GAUSSIAN_SIGMA));
+                }
+                Filter::Gaussian(sigma)
+            }
+            "median" => {
+                let a = arg(0, "1");
+                let radius: usize = a.parse().map_err(|_| bad(&a))?;
+                if radius == 0 || radius > MAX_MEDIAN_RADIUS {
+                    return Err(format!("Median radius must be in 1..={}", MAX_MEDIAN_RADIUS));
+                }
+                Filter::Median(radius)
+            }
+            "diffusion" => {
+                let a = arg(0, "10");
+                let iterations: usize = a.parse().map_err(|_| bad(&a))?;
+                let b = arg(1, "0.05");
+                let kappa: f32 = b.parse().map_err(|_| bad(&b))?;
+                if iterations == 0 || iterations > 100 || kappa <= 0.0 {
+                    return Err("Diffusion needs 1..=100 iterations and kappa > 0".to_string());
+                }
+                Filter::Diffusion { iterations, kappa }
+            }
+            "gradmag" | "gradient" => Filter::GradientMagnitude,
+            "laplacian" => Filter::Laplacian,
+            _ => return Err(format!("Unknown filter '{}'", name)),
+        };
+        Ok(filter)
+    }
+}
+
+impl Filter {
+    /// Names of the available filters with their argument syntax
+    pub const SYNTAX: [&'static str; 5] = [
+        "gauss(sigma)",
+        "median(radius)",
+        "diffusion(iterations,kappa)",
+        "gradmag",
+        "laplacian",
+    ];
+
+    /// Apply the filter. Checks `cancel` between slabs.
+    pub fn apply(&self, data: &Array3<f32>, cancel: &CancellationToken) -> Result<Array3<f32>, HDF5Error> {
+        match *self {
+            Filter::Gaussian(sigma) => gaussian(data, sigma, cancel),
+            Filter::Median(radius) => median(data, radius, cancel),
+            Filter::Diffusion { iterations, kappa } => diffusion(data, iterations, kappa, cancel),
+            Filter::GradientMagnitude => gradient_magnitude(data, cancel),
+            Filter::Laplacian => laplacian(data, cancel),
+        }
+    }
+}
+
+pub(crate) fn check(cancel: &CancellationToken) -> Result<(), HDF5Error> {
+    if cancel.is_cancelled() {
+        Err(HDF5Error::Cancelled)
+    } else {
+        Ok(())
+    }
+}
+
+/// Value at (x, y, z) with coordinates clamped to the volume edges
+fn clamped(data: &Array3<f32>, x: isize, y: isize, z: isize) -> f32 {
+    let s = data.shape();
+    let cx = x.clamp(0, s[0] as isize - 1) as usize;
+    let cy = y.clamp(0, s[1] as isize - 1) as usize;
+    let cz = z.clamp(0, s[2] as isize - 1) as usize;
+    data[[cx, cy, cz]]
+}
+
+fn gaussian(data: &Array3<f32>, sigma: f32, cancel: &CancellationToken) -> Result<Array3<f32>, HDF5Error> {
+    let radius = (3.0 * sigma).ceil() as isize;
+    let mut kernel: Vec<f32> = (-radius..=radius)
+        .map(|i| (-(i * i) as f32 / (2.0 * sigma * sigma)).exp())
+        .collect();
+    let sum: f32 = kernel.iter().sum();
+    kernel.iter_mut().for_each(|k| *k /= sum);
+
+    // Separable: convolve along each axis in turn
+    let mut current = data.clone();
+    for axis in 0..3 {
+        let len = current.len_of(Axis(axis)) as isize;
+        let mut out = Array3::zeros(current.raw_dim());
+        for ((x, y, z), value) in out.indexed_iter_mut() {
+            if y == 0 && z == 0 {
+                check(cancel)?;
+            }
+            let mut acc = 0.0;
+            for (k, weight) in kernel.iter().enumerate() {
+                let offset = k as isize - radius;
+                let mut idx = [x as isize, y as isize, z as isize];
+                idx[axis] = (idx[axis] + offset).clamp(0, len - 1);
+                acc += weight * current[[idx[0] as usize, idx[1] as usize, idx[2] as usize]];
+            }
+            *value = acc;
+        }
+        current = out;
+    }
+    Ok(current)
+}
+
+fn median(data: &Array3<f32>, radius: usize, cancel: &CancellationToken) -> Result<Array3<f32>, HDF5Error> {
+    let r = radius as isize;
+    let mut window = Vec::with_capacity((2 * radius + 1).pow(3));
+    let mut out = Array3::zeros(data.raw_dim());
+
+    for ((x, y, z), value) in out.indexed_iter_mut() {
+        if y == 0 && z == 0 {
+            check(cancel)?;
+        }
+        window.clear();
+        for dx in -r..=r {
+            for dy in -r..=r {
+                for dz in -r..=r {
+                    window.push(clamped(data, x as isize + dx, y as isize + dy, z as isize + dz));
+                }
+            }
+        }
+        let mid = window.len() / 2;
+        let (_, m, _) = window.select_nth_unstable_by(mid, |a, b| a.total_cmp(b));
+        *value = *m;
+    }
+    Ok(out)
+}
+
+fn diffusion(
+    data: &Array3<f32>,
+    iterations: usize,
+    kappa: f32,
+    cancel: &CancellationToken,
+) -> Result<Array3<f32>, HDF5Error> {
+    let min = data.iter().cloned().fold(f32::INFINITY, f32::min);
+    let max = data.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+    let k = (kappa * (max - min)).max(f32::EPSILON);
+    // Time step for stability with 6 neighbours
+    let dt = 1.0 / 7.0;
+    let conductance = |g: f32| (-(g / k) * (g / k)).exp();
+
+    let mut current = data.clone();
+    for _ in 0..iterations {
+        let mut out = Array3::zeros(current.raw_dim());
+        for ((x, y, z), value) in out.indexed_iter_mut() {
+            if y == 0 && z == 0 {
+                check(cancel)?;
+            }
+            let (xi, yi, zi) = (x as isize, y as isize, z as isize);
+            let center = current[[x, y, z]];
+            let mut flux = 0.0;
+            for (dx, dy, dz) in [(1, 0, 0), (-1, 0, 0), (0, 1, 0), (0, -1, 0), (0, 0, 1), (0, 0, -1)] {
+                let g = clamped(&current, xi + dx, yi + dy, zi + dz) - center;
+                flux += conductance(g) * g;
+            }
+            *value = center + dt * flux;
+        }
+        current = out;
+    }
+    Ok(current)
+}
+
+fn gradient_magnitude(data: &Array3<f32>, cancel: &CancellationToken) -> Result<Array3<f32>, HDF5Error> {
+    let mut out = Array3::zeros(data.raw_dim());
+    for ((x, y, z), value) in out.indexed_iter_mut() {
+        if y == 0 && z == 0 {
+            check(cancel)?;
+        }
+        let (xi, yi, zi) = (x as isize, y as isize, z as isize);
+        let gx = (clamped(data, xi + 1, yi, zi) - clamped(data, xi - 1, yi, zi)) * 0.5;
+        let gy = (clamped(data, xi, yi + 1, zi) - clamped(data, xi, yi - 1, zi)) * 0.5;
+        let gz = (clamped(data, xi, yi, zi + 1) - clamped(data, xi, yi, zi - 1)) * 0.5;
+        *value = (gx * gx + gy * gy + gz * gz).sqrt();
+    }
+    Ok(out)
+}
+
+fn laplacian(data: &Array3<f32>, cancel: &CancellationToken) -> Result<Array3<f32>, HDF5Error> {
+    let mut out = Array3::zeros(data.raw_dim());
+    for ((x, y, z), value) in out.indexed_iter_mut() {
+        if y == 0 && z == 0 {
+            check(cancel)?;
+        }
+        let (xi, yi, zi) = (x as isize, y as isize, z as isize);
+        let neighbours = clamped(data, xi + 1, yi, zi)
+            + clamped(data, xi - 1, yi, zi)
+            + clamped(data, xi, yi + 1, zi)
+            + clamped(data, xi, yi - 1, zi)
+            + clamped(data, xi, yi, zi + 1)
+            + clamped(data, xi, yi, zi - 1);
+        *value = neighbours - 6.0 * data[[x, y, z]];
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn constant(value: f32) -> Array3<f32> {
+        Array3::from_elem([5, 6, 7], value)
+    }
+
+    /// Linear ramp along x, whose Laplacian is zero away from the edges
+    fn ramp() -> Array3<f32> {
+        Array3::from_shape_fn([8, 5, 5], |(x, _, _)| x as f32)
+    }
+
+    #[test]
+    fn parses_and_prints_canonical_forms() {
+        for (text, filter) in [
+            ("gauss(1.5)", Filter::Gaussian(1.5)),
+            ("median(2)", Filter::Median(2)),
+            ("diffusion(5,0.1)", Filter::Diffusion { iterations: 5, kappa: 0.1 }),
+            ("gradmag", Filter::GradientMagnitude),
+            ("laplacian", Filter::Laplacian),
+        ] {
+            assert_eq!(text.parse::<Filter>(), Ok(filter));
+            assert_eq!(filter.to_string(), text);
+        }
+        // Aliases and defaults
+        assert_eq!("gaussian".parse::<Filter>(), Ok(Filter::Gaussian(1.0)));
+        assert_eq!("gradient".parse::<Filter>(), Ok(Filter::GradientMagnitude));
+        assert_eq!(
+            "diffusion(,0.2)".parse::<Filter>(),
+            Ok(Filter::Diffusion { iterations: 10, kappa: 0.2 })
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_arguments() {
+        for text in ["gauss(0)", "gauss(17)", "gauss(x)", "median(0)", "median(4)", "diffusion(0,0.1)", "diffusion(5,0)", "gauss(1", "sharpen"] {
+            assert!(text.parse::<Filter>().is_err(), "{} should not parse", text);
+        }
+    }
+
+    #[test]
+    fn smoothing_filters_keep_constant_volumes() {
+        let cancel = CancellationToken::new();
+        for filter in [Filter::Gaussian(1.0), Filter::Median(1), Filter::Diffusion { iterations: 3, kappa: 0.1 }] {
+            let result = filter.apply(&constant(2.5), &cancel).unwrap();
+            assert!(result.iter().all(|v| (v - 2.5).abs() < 1e-5), "{} changed a constant volume", filter);
+        }
+    }
+
+    #[test]
+    fn derivatives_of_a_ramp() {
+        let cancel = CancellationToken::new();
+        let gradient = Filter::GradientMagnitude.apply(&ramp(), &cancel).unwrap();
+        assert!((gradient[[4, 2, 2]] - 1.0).abs() < 1e-6);
+        // One-sided at the clamped edge
+        assert!((gradient[[0, 2, 2]] - 0.5).abs() < 1e-6);
+
+        let laplacian = Filter::Laplacian.apply(&ramp(), &cancel).unwrap();
+        assert_eq!(laplacian[[4, 2, 2]], 0.0);
+        assert_eq!(laplacian[[0, 2, 2]], 1.0);
+    }
+
+    #[test]
+    fn median_removes_a_single_outlier() {
+        let mut data = constant(1.0);
+        data[[2, 3, 3]] = 100.0;
+        let result = Filter::Median(1).apply(&data, &CancellationToken::new()).unwrap();
+        assert_eq!(result[[2, 3, 3]], 1.0);
+    }
+
+    #[test]
+    fn stops_when_cancelled() {
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        assert!(matches!(
+            Filter::Gaussian(1.0).apply(&ramp(), &cancel),
+            Err(HDF5Error::Cancelled)
+        ));
+    }
+}
@@ -1,8 +1,47 @@
+use axum::body::Bytes;
+use hdf5::types::{VarLenAscii, VarLenUnicode};
 use hdf5::File;
-use ndarray::Array3;
+use ndarray::{s, Array3, Axis};
 use shared::VolumeInfo;
+use std::collections::BTreeMap;
+use std::ops::Range;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 use thiserror::Error;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+use crate::catalog;
+use crate::fastmri::{self, Reconstruction};
+use crate::phantom::Phantom;
+use crate::storage::NpyObject;
+
+/// Largest dimension of the low-res preview
+const LOW_RES_SIZE: usize = 64;
+
+/// Target size of each streamed chunk (rounded to whole X slabs)
+const STREAM_CHUNK_BYTES: usize = 4 * 1024 * 1024;
+
+/// Complex sample as h5py stores `complex64`: a compound of `r` and `i`
+#[derive(hdf5::H5Type, Clone, Copy, Debug, Default)]
+#[repr(C)]
+pub struct Complex32 {
+    pub r: f32,
+    pub i: f32,
+}
+
+impl Complex32 {
+    pub fn norm(self) -> f32 {
+        self.r.hypot(self.i)
+    }
+}
+
+impl From<Complex32> for num_complex::Complex32 {
+    fn from(value: Complex32) -> Self {
+        Self::new(value.r, value.i)
+    }
+}
 
 #[derive(Error, Debug)]
 pub enum HDF5Error {
@@ -10,64 +49,385 @@ pub enum HDF5Error {
     Hdf5(#[from] hdf5::Error),
     #[error("Dataset not found: {0}")]
     DatasetNotFound(String),
+    #[error("Shape mismatch: {0}")]
+    ShapeMismatch(String),
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
+    #[error("Object storage error: {0}")]
+    Object(#[from] object_store::Error),
+    #[error("Unsupported data: {0}")]
+    Format(String),
+    #[error("Request cancelled")]
+    Cancelled,
 }
 
-/// Represents a loaded HDF5 volume
+/// Hash an identity string into a version (FNV-1a, stable across runs)
+pub fn version_hash(identity: &str) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in identity.bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    format!("{:016x}", hash)
+}
+
+/// Error for a blocking read task that panicked
+pub fn join_error(e: tokio::task::JoinError) -> HDF5Error {
+    HDF5Error::Io(std::io::Error::other(format!("read task failed: {}", e)))
+}
+
+/// Where the data of a volume is read from
+#[derive(Clone)]
+pub enum VolumeSource {
+    /// An HDF5 file on local disk
+    File(PathBuf),
+    /// One reconstruction of a fastMRI file on local disk
+    FastMri { path: PathBuf, recon: Reconstruction },
+    /// A NumPy array in object storage
+    Object(Arc<NpyObject>),
+    /// Generated on the fly
+    Phantom(Arc<Phantom>),
+}
+
+/// An opened source, read in X slabs
+enum Reader {
+    Hdf5 { _file: File, dataset: hdf5::Dataset },
+    KSpace { _file: File, kspace: hdf5::Dataset, crop: [usize; 2] },
+    Object(Arc<NpyObject>),
+    Phantom(Arc<Phantom>),
+}
+
+impl Reader {
+    fn open(source: &VolumeSource) -> Result<Self, HDF5Error> {
+        match source {
+            VolumeSource::File(path) => {
+                let file = File::open(path)?;
+                let dataset = HDF5Volume::open_dataset(&file)?;
+                Ok(Reader::Hdf5 { _file: file, dataset })
+            }
+            VolumeSource::FastMri { path, recon } => {
+                let file = File::open(path)?;
+                match recon {
+                    Reconstruction::Stored(name) => {
+                        let dataset = file.dataset(name)?;
+                        Ok(Reader::Hdf5 { _file: file, dataset })
+                    }
+                    Reconstruction::ZeroFilled { crop } => {
+                        let kspace = file.dataset(fastmri::KSPACE)?;
+                        Ok(Reader::KSpace { _file: file, kspace, crop: *crop })
+                    }
+                }
+            }
+            VolumeSource::Object(object) => Ok(Reader::Object(object.clone())),
+            VolumeSource::Phantom(phantom) => Ok(Reader::Phantom(phantom.clone())),
+        }
+    }
+
+    /// Read X slabs `x` of one frame. 3D data only has frame 0.
+    fn read_slab(&self, frame: usize, x: Range<usize>) -> Result<Array3<f32>, HDF5Error> {
+        match self {
+            Reader::Hdf5 { dataset, .. } if dataset.shape().len() == 4 => Ok(dataset.read_slice(s![frame, x, .., ..])?),
+            Reader::Hdf5 { dataset, .. } => Ok(dataset.read_slice(s![x, .., ..])?),
+            Reader::KSpace { kspace, crop, .. } => fastmri::zero_filled(kspace, x, *crop),
+            Reader::Object(object) => object.read_slab(frame, x),
+            Reader::Phantom(phantom) => Ok(phantom.read_slab(x)),
+        }
+    }
+}
+
+/// A loaded volume: an HDF5 file on disk, an array in object storage or a phantom
 pub struct HDF5Volume {
     pub info: VolumeInfo,
-    pub path: PathBuf,
+    pub source: VolumeSource,
+    /// Modification time of the file or object when it was opened
+    pub modified: SystemTime,
     /// Cached low-res data (generated on load)
     low_res_cache: Vec<u8>,
 }
 
 impl HDF5Volume {
     /// Open an HDF5 file and extract volume metadata
+    #[tracing::instrument(skip_all, fields(path = %path.display()))]
     pub async fn open(path: &Path) -> Result<Self, HDF5Error> {
         let path_buf = path.to_path_buf();
         let path_clone = path_buf.clone();
 
+        let metadata = std::fs::metadata(path)?;
+        let modified = metadata.modified().unwrap_or(UNIX_EPOCH);
+
         // Read the file in a blocking task
-        let (info, low_res_cache) = tokio::task::spawn_blocking(move || {
+        let (mut info, low_res_cache) = tokio::task::spawn_blocking(move || {
             Self::read_volume_sync(&path_clone)
         })
         .await
-        .unwrap()?;
+        .map_err(join_error)??;
+
+        info.version = Self::content_version(path, metadata.len(), modified);
+
+        Ok(Self {
+            info,
+            source: VolumeSource::File(path_buf),
+            modified,
+            low_res_cache,
+        })
+    }
+
+    /// Open a fastMRI file as its stored reconstruction, when it has one, and
+    /// a zero-filled reconstruction computed from its k-space
+    #[tracing::instrument(skip_all, fields(path = %path.display()))]
+    pub async fn open_fastmri(path: &Path) -> Result<Vec<Self>, HDF5Error> {
+        let path_buf = path.to_path_buf();
+        let metadata = std::fs::metadata(path)?;
+        let modified = metadata.modified().unwrap_or(UNIX_EPOCH);
+
+        let opened = tokio::task::spawn_blocking(move || Self::read_fastmri_sync(&path_buf))
+            .await
+            .map_err(join_error)??;
+
+        let version = Self::content_version(path, metadata.len(), modified);
+        Ok(opened
+            .into_iter()
+            .map(|(mut info, low_res_cache, source)| {
+                if let Some(part) = Self::part_of(&source) {
+                    info.version = version_hash(&format!("{}:{}", version, part));
+                }
+                Self {
+                    info,
+                    source,
+                    modified,
+                    low_res_cache,
+                }
+            })
+            .collect())
+    }
+
+    /// Open a NumPy array in object storage. Only every few X slabs are read,
+    /// for an approximate value range and a point-sampled preview, so that
+    /// opening a bucket does not download it.
+    #[tracing::instrument(skip_all, fields(object = %object.url()))]
+    pub async fn open_object(object: NpyObject) -> Result<Self, HDF5Error> {
+        let object = Arc::new(object);
+        let meta = object.meta().clone();
+        let reader = Reader::Object(object.clone());
+        let (name, frames, dims) = (object.name(), object.frames(), object.dims());
+
+        let (mut info, low_res_cache) =
+            tokio::task::spawn_blocking(move || Self::summarize(&reader, name, frames, dims))
+                .await
+                .map_err(join_error)??;
+
+        info.dtype = object.dtype().to_string();
+        info.version = version_hash(&format!(
+            "{}:{}:{}",
+            object.url(),
+            meta.size,
+            meta.e_tag.as_deref().unwrap_or_default()
+        ));
+
+        Ok(Self {
+            info,
+            source: VolumeSource::Object(object),
+            modified: meta.last_modified.into(),
+            low_res_cache,
+        })
+    }
+
+    /// Generate a phantom and, like a file, read it once for the value range and preview
+    #[tracing::instrument(skip_all, fields(phantom = %phantom.name()))]
+    pub async fn open_phantom(phantom: Phantom) -> Result<Self, HDF5Error> {
+        let phantom = Arc::new(phantom);
+        let reader = Reader::Phantom(phantom.clone());
+        let (name, dims) = (phantom.name(), phantom.dims);
+
+        let (mut info, low_res_cache) =
+            tokio::task::spawn_blocking(move || Self::summarize(&reader, name, 1, dims))
+                .await
+                .map_err(join_error)??;
+
+        info.dtype = phantom.dtype.name().to_string();
+        info.tags = vec!["phantom".to_string()];
+        // Generated data only changes with its spec, which is in the name
+        info.version = version_hash(&info.name);
 
         Ok(Self {
             info,
-            path: path_buf,
+            source: VolumeSource::Phantom(phantom),
+            modified: SystemTime::UNIX_EPOCH,
             low_res_cache,
         })
     }
 
+    /// Path of the file on local disk, which sidecars (labels, annotations) sit next to
+    pub fn path(&self) -> Option<&Path> {
+        match &self.source {
+            VolumeSource::File(path) | VolumeSource::FastMri { path, .. } => Some(path),
+            VolumeSource::Object(_) | VolumeSource::Phantom(_) => None,
+        }
+    }
+
+    /// Name of the volume within its file, for files served as several volumes
+    pub fn part(&self) -> Option<&str> {
+        Self::part_of(&self.source)
+    }
+
+    fn part_of(source: &VolumeSource) -> Option<&str> {
+        match source {
+            VolumeSource::FastMri { recon, .. } => Some(recon.name()),
+            _ => None,
+        }
+    }
+
+    /// Stable version string from file identity (path, size, mtime)
+    fn content_version(path: &Path, len: u64, modified: SystemTime) -> String {
+        let mtime = modified.duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos();
+        version_hash(&format!("{}:{}:{}", path.display(), len, mtime))
+    }
+
     fn read_volume_sync(path: &Path) -> Result<(VolumeInfo, Vec<u8>), HDF5Error> {
         let file = File::open(path)?;
+        let dataset = Self::open_dataset(&file)?;
 
-        // Try to find the target dataset
-        let dataset = file
-            .dataset("target")
-            .or_else(|_| file.dataset("volume"))
-            .or_else(|_| file.dataset("data"))
-            .map_err(|_| HDF5Error::DatasetNotFound("target, volume, or data".to_string()))?;
-
+        // 4D datasets are time series with time as the first axis
         let shape = dataset.shape();
-        if shape.len() < 3 {
-            return Err(HDF5Error::DatasetNotFound("Need at least 3D data".to_string()));
+        let (frames, spatial) = match shape.len() {
+            3 => (1, &shape[..]),
+            4 => (shape[0], &shape[1..]),
+            _ => return Err(HDF5Error::DatasetNotFound("Need 3D or 4D data".to_string())),
+        };
+
+        let dims = [spatial[0] as u32, spatial[1] as u32, spatial[2] as u32];
+
+        // Generate ID from filename
+        let name = path
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or("unknown")
+            .to_string();
+
+        let spacing = Self::read_spacing(&file, &dataset);
+        let frame_interval = (frames > 1).then(|| Self::read_frame_interval(&file, &dataset)).flatten();
+        let tags = Self::read_tags(&file, &dataset);
+        let dtype = Self::read_dtype(&dataset);
+        let attributes = Self::read_attributes(&file, &dataset);
+
+        let reader = Reader::Hdf5 { _file: file, dataset };
+        let (mut info, low_res_bytes) = Self::summarize(&reader, name, frames, dims)?;
+        info.spacing = spacing;
+        info.frame_interval = frame_interval;
+        info.tags = tags;
+        info.dtype = dtype;
+        info.attributes = attributes;
+
+        Ok((info, low_res_bytes))
+    }
+
+    /// Read the reconstructions of a fastMRI file, each with the geometry and
+    /// acquisition parameters of the ISMRMRD header
+    fn read_fastmri_sync(path: &Path) -> Result<Vec<(VolumeInfo, Vec<u8>, VolumeSource)>, HDF5Error> {
+        let file = File::open(path)?;
+        let kspace = file
+            .dataset(fastmri::KSPACE)
+            .map_err(|_| HDF5Error::DatasetNotFound(fastmri::KSPACE.to_string()))?;
+        let shape = kspace.shape();
+        if !(3..=4).contains(&shape.len()) {
+            return Err(HDF5Error::ShapeMismatch(format!(
+                "k-space must be (slices, [coils,] kx, ky), got {:?}",
+                shape
+            )));
         }
+        let (slices, rows, cols) = (shape[0], shape[shape.len() - 2], shape[shape.len() - 1]);
 
-        let dims = [shape[0] as u32, shape[1] as u32, shape[2] as u32];
+        let header = fastmri::Header::read(&file);
+        let stored = fastmri::STORED
+            .iter()
+            .find_map(|name| file.dataset(name).ok().map(|dataset| (*name, dataset)))
+            .filter(|(_, dataset)| dataset.shape().len() == 3);
 
-        // Read the data as f32
-        let data: Array3<f32> = dataset.read()?;
+        // The zero-filled image is cropped to the reconstructed matrix, like the stored one
+        let crop = header
+            .as_ref()
+            .and_then(|h| h.recon_matrix())
+            .or_else(|| stored.as_ref().map(|(_, dataset)| [dataset.shape()[1], dataset.shape()[2]]))
+            .unwrap_or([rows, cols]);
+        let crop = [crop[0].min(rows), crop[1].min(cols)];
 
-        // Calculate value range
-        let min_val = data.iter().cloned().fold(f32::INFINITY, f32::min);
-        let max_val = data.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+        let name = path
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or("unknown")
+            .to_string();
+        let spacing = match header.as_ref().and_then(|h| h.spacing()) {
+            Some(spacing) => spacing,
+            None => Self::read_spacing(&file, &kspace),
+        };
+        let mut tags = Self::read_tags(&file, &kspace);
+        tags.push("fastmri".to_string());
+        let mut attributes = Self::read_attributes(&file, &kspace);
+        if let Some(header) = &header {
+            attributes.extend(header.attributes());
+        }
+        if shape.len() == 4 {
+            attributes.insert("coils".to_string(), shape[1].to_string());
+        }
+
+        let mut recons = Vec::new();
+        if let Some((dataset_name, dataset)) = stored {
+            let dims = [dataset.shape()[0], dataset.shape()[1], dataset.shape()[2]].map(|d| d as u32);
+            let dtype = Self::read_dtype(&dataset);
+            recons.push((Reconstruction::Stored(dataset_name.to_string()), dims, dtype));
+        }
+        let dims = [slices, crop[0], crop[1]].map(|d| d as u32);
+        recons.push((Reconstruction::ZeroFilled { crop }, dims, "f32".to_string()));
+
+        let mut opened = recons
+            .into_iter()
+            .map(|(recon, dims, dtype)| {
+                let source = VolumeSource::FastMri {
+                    path: path.to_path_buf(),
+                    recon,
+                };
+                let reader = Reader::open(&source)?;
+                let part = Self::part_of(&source).unwrap_or_default();
+                let (mut info, low_res_bytes) = Self::summarize(&reader, format!("{} {}", name, part), 1, dims)?;
+                info.spacing = spacing;
+                info.tags = tags.clone();
+                info.dtype = dtype;
+                info.attributes = attributes.clone();
+                Ok((info, low_res_bytes, source))
+            })
+            .collect::<Result<Vec<_>, HDF5Error>>()?;
+
+        // Both reconstruct the same k-space, so the zero-filled one takes the
+        // exact range of the stored one over the estimate from its samples
+        if let [(stored, ..), (zero_filled, ..)] = &mut opened[..] {
+            zero_filled.value_range = stored.value_range;
+        }
+        Ok(opened)
+    }
+
+    /// Read the first frame of a volume for its value range and low-res
+    /// preview. Later frames only widen the range by a preview-sized sample
+    /// each, so playback keeps one window without reading every frame.
+    /// Metadata the data cannot tell is left at its default.
+    fn summarize(reader: &Reader, name: String, frames: usize, dims: [u32; 3]) -> Result<(VolumeInfo, Vec<u8>), HDF5Error> {
+        // Read the (first frame of the) data as f32; objects and k-space only
+        // in samples, since every byte read is a download or an FFT
+        let never = CancellationToken::new();
+        let data = match reader {
+            Reader::Object(_) | Reader::KSpace { .. } => Self::read_sampled(reader, 0, dims, LOW_RES_SIZE, &never)?,
+            _ => Self::read_cancellable(reader, 0, dims, &never)?,
+        };
+
+        let mut min_val = data.iter().cloned().fold(f32::INFINITY, f32::min);
+        let mut max_val = data.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+        for frame in 1..frames {
+            let sample = Self::read_sampled(reader, frame, dims, LOW_RES_SIZE, &never)?;
+            min_val = sample.iter().cloned().fold(min_val, f32::min);
+            max_val = sample.iter().cloned().fold(max_val, f32::max);
+        }
 
         // Generate low-res version (downsample to ~64^3)
-        let low_res = Self::downsample(&data, 64);
+        let low_res = Self::downsample(&data, LOW_RES_SIZE, &never)?;
         let low_res_shape = low_res.shape();
         let low_res_dims = [
             low_res_shape[0] as u32,
@@ -78,38 +438,171 @@ impl HDF5Volume {
 
         // Calculate sizes
         let low_res_size = low_res_bytes.len() as u64;
-        let full_res_size = (data.len() * std::mem::size_of::<f32>()) as u64;
-
-        // Generate ID from filename
-        let id = path
-            .file_stem()
-            .and_then(|s| s.to_str())
-            .unwrap_or("unknown")
-            .to_string();
-
-        let name = id.clone();
+        let full_res_size = dims.iter().map(|&d| d as u64).product::<u64>() * std::mem::size_of::<f32>() as u64;
 
         let info = VolumeInfo {
-            id,
+            id: name.clone(),
             name,
             dimensions: dims,
             low_res_dimensions: low_res_dims,
             low_res_size,
             full_res_size,
             value_range: [min_val, max_val],
+            spacing: [1.0, 1.0, 1.0],
+            version: String::new(),
+            labels: Vec::new(),
+            frames: frames as u32,
+            frame_interval: None,
+            folder: String::new(),
+            tags: Vec::new(),
+            dtype: String::new(),
+            attributes: BTreeMap::new(),
         };
 
         Ok((info, low_res_bytes))
     }
 
+    /// Find the volume dataset in a file
+    fn open_dataset(file: &File) -> Result<hdf5::Dataset, HDF5Error> {
+        file.dataset("target")
+            .or_else(|_| file.dataset("volume"))
+            .or_else(|_| file.dataset("data"))
+            .map_err(|_| HDF5Error::DatasetNotFound("target, volume, or data".to_string()))
+    }
+
+    /// Voxel spacing from a `spacing`, `voxel_size` or `pixdim` attribute on
+    /// the dataset or the file root, defaulting to 1mm isotropic
+    pub fn read_spacing(file: &File, dataset: &hdf5::Dataset) -> [f32; 3] {
+        for name in ["spacing", "voxel_size", "pixdim"] {
+            let attr = dataset.attr(name).or_else(|_| file.attr(name));
+            if let Ok(values) = attr.and_then(|a| a.read_raw::<f32>()) {
+                if values.len() >= 3 && values[..3].iter().all(|v| *v > 0.0) {
+                    return [values[0], values[1], values[2]];
+                }
+            }
+        }
+        [1.0, 1.0, 1.0]
+    }
+
+    /// Seconds between frames from a `frame_interval`, `temporal_resolution`
+    /// or `dt` attribute on the dataset or the file root
+    fn read_frame_interval(file: &File, dataset: &hdf5::Dataset) -> Option<f32> {
+        ["frame_interval", "temporal_resolution", "dt"].iter().find_map(|name| {
+            let attr = dataset.attr(name).or_else(|_| file.attr(name)).ok()?;
+            let values = attr.read_raw::<f32>().ok()?;
+            values.first().copied().filter(|v| *v > 0.0)
+        })
+    }
+
+    /// Element type of the dataset, named as in `VolumeInfo::dtype`
+    fn read_dtype(dataset: &hdf5::Dataset) -> String {
+        dataset
+            .dtype()
+            .and_then(|t| t.to_descriptor())
+            .map(|d| catalog::dtype_name(&d.to_string()))
+            .unwrap_or_default()
+    }
+
+    /// Tags from a `tags` attribute on the dataset or the file root, either a
+    /// list of strings or one comma-separated string
+    fn read_tags(file: &File, dataset: &hdf5::Dataset) -> Vec<String> {
+        let Ok(attr) = dataset.attr("tags").or_else(|_| file.attr("tags")) else {
+            return Vec::new();
+        };
+        let values: Vec<String> = match attr.read_raw::<VarLenUnicode>() {
+            Ok(values) => values.iter().map(|v| v.as_str().to_string()).collect(),
+            Err(_) => attr
+                .read_raw::<VarLenAscii>()
+                .map(|values| values.iter().map(|v| v.as_str().to_string()).collect())
+                .unwrap_or_default(),
+        };
+        values
+            .iter()
+            .flat_map(|v| v.split(','))
+            .map(|tag| tag.trim().to_string())
+            .filter(|tag| !tag.is_empty())
+            .collect()
+    }
+
+    /// Numeric and string scalar attributes of the file root and the dataset,
+    /// the dataset's taking precedence
+    fn read_attributes(file: &File, dataset: &hdf5::Dataset) -> BTreeMap<String, String> {
+        let mut attributes = BTreeMap::new();
+        for name in file.attr_names().unwrap_or_default() {
+            if let Some(value) = file.attr(&name).ok().and_then(|a| Self::scalar_attribute(&a)) {
+                attributes.insert(name, value);
+            }
+        }
+        for name in dataset.attr_names().unwrap_or_default() {
+            if let Some(value) = dataset.attr(&name).ok().and_then(|a| Self::scalar_attribute(&a)) {
+                attributes.insert(name, value);
+            }
+        }
+        attributes.remove("tags");
+        attributes
+    }
+
+    fn scalar_attribute(attr: &hdf5::Attribute) -> Option<String> {
+        if let Ok(values) = attr.read_raw::<f64>() {
+            return (values.len() == 1).then(|| values[0].to_string());
+        }
+        attr.read_scalar::<VarLenUnicode>()
+            .map(|v| v.as_str().to_string())
+            .or_else(|_| attr.read_scalar::<VarLenAscii>().map(|v| v.as_str().to_string()))
+            .ok()
+    }
+
+    /// Number of X slabs per hyperslab read so each read is about `STREAM_CHUNK_BYTES`
+    fn slabs_per_chunk(dims: [u32; 3]) -> usize {
+        let slab_bytes = dims[1] as usize * dims[2] as usize * std::mem::size_of::<f32>();
+        (STREAM_CHUNK_BYTES / slab_bytes.max(1)).max(1)
+    }
+
+    /// Read one whole frame in hyperslab chunks, checking for cancellation between chunks
+    fn read_cancellable(
+        reader: &Reader,
+        frame: usize,
+        dims: [u32; 3],
+        cancel: &CancellationToken,
+    ) -> Result<Array3<f32>, HDF5Error> {
+        let mut data = Array3::zeros([dims[0] as usize, dims[1] as usize, dims[2] as usize]);
+        let step = Self::slabs_per_chunk(dims);
+
+        for x0 in (0..dims[0] as usize).step_by(step) {
+            if cancel.is_cancelled() {
+                return Err(HDF5Error::Cancelled);
+            }
+            let x1 = (x0 + step).min(dims[0] as usize);
+            let slab = reader.read_slab(frame, x0..x1)?;
+            data.slice_mut(s![x0..x1, .., ..]).assign(&slab);
+        }
+
+        Ok(data)
+    }
+
+    /// Step between the voxels `downsample` keeps for a volume of `dims`
+    pub fn downsample_factor(dims: &[usize], target_size: usize) -> usize {
+        let max_dim = dims.iter().max().copied().unwrap_or(1);
+        (max_dim / target_size.max(1)).max(1)
+    }
+
+    /// Dimensions produced by `downsample` for a volume of `dims`
+    pub fn downsampled_dims(dims: [u32; 3], target_size: usize) -> [u32; 3] {
+        let factor = Self::downsample_factor(&dims.map(|d| d as usize), target_size) as u32;
+        [dims[0] / factor, dims[1] / factor, dims[2] / factor]
+    }
+
     /// Downsample volume to approximately target_size in each dimension
-    fn downsample(data: &Array3<f32>, target_size: usize) -> Array3<f32> {
+    pub fn downsample(
+        data: &Array3<f32>,
+        target_size: usize,
+        cancel: &CancellationToken,
+    ) -> Result<Array3<f32>, HDF5Error> {
         let shape = data.shape();
-        let max_dim = shape.iter().max().copied().unwrap_or(1);
-        let factor = (max_dim / target_size).max(1);
+        let factor = Self::downsample_factor(shape, target_size);
 
         if factor == 1 {
-            return data.clone();
+            return Ok(data.clone());
         }
 
         let new_shape = [
@@ -121,6 +614,9 @@ impl HDF5Volume {
         let mut result = Array3::zeros(new_shape);
 
         for x in 0..new_shape[0] {
+            if cancel.is_cancelled() {
+                return Err(HDF5Error::Cancelled);
+            }
             for y in 0..new_shape[1] {
                 for z in 0..new_shape[2] {
                     // Simple point sampling (could use averaging for better quality)
@@ -129,11 +625,11 @@ impl HDF5Volume {
             }
         }
 
-        result
+        Ok(result)
     }
 
     /// Convert ndarray to bytes
-    fn to_bytes(data: &Array3<f32>) -> Vec<u8> {
+    pub fn to_bytes(data: &Array3<f32>) -> Vec<u8> {
         let slice = data.as_slice().unwrap_or(&[]);
         let bytes: Vec<u8> = slice
             .iter()
@@ -147,45 +643,153 @@ impl HDF5Volume {
         Ok(self.low_res_cache.clone())
     }
 
-    /// Get full-res data (read from file)
-    pub async fn get_full_res_data(&self) -> Result<Vec<u8>, HDF5Error> {
-        let path = self.path.clone();
+    /// Read the full-res array (the first frame of a time series), stopping once `cancel` fires
+    pub async fn read_array(&self, cancel: CancellationToken) -> Result<Array3<f32>, HDF5Error> {
+        self.read_frame(0, cancel).await
+    }
+
+    /// Read one full-res frame, stopping once `cancel` fires
+    #[tracing::instrument(skip_all, fields(volume = %self.info.id, frame = frame))]
+    pub async fn read_frame(&self, frame: u32, cancel: CancellationToken) -> Result<Array3<f32>, HDF5Error> {
+        let source = self.source.clone();
+        let dims = self.info.dimensions;
 
         tokio::task::spawn_blocking(move || {
-            let file = File::open(&path)?;
-            let dataset = file
-                .dataset("target")
-                .or_else(|_| file.dataset("volume"))
-                .or_else(|_| file.dataset("data"))
-                .map_err(|_| HDF5Error::DatasetNotFound("target, volume, or data".to_string()))?;
-
-            let data: Array3<f32> = dataset.read()?;
-            Ok(Self::to_bytes(&data))
+            let reader = Reader::open(&source)?;
+            Self::read_cancellable(&reader, frame as usize, dims, &cancel)
         })
         .await
-        .unwrap()
+        .map_err(join_error)?
+    }
+
+    /// Upper bound on memory held by one full-res stream (chunks in the
+    /// channel plus the one being read)
+    pub fn stream_memory_estimate(&self) -> u64 {
+        3 * self.chunk_bytes()
+    }
+
+    /// Largest chunk read at once: `STREAM_CHUNK_BYTES`, or one X slab if larger
+    fn chunk_bytes(&self) -> u64 {
+        let dims = self.info.dimensions;
+        let slab_bytes = (dims[1] as u64) * (dims[2] as u64) * std::mem::size_of::<f32>() as u64;
+        slab_bytes.max(STREAM_CHUNK_BYTES as u64)
     }
 
-    /// Get volume data resampled to a target resolution
+    /// Stream a byte range of the full-res data, reading X slabs from the file
+    /// as hyperslabs so the whole volume is never held in memory at once.
+    /// The range must lie within `info.full_res_size`. Reading stops when the
+    /// receiver is dropped or `cancel` fires.
+    pub fn stream_full_res_data(
+        &self,
+        range: Range<u64>,
+        cancel: CancellationToken,
+    ) -> mpsc::Receiver<Result<Bytes, HDF5Error>> {
+        let source = self.source.clone();
+        let dims = self.info.dimensions;
+        let (tx, rx) = mpsc::channel(2);
+
+        // Covers the whole stream, which outlives the request handler
+        let span = tracing::info_span!("stream_full_res", volume = %self.info.id, start = range.start, end = range.end);
+        tokio::task::spawn_blocking(move || {
+            let _span = span.enter();
+            let result = (|| {
+                let reader = Reader::open(&source)?;
+
+                let slab_bytes = (dims[1] as u64) * (dims[2] as u64) * std::mem::size_of::<f32>() as u64;
+                let slabs_per_chunk = Self::slabs_per_chunk(dims) as u64;
+                let first_slab = range.start / slab_bytes;
+                let end_slab = range.end.div_ceil(slab_bytes).min(dims[0] as u64);
+
+                let mut x0 = first_slab;
+                while x0 < end_slab {
+                    if cancel.is_cancelled() {
+                        return Err(HDF5Error::Cancelled);
+                    }
+                    let x1 = (x0 + slabs_per_chunk).min(end_slab);
+                    let slab = reader.read_slab(0, x0 as usize..x1 as usize)?;
+                    let bytes = Self::to_bytes(&slab);
+
+                    // Trim the chunk to the requested byte range
+                    let chunk_start = x0 * slab_bytes;
+                    let from = range.start.saturating_sub(chunk_start) as usize;
+                    let to = ((range.end - chunk_start) as usize).min(bytes.len());
+
+                    // Stop reading once the receiver (the HTTP response) is gone
+                    if tx.blocking_send(Ok(Bytes::copy_from_slice(&bytes[from..to]))).is_err() {
+                        return Ok(());
+                    }
+                    x0 = x1;
+                }
+                Ok(())
+            })();
+
+            match result {
+                Err(HDF5Error::Cancelled) => tracing::debug!("Stream cancelled"),
+                Err(e) => {
+                    let _ = tx.blocking_send(Err(e));
+                }
+                Ok(()) => {}
+            }
+        });
+
+        rx
+    }
+
+    /// Upper bound on memory held while resampling to `target_size`: the
+    /// resampled array and its bytes, plus the chunk being read
+    pub fn resample_memory_estimate(&self, target_size: usize) -> u64 {
+        let dims = Self::downsampled_dims(self.info.dimensions, target_size);
+        let voxels: u64 = dims.iter().map(|&d| d as u64).product();
+        2 * voxels * std::mem::size_of::<f32>() as u64 + self.chunk_bytes()
+    }
+
+    /// Get volume data resampled to a target resolution, reading only the X
+    /// slabs the point sampling keeps so the full volume is never held
     /// Returns (bytes, [x, y, z] dimensions)
-    pub async fn get_data_at_resolution(&self, target_size: usize) -> Result<(Vec<u8>, [u32; 3]), HDF5Error> {
-        let path = self.path.clone();
+    #[tracing::instrument(skip_all, fields(volume = %self.info.id, resolution = target_size))]
+    pub async fn get_data_at_resolution(
+        &self,
+        target_size: usize,
+        cancel: CancellationToken,
+    ) -> Result<(Vec<u8>, [u32; 3]), HDF5Error> {
+        let source = self.source.clone();
+        let dims = self.info.dimensions;
 
         tokio::task::spawn_blocking(move || {
-            let file = File::open(&path)?;
-            let dataset = file
-                .dataset("target")
-                .or_else(|_| file.dataset("volume"))
-                .or_else(|_| file.dataset("data"))
-                .map_err(|_| HDF5Error::DatasetNotFound("target, volume, or data".to_string()))?;
-
-            let data: Array3<f32> = dataset.read()?;
-            let resampled = Self::downsample(&data, target_size);
-            let shape = resampled.shape();
-            let dims = [shape[0] as u32, shape[1] as u32, shape[2] as u32];
-            Ok((Self::to_bytes(&resampled), dims))
+            let reader = Reader::open(&source)?;
+            let resampled = Self::read_sampled(&reader, 0, dims, target_size, &cancel)?;
+            let new_dims = Self::downsampled_dims(dims, target_size);
+            Ok((Self::to_bytes(&resampled), new_dims))
         })
         .await
-        .unwrap()
+        .map_err(join_error)?
+    }
+
+    /// One frame point-sampled like `downsample`, reading only the X slabs
+    /// it keeps
+    fn read_sampled(
+        reader: &Reader,
+        frame: usize,
+        dims: [u32; 3],
+        target_size: usize,
+        cancel: &CancellationToken,
+    ) -> Result<Array3<f32>, HDF5Error> {
+        let factor = Self::downsample_factor(&dims.map(|d| d as usize), target_size);
+        if factor == 1 {
+            return Self::read_cancellable(reader, frame, dims, cancel);
+        }
+
+        let [nx, ny, nz] = Self::downsampled_dims(dims, target_size).map(|d| d as usize);
+        let mut sampled = Array3::zeros([nx, ny, nz]);
+        for x in 0..nx {
+            if cancel.is_cancelled() {
+                return Err(HDF5Error::Cancelled);
+            }
+            let slab = reader.read_slab(frame, x * factor..x * factor + 1)?;
+            sampled
+                .index_axis_mut(Axis(0), x)
+                .assign(&slab.slice(s![0, ..ny * factor;factor, ..nz * factor;factor]));
+        }
+        Ok(sampled)
     }
 }
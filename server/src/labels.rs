@@ -0,0 +1,503 @@
+use hdf5::types::{IntSize, TypeDescriptor, VarLenUnicode};
+use hdf5::File;
+use ndarray::{Array3, Array4, Axis, Ix3, Zip};
+use shared::{ComponentStats, LabelDtype, LabelEntry, LabelVolumeInfo, SegmentRequest, VolumeInfo};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio::sync::OnceCell;
+
+use crate::hdf5_reader::{join_error, HDF5Error};
+
+/// Datasets holding labels next to the image in an intensity volume's file
+const LABEL_DATASETS: [&str; 3] = ["seg", "segmentation", "labels"];
+
+/// Datasets holding labels in a `*_seg.h5` sibling file
+const SIBLING_DATASETS: [&str; 6] = ["seg", "segmentation", "labels", "target", "volume", "data"];
+
+/// Stem suffix of sibling label files, e.g. `case_01_seg.h5` for `case_01.h5`
+pub const LABEL_FILE_SUFFIX: &str = "_seg";
+
+/// Attribute holding the label table as a JSON string
+const LABEL_TABLE_ATTR: &str = "label_table";
+
+/// Extension of JSON sidecar label tables, e.g. `case_01.labels.json`
+const LABEL_TABLE_EXTENSION: &str = "labels.json";
+
+/// Where the voxels of a label volume come from. They are kept in the volume
+/// cache, so they are read or computed again after being evicted.
+#[derive(Debug, Clone)]
+pub enum LabelSource {
+    /// A label dataset in an HDF5 file
+    File { path: PathBuf, dataset: String },
+    /// Segmentation of the intensity volume by the server
+    Segmentation(SegmentRequest),
+}
+
+/// Integer label volume belonging to an intensity volume
+pub struct LabelVolume {
+    pub info: LabelVolumeInfo,
+    pub source: LabelSource,
+    /// Intensity threshold used to produce the labels, if segmented by the server
+    pub threshold: Option<f32>,
+}
+
+/// A label dataset shipped with a file volume. Only its shape is checked when
+/// it is found; the voxels and statistics are read on first use.
+pub struct LabelFile {
+    pub id: String,
+    volume: VolumeInfo,
+    /// The intensity volume's file, whose sidecar may hold the label table
+    volume_path: PathBuf,
+    path: PathBuf,
+    dataset: String,
+    /// Bytes of the dataset as stored, e.g. every channel of one-hot labels
+    stored_bytes: u64,
+    /// The label volume, once read
+    pub loaded: OnceCell<Arc<LabelVolume>>,
+}
+
+impl LabelFile {
+    /// Id of the labels shipped with a volume
+    pub fn id_for(volume_id: &str) -> String {
+        format!("{}~labels", volume_id)
+    }
+
+    /// Labels shipped with the volume at `path`: a label dataset in the file
+    /// itself or in a `{stem}_seg.h5` sibling. `Ok(None)` if there are none.
+    pub async fn find(path: &Path, volume: &VolumeInfo) -> Result<Option<Self>, HDF5Error> {
+        let path = path.to_path_buf();
+        let volume = volume.clone();
+        tokio::task::spawn_blocking(move || Self::find_sync(path, volume))
+            .await
+            .map_err(join_error)?
+    }
+
+    fn find_sync(path: PathBuf, volume: VolumeInfo) -> Result<Option<Self>, HDF5Error> {
+        let sibling = sibling_path(&path);
+        let candidates = [
+            (path.clone(), &LABEL_DATASETS[..]),
+            (sibling, &SIBLING_DATASETS[..]),
+        ];
+
+        for (label_path, names) in candidates {
+            if !label_path.exists() {
+                continue;
+            }
+            let file = File::open(&label_path)?;
+            let Some((name, dataset)) = names.iter().find_map(|&name| Some((name, file.dataset(name).ok()?))) else {
+                continue;
+            };
+
+            let shape = dataset.shape();
+            layout(&shape, volume.dimensions)?;
+            let stored_bytes = shape.iter().product::<usize>() as u64 * dataset.dtype()?.size() as u64;
+            return Ok(Some(Self {
+                id: Self::id_for(&volume.id),
+                volume,
+                volume_path: path,
+                path: label_path,
+                dataset: name.to_string(),
+                stored_bytes,
+                loaded: OnceCell::new(),
+            }));
+        }
+        Ok(None)
+    }
+
+    pub fn volume_id(&self) -> &str {
+        &self.volume.id
+    }
+
+    /// Memory needed to load the labels: the dataset as stored, its values
+    /// as u16 and their encoding
+    pub fn load_memory_estimate(&self) -> u64 {
+        let voxels = self.volume.dimensions.iter().map(|&d| d as u64).product::<u64>();
+        self.stored_bytes + 4 * voxels
+    }
+
+    /// Read the labels, their statistics and label table, and encode the voxels
+    pub async fn load(&self) -> Result<(LabelVolume, Vec<u8>), HDF5Error> {
+        let (path, dataset, volume_path) = (self.path.clone(), self.dataset.clone(), self.volume_path.clone());
+        let (id, volume) = (self.id.clone(), self.volume.clone());
+        tokio::task::spawn_blocking(move || {
+            let file = File::open(&path)?;
+            let data = file.dataset(&dataset)?;
+            let values = read_labels(&data, volume.dimensions)?;
+            let table = read_table(&file, &data)
+                .or_else(|| read_sidecar(&path))
+                .or_else(|| read_sidecar(&volume_path))
+                .unwrap_or_default();
+
+            let info = LabelVolume::info_from_array(id, &volume, &values, table);
+            let bytes = encode(&values, info.dtype);
+            tracing::info!(labels = %info.id, components = info.components.len(), "Loaded labels");
+            let labels = LabelVolume {
+                info,
+                source: LabelSource::File { path, dataset },
+                threshold: None,
+            };
+            Ok((labels, bytes))
+        })
+        .await
+        .map_err(join_error)?
+    }
+}
+
+impl LabelVolume {
+    /// Read the voxels of a label dataset again, encoded like when first loaded
+    pub async fn read_file(&self, path: &Path, dataset: &str) -> Result<Vec<u8>, HDF5Error> {
+        let (path, dataset) = (path.to_path_buf(), dataset.to_string());
+        let (dims, dtype) = (self.info.dimensions, self.info.dtype);
+        tokio::task::spawn_blocking(move || {
+            let file = File::open(&path)?;
+            let data = read_labels(&file.dataset(&dataset)?, dims)?;
+            Ok(encode(&data, dtype))
+        })
+        .await
+        .map_err(join_error)?
+    }
+
+    /// Metadata of an array of label values, with per-label statistics
+    fn info_from_array(id: String, volume: &VolumeInfo, data: &Array3<u16>, table: Vec<LabelEntry>) -> LabelVolumeInfo {
+        let mut stats: BTreeMap<u16, ComponentStats> = BTreeMap::new();
+        for ((x, y, z), &value) in data.indexed_iter() {
+            if value == 0 {
+                continue;
+            }
+            let voxel = [x as u32, y as u32, z as u32];
+            let entry = stats.entry(value).or_insert(ComponentStats {
+                label: value as u32,
+                voxel_count: 0,
+                bbox_min: voxel,
+                bbox_max: voxel,
+                physical_volume: 0.0,
+            });
+            entry.voxel_count += 1;
+            for (i, &c) in voxel.iter().enumerate() {
+                entry.bbox_min[i] = entry.bbox_min[i].min(c);
+                entry.bbox_max[i] = entry.bbox_max[i].max(c);
+            }
+        }
+
+        let voxel_volume = volume.spacing.iter().map(|&s| s as f64).product::<f64>();
+        let components: Vec<ComponentStats> = stats
+            .into_values()
+            .map(|mut c| {
+                c.physical_volume = c.voxel_count as f64 * voxel_volume;
+                c
+            })
+            .collect();
+
+        let dtype = if data.iter().all(|&v| v <= u8::MAX as u16) {
+            LabelDtype::U8
+        } else {
+            LabelDtype::U16
+        };
+
+        LabelVolumeInfo {
+            id,
+            volume_id: volume.id.clone(),
+            dimensions: volume.dimensions,
+            dtype,
+            components,
+            table,
+        }
+    }
+
+    /// Decode `bytes`, the voxels of this label volume, shaped like the intensity volume
+    pub fn values(&self, bytes: &[u8]) -> Array3<u16> {
+        let values: Vec<u16> = match self.info.dtype {
+            LabelDtype::U8 => bytes.iter().map(|&v| v as u16).collect(),
+            LabelDtype::U16 => bytes
+                .chunks_exact(2)
+                .map(|b| u16::from_le_bytes([b[0], b[1]]))
+                .collect(),
+        };
+        Array3::from_shape_vec(self.info.dimensions.map(|d| d as usize), values).unwrap()
+    }
+}
+
+/// Label values as little-endian integers of `dtype`
+fn encode(data: &Array3<u16>, dtype: LabelDtype) -> Vec<u8> {
+    match dtype {
+        LabelDtype::U8 => data.iter().map(|&v| v as u8).collect(),
+        LabelDtype::U16 => data.iter().flat_map(|v| v.to_le_bytes()).collect(),
+    }
+}
+
+/// Label volumes by id: those shipped with the file volumes, and at most
+/// `capacity` segmentations. Every distinct segment request adds one, so the
+/// least recently used are forgotten and must be requested again.
+pub struct LabelRegistry {
+    files: HashMap<String, LabelFile>,
+    capacity: usize,
+    segmentations: Mutex<SegmentationsInner>,
+}
+
+#[derive(Default)]
+struct SegmentationsInner {
+    /// Each segmentation with the tick of its last use
+    volumes: HashMap<String, (Arc<LabelVolume>, u64)>,
+    tick: u64,
+}
+
+impl LabelRegistry {
+    pub fn new(files: Vec<LabelFile>, capacity: usize) -> Self {
+        Self {
+            files: files.into_iter().map(|file| (file.id.clone(), file)).collect(),
+            capacity: capacity.max(1),
+            segmentations: Mutex::new(SegmentationsInner::default()),
+        }
+    }
+
+    pub fn file(&self, id: &str) -> Option<&LabelFile> {
+        self.files.get(id)
+    }
+
+    pub fn segmentation(&self, id: &str) -> Option<Arc<LabelVolume>> {
+        let mut inner = self.segmentations.lock().unwrap();
+        inner.tick += 1;
+        let tick = inner.tick;
+        let (labels, last_used) = inner.volumes.get_mut(id)?;
+        *last_used = tick;
+        Some(labels.clone())
+    }
+
+    /// Register a segmentation, or return the one already registered with its id
+    pub fn insert(&self, labels: LabelVolume) -> Arc<LabelVolume> {
+        let mut inner = self.segmentations.lock().unwrap();
+        inner.tick += 1;
+        let tick = inner.tick;
+        if let Some((existing, last_used)) = inner.volumes.get_mut(&labels.info.id) {
+            *last_used = tick;
+            return existing.clone();
+        }
+
+        if inner.volumes.len() >= self.capacity {
+            let oldest = inner
+                .volumes
+                .iter()
+                .min_by_key(|(_, (_, last_used))| *last_used)
+                .map(|(id, _)| id.clone());
+            if let Some(oldest) = oldest {
+                inner.volumes.remove(&oldest);
+                tracing::debug!(labels = %oldest, "Forgot segmentation");
+            }
+        }
+        let labels = Arc::new(labels);
+        inner.volumes.insert(labels.info.id.clone(), (labels.clone(), tick));
+        labels
+    }
+
+    /// Ids of the label volumes of an intensity volume, sorted
+    pub fn ids(&self, volume_id: &str) -> Vec<String> {
+        let inner = self.segmentations.lock().unwrap();
+        let mut ids: Vec<String> = self
+            .files
+            .values()
+            .filter(|file| file.volume_id() == volume_id)
+            .map(|file| file.id.clone())
+            .chain(
+                inner
+                    .volumes
+                    .values()
+                    .filter(|(labels, _)| labels.info.volume_id == volume_id)
+                    .map(|(labels, _)| labels.info.id.clone()),
+            )
+            .collect();
+        ids.sort();
+        ids
+    }
+}
+
+/// Whether `path` is a sibling label file rather than an intensity volume
+pub fn is_label_file(path: &Path) -> bool {
+    path.file_stem()
+        .and_then(|s| s.to_str())
+        .is_some_and(|s| s.ends_with(LABEL_FILE_SUFFIX))
+}
+
+fn sibling_path(path: &Path) -> PathBuf {
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or_default();
+    path.with_file_name(format!("{}{}.h5", stem, LABEL_FILE_SUFFIX))
+}
+
+/// How a label dataset's shape matches the intensity volume
+#[derive(Debug, PartialEq)]
+enum Layout {
+    /// A 3D array of label values
+    Values,
+    /// A 4D one-hot array with the channel axis first (0) or last (3)
+    OneHot { channel_axis: usize },
+}
+
+fn layout(shape: &[usize], dims: [u32; 3]) -> Result<Layout, HDF5Error> {
+    let dims = dims.map(|d| d as usize);
+    match shape.len() {
+        3 if shape[..] == dims[..] => Ok(Layout::Values),
+        4 if shape[..3] == dims[..] => Ok(Layout::OneHot { channel_axis: 3 }),
+        4 if shape[1..] == dims[..] => Ok(Layout::OneHot { channel_axis: 0 }),
+        _ => Err(HDF5Error::ShapeMismatch(format!("labels {:?}, volume {:?}", shape, dims))),
+    }
+}
+
+/// Read a label dataset as values, accepting either a 3D array of label
+/// values or a 4D one-hot array with the channel axis first or last
+fn read_labels(dataset: &hdf5::Dataset, dims: [u32; 3]) -> Result<Array3<u16>, HDF5Error> {
+    match layout(&dataset.shape(), dims)? {
+        Layout::Values => read_values(dataset),
+        Layout::OneHot { channel_axis } => {
+            // Channel c becomes label c + 1; the first set channel wins
+            let one_hot: Array4<u8> = dataset.read()?;
+            let mut labels = Array3::zeros(dims.map(|d| d as usize));
+            for (c, channel) in one_hot.axis_iter(Axis(channel_axis)).enumerate() {
+                Zip::from(&mut labels).and(&channel).for_each(|label, &v| {
+                    if v > 0 && *label == 0 {
+                        *label = c as u16 + 1;
+                    }
+                });
+            }
+            Ok(labels)
+        }
+    }
+}
+
+/// Read a 3D array of label values stored as any integer type
+fn read_values(dataset: &hdf5::Dataset) -> Result<Array3<u16>, HDF5Error> {
+    let descriptor = dataset.dtype()?.to_descriptor()?;
+    match descriptor {
+        TypeDescriptor::Unsigned(IntSize::U1) => Ok(dataset.read::<u8, Ix3>()?.mapv(u16::from)),
+        TypeDescriptor::Unsigned(IntSize::U2) => Ok(dataset.read()?),
+        TypeDescriptor::Unsigned(IntSize::U4) => to_u16(dataset.read::<u32, Ix3>()?),
+        TypeDescriptor::Unsigned(IntSize::U8) => to_u16(dataset.read::<u64, Ix3>()?),
+        TypeDescriptor::Integer(IntSize::U1) => to_u16(dataset.read::<i8, Ix3>()?),
+        TypeDescriptor::Integer(IntSize::U2) => to_u16(dataset.read::<i16, Ix3>()?),
+        TypeDescriptor::Integer(IntSize::U4) => to_u16(dataset.read::<i32, Ix3>()?),
+        TypeDescriptor::Integer(IntSize::U8) => to_u16(dataset.read::<i64, Ix3>()?),
+        other => Err(HDF5Error::Format(format!("labels of type {}, expected integers", other))),
+    }
+}
+
+/// Label values as u16, which holds every label the server serves
+fn to_u16<T: Copy + TryInto<u16> + std::fmt::Display>(data: Array3<T>) -> Result<Array3<u16>, HDF5Error> {
+    let values = data
+        .iter()
+        .map(|&v| {
+            v.try_into()
+                .map_err(|_| HDF5Error::Format(format!("label value {} outside 0..=65535", v)))
+        })
+        .collect::<Result<Vec<u16>, _>>()?;
+    Ok(Array3::from_shape_vec(data.raw_dim(), values).unwrap())
+}
+
+/// Label table from a JSON `label_table` attribute on the dataset or file root
+fn read_table(file: &File, dataset: &hdf5::Dataset) -> Option<Vec<LabelEntry>> {
+    let attr = dataset.attr(LABEL_TABLE_ATTR).or_else(|_| file.attr(LABEL_TABLE_ATTR)).ok()?;
+    let json = attr.read_scalar::<VarLenUnicode>().ok()?;
+    parse_table(json.as_str(), LABEL_TABLE_ATTR)
+}
+
+/// Label table from a `{stem}.labels.json` file next to `path`
+fn read_sidecar(path: &Path) -> Option<Vec<LabelEntry>> {
+    let sidecar = path.with_extension(LABEL_TABLE_EXTENSION);
+    let json = std::fs::read_to_string(&sidecar).ok()?;
+    parse_table(&json, &sidecar.display().to_string())
+}
+
+/// Parse a JSON list of `{"value", "name", "color"}` entries
+fn parse_table(json: &str, source: &str) -> Option<Vec<LabelEntry>> {
+    match serde_json::from_str(json) {
+        Ok(table) => Some(table),
+        Err(e) => {
+            tracing::warn!(source, error = %e, "Ignoring invalid label table");
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shared::ThresholdMethod;
+
+    fn segmentation(id: &str, volume_id: &str) -> LabelVolume {
+        LabelVolume {
+            info: LabelVolumeInfo {
+                id: id.to_string(),
+                volume_id: volume_id.to_string(),
+                dimensions: [1, 1, 1],
+                dtype: LabelDtype::U8,
+                components: Vec::new(),
+                table: Vec::new(),
+            },
+            source: LabelSource::Segmentation(SegmentRequest {
+                threshold: ThresholdMethod::Otsu,
+                open_radius: 0,
+                close_radius: 0,
+                min_component_size: 0,
+                full_connectivity: false,
+            }),
+            threshold: Some(0.5),
+        }
+    }
+
+    fn label_file(volume_id: &str) -> LabelFile {
+        LabelFile {
+            id: format!("{}~labels", volume_id),
+            volume: VolumeInfo {
+                id: volume_id.to_string(),
+                dimensions: [1, 1, 1],
+                version: "v1".to_string(),
+                ..Default::default()
+            },
+            volume_path: PathBuf::from("a.h5"),
+            path: PathBuf::from("a_seg.h5"),
+            dataset: "seg".to_string(),
+            stored_bytes: 0,
+            loaded: OnceCell::new(),
+        }
+    }
+
+    #[test]
+    fn registry_forgets_least_recently_used_segmentations() {
+        let registry = LabelRegistry::new(vec![label_file("a")], 2);
+        registry.insert(segmentation("a~seg-1", "a"));
+        registry.insert(segmentation("a~seg-2", "a"));
+        registry.segmentation("a~seg-1");
+        registry.insert(segmentation("b~seg-3", "b"));
+
+        assert!(registry.segmentation("a~seg-2").is_none());
+        assert!(registry.file("a~labels").is_some());
+        assert_eq!(registry.ids("a"), ["a~labels", "a~seg-1"]);
+        assert_eq!(registry.ids("b"), ["b~seg-3"]);
+    }
+
+    #[test]
+    fn layout_matches_values_or_one_hot() {
+        assert_eq!(layout(&[4, 5, 6], [4, 5, 6]).unwrap(), Layout::Values);
+        assert_eq!(layout(&[4, 5, 6, 3], [4, 5, 6]).unwrap(), Layout::OneHot { channel_axis: 3 });
+        assert_eq!(layout(&[3, 4, 5, 6], [4, 5, 6]).unwrap(), Layout::OneHot { channel_axis: 0 });
+        assert!(layout(&[4, 5, 7], [4, 5, 6]).is_err());
+        assert!(layout(&[4, 5], [4, 5, 6]).is_err());
+    }
+
+    #[test]
+    fn wider_integers_convert_when_in_range() {
+        let data = Array3::from_shape_vec((1, 1, 3), vec![0u32, 7, 65535]).unwrap();
+        assert_eq!(to_u16(data).unwrap().into_raw_vec(), vec![0, 7, 65535]);
+        assert!(to_u16(Array3::from_elem((1, 1, 1), 70000u32)).is_err());
+        assert!(to_u16(Array3::from_elem((1, 1, 1), -1i32)).is_err());
+    }
+
+    #[test]
+    fn values_decode_both_dtypes() {
+        let mut labels = segmentation("a~seg", "a");
+        labels.info.dimensions = [2, 1, 1];
+        assert_eq!(labels.values(&[3, 4]).into_raw_vec(), vec![3, 4]);
+        labels.info.dtype = LabelDtype::U16;
+        assert_eq!(labels.values(&[1, 1, 0, 2]).into_raw_vec(), vec![257, 512]);
+        let data = Array3::from_shape_vec((2, 1, 1), vec![257u16, 512]).unwrap();
+        assert_eq!(encode(&data, LabelDtype::U16), vec![1, 1, 0, 2]);
+    }
+}
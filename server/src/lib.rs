@@ -0,0 +1,26 @@
+//! Volume reading, processing and the HTTP API, shared by the server binary
+//! and the `3dlab` command-line tool
+
+pub mod admission;
+pub mod annotations;
+pub mod api;
+pub mod cache;
+pub mod catalog;
+pub mod compare;
+pub mod derived;
+pub mod error;
+pub mod export;
+pub mod fastmri;
+pub mod fft;
+pub mod filters;
+pub mod hdf5_reader;
+pub mod labels;
+pub mod mesh;
+pub mod metrics;
+pub mod phantom;
+pub mod routes;
+pub mod segment;
+pub mod session;
+pub mod spectrum;
+pub mod state;
+pub mod storage;
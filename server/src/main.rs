@@ -1,85 +1,97 @@
-mod hdf5_reader;
-mod routes;
-mod state;
-
 use axum::{
     extract::ConnectInfo,
+    http::HeaderValue,
     middleware::{self, Next},
     response::Response,
     routing::get,
-    Router,
+    Json,
 };
 use std::net::SocketAddr;
 use std::sync::Arc;
-use std::time::Instant;
 use tower_http::cors::{Any, CorsLayer};
 use tower_http::services::ServeDir;
-
-use state::AppState;
-
-// ANSI colors
-const GREEN: &str = "\x1b[32m";
-const BLUE: &str = "\x1b[34m";
-const YELLOW: &str = "\x1b[33m";
-const RED: &str = "\x1b[31m";
-const MAGENTA: &str = "\x1b[35m";
-const CYAN: &str = "\x1b[36m";
-const RESET: &str = "\x1b[0m";
-const DIM: &str = "\x1b[2m";
-
-fn method_color(method: &str) -> &'static str {
-    match method {
-        "GET" => GREEN,
-        "POST" => BLUE,
-        "PUT" => YELLOW,
-        "DELETE" => RED,
-        "PATCH" => MAGENTA,
-        _ => CYAN,
+use tracing::Instrument;
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::EnvFilter;
+
+use server::api;
+use server::catalog;
+use server::metrics;
+use server::phantom;
+use server::state::AppState;
+
+/// Header carrying the id of a request, taken from the client or generated
+const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Longest client-supplied request id that is kept
+const MAX_REQUEST_ID_LEN: usize = 64;
+
+/// Log to stderr as text, or as one JSON object per line with `LOG_FORMAT=json`.
+/// `RUST_LOG` sets the filter (default `info`). Closing spans are logged with
+/// their duration, so each request, HDF5 read and resample gets a timing line.
+fn init_logging() {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_span_events(FmtSpan::CLOSE)
+        .with_writer(std::io::stderr);
+
+    if std::env::var("LOG_FORMAT").is_ok_and(|f| f.eq_ignore_ascii_case("json")) {
+        builder.json().init();
+    } else {
+        builder.init();
     }
 }
 
-async fn log_request(
+/// Run each request in a span named by its request id, echo the id in the
+/// response, and record the status and body size on the span
+async fn trace_request(
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
     request: axum::extract::Request,
     next: Next,
 ) -> Response {
-    let method = request.method().to_string();
-    let uri = request.uri().to_string();
-    let start = Instant::now();
-
-    let response = next.run(request).await;
-
-    let status = response.status().as_u16();
-    let duration = start.elapsed();
-    let duration_ms = duration.as_secs_f64() * 1000.0;
-
-    let status_color = if status < 400 { GREEN } else { RED };
-
-    println!(
-        "{DIM}{:<15}{RESET} {}{:>7}{} {}{:<50}{} {}{:>3}{} {DIM}{:.1}ms{RESET}",
-        addr.ip(),
-        method_color(&method),
-        method,
-        RESET,
-        RESET,
-        uri,
-        DIM,
-        status_color,
-        status,
-        RESET,
-        duration_ms,
+    let request_id = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .filter(|id| !id.is_empty() && id.len() <= MAX_REQUEST_ID_LEN)
+        .map(str::to_string)
+        .unwrap_or_else(|| uuid::Uuid::new_v4().simple().to_string());
+
+    let span = tracing::info_span!(
+        "request",
+        id = %request_id,
+        client = %addr.ip(),
+        method = %request.method(),
+        uri = %request.uri(),
+        status = tracing::field::Empty,
+        bytes = tracing::field::Empty,
     );
 
+    let mut response = next.run(request).instrument(span.clone()).await;
+
+    span.record("status", response.status().as_u16());
+    if let Some(bytes) = metrics::response_bytes(&response) {
+        span.record("bytes", bytes);
+    }
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
     response
 }
 
 #[tokio::main]
 async fn main() {
-    println!("{}Starting 3DLab server...{}", CYAN, RESET);
-
-    // Initialize app state (scans samples/ for H5 files)
-    let state = Arc::new(AppState::new("samples").await);
-    println!("{}Found {} volumes{}", GREEN, state.volumes.len(), RESET);
+    init_logging();
+    tracing::info!("Starting 3DLab server");
+
+    // Initialize app state (scans the data roots for volume files and generates the phantoms)
+    let state = Arc::new(AppState::new(&catalog::data_roots(), &phantom::from_env()).await);
+    tracing::info!(
+        volumes = state.volumes.len(),
+        cache_budget_mb = state.cache.budget_bytes() / (1024 * 1024),
+        "Volumes loaded"
+    );
 
     // CORS for development
     let cors = CorsLayer::new()
@@ -88,25 +100,20 @@ async fn main() {
         .allow_headers(Any)
         .expose_headers(Any);
 
-    // API routes
-    let api_routes = Router::new()
-        .route("/health", get(routes::health))
-        .route("/volumes", get(routes::list_volumes))
-        .route("/volumes/{id}/info", get(routes::get_volume_info))
-        .route("/volumes/{id}/low", get(routes::get_volume_low))
-        .route("/volumes/{id}/full", get(routes::get_volume_full))
-        .route("/volumes/{id}/at/{resolution}", get(routes::get_volume_at_resolution))
-        .with_state(state.clone());
+    // API routes, and their OpenAPI description
+    let (api_routes, openapi) = api::router().split_for_parts();
 
     // Main router
-    let app = Router::new()
-        .nest("/api", api_routes)
+    let app = api_routes
+        .route("/api/openapi.json", get(|| async move { Json(openapi) }))
+        .route_layer(middleware::from_fn_with_state(state.clone(), metrics::track))
+        .with_state(state.clone())
         .fallback_service(ServeDir::new("client/dist").append_index_html_on_directories(true))
         .layer(cors)
-        .layer(middleware::from_fn(log_request));
+        .layer(middleware::from_fn(trace_request));
 
     let addr = SocketAddr::from(([0, 0, 0, 0], 9000));
-    println!("{}Server listening on http://localhost:9000{}", GREEN, RESET);
+    tracing::info!("Server listening on http://localhost:9000");
 
     let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
     axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
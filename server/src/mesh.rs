@@ -0,0 +1,477 @@
+use ndarray::{s, Array3};
+use std::collections::HashMap;
+use tokio_util::sync::CancellationToken;
+
+use crate::filters::check;
+use crate::hdf5_reader::HDF5Error;
+
+pub use shared::MeshFormat;
+
+/// Largest number of smoothing iterations accepted
+pub const MAX_SMOOTHING: u32 = 50;
+
+/// Largest decimation cell size accepted, in voxels
+pub const MAX_DECIMATION: u32 = 16;
+
+/// Marks a cell without a vertex in the cell-to-vertex index
+const NO_VERTEX: u32 = u32::MAX;
+
+/// Bytes of mesh per cell the surface passes through: a position and a
+/// normal, and up to three quads of two triangles
+const MESH_BYTES_PER_SURFACE_CELL: u64 = 24 + 3 * 2 * 12;
+
+/// Fraction of the cells assumed to hold surface when estimating memory
+const SURFACE_CELL_FRACTION: u64 = 8;
+
+/// Memory needed to extract a surface from a field of `dims`: the field,
+/// its padded copy and the cell-to-vertex index at 4 bytes per voxel each,
+/// plus a mesh through one cell in `SURFACE_CELL_FRACTION`
+pub fn memory_estimate(dims: [u32; 3]) -> u64 {
+    let voxels = dims.iter().map(|&d| d as u64 + 2).product::<u64>();
+    3 * 4 * voxels + voxels / SURFACE_CELL_FRACTION * MESH_BYTES_PER_SURFACE_CELL
+}
+
+/// Triangle mesh with per-vertex normals
+#[derive(Default)]
+pub struct Mesh {
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+    pub triangles: Vec<[u32; 3]>,
+}
+
+/// Extract the surface where `field` crosses `iso` with surface nets (a
+/// dual-contouring variant: one vertex per boundary cell, one quad per
+/// crossed grid edge). Positions are in voxel coordinates; triangles face
+/// away from values above `iso`. The surface is closed at the volume edges.
+pub fn extract(field: &Array3<f32>, iso: f32, cancel: &CancellationToken) -> Result<Mesh, HDF5Error> {
+    // Pad with a layer below the iso-value so surfaces touching the edges close
+    let outside = field.iter().cloned().fold(iso, f32::min) - 1.0;
+    let (fx, fy, fz) = field.dim();
+    let mut padded = Array3::from_elem((fx + 2, fy + 2, fz + 2), outside);
+    padded.slice_mut(s![1..fx + 1, 1..fy + 1, 1..fz + 1]).assign(field);
+    let field = &padded;
+
+    let shape = field.shape();
+    let (nx, ny, nz) = (shape[0], shape[1], shape[2]);
+    let mut mesh = Mesh::default();
+
+    // One vertex per cell that the surface passes through
+    let mut cell_vertex = Array3::from_elem((nx - 1, ny - 1, nz - 1), NO_VERTEX);
+    for x in 0..nx - 1 {
+        check(cancel)?;
+        for y in 0..ny - 1 {
+            for z in 0..nz - 1 {
+                let corner = |c: usize| [x + (c & 1), y + ((c >> 1) & 1), z + ((c >> 2) & 1)];
+                let values: [f32; 8] = std::array::from_fn(|c| field[corner(c)]);
+                let inside = values.iter().filter(|&&v| v > iso).count();
+                if inside == 0 || inside == 8 {
+                    continue;
+                }
+
+                // Average of the points where the surface crosses the cell's edges
+                let mut sum = [0.0f32; 3];
+                let mut crossings = 0;
+                for a in 0..8 {
+                    for bit in [1, 2, 4] {
+                        let b = a | bit;
+                        if b == a || (values[a] > iso) == (values[b] > iso) {
+                            continue;
+                        }
+                        let t = (iso - values[a]) / (values[b] - values[a]);
+                        let (pa, pb) = (corner(a), corner(b));
+                        for i in 0..3 {
+                            sum[i] += pa[i] as f32 + t * (pb[i] as f32 - pa[i] as f32);
+                        }
+                        crossings += 1;
+                    }
+                }
+                cell_vertex[[x, y, z]] = mesh.positions.len() as u32;
+                // Back to unpadded voxel coordinates
+                mesh.positions.push(sum.map(|s| s / crossings as f32 - 1.0));
+            }
+        }
+    }
+
+    // One quad per grid edge with a sign change, joining the four cells around it
+    for x in 0..nx {
+        check(cancel)?;
+        for y in 0..ny {
+            for z in 0..nz {
+                let p = [x, y, z];
+                for axis in 0..3 {
+                    let mut q = p;
+                    q[axis] += 1;
+                    if q[axis] >= shape[axis] {
+                        continue;
+                    }
+                    let (v0, v1) = (field[p], field[q]);
+                    if (v0 > iso) == (v1 > iso) {
+                        continue;
+                    }
+
+                    // The four cells around the edge lie below and at p along the other axes
+                    let (b, c) = ((axis + 1) % 3, (axis + 2) % 3);
+                    if p[b] == 0 || p[c] == 0 || p[b] >= shape[b] - 1 || p[c] >= shape[c] - 1 {
+                        continue;
+                    }
+                    let cell = |db: usize, dc: usize| {
+                        let mut cell = p;
+                        cell[b] -= db;
+                        cell[c] -= dc;
+                        Some(cell_vertex[cell]).filter(|&v| v != NO_VERTEX)
+                    };
+                    let quad = [cell(1, 1), cell(0, 1), cell(0, 0), cell(1, 0)];
+                    let Some(quad) = quad.into_iter().collect::<Option<Vec<u32>>>() else {
+                        continue;
+                    };
+
+                    // Outward is towards the end of the edge below the iso-value
+                    let mut outward = [0.0; 3];
+                    outward[axis] = if v0 > iso { 1.0 } else { -1.0 };
+                    for tri in [[quad[0], quad[1], quad[2]], [quad[0], quad[2], quad[3]]] {
+                        let normal = face_normal(&mesh.positions, tri);
+                        if dot(normal, outward) < 0.0 {
+                            mesh.triangles.push([tri[0], tri[2], tri[1]]);
+                        } else {
+                            mesh.triangles.push(tri);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    mesh.compute_normals();
+    Ok(mesh)
+}
+
+impl Mesh {
+    pub fn is_empty(&self) -> bool {
+        self.triangles.is_empty()
+    }
+
+    /// Merge vertices within each cube of `cell` voxels (vertex clustering)
+    /// and drop triangles that collapse
+    pub fn decimate(&mut self, cell: u32) {
+        if cell <= 1 {
+            return;
+        }
+        let size = cell as f32;
+        let mut clusters: HashMap<[i32; 3], u32> = HashMap::new();
+        let mut sums: Vec<([f32; 3], u32)> = Vec::new();
+        let remap: Vec<u32> = self
+            .positions
+            .iter()
+            .map(|p| {
+                let key = p.map(|v| (v / size).floor() as i32);
+                let index = *clusters.entry(key).or_insert_with(|| {
+                    sums.push(([0.0; 3], 0));
+                    sums.len() as u32 - 1
+                });
+                let (sum, count) = &mut sums[index as usize];
+                for i in 0..3 {
+                    sum[i] += p[i];
+                }
+                *count += 1;
+                index
+            })
+            .collect();
+
+        self.positions = sums.iter().map(|(sum, n)| sum.map(|s| s / *n as f32)).collect();
+        self.triangles = self
+            .triangles
+            .iter()
+            .map(|t| t.map(|i| remap[i as usize]))
+            .filter(|t| t[0] != t[1] && t[1] != t[2] && t[0] != t[2])
+            .collect();
+        self.compute_normals();
+    }
+
+    /// Taubin smoothing: alternating shrink and inflate Laplacian steps, which
+    /// smooths without the volume loss of plain Laplacian smoothing
+    pub fn smooth(&mut self, iterations: u32) {
+        if iterations == 0 {
+            return;
+        }
+        let mut neighbours: Vec<Vec<u32>> = vec![Vec::new(); self.positions.len()];
+        for t in &self.triangles {
+            for (a, b) in [(t[0], t[1]), (t[1], t[2]), (t[2], t[0])] {
+                neighbours[a as usize].push(b);
+                neighbours[b as usize].push(a);
+            }
+        }
+        for list in &mut neighbours {
+            list.sort_unstable();
+            list.dedup();
+        }
+
+        for _ in 0..iterations {
+            for factor in [0.5, -0.53] {
+                let previous = self.positions.clone();
+                for (i, list) in neighbours.iter().enumerate() {
+                    if list.is_empty() {
+                        continue;
+                    }
+                    let mut mean = [0.0f32; 3];
+                    for &n in list {
+                        for (m, v) in mean.iter_mut().zip(previous[n as usize]) {
+                            *m += v;
+                        }
+                    }
+                    for (k, m) in mean.iter().enumerate() {
+                        let average = m / list.len() as f32;
+                        self.positions[i][k] += factor * (average - previous[i][k]);
+                    }
+                }
+            }
+        }
+        self.compute_normals();
+    }
+
+    /// Map voxel coordinates to millimetres
+    pub fn scale(&mut self, spacing: [f32; 3]) {
+        for p in &mut self.positions {
+            for i in 0..3 {
+                p[i] *= spacing[i];
+            }
+        }
+        self.compute_normals();
+    }
+
+    /// Area-weighted vertex normals from the triangles
+    fn compute_normals(&mut self) {
+        let mut normals = vec![[0.0f32; 3]; self.positions.len()];
+        for &t in &self.triangles {
+            let n = face_normal(&self.positions, t);
+            for &i in &t {
+                for k in 0..3 {
+                    normals[i as usize][k] += n[k];
+                }
+            }
+        }
+        self.normals = normals.into_iter().map(normalize).collect();
+    }
+
+    /// Serialize in the requested format
+    pub fn encode(&self, format: MeshFormat) -> Vec<u8> {
+        match format {
+            MeshFormat::Glb => self.to_glb(),
+            MeshFormat::Obj => self.to_obj(),
+            MeshFormat::Stl => self.to_stl(),
+        }
+    }
+
+    fn to_obj(&self) -> Vec<u8> {
+        use std::fmt::Write;
+
+        let mut out = String::from("# 3DLab isosurface, units: mm\n");
+        for p in &self.positions {
+            let _ = writeln!(out, "v {} {} {}", p[0], p[1], p[2]);
+        }
+        for n in &self.normals {
+            let _ = writeln!(out, "vn {} {} {}", n[0], n[1], n[2]);
+        }
+        for t in &self.triangles {
+            let (a, b, c) = (t[0] + 1, t[1] + 1, t[2] + 1);
+            let _ = writeln!(out, "f {a}//{a} {b}//{b} {c}//{c}");
+        }
+        out.into_bytes()
+    }
+
+    fn to_stl(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(84 + self.triangles.len() * 50);
+        let mut header = [0u8; 80];
+        let title = b"3DLab isosurface, units: mm";
+        header[..title.len()].copy_from_slice(title);
+        out.extend_from_slice(&header);
+        out.extend_from_slice(&(self.triangles.len() as u32).to_le_bytes());
+        for &t in &self.triangles {
+            let normal = normalize(face_normal(&self.positions, t));
+            for v in normal.iter().chain(t.iter().flat_map(|&i| &self.positions[i as usize])) {
+                out.extend_from_slice(&v.to_le_bytes());
+            }
+            out.extend_from_slice(&0u16.to_le_bytes());
+        }
+        out
+    }
+
+    fn to_glb(&self) -> Vec<u8> {
+        let floats = |values: &[[f32; 3]]| -> Vec<u8> {
+            values.iter().flatten().flat_map(|v| v.to_le_bytes()).collect()
+        };
+        let mut bin = floats(&self.positions);
+        let normals_offset = bin.len();
+        bin.extend(floats(&self.normals));
+        let indices_offset = bin.len();
+        bin.extend(self.triangles.iter().flatten().flat_map(|i| i.to_le_bytes()));
+
+        let mut min = [f32::INFINITY; 3];
+        let mut max = [f32::NEG_INFINITY; 3];
+        for p in &self.positions {
+            for i in 0..3 {
+                min[i] = min[i].min(p[i]);
+                max[i] = max[i].max(p[i]);
+            }
+        }
+
+        // glTF units are metres; the node scale maps the millimetre positions
+        let json = serde_json::json!({
+            "asset": { "version": "2.0", "generator": "3DLab" },
+            "scene": 0,
+            "scenes": [{ "nodes": [0] }],
+            "nodes": [{ "mesh": 0, "scale": [0.001, 0.001, 0.001] }],
+            "meshes": [{
+                "primitives": [{ "attributes": { "POSITION": 0, "NORMAL": 1 }, "indices": 2 }]
+            }],
+            "buffers": [{ "byteLength": bin.len() }],
+            "bufferViews": [
+                { "buffer": 0, "byteOffset": 0, "byteLength": normals_offset, "target": 34962 },
+                { "buffer": 0, "byteOffset": normals_offset, "byteLength": indices_offset - normals_offset, "target": 34962 },
+                { "buffer": 0, "byteOffset": indices_offset, "byteLength": bin.len() - indices_offset, "target": 34963 }
+            ],
+            "accessors": [
+                { "bufferView": 0, "componentType": 5126, "count": self.positions.len(), "type": "VEC3", "min": min, "max": max },
+                { "bufferView": 1, "componentType": 5126, "count": self.normals.len(), "type": "VEC3" },
+                { "bufferView": 2, "componentType": 5125, "count": self.triangles.len() * 3, "type": "SCALAR" }
+            ]
+        });
+
+        // Chunks are padded to 4 bytes: JSON with spaces, binary with zeros
+        let mut json = serde_json::to_vec(&json).unwrap();
+        json.resize(json.len().next_multiple_of(4), b' ');
+        bin.resize(bin.len().next_multiple_of(4), 0);
+
+        let total = 12 + 8 + json.len() + 8 + bin.len();
+        let mut out = Vec::with_capacity(total);
+        out.extend_from_slice(b"glTF");
+        out.extend_from_slice(&2u32.to_le_bytes());
+        out.extend_from_slice(&(total as u32).to_le_bytes());
+        out.extend_from_slice(&(json.len() as u32).to_le_bytes());
+        out.extend_from_slice(b"JSON");
+        out.extend_from_slice(&json);
+        out.extend_from_slice(&(bin.len() as u32).to_le_bytes());
+        out.extend_from_slice(b"BIN\0");
+        out.extend_from_slice(&bin);
+        out
+    }
+}
+
+/// Unnormalized normal of a triangle (length is twice its area)
+fn face_normal(positions: &[[f32; 3]], t: [u32; 3]) -> [f32; 3] {
+    let [a, b, c] = t.map(|i| positions[i as usize]);
+    let u = [b[0] - a[0], b[1] - a[1], b[2] - a[2]];
+    let v = [c[0] - a[0], c[1] - a[1], c[2] - a[2]];
+    [
+        u[1] * v[2] - u[2] * v[1],
+        u[2] * v[0] - u[0] * v[2],
+        u[0] * v[1] - u[1] * v[0],
+    ]
+}
+
+fn dot(a: [f32; 3], b: [f32; 3]) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn normalize(v: [f32; 3]) -> [f32; 3] {
+    let length = dot(v, v).sqrt();
+    if length > 0.0 {
+        v.map(|c| c / length)
+    } else {
+        v
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Distance from the centre of an `n`-voxel cube, so the surface at
+    /// `-radius` is a sphere
+    fn sphere(n: usize) -> Array3<f32> {
+        let c = (n - 1) as f32 / 2.0;
+        Array3::from_shape_fn((n, n, n), |(x, y, z)| {
+            -((x as f32 - c).powi(2) + (y as f32 - c).powi(2) + (z as f32 - c).powi(2)).sqrt()
+        })
+    }
+
+    /// Every edge of a closed, consistently oriented mesh is used once in each direction
+    fn assert_closed(mesh: &Mesh) {
+        let mut edges = HashMap::new();
+        for t in &mesh.triangles {
+            for (a, b) in [(t[0], t[1]), (t[1], t[2]), (t[2], t[0])] {
+                *edges.entry((a, b)).or_insert(0) += 1;
+            }
+        }
+        for (&(a, b), &count) in &edges {
+            assert_eq!(count, 1, "edge {}-{} used {} times", a, b, count);
+            assert_eq!(edges.get(&(b, a)), Some(&1), "edge {}-{} has no twin", a, b);
+        }
+    }
+
+    #[test]
+    fn sphere_surface_is_closed_and_on_the_radius() {
+        let mesh = extract(&sphere(16), -5.0, &CancellationToken::new()).unwrap();
+        assert!(!mesh.is_empty());
+        assert_closed(&mesh);
+        let c = 7.5;
+        for p in &mesh.positions {
+            let r = ((p[0] - c).powi(2) + (p[1] - c).powi(2) + (p[2] - c).powi(2)).sqrt();
+            assert!((r - 5.0).abs() < 0.5, "vertex at radius {}", r);
+        }
+
+        // Normals point away from the inside (values above the iso-value)
+        for (p, n) in mesh.positions.iter().zip(&mesh.normals) {
+            assert!(dot([p[0] - c, p[1] - c, p[2] - c], *n) > 0.0);
+        }
+    }
+
+    #[test]
+    fn surfaces_touching_the_edges_are_closed() {
+        let mesh = extract(&Array3::from_elem((3, 4, 5), 1.0), 0.5, &CancellationToken::new()).unwrap();
+        assert_closed(&mesh);
+        let max = mesh.positions.iter().fold([f32::MIN; 3], |m, p| std::array::from_fn(|i| m[i].max(p[i])));
+        assert!(max[0] > 2.0 && max[1] > 3.0 && max[2] > 4.0, "{:?}", max);
+    }
+
+    #[test]
+    fn no_crossing_gives_an_empty_mesh() {
+        let mesh = extract(&Array3::zeros((4, 4, 4)), 0.5, &CancellationToken::new()).unwrap();
+        assert!(mesh.is_empty());
+    }
+
+    #[test]
+    fn cancelled_extraction_stops() {
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        assert!(matches!(extract(&sphere(8), -2.0, &cancel), Err(HDF5Error::Cancelled)));
+    }
+
+    #[test]
+    fn decimation_merges_vertices_and_smoothing_keeps_them() {
+        let mut mesh = extract(&sphere(16), -5.0, &CancellationToken::new()).unwrap();
+        let (vertices, triangles) = (mesh.positions.len(), mesh.triangles.len());
+        mesh.decimate(2);
+        assert!(mesh.positions.len() < vertices && mesh.triangles.len() < triangles);
+        mesh.smooth(3);
+        assert_eq!(mesh.normals.len(), mesh.positions.len());
+        assert!(mesh.positions.iter().flatten().all(|v| v.is_finite()));
+    }
+
+    #[test]
+    fn encodings_have_the_expected_layout() {
+        let mut mesh = extract(&sphere(8), -2.0, &CancellationToken::new()).unwrap();
+        mesh.scale([1.0, 2.0, 0.5]);
+
+        let stl = mesh.encode(MeshFormat::Stl);
+        assert_eq!(stl.len(), 84 + 50 * mesh.triangles.len());
+        assert_eq!(u32::from_le_bytes(stl[80..84].try_into().unwrap()) as usize, mesh.triangles.len());
+
+        let glb = mesh.encode(MeshFormat::Glb);
+        assert_eq!(&glb[..4], b"glTF");
+        assert_eq!(u32::from_le_bytes(glb[8..12].try_into().unwrap()) as usize, glb.len());
+
+        let obj = String::from_utf8(mesh.encode(MeshFormat::Obj)).unwrap();
+        assert_eq!(obj.lines().filter(|l| l.starts_with("v ")).count(), mesh.positions.len());
+        assert_eq!(obj.lines().filter(|l| l.starts_with("f ")).count(), mesh.triangles.len());
+    }
+}
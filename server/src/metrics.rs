@@ -0,0 +1,331 @@
+use axum::{
+    body::{Body, HttpBody},
+    extract::{MatchedPath, Request, State},
+    http::{header, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tokio_stream::StreamExt;
+
+use shared::{AdmissionStatus, CacheStats};
+use crate::state::AppState;
+
+/// Upper bounds of the latency histogram buckets, in seconds
+const BUCKETS: [f64; 12] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0];
+
+#[derive(Default)]
+struct Histogram {
+    /// Observations per bucket (not cumulative); the last one is +Inf
+    counts: [u64; BUCKETS.len() + 1],
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, seconds: f64) {
+        let bucket = BUCKETS.iter().position(|&b| seconds <= b).unwrap_or(BUCKETS.len());
+        self.counts[bucket] += 1;
+        self.sum += seconds;
+    }
+
+    fn render(&self, out: &mut String, name: &str, labels: &str) {
+        let separator = if labels.is_empty() { "" } else { "," };
+        let mut cumulative = 0;
+        for (i, count) in self.counts.iter().enumerate() {
+            cumulative += count;
+            let le = BUCKETS.get(i).map_or("+Inf".to_string(), |b| b.to_string());
+            let _ = writeln!(out, "{name}_bucket{{{labels}{separator}le=\"{le}\"}} {cumulative}");
+        }
+        let _ = writeln!(out, "{name}_sum{{{labels}}} {}", self.sum);
+        let _ = writeln!(out, "{name}_count{{{labels}}} {cumulative}");
+    }
+}
+
+/// Counters of one route and method
+#[derive(Default)]
+struct RouteMetrics {
+    latency: Histogram,
+    /// Body bytes sent, counted as they are handed to the connection
+    bytes: u64,
+    /// Responses by status code
+    statuses: BTreeMap<u16, u64>,
+}
+
+/// Request and volume load metrics, rendered in the Prometheus text format at /metrics
+#[derive(Default)]
+pub struct Metrics {
+    /// By (route pattern, method)
+    requests: Mutex<BTreeMap<(String, String), RouteMetrics>>,
+    /// Time to open a file or load data into the cache, by stage
+    loads: Mutex<BTreeMap<&'static str, Histogram>>,
+}
+
+impl Metrics {
+    pub fn record_request(&self, route: &str, method: &str, status: u16, seconds: f64) {
+        let mut requests = self.requests.lock().unwrap();
+        let entry = requests.entry((route.to_string(), method.to_string())).or_default();
+        entry.latency.observe(seconds);
+        *entry.statuses.entry(status).or_default() += 1;
+    }
+
+    pub fn record_bytes(&self, route: &str, method: &str, bytes: u64) {
+        let mut requests = self.requests.lock().unwrap();
+        requests.entry((route.to_string(), method.to_string())).or_default().bytes += bytes;
+    }
+
+    pub fn record_load(&self, stage: &'static str, seconds: f64) {
+        self.loads.lock().unwrap().entry(stage).or_default().observe(seconds);
+    }
+
+    /// Run `load` and, if it succeeds, record how long it took under `stage`
+    pub async fn time_load<T, E>(&self, stage: &'static str, load: impl Future<Output = Result<T, E>>) -> Result<T, E> {
+        let start = Instant::now();
+        let result = load.await;
+        if result.is_ok() {
+            self.record_load(stage, start.elapsed().as_secs_f64());
+        }
+        result
+    }
+
+    pub fn render(&self, cache: &CacheStats, admission: &AdmissionStatus) -> String {
+        let mut out = String::new();
+
+        let requests = self.requests.lock().unwrap();
+        out.push_str("# HELP http_requests_total API requests by route, method and status.\n");
+        out.push_str("# TYPE http_requests_total counter\n");
+        for ((route, method), metrics) in requests.iter() {
+            for (status, count) in &metrics.statuses {
+                let _ = writeln!(
+                    out,
+                    "http_requests_total{{route=\"{route}\",method=\"{method}\",status=\"{status}\"}} {count}"
+                );
+            }
+        }
+        out.push_str("# HELP http_request_duration_seconds Time until the response headers were sent.\n");
+        out.push_str("# TYPE http_request_duration_seconds histogram\n");
+        for ((route, method), metrics) in requests.iter() {
+            let labels = format!("route=\"{route}\",method=\"{method}\"");
+            metrics.latency.render(&mut out, "http_request_duration_seconds", &labels);
+        }
+        out.push_str("# HELP http_response_bytes_total Response body bytes sent, including those of aborted downloads.\n");
+        out.push_str("# TYPE http_response_bytes_total counter\n");
+        for ((route, method), metrics) in requests.iter() {
+            let _ = writeln!(
+                out,
+                "http_response_bytes_total{{route=\"{route}\",method=\"{method}\"}} {}",
+                metrics.bytes
+            );
+        }
+        drop(requests);
+
+        out.push_str("# HELP volume_load_duration_seconds Time to open a volume file or load data into the cache.\n");
+        out.push_str("# TYPE volume_load_duration_seconds histogram\n");
+        for (stage, histogram) in self.loads.lock().unwrap().iter() {
+            histogram.render(&mut out, "volume_load_duration_seconds", &format!("stage=\"{stage}\""));
+        }
+
+        let lookups = cache.hits + cache.misses;
+        let hit_ratio = if lookups > 0 { cache.hits as f64 / lookups as f64 } else { 0.0 };
+        for (name, kind, help, value) in [
+            ("volume_cache_hits_total", "counter", "Cache lookups served from memory.", cache.hits as f64),
+            ("volume_cache_misses_total", "counter", "Cache lookups that computed the value.", cache.misses as f64),
+            ("volume_cache_coalesced_total", "counter", "Lookups that waited on an identical computation.", cache.coalesced as f64),
+            ("volume_cache_hit_ratio", "gauge", "Hits over hits plus misses since startup.", hit_ratio),
+            ("volume_cache_entries", "gauge", "Cached entries.", cache.entries as f64),
+            ("volume_cache_used_bytes", "gauge", "Bytes held by the cache.", cache.used_bytes as f64),
+            ("volume_cache_budget_bytes", "gauge", "Cache memory budget.", cache.budget_bytes as f64),
+            ("admission_in_flight_bytes", "gauge", "Memory reserved by admitted heavy requests.", admission.in_flight_bytes as f64),
+            ("admission_rejected_total", "counter", "Heavy requests rejected as overloaded.", admission.rejected as f64),
+        ] {
+            let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} {kind}\n{name} {value}");
+        }
+
+        out.push_str("# HELP admission_queued Heavy requests waiting for a slot, by route.\n");
+        out.push_str("# TYPE admission_queued gauge\n");
+        for route in &admission.routes {
+            let _ = writeln!(out, "admission_queued{{route=\"{}\"}} {}", route.route, route.queued);
+        }
+        out
+    }
+}
+
+/// Body size of a response, if known before it is sent
+pub fn response_bytes(response: &Response) -> Option<u64> {
+    response
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok()?.parse().ok())
+        .or_else(|| response.body().size_hint().exact())
+}
+
+/// Calls `record` with the bytes counted so far when dropped
+struct SentBytes<F: FnOnce(u64)> {
+    bytes: u64,
+    record: Option<F>,
+}
+
+impl<F: FnOnce(u64)> SentBytes<F> {
+    fn add(&mut self, bytes: usize) {
+        self.bytes += bytes as u64;
+    }
+}
+
+impl<F: FnOnce(u64)> Drop for SentBytes<F> {
+    fn drop(&mut self) {
+        if let Some(record) = self.record.take() {
+            record(self.bytes);
+        }
+    }
+}
+
+/// `body`, passing the number of bytes it sent to `record` once it is
+/// dropped: after its end, or when the client went away part way
+fn count_sent(body: Body, record: impl FnOnce(u64) + Send + 'static) -> Body {
+    let mut sent = SentBytes {
+        bytes: 0,
+        record: Some(record),
+    };
+    Body::from_stream(body.into_data_stream().map(move |chunk| {
+        if let Ok(data) = &chunk {
+            sent.add(data.len());
+        }
+        chunk
+    }))
+}
+
+/// Middleware recording the latency, status and bytes sent of every matched API route
+pub async fn track(State(state): State<Arc<AppState>>, path: MatchedPath, request: Request, next: Next) -> Response {
+    let method = request.method().to_string();
+    let route = path.as_str().to_string();
+    let start = Instant::now();
+    let response = next.run(request).await;
+    state
+        .metrics
+        .record_request(&route, &method, response.status().as_u16(), start.elapsed().as_secs_f64());
+
+    // The counting body has no size of its own, so keep a known one as Content-Length
+    let (mut parts, body) = response.into_parts();
+    match body.size_hint().exact() {
+        Some(0) => return Response::from_parts(parts, body),
+        Some(size) => {
+            parts.headers.entry(header::CONTENT_LENGTH).or_insert(HeaderValue::from(size));
+        }
+        None => {}
+    }
+    let body = count_sent(body, move |bytes| state.metrics.record_bytes(&route, &method, bytes));
+    Response::from_parts(parts, body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shared::RouteStatus;
+    use std::convert::Infallible;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    #[test]
+    fn buckets_include_their_upper_bound() {
+        let mut histogram = Histogram::default();
+        for seconds in [0.001, 0.005, 0.0051, 0.75, 30.0, 31.0] {
+            histogram.observe(seconds);
+        }
+        let mut expected = [0; BUCKETS.len() + 1];
+        expected[0] = 2; // 0.001 and 0.005
+        expected[1] = 1; // 0.0051
+        expected[7] = 1; // 0.75 in (0.5, 1]
+        expected[11] = 1; // 30 in (10, 30]
+        expected[12] = 1; // 31 in +Inf
+        assert_eq!(histogram.counts, expected);
+        assert!((histogram.sum - 61.7611).abs() < 1e-9);
+    }
+
+    #[test]
+    fn renders_cumulative_histograms() {
+        let mut histogram = Histogram::default();
+        histogram.observe(0.02);
+        histogram.observe(3.0);
+        let mut out = String::new();
+        histogram.render(&mut out, "t", "stage=\"open\"");
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines.len(), BUCKETS.len() + 3);
+        assert_eq!(lines[0], "t_bucket{stage=\"open\",le=\"0.005\"} 0");
+        assert_eq!(lines[2], "t_bucket{stage=\"open\",le=\"0.025\"} 1");
+        assert_eq!(lines[9], "t_bucket{stage=\"open\",le=\"5\"} 2");
+        assert_eq!(lines[12], "t_bucket{stage=\"open\",le=\"+Inf\"} 2");
+        assert_eq!(lines[13], "t_sum{stage=\"open\"} 3.02");
+        assert_eq!(lines[14], "t_count{stage=\"open\"} 2");
+
+        // Without other labels there is no leading comma
+        let mut out = String::new();
+        histogram.render(&mut out, "t", "");
+        assert!(out.starts_with("t_bucket{le=\"0.005\"} 0\n"));
+    }
+
+    #[test]
+    fn renders_the_text_format() {
+        let metrics = Metrics::default();
+        metrics.record_request("/api/volumes/{id}/full", "GET", 200, 0.2);
+        metrics.record_request("/api/volumes/{id}/full", "GET", 503, 0.001);
+        metrics.record_bytes("/api/volumes/{id}/full", "GET", 1234);
+        metrics.record_load("open", 0.5);
+
+        let cache = CacheStats {
+            entries: 2,
+            used_bytes: 100,
+            budget_bytes: 1000,
+            hits: 3,
+            misses: 1,
+            coalesced: 0,
+        };
+        let admission = AdmissionStatus {
+            routes: vec![RouteStatus {
+                route: "full".to_string(),
+                limit: 2,
+                in_flight: 1,
+                queued: 0,
+            }],
+            in_flight_bytes: 0,
+            memory_budget_bytes: 1000,
+            rejected: 0,
+        };
+        let out = metrics.render(&cache, &admission);
+        for line in [
+            "http_requests_total{route=\"/api/volumes/{id}/full\",method=\"GET\",status=\"200\"} 1",
+            "http_requests_total{route=\"/api/volumes/{id}/full\",method=\"GET\",status=\"503\"} 1",
+            "http_request_duration_seconds_count{route=\"/api/volumes/{id}/full\",method=\"GET\"} 2",
+            "http_response_bytes_total{route=\"/api/volumes/{id}/full\",method=\"GET\"} 1234",
+            "volume_load_duration_seconds_count{stage=\"open\"} 1",
+            "volume_cache_hit_ratio 0.75",
+            "admission_queued{route=\"full\"} 0",
+        ] {
+            assert!(out.lines().any(|l| l == line), "missing {}", line);
+        }
+        // Every sample has its HELP and TYPE
+        assert_eq!(out.matches("# HELP").count(), out.matches("# TYPE").count());
+    }
+
+    #[tokio::test]
+    async fn counts_only_the_bytes_sent() {
+        let sent = Arc::new(AtomicU64::new(0));
+        let chunks = ["abcd", "efgh", "ij"].map(|c| Ok::<_, Infallible>(c.as_bytes().to_vec()));
+        let record = |sent: &Arc<AtomicU64>| {
+            let sent = sent.clone();
+            move |bytes| sent.store(bytes, Ordering::SeqCst)
+        };
+
+        let body = count_sent(Body::from_stream(tokio_stream::iter(chunks.clone())), record(&sent));
+        assert_eq!(axum::body::to_bytes(body, usize::MAX).await.unwrap().len(), 10);
+        assert_eq!(sent.load(Ordering::SeqCst), 10);
+
+        // A client that goes away after the first chunk
+        let body = count_sent(Body::from_stream(tokio_stream::iter(chunks)), record(&sent));
+        let mut stream = body.into_data_stream();
+        stream.next().await.unwrap().unwrap();
+        drop(stream);
+        assert_eq!(sent.load(Ordering::SeqCst), 4);
+    }
+}
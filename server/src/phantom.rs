@@ -0,0 +1,339 @@
+//! Procedural volumes: a 3D Shepp–Logan phantom and test patterns, served as
+//! virtual catalog entries under `phantoms/` when `PHANTOMS` asks for them
+
+use ndarray::Array3;
+use std::ops::Range;
+use std::str::FromStr;
+
+/// Catalog folder of the phantoms
+pub const PHANTOM_FOLDER: &str = "phantoms";
+
+/// `PHANTOMS` value that serves one of each kind at the default size
+const ALL_PHANTOMS: &str = "all";
+
+/// Edge length when a spec has no size
+const DEFAULT_SIZE: u32 = 128;
+
+/// Largest edge length
+const MAX_SIZE: u32 = 1024;
+
+/// Most voxels in a phantom (512³, 512 MiB as f32), so a typo does not allocate gigabytes
+const MAX_VOXELS: u64 = 512 * 512 * 512;
+
+/// Ellipsoids of the modified 3D Shepp–Logan phantom: intensity, semi-axes
+/// (x, y, z), center (x, y, z) and rotation about z in degrees
+const SHEPP_LOGAN: [[f32; 8]; 10] = [
+    [1.0, 0.69, 0.92, 0.81, 0.0, 0.0, 0.0, 0.0],
+    [-0.8, 0.6624, 0.874, 0.78, 0.0, -0.0184, 0.0, 0.0],
+    [-0.2, 0.11, 0.31, 0.22, 0.22, 0.0, 0.0, -18.0],
+    [-0.2, 0.16, 0.41, 0.28, -0.22, 0.0, 0.0, 18.0],
+    [0.1, 0.21, 0.25, 0.41, 0.0, 0.35, -0.15, 0.0],
+    [0.1, 0.046, 0.046, 0.05, 0.0, 0.1, 0.25, 0.0],
+    [0.1, 0.046, 0.046, 0.05, 0.0, -0.1, 0.25, 0.0],
+    [0.1, 0.046, 0.023, 0.05, -0.08, -0.605, 0.0, 0.0],
+    [0.1, 0.023, 0.023, 0.02, 0.0, -0.606, 0.0, 0.0],
+    [0.1, 0.023, 0.046, 0.02, 0.06, -0.605, 0.0, 0.0],
+];
+
+/// Spheres of the `spheres` pattern: center (x, y, z), radius and intensity
+const SPHERES: [[f32; 5]; 5] = [
+    [0.0, 0.0, 0.0, 0.5, 0.4],
+    [0.5, 0.5, 0.5, 0.3, 0.6],
+    [-0.5, 0.5, -0.5, 0.25, 0.8],
+    [0.5, -0.5, -0.5, 0.2, 1.0],
+    [-0.5, -0.5, 0.5, 0.15, 0.2],
+];
+
+/// Number of checks along x, y and z of the `checkerboard` pattern
+const CHECKS: [usize; 3] = [2, 4, 8];
+
+/// What a phantom shows
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PhantomKind {
+    /// The modified 3D Shepp–Logan head phantom
+    SheppLogan,
+    /// Spheres of different sizes and intensities
+    Spheres,
+    /// Linear ramp with different slopes along x, y and z
+    Gradient,
+    /// Uniform white noise, the same on every run
+    Noise,
+    /// 2, 4 and 8 checks along x, y and z with the check at the origin brightest,
+    /// so swapped or flipped axes are easy to spot
+    Checkerboard,
+}
+
+impl PhantomKind {
+    const ALL: [PhantomKind; 5] = [
+        PhantomKind::SheppLogan,
+        PhantomKind::Spheres,
+        PhantomKind::Gradient,
+        PhantomKind::Noise,
+        PhantomKind::Checkerboard,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            PhantomKind::SheppLogan => "shepp-logan",
+            PhantomKind::Spheres => "spheres",
+            PhantomKind::Gradient => "gradient",
+            PhantomKind::Noise => "noise",
+            PhantomKind::Checkerboard => "checkerboard",
+        }
+    }
+}
+
+/// Element type the values are quantized to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PhantomDtype {
+    U8,
+    U16,
+    F32,
+}
+
+impl PhantomDtype {
+    /// Name as in `VolumeInfo::dtype`
+    pub fn name(self) -> &'static str {
+        match self {
+            PhantomDtype::U8 => "u8",
+            PhantomDtype::U16 => "u16",
+            PhantomDtype::F32 => "f32",
+        }
+    }
+
+    /// Integer types span their whole range; f32 keeps 0..1
+    fn quantize(self, value: f32) -> f32 {
+        match self {
+            PhantomDtype::U8 => (value.clamp(0.0, 1.0) * u8::MAX as f32).round(),
+            PhantomDtype::U16 => (value.clamp(0.0, 1.0) * u16::MAX as f32).round(),
+            PhantomDtype::F32 => value,
+        }
+    }
+}
+
+/// A procedural volume, given as `kind[:size[:dtype]]`, e.g. `shepp-logan`,
+/// `noise:64` or `checkerboard:128x96x64:u8`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Phantom {
+    pub kind: PhantomKind,
+    pub dims: [u32; 3],
+    pub dtype: PhantomDtype,
+}
+
+impl FromStr for Phantom {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.trim().split(':');
+        let name = parts.next().unwrap_or_default();
+        let kind = PhantomKind::ALL.into_iter().find(|k| k.name() == name).ok_or_else(|| {
+            let names: Vec<_> = PhantomKind::ALL.iter().map(|k| k.name()).collect();
+            format!("Unknown phantom '{}' (expected {})", name, names.join(", "))
+        })?;
+        let dims = match parts.next() {
+            Some(size) => parse_size(size)?,
+            None => [DEFAULT_SIZE; 3],
+        };
+        let dtype = match parts.next() {
+            None | Some("f32") => PhantomDtype::F32,
+            Some("u8") => PhantomDtype::U8,
+            Some("u16") => PhantomDtype::U16,
+            Some(other) => return Err(format!("Unsupported phantom dtype '{}' (expected u8, u16 or f32)", other)),
+        };
+        if parts.next().is_some() {
+            return Err(format!("Invalid phantom '{}' (expected kind[:size[:dtype]])", s));
+        }
+        Ok(Self { kind, dims, dtype })
+    }
+}
+
+/// `N` for a cube or `XxYxZ`
+fn parse_size(size: &str) -> Result<[u32; 3], String> {
+    let invalid = || format!("Invalid phantom size '{}' (expected N or XxYxZ)", size);
+    let edges: Vec<u32> = size
+        .split('x')
+        .map(|e| e.trim().parse())
+        .collect::<Result<_, _>>()
+        .map_err(|_| invalid())?;
+    let dims = match edges[..] {
+        [n] => [n, n, n],
+        [x, y, z] => [x, y, z],
+        _ => return Err(invalid()),
+    };
+    if dims.iter().any(|&d| d == 0 || d > MAX_SIZE) {
+        return Err(format!("Phantom edges must be 1 to {}, got '{}'", MAX_SIZE, size));
+    }
+    if dims.iter().map(|&d| d as u64).product::<u64>() > MAX_VOXELS {
+        return Err(format!("Phantoms have at most {} voxels, got '{}'", MAX_VOXELS, size));
+    }
+    Ok(dims)
+}
+
+impl Phantom {
+    /// Name in the catalog, e.g. `shepp-logan-128-f32` or `checkerboard-128x96x64-u8`
+    pub fn name(&self) -> String {
+        let [x, y, z] = self.dims;
+        let size = if x == y && y == z {
+            x.to_string()
+        } else {
+            format!("{}x{}x{}", x, y, z)
+        };
+        format!("{}-{}-{}", self.kind.name(), size, self.dtype.name())
+    }
+
+    pub fn id(&self) -> String {
+        format!("{}/{}", PHANTOM_FOLDER, self.name())
+    }
+
+    /// X slabs `x` of the volume
+    pub fn read_slab(&self, x: Range<usize>) -> Array3<f32> {
+        let [_, ny, nz] = self.dims.map(|d| d as usize);
+        Array3::from_shape_fn((x.len(), ny, nz), |(i, j, k)| {
+            self.dtype.quantize(self.value([x.start + i, j, k]))
+        })
+    }
+
+    /// Value in 0..1 at a voxel
+    fn value(&self, voxel: [usize; 3]) -> f32 {
+        // Voxel centers in -1..1 along each axis
+        let p: [f32; 3] = std::array::from_fn(|a| (voxel[a] as f32 + 0.5) / self.dims[a] as f32 * 2.0 - 1.0);
+        match self.kind {
+            PhantomKind::SheppLogan => shepp_logan(p),
+            PhantomKind::Spheres => SPHERES
+                .iter()
+                .filter(|s| (0..3).map(|a| (p[a] - s[a]).powi(2)).sum::<f32>() <= s[3] * s[3])
+                .map(|s| s[4])
+                .fold(0.0, f32::max),
+            PhantomKind::Gradient => 0.6 * (p[0] + 1.0) / 2.0 + 0.3 * (p[1] + 1.0) / 2.0 + 0.1 * (p[2] + 1.0) / 2.0,
+            PhantomKind::Noise => noise(voxel),
+            PhantomKind::Checkerboard => {
+                let check: [usize; 3] = std::array::from_fn(|a| voxel[a] * CHECKS[a] / self.dims[a] as usize);
+                if check == [0, 0, 0] {
+                    1.0
+                } else if check.iter().sum::<usize>() % 2 == 0 {
+                    0.7
+                } else {
+                    0.2
+                }
+            }
+        }
+    }
+}
+
+/// Sum of the intensities of the ellipsoids containing `p`
+fn shepp_logan(p: [f32; 3]) -> f32 {
+    SHEPP_LOGAN
+        .iter()
+        .filter(|e| {
+            let (sin, cos) = e[7].to_radians().sin_cos();
+            let (dx, dy, dz) = (p[0] - e[4], p[1] - e[5], p[2] - e[6]);
+            let (rx, ry) = (dx * cos + dy * sin, dy * cos - dx * sin);
+            (rx / e[1]).powi(2) + (ry / e[2]).powi(2) + (dz / e[3]).powi(2) <= 1.0
+        })
+        .fold(0.0, |sum, e| sum + e[0])
+        .max(0.0)
+}
+
+/// Uniform value in 0..1 hashed from the voxel position (SplitMix64), so slabs
+/// read separately agree
+fn noise([x, y, z]: [usize; 3]) -> f32 {
+    let mut h = ((x as u64) << 42 ^ (y as u64) << 21 ^ z as u64).wrapping_add(0x9e37_79b9_7f4a_7c15);
+    h = (h ^ (h >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    h = (h ^ (h >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    h ^= h >> 31;
+    (h >> 40) as f32 / (1u64 << 24) as f32
+}
+
+/// Phantoms from `PHANTOMS`, none when it is not set
+pub fn from_env() -> Vec<Phantom> {
+    parse_list(&std::env::var("PHANTOMS").unwrap_or_default())
+}
+
+/// A comma-separated list of specs, or `all` for one of each kind. Invalid
+/// specs are logged and skipped.
+fn parse_list(specs: &str) -> Vec<Phantom> {
+    if specs.trim() == ALL_PHANTOMS {
+        return PhantomKind::ALL
+            .into_iter()
+            .map(|kind| Phantom {
+                kind,
+                dims: [DEFAULT_SIZE; 3],
+                dtype: PhantomDtype::F32,
+            })
+            .collect();
+    }
+    specs
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .filter_map(|spec| match spec.parse() {
+            Ok(phantom) => Some(phantom),
+            Err(e) => {
+                tracing::warn!(spec, error = %e, "Invalid phantom");
+                None
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_specs() {
+        let phantom: Phantom = "shepp-logan".parse().unwrap();
+        assert_eq!(phantom.kind, PhantomKind::SheppLogan);
+        assert_eq!(phantom.dims, [DEFAULT_SIZE; 3]);
+        assert_eq!(phantom.dtype, PhantomDtype::F32);
+        assert_eq!(phantom.id(), "phantoms/shepp-logan-128-f32");
+
+        let phantom: Phantom = "checkerboard:128x96x64:u8".parse().unwrap();
+        assert_eq!(phantom.dims, [128, 96, 64]);
+        assert_eq!(phantom.name(), "checkerboard-128x96x64-u8");
+        assert_eq!("noise:64:u16".parse::<Phantom>().unwrap().name(), "noise-64-u16");
+    }
+
+    #[test]
+    fn rejects_bad_specs() {
+        for spec in ["sphere", "noise:0", "noise:1025x1x1", "noise:64x64", "noise:64:i8", "noise:64:u8:x"] {
+            assert!(spec.parse::<Phantom>().is_err(), "{}", spec);
+        }
+        // Every edge may reach the limit, but not all of them at once
+        assert!("noise:1024x512x256".parse::<Phantom>().is_ok());
+        assert!("noise:1024".parse::<Phantom>().is_err());
+    }
+
+    #[test]
+    fn lists_are_opt_in() {
+        assert!(parse_list("").is_empty());
+        assert_eq!(parse_list("all").len(), PhantomKind::ALL.len());
+        // Invalid specs are skipped
+        let phantoms = parse_list("spheres:32, sphere ,gradient");
+        let names: Vec<_> = phantoms.iter().map(|p| p.name()).collect();
+        assert_eq!(names, ["spheres-32-f32", "gradient-128-f32"]);
+    }
+
+    #[test]
+    fn generates_values() {
+        let phantom: Phantom = "checkerboard:16:u8".parse().unwrap();
+        let data = phantom.read_slab(0..16);
+        assert_eq!(data.dim(), (16, 16, 16));
+        // Brightest at the origin, and quantized to the full u8 range
+        assert_eq!(data[[0, 0, 0]], 255.0);
+        assert!(data.iter().all(|&v| v == v.round() && (0.0..=255.0).contains(&v)));
+
+        // Slabs read separately agree with the whole volume
+        let noise: Phantom = "noise:8".parse().unwrap();
+        let whole = noise.read_slab(0..8);
+        let slab = noise.read_slab(3..5);
+        assert_eq!(slab, whole.slice(ndarray::s![3..5, .., ..]));
+        assert!(whole.iter().all(|v| (0.0..1.0).contains(v)));
+
+        // The Shepp–Logan phantom is empty outside the head and 0.2 inside the skull
+        let head: Phantom = "shepp-logan:32".parse().unwrap();
+        let data = head.read_slab(0..32);
+        assert_eq!(data[[0, 0, 0]], 0.0);
+        assert!((data[[16, 8, 16]] - 0.2).abs() < 1e-6);
+    }
+}
@@ -1,151 +1,1403 @@
 use axum::{
-    extract::{Path, State},
-    http::{HeaderMap, HeaderValue, StatusCode},
+    body::{Body, Bytes},
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
     response::IntoResponse,
     Json,
 };
-use serde::Serialize;
+use std::collections::BTreeSet;
+use std::convert::Infallible;
+use std::ops::Range;
 use std::sync::Arc;
+use std::time::SystemTime;
+use tokio::sync::mpsc;
+use tokio_stream::{wrappers::ReceiverStream, StreamExt};
 
-use shared::{ErrorResponse, VolumeListResponse, VolumeMetadataResponse};
+use shared::{
+    AdmissionStatus, Annotation, AnnotationListResponse, AnnotationRequest, CacheStats, CompareRequest, CompareResponse, DeriveRequest,
+    ErrorResponse, ExportQuery, FrameRangeQuery, HealthResponse, LabelListResponse, LabelVolumeInfo, MeshQuery, ReadinessResponse,
+    SegmentRequest, SegmentResponse, SpectrumRequest, SpectrumResponse, VersionQuery, VolumeInfo, VolumeListQuery, VolumeListResponse, VolumeHeader, VolumeMetadataResponse,
+    VoxelDtype,
+};
+use tokio_util::sync::CancellationToken;
+use utoipa::openapi::schema::{KnownFormat, ObjectBuilder, Schema, SchemaFormat, Type};
+use utoipa::openapi::RefOr;
+use utoipa::{PartialSchema, ToSchema};
+
+use crate::admission::HeavyRoute;
+use crate::cache::{CacheKey, CacheLevel, CachedVolume};
+use crate::catalog;
+use crate::error::ApiError;
+use crate::derived::Operation;
+use crate::export::{self, Export};
+use crate::filters::Filter;
+use crate::hdf5_reader::{join_error, HDF5Volume};
+use crate::labels::LabelVolume;
+use crate::mesh::{self, MAX_DECIMATION, MAX_SMOOTHING};
+use crate::segment;
+use crate::state::{AppState, VolumeRef};
+
+/// Non-empty trimmed items of a comma-separated query value
+fn comma_list(value: Option<&str>) -> Vec<String> {
+    value
+        .into_iter()
+        .flat_map(|v| v.split(','))
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Body of the binary routes, documented as raw bytes
+pub struct RawBytes;
+
+impl PartialSchema for RawBytes {
+    fn schema() -> RefOr<Schema> {
+        ObjectBuilder::new()
+            .schema_type(Type::String)
+            .format(Some(SchemaFormat::KnownFormat(KnownFormat::Binary)))
+            .into()
+    }
+}
+
+impl ToSchema for RawBytes {}
+
+/// Cache-Control for URLs pinned to a content version with `?v=`
+const IMMUTABLE_CACHE_CONTROL: &str = "public, max-age=31536000, immutable";
+
+/// Largest number of frames served by one /frames request
+const MAX_FRAMES_PER_REQUEST: u32 = 16;
+
+/// Header of `dims` f32 voxels of `info`, with the spacing scaled for resampled data
+fn volume_header(info: &VolumeInfo, dims: [u32; 3]) -> VolumeHeader {
+    let spacing = std::array::from_fn(|a| info.spacing[a] * info.dimensions[a] as f32 / dims[a].max(1) as f32);
+    VolumeHeader::new(dims, VoxelDtype::F32, info.value_range, spacing)
+}
+
+/// Body of a data payload, `header` followed by `data`, with its content type and length
+fn payload_body(header: &VolumeHeader, data: Bytes, headers: &mut HeaderMap) -> Body {
+    headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("application/octet-stream"));
+    headers.insert(header::CONTENT_LENGTH, HeaderValue::from(VolumeHeader::SIZE + data.len()));
+    let chunks = [Bytes::copy_from_slice(&header.encode()), data];
+    Body::from_stream(tokio_stream::iter(chunks.map(Ok::<_, Infallible>)))
+}
+
+/// Set ETag, Last-Modified and Cache-Control for a volume payload.
+/// `variant` distinguishes payloads of the same file (e.g. "full", "at256").
+/// Returns true when the request's validators match and a 304 should be sent.
+fn apply_cache_headers(
+    volume: &VolumeRef,
+    variant: &str,
+    query: &VersionQuery,
+    request_headers: &HeaderMap,
+    headers: &mut HeaderMap,
+) -> bool {
+    let version = volume.info().version;
+    let modified = volume.modified();
+    // The container version is part of the tag, so a new payload layout is not served from a stale cache
+    let etag = format!("\"{}-{}-c{}\"", version, variant, VolumeHeader::VERSION);
+    headers.insert(header::ETAG, HeaderValue::from_str(&etag).unwrap());
+    headers.insert(
+        header::LAST_MODIFIED,
+        HeaderValue::from_str(&httpdate::fmt_http_date(modified)).unwrap(),
+    );
 
-use crate::state::AppState;
+    // Only a URL naming the current version can never change underneath the client
+    let cache_control = if query.v.as_deref() == Some(version.as_str()) {
+        IMMUTABLE_CACHE_CONTROL
+    } else {
+        "no-cache"
+    };
+    headers.insert(header::CACHE_CONTROL, HeaderValue::from_static(cache_control));
+
+    // If-None-Match takes precedence over If-Modified-Since
+    if let Some(value) = request_headers.get(header::IF_NONE_MATCH).and_then(|v| v.to_str().ok()) {
+        return value
+            .split(',')
+            .map(|tag| tag.trim().trim_start_matches("W/"))
+            .any(|tag| tag == "*" || tag == etag);
+    }
 
-#[derive(Serialize)]
-pub struct HealthResponse {
-    pub status: String,
-    pub available_samples: Vec<String>,
+    if let Some(since) = request_headers
+        .get(header::IF_MODIFIED_SINCE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| httpdate::parse_http_date(v).ok())
+    {
+        // HTTP dates have whole-second precision
+        let modified = httpdate::parse_http_date(&httpdate::fmt_http_date(modified))
+            .unwrap_or(SystemTime::UNIX_EPOCH);
+        return modified <= since;
+    }
+
+    false
 }
 
-/// GET /api/health - Health check with available samples
+/// GET /api/health - Health check with available samples and the files that failed to load
+#[utoipa::path(
+    get, path = "/health", tag = "server",
+    responses((status = 200, description = "Server is up", body = HealthResponse))
+)]
 pub async fn health(State(state): State<Arc<AppState>>) -> impl IntoResponse {
-    let samples: Vec<String> = state
+    let mut samples: Vec<String> = state
         .volumes
-        .keys()
-        .filter(|id| id.starts_with("target"))
-        .cloned()
+        .values()
+        .map(|v| v.info.id.clone())
         .collect();
+    samples.sort();
 
+    let status = if state.failed.is_empty() { "ok" } else { "degraded" };
     Json(HealthResponse {
-        status: "ok".to_string(),
+        status: status.to_string(),
         available_samples: samples,
+        volumes: state.volumes.len(),
+        failed: state.failed.clone(),
     })
 }
 
-/// GET /api/volumes - List all available volumes
-pub async fn list_volumes(State(state): State<Arc<AppState>>) -> impl IntoResponse {
-    let volumes = state.list_volumes();
-    Json(VolumeListResponse { volumes })
+/// GET /api/ready - Readiness probe: 200 once at least one volume is loaded
+#[utoipa::path(
+    get, path = "/ready", tag = "server",
+    responses(
+        (status = 200, description = "Ready to serve volumes", body = ReadinessResponse),
+        (status = 503, description = "No volume could be loaded", body = ReadinessResponse),
+    )
+)]
+pub async fn ready(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    if !state.volumes.is_empty() {
+        return (StatusCode::OK, Json(ReadinessResponse { ready: true, reason: None }));
+    }
+    let reason = if state.failed.is_empty() {
+        "No volume files found".to_string()
+    } else {
+        format!("All {} volume files failed to load", state.failed.len())
+    };
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        Json(ReadinessResponse {
+            ready: false,
+            reason: Some(reason),
+        }),
+    )
+}
+
+/// GET /api/cache - Volume cache usage and hit/miss counters
+#[utoipa::path(
+    get, path = "/cache", tag = "server",
+    responses((status = 200, description = "Cache usage", body = CacheStats))
+)]
+pub async fn cache_stats(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    Json(state.cache.stats())
+}
+
+/// GET /api/status - Queue depth and in-flight work of the heavy routes
+#[utoipa::path(
+    get, path = "/status", tag = "server",
+    responses((status = 200, description = "Admission state of the heavy routes", body = AdmissionStatus))
+)]
+pub async fn status(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    Json(state.admission.status())
+}
+
+/// GET /metrics - Request, cache and volume load metrics for Prometheus
+#[utoipa::path(
+    get, path = "/metrics", tag = "server",
+    responses((status = 200, description = "Prometheus text exposition format", body = String, content_type = "text/plain"))
+)]
+pub async fn metrics(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let body = state.metrics.render(&state.cache.stats(), &state.admission.status());
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], body)
+}
+
+/// GET /api/volumes - List the volumes matching the filters, sorted and paged,
+/// and with `?include=failed` the files that could not be loaded
+#[utoipa::path(
+    get, path = "/volumes", tag = "volumes", params(VolumeListQuery),
+    responses((status = 200, description = "Matching volumes", body = VolumeListResponse), (status = 400, description = "Invalid parameters", body = ErrorResponse))
+)]
+pub async fn list_volumes(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<VolumeListQuery>,
+) -> Result<impl IntoResponse, ApiError> {
+    let mut include_failed = false;
+    for include in query.include.iter().flat_map(|i| i.split(',')) {
+        match include.trim() {
+            "failed" => include_failed = true,
+            "" => {}
+            other => return Err(ApiError::BadRequest(format!("Unknown include '{}' (available: failed)", other))),
+        }
+    }
+
+    let limit = match query.limit {
+        Some(limit) if !(1..=catalog::MAX_PAGE_SIZE).contains(&limit) => {
+            return Err(ApiError::BadRequest(format!(
+                "limit must be between 1 and {}",
+                catalog::MAX_PAGE_SIZE
+            )))
+        }
+        limit => limit,
+    };
+    let filter = catalog::Filter {
+        text: query.q.filter(|q| !q.trim().is_empty()),
+        tags: comma_list(query.tags.as_deref()),
+        dtypes: comma_list(query.dtype.as_deref()).iter().map(|d| catalog::dtype_name(d)).collect(),
+        folder: query.folder,
+        min_dim: query.min_dim,
+        max_dim: query.max_dim,
+    };
+
+    let mut volumes: Vec<VolumeInfo> = state.list_volumes().into_iter().filter(|v| filter.matches(v)).collect();
+    catalog::sort(&mut volumes, query.sort.unwrap_or_default(), query.order.unwrap_or_default());
+    let total = volumes.len();
+    let (volumes, next_cursor) = catalog::page(volumes, query.cursor.as_deref(), limit)
+        .ok_or_else(|| ApiError::BadRequest("Unknown cursor".to_string()))?;
+
+    let failed = if include_failed { state.failed.clone() } else { Vec::new() };
+    let tree = catalog::tree(state.volumes.values().map(|v| &v.info));
+    let tags: BTreeSet<String> = state.volumes.values().flat_map(|v| v.info.tags.iter().cloned()).collect();
+    Ok(Json(VolumeListResponse {
+        volumes,
+        failed,
+        tree,
+        tags: tags.into_iter().collect(),
+        total,
+        next_cursor,
+    }))
 }
 
 /// GET /api/volumes/:id/info - Get volume metadata
+/// Derived volumes are not computed for it: until their data has been read
+/// the value range is an estimate, and the data payload's header has the exact one.
+#[utoipa::path(
+    get, path = "/volumes/{id}/info", tag = "volumes", params(("id" = String, Path, description = "Volume id")),
+    responses((status = 200, description = "Volume metadata", body = VolumeMetadataResponse), (status = 404, description = "No such volume", body = ErrorResponse))
+)]
 pub async fn get_volume_info(
     State(state): State<Arc<AppState>>,
     Path(id): Path<String>,
-) -> impl IntoResponse {
-    match state.get_volume(&id) {
-        Some(volume) => Ok(Json(VolumeMetadataResponse {
-            info: volume.info.clone(),
-        })),
-        None => Err((
-            StatusCode::NOT_FOUND,
-            Json(ErrorResponse {
-                error: format!("Volume '{}' not found", id),
-            }),
-        )),
+) -> Result<impl IntoResponse, ApiError> {
+    let volume = state.find_volume(&id)?;
+    Ok(Json(VolumeMetadataResponse {
+        info: state.with_labels(volume.info()),
+    }))
+}
+
+/// POST /api/volumes/:id/derive - Register a filtered version of a volume
+/// The result is computed lazily when its data is first requested.
+#[utoipa::path(
+    post, path = "/volumes/{id}/derive", tag = "volumes", params(("id" = String, Path, description = "Volume id")), request_body = DeriveRequest,
+    responses(
+        (status = 200, description = "The derived volume, id `{id}~{filter}`", body = VolumeMetadataResponse),
+        (status = 400, description = "Invalid parameters", body = ErrorResponse),
+        (status = 404, description = "No such volume", body = ErrorResponse),
+    )
+)]
+pub async fn derive_volume(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    Json(request): Json<DeriveRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    let filter: Filter = request.filter.parse().map_err(|e| {
+        ApiError::BadRequest(format!("{} (available: {})", e, Filter::SYNTAX.join(", ")))
+    })?;
+    let derived = state.derive(&id, Operation::Filter(filter))?;
+    Ok(Json(VolumeMetadataResponse { info: derived.info() }))
+}
+
+/// POST /api/volumes/:id/compare - PSNR/SSIM/NMSE/MAE against a reference volume
+/// Also registers the absolute-difference volume `{id}~absdiff({reference})`.
+#[utoipa::path(
+    post, path = "/volumes/{id}/compare", tag = "volumes", params(("id" = String, Path, description = "Volume id")), request_body = CompareRequest,
+    responses(
+        (status = 200, description = "Global and per-slice metrics", body = CompareResponse),
+        (status = 400, description = "Invalid parameters", body = ErrorResponse),
+        (status = 404, description = "No such volume", body = ErrorResponse),
+        (status = 503, description = "Server busy; retry after `Retry-After` seconds", body = ErrorResponse),
+        (status = 507, description = "The request needs more memory than the server's whole budget", body = ErrorResponse),
+    )
+)]
+pub async fn compare_volume(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    request_headers: HeaderMap,
+    Json(request): Json<CompareRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    let cancel = state.sessions.begin(&request_headers);
+    let comparison = state.compare(&id, &request, cancel.token.clone()).await?;
+    Ok(Json(comparison))
+}
+
+/// POST /api/volumes/:id/spectrum - Register the centred FFT of a volume
+/// as its log-magnitude `{id}~fft(logmag)` and phase `{id}~fft(phase)`, or
+/// `fft2(...,{axis})` per slice. Both are computed when their data is first requested.
+#[utoipa::path(
+    post, path = "/volumes/{id}/spectrum", tag = "volumes", params(("id" = String, Path, description = "Volume id")), request_body = SpectrumRequest,
+    responses(
+        (status = 200, description = "The log-magnitude and phase volumes", body = SpectrumResponse),
+        (status = 400, description = "Invalid parameters", body = ErrorResponse),
+        (status = 404, description = "No such volume", body = ErrorResponse),
+    )
+)]
+pub async fn spectrum_volume(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    Json(request): Json<SpectrumRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    Ok(Json(state.spectrum(&id, &request)?))
+}
+
+/// POST /api/volumes/:id/segment - Threshold and label connected components
+#[utoipa::path(
+    post, path = "/volumes/{id}/segment", tag = "labels", params(("id" = String, Path, description = "Volume id")), request_body = SegmentRequest,
+    responses(
+        (status = 200, description = "The new label volume", body = SegmentResponse),
+        (status = 400, description = "Invalid parameters", body = ErrorResponse),
+        (status = 404, description = "No such volume", body = ErrorResponse),
+        (status = 503, description = "Server busy; retry after `Retry-After` seconds", body = ErrorResponse),
+        (status = 507, description = "The request needs more memory than the server's whole budget", body = ErrorResponse),
+    )
+)]
+pub async fn segment_volume(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    request_headers: HeaderMap,
+    Json(request): Json<SegmentRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    segment::validate(&request).map_err(ApiError::BadRequest)?;
+    let cancel = state.sessions.begin(&request_headers);
+    let labels = state.segment(&id, &request, cancel.token.clone()).await?;
+    Ok(Json(SegmentResponse {
+        labels: labels.info.clone(),
+        threshold_value: labels.threshold.unwrap_or_default(),
+    }))
+}
+
+/// GET /api/volumes/:id/labels - List label volumes of a volume
+#[utoipa::path(
+    get, path = "/volumes/{id}/labels", tag = "labels", params(("id" = String, Path, description = "Volume id")),
+    responses((status = 200, description = "Label volumes of the volume", body = LabelListResponse), (status = 404, description = "No such volume", body = ErrorResponse))
+)]
+pub async fn list_volume_labels(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<impl IntoResponse, ApiError> {
+    state.find_volume(&id)?;
+    Ok(Json(LabelListResponse {
+        labels: state.list_labels(&id).await?,
+    }))
+}
+
+/// File path and info of a volume that can hold annotations
+fn annotated_volume(state: &AppState, id: &str) -> Result<(std::path::PathBuf, VolumeInfo), ApiError> {
+    match state.find_volume(id)? {
+        VolumeRef::File(volume) => match volume.path() {
+            Some(path) => Ok((path.to_path_buf(), volume.info.clone())),
+            None => Err(ApiError::BadRequest(format!(
+                "Volume '{}' is not a file on disk (object storage and phantoms are read-only); it cannot hold annotations",
+                id
+            ))),
+        },
+        VolumeRef::Derived(_) => Err(ApiError::BadRequest(format!(
+            "Derived volume '{}' cannot hold annotations; annotate its source instead",
+            id
+        ))),
+    }
+}
+
+/// GET /api/volumes/:id/annotations - List the annotations of a volume
+#[utoipa::path(
+    get, path = "/volumes/{id}/annotations", tag = "annotations", params(("id" = String, Path, description = "Volume id")),
+    responses((status = 200, description = "Annotations of the volume", body = AnnotationListResponse), (status = 404, description = "No such volume", body = ErrorResponse))
+)]
+pub async fn list_annotations(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<impl IntoResponse, ApiError> {
+    let (path, info) = annotated_volume(&state, &id)?;
+    Ok(Json(AnnotationListResponse {
+        annotations: state.annotations.list(&path, &info).await?,
+    }))
+}
+
+/// POST /api/volumes/:id/annotations - Add a point, line, box or note
+#[utoipa::path(
+    post, path = "/volumes/{id}/annotations", tag = "annotations", params(("id" = String, Path, description = "Volume id")),
+    request_body = AnnotationRequest,
+    responses((status = 201, description = "The new annotation", body = Annotation), (status = 400, description = "Invalid parameters", body = ErrorResponse), (status = 404, description = "No such volume", body = ErrorResponse))
+)]
+pub async fn create_annotation(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    Json(request): Json<AnnotationRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    let (path, info) = annotated_volume(&state, &id)?;
+    let annotation = state.annotations.create(&path, &info, &request).await?;
+    Ok((StatusCode::CREATED, Json(annotation)))
+}
+
+/// GET /api/volumes/:id/annotations/:annotation_id - Get one annotation
+#[utoipa::path(
+    get, path = "/volumes/{id}/annotations/{annotation_id}", tag = "annotations", params(("id" = String, Path, description = "Volume id"), ("annotation_id" = u64, Path, description = "Annotation id")),
+    responses((status = 200, description = "The annotation", body = Annotation), (status = 404, description = "No such volume", body = ErrorResponse))
+)]
+pub async fn get_annotation(
+    State(state): State<Arc<AppState>>,
+    Path((id, annotation_id)): Path<(String, u64)>,
+) -> Result<impl IntoResponse, ApiError> {
+    let (path, info) = annotated_volume(&state, &id)?;
+    Ok(Json(state.annotations.get(&path, &info, annotation_id).await?))
+}
+
+/// PUT /api/volumes/:id/annotations/:annotation_id - Replace an annotation
+#[utoipa::path(
+    put, path = "/volumes/{id}/annotations/{annotation_id}", tag = "annotations", params(("id" = String, Path, description = "Volume id"), ("annotation_id" = u64, Path, description = "Annotation id")),
+    request_body = AnnotationRequest,
+    responses((status = 200, description = "The updated annotation", body = Annotation), (status = 400, description = "Invalid parameters", body = ErrorResponse), (status = 404, description = "No such volume", body = ErrorResponse))
+)]
+pub async fn update_annotation(
+    State(state): State<Arc<AppState>>,
+    Path((id, annotation_id)): Path<(String, u64)>,
+    Json(request): Json<AnnotationRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    let (path, info) = annotated_volume(&state, &id)?;
+    Ok(Json(state.annotations.update(&path, &info, annotation_id, &request).await?))
+}
+
+/// DELETE /api/volumes/:id/annotations/:annotation_id - Remove an annotation
+#[utoipa::path(
+    delete, path = "/volumes/{id}/annotations/{annotation_id}", tag = "annotations", params(("id" = String, Path, description = "Volume id"), ("annotation_id" = u64, Path, description = "Annotation id")),
+    responses((status = 204, description = "Annotation removed"), (status = 404, description = "No such volume", body = ErrorResponse))
+)]
+pub async fn delete_annotation(
+    State(state): State<Arc<AppState>>,
+    Path((id, annotation_id)): Path<(String, u64)>,
+) -> Result<impl IntoResponse, ApiError> {
+    let (path, _) = annotated_volume(&state, &id)?;
+    state.annotations.delete(&path, annotation_id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// GET /api/labels/:id/info - Get label volume metadata and component statistics
+#[utoipa::path(
+    get, path = "/labels/{id}/info", tag = "labels", params(("id" = String, Path, description = "Label volume id")),
+    responses((status = 200, description = "Label volume metadata", body = LabelVolumeInfo), (status = 404, description = "No such volume", body = ErrorResponse))
+)]
+pub async fn get_label_info(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<Json<LabelVolumeInfo>, ApiError> {
+    let labels = state.get_labels(&id).await?;
+    Ok(Json(labels.info.clone()))
+}
+
+/// GET /api/labels/:id/data - Get label voxels as little-endian integers
+#[utoipa::path(
+    get, path = "/labels/{id}/data", tag = "labels", params(("id" = String, Path, description = "Label volume id"), VersionQuery),
+    responses(
+        (status = 200, description = "Volume header, then u8 or u16 label values, z fastest", body = RawBytes, content_type = "application/octet-stream"),
+        (status = 304, description = "Not modified since the given `If-None-Match` or `If-Modified-Since`"),
+        (status = 404, description = "No such volume, or a segmentation no longer registered; segment again", body = ErrorResponse),
+        (status = 503, description = "Server busy; retry after `Retry-After` seconds", body = ErrorResponse),
+        (status = 507, description = "The request needs more memory than the server's whole budget", body = ErrorResponse),
+    )
+)]
+pub async fn get_label_data(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    Query(query): Query<VersionQuery>,
+    request_headers: HeaderMap,
+) -> Result<impl IntoResponse, ApiError> {
+    let labels = state.get_labels(&id).await?;
+    let info = &labels.info;
+    let volume = state.find_volume(&info.volume_id)?;
+
+    // Labels change with the intensity volume's version, which segmentation ids include too
+    let mut headers = HeaderMap::new();
+    if apply_cache_headers(&volume, &info.id, &query, &request_headers, &mut headers) {
+        return Ok((StatusCode::NOT_MODIFIED, headers, Body::empty()));
+    }
+
+    let cancel = state.sessions.begin(&request_headers);
+    let data = state.label_data(&labels, cancel.token.clone()).await?;
+    let max_label = info.components.iter().map(|c| c.label).max().unwrap_or(0);
+    let header = VolumeHeader::new(info.dimensions, info.dtype.into(), [0.0, max_label as f32], volume.info().spacing);
+    let body = payload_body(&header, data.bytes, &mut headers);
+    Ok((StatusCode::OK, headers, body))
+}
+
+/// GET /api/volumes/:id/mesh - Extract an isosurface as glTF, OBJ or STL
+/// The surface is at `iso` on the intensity, or around `label`; positions are in millimetres.
+#[utoipa::path(
+    get, path = "/volumes/{id}/mesh", tag = "volumes", params(("id" = String, Path, description = "Volume id"), MeshQuery),
+    responses(
+        (status = 200, description = "Surface as glTF, OBJ or STL, in millimetres", body = RawBytes, content_type = "application/octet-stream"),
+        (status = 400, description = "Invalid parameters", body = ErrorResponse),
+        (status = 404, description = "No such volume", body = ErrorResponse),
+        (status = 503, description = "Server busy; retry after `Retry-After` seconds", body = ErrorResponse),
+        (status = 507, description = "The request needs more memory than the server's whole budget", body = ErrorResponse),
+    )
+)]
+pub async fn get_volume_mesh(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    Query(query): Query<MeshQuery>,
+    request_headers: HeaderMap,
+) -> Result<impl IntoResponse, ApiError> {
+    if query.smooth > MAX_SMOOTHING || query.decimate > MAX_DECIMATION {
+        return Err(ApiError::BadRequest(format!(
+            "smooth must be at most {} and decimate at most {}",
+            MAX_SMOOTHING, MAX_DECIMATION
+        )));
+    }
+    let volume = state.find_volume(&id)?;
+    let info = volume.info();
+
+    // Label surfaces are extracted from a 0/1 mask at 0.5
+    let label = match query.label {
+        Some(value) => Some((linked_labels(&state, &id, query.labels.clone()).await?, value)),
+        None => None,
+    };
+    let iso = query.iso.unwrap_or((info.value_range[0] + info.value_range[1]) / 2.0);
+    let surface = match &label {
+        Some((labels, value)) => format!("label({},{})", labels.info.id, value),
+        None => format!("iso({})", iso),
+    };
+
+    let key = CacheKey {
+        volume: id.clone(),
+        level: CacheLevel::Mesh,
+        component: format!("{}:smooth({}):decimate({})", surface, query.smooth, query.decimate),
+        encoding: query.format.name().to_string(),
+    };
+    let cancel = state.sessions.begin(&request_headers);
+    let (state_ref, label_ref, id_ref, info_ref) = (&state, &label, &id, &info);
+    let CachedVolume { bytes, .. } = state
+        .cache
+        .get_or_compute(key, &cancel.token, |token| async move {
+            let (state, label, id, info) = (state_ref, label_ref, id_ref, info_ref);
+            let (field, iso) = match label {
+                Some((labels, value)) => {
+                    let data = state.label_data(labels, token.clone()).await?;
+                    (labels.values(&data.bytes).mapv(|v| (v as u32 == *value) as u8 as f32), 0.5)
+                }
+                None => (state.read_array(id, token.clone()).await?, iso),
+            };
+
+            let _permit = state
+                .admission
+                .admit(HeavyRoute::Mesh, mesh::memory_estimate(info.dimensions))
+                .await?;
+            let (format, smooth, decimate, spacing) = (query.format, query.smooth, query.decimate, info.spacing);
+            let bytes = tokio::task::spawn_blocking(move || {
+                let mut mesh = mesh::extract(&field, iso, &token)?;
+                if mesh.is_empty() {
+                    return Err(ApiError::BadRequest(format!("No surface found for {}", surface)));
+                }
+                mesh.decimate(decimate);
+                mesh.smooth(smooth);
+                mesh.scale(spacing);
+                Ok(mesh.encode(format))
+            })
+            .await
+            .map_err(join_error)??;
+
+            Ok::<_, ApiError>(CachedVolume {
+                bytes: bytes.into(),
+                dims: info.dimensions,
+            })
+        })
+        .await?;
+
+    let name = file_name(&match &label {
+        Some((_, value)) => format!("{}_label{}", id, value),
+        None => id.clone(),
+    });
+
+    let mut headers = HeaderMap::new();
+    headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(query.format.content_type()));
+    headers.insert(
+        header::CONTENT_DISPOSITION,
+        HeaderValue::from_str(&format!("attachment; filename=\"{}.{}\"", name, query.format.name())).unwrap(),
+    );
+    Ok((StatusCode::OK, headers, Body::from(bytes)))
+}
+
+/// Label volume `labels` of volume `id`, or its first one
+async fn linked_labels(state: &AppState, id: &str, labels: Option<String>) -> Result<Arc<LabelVolume>, ApiError> {
+    let label_id = labels
+        .or_else(|| state.labels.ids(id).into_iter().next())
+        .ok_or_else(|| ApiError::BadRequest(format!("Volume '{}' has no label volumes", id)))?;
+    let labels = state.get_labels(&label_id).await?;
+    if labels.info.volume_id != id {
+        return Err(ApiError::NotFound(label_id));
+    }
+    Ok(labels)
+}
+
+/// Download file stem for an id, which may contain derived-volume syntax
+fn file_name(id: &str) -> String {
+    id.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' })
+        .collect()
+}
+
+/// GET /api/volumes/:id/export - Download a volume or subvolume as NIfTI,
+/// NPY, MetaImage or HDF5, with its spacing and crop origin
+#[utoipa::path(
+    get, path = "/volumes/{id}/export", tag = "volumes", params(("id" = String, Path, description = "Volume id"), ExportQuery),
+    responses(
+        (status = 200, description = "Volume file in the requested format", body = RawBytes, content_type = "application/octet-stream",
+            headers(("x-volume-dims" = String, description = "Dimensions `x,y,z` of the data"), ("x-volume-spacing" = String, description = "Voxel spacing `x,y,z` in millimetres"))),
+        (status = 400, description = "Invalid parameters", body = ErrorResponse),
+        (status = 404, description = "No such volume", body = ErrorResponse),
+        (status = 503, description = "Server busy; retry after `Retry-After` seconds", body = ErrorResponse),
+        (status = 507, description = "The request needs more memory than the server's whole budget", body = ErrorResponse),
+    )
+)]
+pub async fn export_volume(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    Query(query): Query<ExportQuery>,
+    request_headers: HeaderMap,
+) -> Result<impl IntoResponse, ApiError> {
+    let volume = state.find_volume(&id)?;
+    let info = volume.info();
+    if query.frame >= info.frames {
+        return Err(ApiError::BadRequest(format!(
+            "Frame {} out of range ({} frames)",
+            query.frame, info.frames
+        )));
+    }
+    let crop = query
+        .crop
+        .as_deref()
+        .map(|crop| export::parse_crop(crop, info.dimensions))
+        .transpose()
+        .map_err(ApiError::BadRequest)?;
+    let resolution = query.resolution.map(|r| r.clamp(16, 512));
+    export::check_dims(query.format, export::output_dims(info.dimensions, crop.as_ref(), resolution))
+        .map_err(ApiError::BadRequest)?;
+    let mask = match query.label {
+        Some(value) => Some((linked_labels(&state, &id, query.labels.clone()).await?, value)),
+        None => None,
+    };
+
+    // Derived data is computed under its own permit before this one is taken
+    let cancel = state.sessions.begin(&request_headers);
+    let token = cancel.token.clone();
+    if let VolumeRef::Derived(derived) = &volume {
+        state.derived_data(derived, token.clone()).await?;
+    }
+
+    // The frame, its masked or cropped copy and the encoded file, plus the
+    // decoded labels of a mask
+    let mask_size = if mask.is_some() { info.full_res_size / 2 } else { 0 };
+    let _permit = state
+        .admission
+        .admit(HeavyRoute::Full, 3 * info.full_res_size + mask_size)
+        .await?;
+    let data = match &volume {
+        VolumeRef::File(volume) => volume.read_frame(query.frame, token.clone()).await?,
+        VolumeRef::Derived(_) => state.read_array(&id, token.clone()).await?,
+    };
+
+    let mut name = file_name(&id);
+    if info.frames > 1 {
+        name = format!("{}_frame{}", name, query.frame);
     }
+    let (format, spacing) = (query.format, info.spacing);
+    let labels = match mask {
+        Some((labels, value)) => Some((labels.values(&state.label_data(&labels, token.clone()).await?.bytes), value)),
+        None => None,
+    };
+    let export_name = name.clone();
+    let (bytes, dims, spacing) = tokio::task::spawn_blocking(move || {
+        let export = Export::prepare(data, spacing, labels, crop, resolution, &token)?;
+        let bytes = export.encode(format, &export_name)?;
+        Ok::<_, ApiError>((bytes, export.dims(), export.spacing()))
+    })
+    .await
+    .map_err(join_error)??;
+
+    let mut headers = HeaderMap::new();
+    headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(format.content_type()));
+    headers.insert(
+        header::CONTENT_DISPOSITION,
+        HeaderValue::from_str(&format!("attachment; filename=\"{}.{}\"", name, format.extension())).unwrap(),
+    );
+    headers.insert(
+        "x-volume-dims",
+        HeaderValue::from_str(&format!("{},{},{}", dims[0], dims[1], dims[2])).unwrap(),
+    );
+    headers.insert(
+        "x-volume-spacing",
+        HeaderValue::from_str(&format!("{},{},{}", spacing[0], spacing[1], spacing[2])).unwrap(),
+    );
+    Ok((StatusCode::OK, headers, Body::from(bytes)))
+}
+
+/// Resampled data of any volume, computed once per resolution and cached
+#[tracing::instrument(skip_all, fields(volume = %info.id, resolution = resolution))]
+async fn resampled(
+    state: &AppState,
+    volume: &VolumeRef<'_>,
+    info: &VolumeInfo,
+    resolution: usize,
+    cancel: CancellationToken,
+) -> Result<CachedVolume, ApiError> {
+    state
+        .cache
+        .get_or_compute(CacheKey::new(&info.id, CacheLevel::Resolution(resolution)), &cancel, |cancel| async move {
+            match volume {
+                VolumeRef::File(volume) => {
+                    let _permit = state
+                        .admission
+                        .admit(HeavyRoute::Resample, volume.resample_memory_estimate(resolution))
+                        .await?;
+                    let (bytes, dims) = state
+                        .metrics
+                        .time_load("resample", volume.get_data_at_resolution(resolution, cancel))
+                        .await?;
+                    Ok(CachedVolume {
+                        bytes: bytes.into(),
+                        dims,
+                    })
+                }
+                VolumeRef::Derived(_) => {
+                    let data = state.read_array(&info.id, cancel.clone()).await?;
+                    let downsample = tokio::task::spawn_blocking(move || {
+                        HDF5Volume::downsample(&data, resolution, &cancel)
+                    });
+                    let resampled = state
+                        .metrics
+                        .time_load("resample", async { downsample.await.map_err(join_error)? })
+                        .await?;
+                    let shape = resampled.shape();
+                    Ok(CachedVolume {
+                        dims: [shape[0] as u32, shape[1] as u32, shape[2] as u32],
+                        bytes: HDF5Volume::to_bytes(&resampled).into(),
+                    })
+                }
+            }
+        })
+        .await
 }
 
 /// GET /api/volumes/:id/low - Get low-res volume data (64^3)
+#[utoipa::path(
+    get, path = "/volumes/{id}/low", tag = "volumes", params(("id" = String, Path, description = "Volume id"), VersionQuery),
+    responses(
+        (status = 200, description = "Volume header, then preview voxels at `low_res_dimensions`, little-endian f32, z fastest", body = RawBytes, content_type = "application/octet-stream"),
+        (status = 304, description = "Not modified since the given `If-None-Match` or `If-Modified-Since`"),
+        (status = 404, description = "No such volume", body = ErrorResponse),
+        (status = 503, description = "Server busy; retry after `Retry-After` seconds", body = ErrorResponse),
+        (status = 507, description = "The request needs more memory than the server's whole budget", body = ErrorResponse),
+    )
+)]
 pub async fn get_volume_low(
     State(state): State<Arc<AppState>>,
     Path(id): Path<String>,
-) -> impl IntoResponse {
-    match state.get_volume(&id) {
-        Some(volume) => match volume.get_low_res_data().await {
-            Ok(data) => Ok((
-                [(axum::http::header::CONTENT_TYPE, "application/octet-stream")],
-                data,
-            )),
-            Err(e) => Err((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ErrorResponse {
-                    error: format!("Failed to read volume: {}", e),
-                }),
-            )),
-        },
-        None => Err((
-            StatusCode::NOT_FOUND,
-            Json(ErrorResponse {
-                error: format!("Volume '{}' not found", id),
-            }),
-        )),
+    Query(query): Query<VersionQuery>,
+    request_headers: HeaderMap,
+) -> Result<impl IntoResponse, ApiError> {
+    let volume = state.find_volume(&id)?;
+
+    let mut headers = HeaderMap::new();
+    if apply_cache_headers(&volume, "low", &query, &request_headers, &mut headers) {
+        return Ok((StatusCode::NOT_MODIFIED, headers, Body::empty()));
+    }
+
+    let info = volume.info();
+    let (data, dims) = match &volume {
+        VolumeRef::File(volume) => (volume.get_low_res_data().await?.into(), info.low_res_dimensions),
+        VolumeRef::Derived(_) => {
+            let cancel = state.sessions.begin(&request_headers);
+            let cached = resampled(&state, &volume, &info, 64, cancel.token.clone()).await?;
+            (cached.bytes, cached.dims)
+        }
+    };
+    let body = payload_body(&volume_header(&volume.info(), dims), data, &mut headers);
+    Ok((StatusCode::OK, headers, body))
+}
+
+/// Parse a single `Range: bytes=...` header against a body of `total` bytes.
+/// Returns `Ok(None)` when no range was requested and `Err(())` when the
+/// range cannot be satisfied. Multi-range requests are served in full.
+fn parse_range(headers: &HeaderMap, total: u64) -> Result<Option<Range<u64>>, ()> {
+    let Some(value) = headers.get(header::RANGE).and_then(|v| v.to_str().ok()) else {
+        return Ok(None);
+    };
+    let Some(spec) = value.trim().strip_prefix("bytes=") else {
+        return Ok(None);
+    };
+    if spec.contains(',') {
+        return Ok(None);
+    }
+    let Some((start, end)) = spec.split_once('-') else {
+        return Err(());
+    };
+
+    let range = match (start.trim(), end.trim()) {
+        // Suffix range: last N bytes
+        ("", suffix) => {
+            let n: u64 = suffix.parse().map_err(|_| ())?;
+            total.saturating_sub(n)..total
+        }
+        (start, "") => start.parse().map_err(|_| ())?..total,
+        (start, end) => {
+            let start: u64 = start.parse().map_err(|_| ())?;
+            let end: u64 = end.parse().map_err(|_| ())?;
+            start..end.saturating_add(1).min(total)
+        }
+    };
+
+    if range.start >= total || range.start >= range.end {
+        return Err(());
     }
+    Ok(Some(range))
 }
 
 /// GET /api/volumes/:id/full - Get full-res volume data
+/// The body is streamed slab by slab and supports `Range` requests for resuming;
+/// byte offsets count the volume header.
+#[utoipa::path(
+    get, path = "/volumes/{id}/full", tag = "volumes", params(("id" = String, Path, description = "Volume id"), VersionQuery),
+    responses(
+        (status = 200, description = "Volume header, then little-endian f32 voxels, z fastest", body = RawBytes, content_type = "application/octet-stream"),
+        (status = 206, description = "The bytes named by `Range`", body = RawBytes, content_type = "application/octet-stream"),
+        (status = 304, description = "Not modified since the given `If-None-Match` or `If-Modified-Since`"),
+        (status = 404, description = "No such volume", body = ErrorResponse),
+        (status = 416, description = "`Range` outside the data"),
+        (status = 503, description = "Server busy; retry after `Retry-After` seconds", body = ErrorResponse),
+        (status = 507, description = "The request needs more memory than the server's whole budget", body = ErrorResponse),
+    )
+)]
 pub async fn get_volume_full(
     State(state): State<Arc<AppState>>,
     Path(id): Path<String>,
-) -> impl IntoResponse {
-    match state.get_volume(&id) {
-        Some(volume) => match volume.get_full_res_data().await {
-            Ok(data) => Ok((
-                [(axum::http::header::CONTENT_TYPE, "application/octet-stream")],
-                data,
-            )),
-            Err(e) => Err((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ErrorResponse {
-                    error: format!("Failed to read volume: {}", e),
-                }),
-            )),
-        },
-        None => Err((
-            StatusCode::NOT_FOUND,
-            Json(ErrorResponse {
-                error: format!("Volume '{}' not found", id),
-            }),
-        )),
+    Query(query): Query<VersionQuery>,
+    request_headers: HeaderMap,
+) -> Result<impl IntoResponse, ApiError> {
+    let volume = state.find_volume(&id)?;
+    let info = volume.info();
+
+    let total = VolumeHeader::SIZE as u64 + info.full_res_size;
+    let mut headers = HeaderMap::new();
+    if apply_cache_headers(&volume, "full", &query, &request_headers, &mut headers) {
+        return Ok((StatusCode::NOT_MODIFIED, headers, Body::empty()));
+    }
+    headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("application/octet-stream"));
+    headers.insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+
+    // A Range is only honoured if If-Range (when given) still names this content
+    let range_valid = request_headers
+        .get(header::IF_RANGE)
+        .is_none_or(|v| Some(v) == headers.get(header::ETAG));
+    let requested_range = if range_valid { parse_range(&request_headers, total) } else { Ok(None) };
+
+    let (status, range) = match requested_range {
+        Ok(Some(range)) => {
+            headers.insert(
+                header::CONTENT_RANGE,
+                HeaderValue::from_str(&format!("bytes {}-{}/{}", range.start, range.end - 1, total)).unwrap(),
+            );
+            (StatusCode::PARTIAL_CONTENT, range)
+        }
+        Ok(None) => (StatusCode::OK, 0..total),
+        Err(()) => {
+            headers.insert(
+                header::CONTENT_RANGE,
+                HeaderValue::from_str(&format!("bytes */{}", total)).unwrap(),
+            );
+            return Ok((StatusCode::RANGE_NOT_SATISFIABLE, headers, Body::empty()));
+        }
+    };
+
+    headers.insert(header::CONTENT_LENGTH, HeaderValue::from(range.end - range.start));
+
+    // Cancelled if the client goes away or its session starts another request
+    let cancel = state.sessions.begin(&request_headers);
+
+    let volume = match volume {
+        VolumeRef::File(volume) => volume,
+        VolumeRef::Derived(derived) => {
+            // Computed before the header is written, so it has the derived value range
+            let data = state.derived_data(&derived, cancel.token.clone()).await?;
+            let header = volume_header(&derived.info(), data.dims).encode();
+            return Ok((status, headers, ranged_body(&header, &data.bytes, range)));
+        }
+    };
+    let header = volume_header(&info, info.dimensions).encode();
+
+    // The first frame is served from memory when /frames already cached it;
+    // otherwise it is streamed from the file and never held whole
+    if let Some(cached) = state.cache.get(&CacheKey::new(&info.id, CacheLevel::Full)) {
+        return Ok((status, headers, ranged_body(&header, &cached.bytes, range)));
     }
+
+    // The permit and cancel guard travel with the stream and are released
+    // when it ends or the client disconnects
+    let permit = state
+        .admission
+        .admit(HeavyRoute::Full, volume.stream_memory_estimate())
+        .await?;
+    let (head, range) = split_range(&header, range);
+    if range.is_empty() {
+        return Ok((status, headers, Body::from(head)));
+    }
+    let chunks = ReceiverStream::new(volume.stream_full_res_data(range, cancel.token.clone())).map(move |chunk| {
+        let _ = (&permit, &cancel);
+        chunk
+    });
+    let body = tokio_stream::once(Ok(head)).chain(chunks);
+    Ok((status, headers, Body::from_stream(body)))
+}
+
+/// The bytes of `header` inside `range` of a body that starts with it, and
+/// the range of the voxel data that follows
+fn split_range(header: &[u8], range: Range<u64>) -> (Bytes, Range<u64>) {
+    let size = header.len() as u64;
+    let head = Bytes::copy_from_slice(&header[range.start.min(size) as usize..range.end.min(size) as usize]);
+    (head, range.start.saturating_sub(size)..range.end.saturating_sub(size))
+}
+
+/// `range` of the payload `header` followed by `data`
+fn ranged_body(header: &[u8], data: &Bytes, range: Range<u64>) -> Body {
+    let (head, range) = split_range(header, range);
+    let chunks = [head, data.slice(range.start as usize..range.end as usize)];
+    Body::from_stream(tokio_stream::iter(chunks.map(Ok::<_, Infallible>)))
+}
+
+/// Reject a frame index past the end of `info`'s frames
+fn check_frame(info: &VolumeInfo, frame: u32) -> Result<(), ApiError> {
+    if frame >= info.frames {
+        return Err(ApiError::BadRequest(format!(
+            "Frame {} out of range ({} frames)",
+            frame, info.frames
+        )));
+    }
+    Ok(())
+}
+
+/// Full-res bytes of one frame of any volume, read once per frame and cached
+async fn frame_data(
+    state: &AppState,
+    volume: &VolumeRef<'_>,
+    info: &VolumeInfo,
+    frame: u32,
+    cancel: CancellationToken,
+) -> Result<CachedVolume, ApiError> {
+    check_frame(info, frame)?;
+
+    match volume {
+        VolumeRef::Derived(derived) => state.derived_data(derived, cancel).await,
+        VolumeRef::File(volume) => {
+            // The first frame shares its entry with /full
+            let mut key = CacheKey::new(&info.id, CacheLevel::Full);
+            if frame > 0 {
+                key.component = format!("frame{}", frame);
+            }
+            state
+                .cache
+                .get_or_compute(key, &cancel, |cancel| async move {
+                    // The frame array plus its encoded bytes
+                    let _permit = state
+                        .admission
+                        .admit(HeavyRoute::Full, 2 * info.full_res_size)
+                        .await?;
+                    let data = state.metrics.time_load("frame", volume.read_frame(frame, cancel)).await?;
+                    Ok::<_, ApiError>(CachedVolume {
+                        bytes: HDF5Volume::to_bytes(&data).into(),
+                        dims: info.dimensions,
+                    })
+                })
+                .await
+        }
+    }
+}
+
+/// GET /api/volumes/:id/frames/:frame - Get one full-res time frame
+#[utoipa::path(
+    get, path = "/volumes/{id}/frames/{frame}", tag = "volumes",
+    params(("id" = String, Path, description = "Volume id"), ("frame" = u32, Path, description = "Frame index"), VersionQuery),
+    responses(
+        (status = 200, description = "Volume header naming the frame, then little-endian f32 voxels, z fastest", body = RawBytes, content_type = "application/octet-stream"),
+        (status = 304, description = "Not modified since the given `If-None-Match` or `If-Modified-Since`"),
+        (status = 400, description = "Invalid parameters", body = ErrorResponse),
+        (status = 404, description = "No such volume", body = ErrorResponse),
+        (status = 503, description = "Server busy; retry after `Retry-After` seconds", body = ErrorResponse),
+        (status = 507, description = "The request needs more memory than the server's whole budget", body = ErrorResponse),
+    )
+)]
+pub async fn get_volume_frame(
+    State(state): State<Arc<AppState>>,
+    Path((id, frame)): Path<(String, u32)>,
+    Query(query): Query<VersionQuery>,
+    request_headers: HeaderMap,
+) -> Result<impl IntoResponse, ApiError> {
+    let volume = state.find_volume(&id)?;
+    let info = volume.info();
+    // An invalid frame is a 400 even when the client's validators match
+    check_frame(&info, frame)?;
+
+    let mut headers = HeaderMap::new();
+    if apply_cache_headers(&volume, &format!("frame{}", frame), &query, &request_headers, &mut headers) {
+        return Ok((StatusCode::NOT_MODIFIED, headers, Body::empty()));
+    }
+
+    let cancel = state.sessions.begin(&request_headers);
+    let data = frame_data(&state, &volume, &info, frame, cancel.token.clone()).await?;
+
+    let header = volume_header(&volume.info(), data.dims).with_frames(frame, 1);
+    let body = payload_body(&header, data.bytes, &mut headers);
+    Ok((StatusCode::OK, headers, body))
+}
+
+/// GET /api/volumes/:id/frames?start=&count= - Get consecutive full-res frames
+/// The frames follow one volume header in order; the header names the range.
+#[utoipa::path(
+    get, path = "/volumes/{id}/frames", tag = "volumes", params(("id" = String, Path, description = "Volume id"), VersionQuery, FrameRangeQuery),
+    responses(
+        (status = 200, description = "Volume header, then consecutive frames of little-endian f32 voxels, z fastest", body = RawBytes, content_type = "application/octet-stream"),
+        (status = 304, description = "Not modified since the given `If-None-Match` or `If-Modified-Since`"),
+        (status = 400, description = "Invalid parameters", body = ErrorResponse),
+        (status = 404, description = "No such volume", body = ErrorResponse),
+        (status = 503, description = "Server busy; retry after `Retry-After` seconds", body = ErrorResponse),
+        (status = 507, description = "The request needs more memory than the server's whole budget", body = ErrorResponse),
+    )
+)]
+pub async fn get_volume_frames(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    Query(query): Query<VersionQuery>,
+    Query(range): Query<FrameRangeQuery>,
+    request_headers: HeaderMap,
+) -> Result<impl IntoResponse, ApiError> {
+    let volume = state.find_volume(&id)?;
+    let info = volume.info();
+
+    if range.count == 0 || range.count > MAX_FRAMES_PER_REQUEST {
+        return Err(ApiError::BadRequest(format!(
+            "Frame count must be in 1..={}",
+            MAX_FRAMES_PER_REQUEST
+        )));
+    }
+    let end = range.start.saturating_add(range.count);
+    if end > info.frames {
+        return Err(ApiError::BadRequest(format!(
+            "Frames {}..{} out of range ({} frames)",
+            range.start, end, info.frames
+        )));
+    }
+
+    let mut headers = HeaderMap::new();
+    let variant = format!("frames{}+{}", range.start, range.count);
+    if apply_cache_headers(&volume, &variant, &query, &request_headers, &mut headers) {
+        return Ok((StatusCode::NOT_MODIFIED, headers, Body::empty()));
+    }
+
+    // The first frame is read before answering, so a busy server still gets a
+    // status code; the rest are read one at a time as the body is sent, so
+    // the range is never held whole
+    let cancel = state.sessions.begin(&request_headers);
+    let first = frame_data(&state, &volume, &info, range.start, cancel.token.clone()).await?;
+    let (sender, receiver) = mpsc::channel(1);
+    let (task_state, token) = (state.clone(), cancel.token.clone());
+    let task_info = info.clone();
+    tokio::spawn(async move {
+        let Some(volume) = task_state.get_volume(&task_info.id) else {
+            return;
+        };
+        for frame in range.start + 1..end {
+            let chunk = frame_data(&task_state, &volume, &task_info, frame, token.clone())
+                .await
+                .map(|data| data.bytes)
+                .map_err(|e| std::io::Error::other(format!("frame {}: {:?}", frame, e)));
+            let failed = chunk.is_err();
+            if sender.send(chunk).await.is_err() || failed {
+                break;
+            }
+        }
+    });
+
+    // The cancel guard travels with the stream, so a disconnect stops the reads
+    let rest = ReceiverStream::new(receiver).map(move |chunk| {
+        let _ = &cancel;
+        chunk
+    });
+    let header = volume_header(&volume.info(), info.dimensions).with_frames(range.start, range.count);
+    headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("application/octet-stream"));
+    headers.insert(
+        header::CONTENT_LENGTH,
+        HeaderValue::from(VolumeHeader::SIZE as u64 + range.count as u64 * first.bytes.len() as u64),
+    );
+    let head = [Bytes::copy_from_slice(&header.encode()), first.bytes];
+    let body = tokio_stream::iter(head.map(Ok)).chain(rest);
+    Ok((StatusCode::OK, headers, Body::from_stream(body)))
 }
 
 /// GET /api/volumes/:id/at/:resolution - Get volume data at specific resolution
 /// Resolution is the target size for the largest dimension (e.g., 64, 128, 256)
+#[utoipa::path(
+    get, path = "/volumes/{id}/at/{resolution}", tag = "volumes",
+    params(("id" = String, Path, description = "Volume id"), ("resolution" = usize, Path, description = "Size of the largest dimension, clamped to 16-512"), VersionQuery),
+    responses(
+        (status = 200, description = "Volume header with the resampled dimensions and spacing, then little-endian f32 voxels, z fastest", body = RawBytes, content_type = "application/octet-stream"),
+        (status = 304, description = "Not modified since the given `If-None-Match` or `If-Modified-Since`"),
+        (status = 404, description = "No such volume", body = ErrorResponse),
+        (status = 503, description = "Server busy; retry after `Retry-After` seconds", body = ErrorResponse),
+        (status = 507, description = "The request needs more memory than the server's whole budget", body = ErrorResponse),
+    )
+)]
 pub async fn get_volume_at_resolution(
     State(state): State<Arc<AppState>>,
     Path((id, resolution)): Path<(String, usize)>,
-) -> impl IntoResponse {
+    Query(query): Query<VersionQuery>,
+    request_headers: HeaderMap,
+) -> Result<impl IntoResponse, ApiError> {
     // Clamp resolution to reasonable bounds
     let resolution = resolution.clamp(16, 512);
 
-    match state.get_volume(&id) {
-        Some(volume) => match volume.get_data_at_resolution(resolution).await {
-            Ok((data, dims)) => {
-                // Return binary data with dimensions in headers
-                let mut headers = HeaderMap::new();
-                headers.insert(
-                    axum::http::header::CONTENT_TYPE,
-                    HeaderValue::from_static("application/octet-stream"),
-                );
-                headers.insert(
-                    "x-volume-dims",
-                    HeaderValue::from_str(&format!("{},{},{}", dims[0], dims[1], dims[2])).unwrap(),
-                );
-                Ok((headers, data))
-            },
-            Err(e) => Err((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ErrorResponse {
-                    error: format!("Failed to read volume: {}", e),
-                }),
-            )),
-        },
-        None => Err((
-            StatusCode::NOT_FOUND,
-            Json(ErrorResponse {
-                error: format!("Volume '{}' not found", id),
-            }),
-        )),
+    let volume = state.find_volume(&id)?;
+
+    let mut headers = HeaderMap::new();
+    let variant = format!("at{}", resolution);
+    if apply_cache_headers(&volume, &variant, &query, &request_headers, &mut headers) {
+        return Ok((StatusCode::NOT_MODIFIED, headers, Body::empty()));
+    }
+
+    let cancel = state.sessions.begin(&request_headers);
+    let CachedVolume { bytes: data, dims } =
+        resampled(&state, &volume, &volume.info(), resolution, cancel.token.clone()).await?;
+
+    let body = payload_body(&volume_header(&volume.info(), dims), data, &mut headers);
+    Ok((StatusCode::OK, headers, body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::derived::{MAX_ABSDIFF_NESTING, MAX_OPERATIONS};
+
+    const PHANTOM: &str = "phantoms/checkerboard-4-f32";
+
+    /// State serving one small phantom, `PHANTOM`
+    async fn phantom_state() -> Arc<AppState> {
+        Arc::new(AppState::new(&[], &["checkerboard:4".parse().unwrap()]).await)
+    }
+
+    async fn info_status(state: &Arc<AppState>, id: String) -> StatusCode {
+        get_volume_info(State(state.clone()), Path(id)).await.into_response().status()
+    }
+
+    #[tokio::test]
+    async fn long_derived_ids_are_bad_requests() {
+        let state = phantom_state().await;
+        let chain = |n| format!("{}{}", PHANTOM, "~laplacian".repeat(n));
+        assert_eq!(info_status(&state, chain(MAX_OPERATIONS)).await, StatusCode::OK);
+        assert_eq!(info_status(&state, chain(MAX_OPERATIONS + 1)).await, StatusCode::BAD_REQUEST);
+        assert_eq!(info_status(&state, chain(100_000)).await, StatusCode::BAD_REQUEST);
+
+        let nested = |n| (0..n).fold(PHANTOM.to_string(), |inner, _| format!("{}~absdiff({})", PHANTOM, inner));
+        assert_eq!(info_status(&state, nested(MAX_ABSDIFF_NESTING)).await, StatusCode::OK);
+        assert_eq!(info_status(&state, nested(MAX_ABSDIFF_NESTING + 1)).await, StatusCode::BAD_REQUEST);
+
+        // Deriving past the cap from a registered id is rejected too
+        let derived = derive_volume(
+            State(state.clone()),
+            Path(chain(MAX_OPERATIONS)),
+            Json(DeriveRequest { filter: "laplacian".to_string() }),
+        )
+        .await;
+        assert_eq!(derived.into_response().status(), StatusCode::BAD_REQUEST);
+
+        assert_eq!(info_status(&state, "missing~laplacian".to_string()).await, StatusCode::NOT_FOUND);
+    }
+
+    /// The JSON body of a handler's response, with its status
+    async fn json<T: serde::de::DeserializeOwned>(response: impl IntoResponse) -> (StatusCode, T) {
+        let response = response.into_response();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    /// A data root holding one file, `broken.h5`, that is not HDF5
+    fn broken_root(name: &str) -> String {
+        let dir = std::env::temp_dir().join(format!("3dlab-routes-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("broken.h5"), b"not an HDF5 file").unwrap();
+        dir.to_string_lossy().into_owned()
+    }
+
+    #[tokio::test]
+    async fn health_lists_volumes_and_failed_files() {
+        let phantom = "checkerboard:4".parse().unwrap();
+        let state = Arc::new(AppState::new(&[broken_root("health")], &[phantom]).await);
+
+        let (status, health) = json::<HealthResponse>(health(State(state.clone())).await).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(health.status, "degraded");
+        assert_eq!((health.volumes, health.available_samples), (1, vec![PHANTOM.to_string()]));
+        assert_eq!(health.failed.len(), 1);
+        assert_eq!((health.failed[0].id.as_str(), health.failed[0].file.as_str()), ("broken", "broken.h5"));
+        assert!(!health.failed[0].error.is_empty());
+
+        // One loaded volume is enough to be ready
+        let (status, readiness) = json::<ReadinessResponse>(ready(State(state)).await).await;
+        assert_eq!((status, readiness.ready), (StatusCode::OK, true));
+    }
+
+    #[tokio::test]
+    async fn not_ready_without_volumes() {
+        let state = Arc::new(AppState::new(&[broken_root("ready")], &[]).await);
+        let (status, readiness) = json::<ReadinessResponse>(ready(State(state)).await).await;
+        assert_eq!((status, readiness.ready), (StatusCode::SERVICE_UNAVAILABLE, false));
+        assert_eq!(readiness.reason.as_deref(), Some("All 1 volume files failed to load"));
+
+        let state = Arc::new(AppState::new(&[], &[]).await);
+        let (status, health) = json::<HealthResponse>(health(State(state.clone())).await).await;
+        assert_eq!((status, health.status.as_str(), health.volumes), (StatusCode::OK, "ok", 0));
+        let (_, readiness) = json::<ReadinessResponse>(ready(State(state)).await).await;
+        assert_eq!(readiness.reason.as_deref(), Some("No volume files found"));
+    }
+
+    #[tokio::test]
+    async fn failed_files_are_listed_on_request() {
+        let phantom = "checkerboard:4".parse().unwrap();
+        let state = Arc::new(AppState::new(&[broken_root("list")], &[phantom]).await);
+        let list = |include: Option<&str>| {
+            let query = VolumeListQuery {
+                include: include.map(str::to_string),
+                ..Default::default()
+            };
+            list_volumes(State(state.clone()), Query(query))
+        };
+
+        let (_, plain) = json::<VolumeListResponse>(list(None).await.unwrap()).await;
+        assert_eq!((plain.volumes.len(), plain.failed.len()), (1, 0));
+        let (_, with_failed) = json::<VolumeListResponse>(list(Some("failed")).await.unwrap()).await;
+        assert_eq!((with_failed.volumes.len(), with_failed.failed.len()), (1, 1));
+        assert_eq!(with_failed.failed[0].id, "broken");
+        assert_eq!(list(Some("failed,labels")).await.into_response().status(), StatusCode::BAD_REQUEST);
+    }
+
+    /// Request headers with `If-None-Match: value`
+    fn if_none_match(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::IF_NONE_MATCH, HeaderValue::from_str(value).unwrap());
+        headers
+    }
+
+    #[tokio::test]
+    async fn cache_headers_set_validators_and_cache_control() {
+        let state = phantom_state().await;
+        let volume = state.find_volume(PHANTOM).unwrap();
+        let version = volume.info().version;
+
+        let mut headers = HeaderMap::new();
+        assert!(!apply_cache_headers(&volume, "full", &VersionQuery::default(), &HeaderMap::new(), &mut headers));
+        let etag = format!("\"{}-full-c{}\"", version, VolumeHeader::VERSION);
+        assert_eq!(headers[header::ETAG], etag.as_str());
+        assert!(headers.contains_key(header::LAST_MODIFIED));
+        assert_eq!(headers[header::CACHE_CONTROL], "no-cache");
+
+        // Only the current version is immutable
+        let pinned = VersionQuery { v: Some(version.clone()) };
+        apply_cache_headers(&volume, "full", &pinned, &HeaderMap::new(), &mut headers);
+        assert_eq!(headers[header::CACHE_CONTROL], IMMUTABLE_CACHE_CONTROL);
+        let stale = VersionQuery { v: Some(format!("{}-old", version)) };
+        apply_cache_headers(&volume, "full", &stale, &HeaderMap::new(), &mut headers);
+        assert_eq!(headers[header::CACHE_CONTROL], "no-cache");
+    }
+
+    #[tokio::test]
+    async fn cache_headers_match_if_none_match() {
+        let state = phantom_state().await;
+        let volume = state.find_volume(PHANTOM).unwrap();
+        let etag = format!("\"{}-full-c{}\"", volume.info().version, VolumeHeader::VERSION);
+        let matches = |request: HeaderMap| {
+            apply_cache_headers(&volume, "full", &VersionQuery::default(), &request, &mut HeaderMap::new())
+        };
+
+        assert!(matches(if_none_match(&etag)));
+        assert!(matches(if_none_match(&format!("W/{}", etag))));
+        assert!(matches(if_none_match(&format!("\"other\", {}", etag))));
+        assert!(matches(if_none_match("*")));
+        assert!(!matches(if_none_match("\"other\"")));
+        // The tag names the variant, so another payload of the same file does not match
+        assert!(!matches(if_none_match(&etag.replace("-full-", "-low-"))));
+
+        // A non-matching If-None-Match wins over a matching If-Modified-Since
+        let mut request = if_none_match("\"other\"");
+        let later = httpdate::fmt_http_date(SystemTime::now() + std::time::Duration::from_secs(3600));
+        request.insert(header::IF_MODIFIED_SINCE, HeaderValue::from_str(&later).unwrap());
+        assert!(!matches(request.clone()));
+        request.remove(header::IF_NONE_MATCH);
+        assert!(matches(request));
+    }
+
+    #[tokio::test]
+    async fn invalid_frames_are_bad_requests_before_not_modified() {
+        let state = phantom_state().await;
+        let frames = state.find_volume(PHANTOM).unwrap().info().frames;
+        let status = |frame| {
+            get_volume_frame(
+                State(state.clone()),
+                Path((PHANTOM.to_string(), frame)),
+                Query(VersionQuery::default()),
+                if_none_match("*"),
+            )
+        };
+        assert_eq!(status(frames - 1).await.into_response().status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(status(frames).await.into_response().status(), StatusCode::BAD_REQUEST);
+    }
+
+    fn range_header(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::RANGE, HeaderValue::from_str(value).unwrap());
+        headers
+    }
+
+    #[test]
+    fn parse_range_forms() {
+        assert_eq!(parse_range(&HeaderMap::new(), 100), Ok(None));
+        assert_eq!(parse_range(&range_header("bytes=0-9"), 100), Ok(Some(0..10)));
+        assert_eq!(parse_range(&range_header("bytes=90-"), 100), Ok(Some(90..100)));
+        assert_eq!(parse_range(&range_header("bytes=-10"), 100), Ok(Some(90..100)));
+        // An end past the body is clamped, a suffix longer than it is the whole body
+        assert_eq!(parse_range(&range_header("bytes=50-500"), 100), Ok(Some(50..100)));
+        assert_eq!(parse_range(&range_header("bytes=-500"), 100), Ok(Some(0..100)));
+    }
+
+    #[test]
+    fn parse_range_ignores_other_units_and_multiple_ranges() {
+        assert_eq!(parse_range(&range_header("items=0-9"), 100), Ok(None));
+        assert_eq!(parse_range(&range_header("bytes=0-9,20-29"), 100), Ok(None));
+    }
+
+    #[test]
+    fn parse_range_rejects_unsatisfiable() {
+        assert_eq!(parse_range(&range_header("bytes=100-"), 100), Err(()));
+        assert_eq!(parse_range(&range_header("bytes=20-10"), 100), Err(()));
+        assert_eq!(parse_range(&range_header("bytes=-0"), 100), Err(()));
+        assert_eq!(parse_range(&range_header("bytes=a-b"), 100), Err(()));
+        assert_eq!(parse_range(&range_header("bytes=10"), 100), Err(()));
+    }
+
+    #[test]
+    fn split_range_across_the_header() {
+        let header = [1u8, 2, 3, 4];
+        let (head, data) = split_range(&header, 2..10);
+        assert_eq!(&head[..], &[3, 4]);
+        assert_eq!(data, 0..6);
+
+        let (head, data) = split_range(&header, 6..10);
+        assert!(head.is_empty());
+        assert_eq!(data, 2..6);
+
+        let (head, data) = split_range(&header, 0..3);
+        assert_eq!(&head[..], &[1, 2, 3]);
+        assert!(data.is_empty());
     }
 }
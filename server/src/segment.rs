@@ -0,0 +1,349 @@
+use ndarray::{Array3, Axis};
+use shared::{ComponentStats, LabelDtype, SegmentRequest, ThresholdMethod};
+use tokio_util::sync::CancellationToken;
+
+use crate::filters::check;
+use crate::hdf5_reader::HDF5Error;
+
+/// Largest opening/closing radius accepted, in voxels
+pub const MAX_MORPHOLOGY_RADIUS: u32 = 5;
+
+/// Number of histogram bins used by Otsu's method
+const OTSU_BINS: usize = 256;
+
+/// Result of segmenting an intensity volume
+pub struct Segmentation {
+    pub threshold: f32,
+    pub dtype: LabelDtype,
+    pub bytes: Vec<u8>,
+    pub components: Vec<ComponentStats>,
+}
+
+/// Check the request parameters before any data is read
+pub fn validate(request: &SegmentRequest) -> Result<(), String> {
+    if let ThresholdMethod::Percentile { percentile } = request.threshold {
+        if !(0.0..=100.0).contains(&percentile) {
+            return Err("Percentile must be in [0, 100]".to_string());
+        }
+    }
+    if request.open_radius > MAX_MORPHOLOGY_RADIUS || request.close_radius > MAX_MORPHOLOGY_RADIUS {
+        return Err(format!("Morphology radius must be at most {}", MAX_MORPHOLOGY_RADIUS));
+    }
+    Ok(())
+}
+
+/// Threshold, clean up and label `data`. Components are numbered by size,
+/// largest first; `spacing` converts voxel counts to physical volumes.
+pub fn segment(
+    data: &Array3<f32>,
+    request: &SegmentRequest,
+    spacing: [f32; 3],
+    cancel: &CancellationToken,
+) -> Result<Segmentation, HDF5Error> {
+    let threshold = match request.threshold {
+        ThresholdMethod::Otsu => otsu(data),
+        ThresholdMethod::Percentile { percentile } => percentile_value(data, percentile),
+        ThresholdMethod::Value { value } => value,
+    };
+
+    let mut mask = data.mapv(|v| v > threshold);
+    check(cancel)?;
+
+    let open = request.open_radius as usize;
+    if open > 0 {
+        mask = morph(&mask, open, false, cancel)?;
+        mask = morph(&mask, open, true, cancel)?;
+    }
+    let close = request.close_radius as usize;
+    if close > 0 {
+        mask = morph(&mask, close, true, cancel)?;
+        mask = morph(&mask, close, false, cancel)?;
+    }
+
+    let (labels, mut components) = label(&mask, request.full_connectivity, cancel)?;
+
+    // Keep components above the size limit, largest first, as many as fit in u16
+    components.retain(|c| c.voxel_count >= request.min_component_size.max(1));
+    components.sort_by_key(|c| std::cmp::Reverse(c.voxel_count));
+    components.truncate(u16::MAX as usize);
+
+    let mut relabel = vec![0u32; labels.iter().max().map_or(0, |&m| m as usize) + 1];
+    let voxel_volume = spacing.iter().map(|&s| s as f64).product::<f64>();
+    for (i, component) in components.iter_mut().enumerate() {
+        relabel[component.label as usize] = i as u32 + 1;
+        component.label = i as u32 + 1;
+        component.physical_volume = component.voxel_count as f64 * voxel_volume;
+    }
+
+    let dtype = if components.len() <= u8::MAX as usize {
+        LabelDtype::U8
+    } else {
+        LabelDtype::U16
+    };
+
+    let mut bytes = Vec::with_capacity(labels.len() * dtype.size());
+    for &value in labels.iter() {
+        let value = relabel[value as usize];
+        match dtype {
+            LabelDtype::U8 => bytes.push(value as u8),
+            LabelDtype::U16 => bytes.extend_from_slice(&(value as u16).to_le_bytes()),
+        }
+    }
+
+    Ok(Segmentation {
+        threshold,
+        dtype,
+        bytes,
+        components,
+    })
+}
+
+/// Otsu's threshold: the histogram split maximising between-class variance
+fn otsu(data: &Array3<f32>) -> f32 {
+    let min = data.iter().cloned().fold(f32::INFINITY, f32::min);
+    let max = data.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+    if max <= min {
+        return min;
+    }
+
+    let scale = (OTSU_BINS - 1) as f32 / (max - min);
+    let mut histogram = [0u64; OTSU_BINS];
+    for &v in data.iter() {
+        histogram[((v - min) * scale) as usize] += 1;
+    }
+
+    let total = data.len() as f64;
+    let sum: f64 = histogram.iter().enumerate().map(|(i, &n)| i as f64 * n as f64).sum();
+    let (mut weight_below, mut sum_below) = (0.0, 0.0);
+    let (mut best_bin, mut best_variance) = (0, 0.0);
+    for (i, &n) in histogram.iter().enumerate() {
+        weight_below += n as f64;
+        sum_below += i as f64 * n as f64;
+        let weight_above = total - weight_below;
+        if weight_below == 0.0 || weight_above == 0.0 {
+            continue;
+        }
+        let mean_below = sum_below / weight_below;
+        let mean_above = (sum - sum_below) / weight_above;
+        let variance = weight_below * weight_above * (mean_below - mean_above).powi(2);
+        if variance > best_variance {
+            best_variance = variance;
+            best_bin = i;
+        }
+    }
+
+    // Upper edge of the last bin in the background class
+    min + (best_bin as f32 + 1.0) / scale
+}
+
+fn percentile_value(data: &Array3<f32>, percentile: f32) -> f32 {
+    let mut values: Vec<f32> = data.iter().cloned().collect();
+    if values.is_empty() {
+        return 0.0;
+    }
+    let index = ((percentile / 100.0) * (values.len() - 1) as f32).round() as usize;
+    let (_, value, _) = values.select_nth_unstable_by(index, |a, b| a.total_cmp(b));
+    *value
+}
+
+/// Binary dilation (or erosion) with a cube of the given radius, done as
+/// one pass per axis. Voxels outside the volume are ignored.
+fn morph(
+    mask: &Array3<bool>,
+    radius: usize,
+    dilate: bool,
+    cancel: &CancellationToken,
+) -> Result<Array3<bool>, HDF5Error> {
+    let mut current = mask.clone();
+    for axis in 0..3 {
+        let len = current.len_of(Axis(axis));
+        let mut out = Array3::from_elem(current.raw_dim(), false);
+        for ((x, y, z), value) in out.indexed_iter_mut() {
+            if y == 0 && z == 0 {
+                check(cancel)?;
+            }
+            let idx = [x, y, z];
+            let lo = idx[axis].saturating_sub(radius);
+            let hi = (idx[axis] + radius).min(len - 1);
+            let mut window = (lo..=hi).map(|i| {
+                let mut p = idx;
+                p[axis] = i;
+                current[p]
+            });
+            *value = if dilate {
+                window.any(|v| v)
+            } else {
+                window.all(|v| v)
+            };
+        }
+        current = out;
+    }
+    Ok(current)
+}
+
+/// Label connected foreground regions with 6- or 26-connectivity. Returns
+/// the label array (0 = background) and unsorted per-label statistics.
+fn label(
+    mask: &Array3<bool>,
+    full_connectivity: bool,
+    cancel: &CancellationToken,
+) -> Result<(Array3<u32>, Vec<ComponentStats>), HDF5Error> {
+    let mut offsets = Vec::new();
+    for dx in -1isize..=1 {
+        for dy in -1isize..=1 {
+            for dz in -1isize..=1 {
+                let steps = dx.abs() + dy.abs() + dz.abs();
+                if steps == 1 || (full_connectivity && steps > 1) {
+                    offsets.push([dx, dy, dz]);
+                }
+            }
+        }
+    }
+
+    let shape = mask.shape();
+    let dims = [shape[0] as isize, shape[1] as isize, shape[2] as isize];
+    let mut labels = Array3::<u32>::zeros(mask.raw_dim());
+    let mut components = Vec::new();
+    let mut stack = Vec::new();
+
+    for ((x, y, z), &foreground) in mask.indexed_iter() {
+        if y == 0 && z == 0 {
+            check(cancel)?;
+        }
+        if !foreground || labels[[x, y, z]] != 0 {
+            continue;
+        }
+
+        let id = components.len() as u32 + 1;
+        let mut stats = ComponentStats {
+            label: id,
+            voxel_count: 0,
+            bbox_min: [x as u32, y as u32, z as u32],
+            bbox_max: [x as u32, y as u32, z as u32],
+            physical_volume: 0.0,
+        };
+        labels[[x, y, z]] = id;
+        stack.push([x, y, z]);
+
+        while let Some(p) = stack.pop() {
+            stats.voxel_count += 1;
+            for (i, &c) in p.iter().enumerate() {
+                stats.bbox_min[i] = stats.bbox_min[i].min(c as u32);
+                stats.bbox_max[i] = stats.bbox_max[i].max(c as u32);
+            }
+            for offset in &offsets {
+                let q = [
+                    p[0] as isize + offset[0],
+                    p[1] as isize + offset[1],
+                    p[2] as isize + offset[2],
+                ];
+                if (0..3).any(|i| q[i] < 0 || q[i] >= dims[i]) {
+                    continue;
+                }
+                let q = [q[0] as usize, q[1] as usize, q[2] as usize];
+                if mask[q] && labels[q] == 0 {
+                    labels[q] = id;
+                    stack.push(q);
+                }
+            }
+        }
+        components.push(stats);
+    }
+    Ok((labels, components))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(threshold: ThresholdMethod) -> SegmentRequest {
+        SegmentRequest {
+            threshold,
+            open_radius: 0,
+            close_radius: 0,
+            min_component_size: 0,
+            full_connectivity: false,
+        }
+    }
+
+    #[test]
+    fn validate_rejects_out_of_range_parameters() {
+        assert!(validate(&request(ThresholdMethod::Percentile { percentile: 150.0 })).is_err());
+        let mut radius = request(ThresholdMethod::Otsu);
+        radius.close_radius = MAX_MORPHOLOGY_RADIUS + 1;
+        assert!(validate(&radius).is_err());
+        assert!(validate(&request(ThresholdMethod::Percentile { percentile: 99.0 })).is_ok());
+    }
+
+    #[test]
+    fn otsu_splits_two_classes() {
+        let data = Array3::from_shape_fn((4, 4, 4), |(x, _, _)| if x < 2 { 1.0 } else { 9.0 });
+        let threshold = otsu(&data);
+        assert!(threshold > 1.0 && threshold < 9.0, "{}", threshold);
+        assert_eq!(otsu(&Array3::from_elem((2, 2, 2), 3.0)), 3.0);
+    }
+
+    #[test]
+    fn percentile_picks_the_ranked_value() {
+        let data = Array3::from_shape_fn((101, 1, 1), |(x, _, _)| (100 - x) as f32);
+        assert_eq!(percentile_value(&data, 0.0), 0.0);
+        assert_eq!(percentile_value(&data, 50.0), 50.0);
+        assert_eq!(percentile_value(&data, 100.0), 100.0);
+    }
+
+    #[test]
+    fn diagonal_voxels_connect_only_with_full_connectivity() {
+        let mut mask = Array3::from_elem((3, 3, 3), false);
+        mask[[0, 0, 0]] = true;
+        mask[[1, 1, 1]] = true;
+        let cancel = CancellationToken::new();
+        assert_eq!(label(&mask, false, &cancel).unwrap().1.len(), 2);
+        let (labels, components) = label(&mask, true, &cancel).unwrap();
+        assert_eq!(components.len(), 1);
+        assert_eq!(components[0].bbox_max, [1, 1, 1]);
+        assert_eq!(labels[[1, 1, 1]], labels[[0, 0, 0]]);
+    }
+
+    #[test]
+    fn opening_removes_isolated_voxels() {
+        let mut mask = Array3::from_elem((7, 7, 7), false);
+        mask[[0, 0, 0]] = true;
+        for x in 3..6 {
+            for y in 3..6 {
+                for z in 3..6 {
+                    mask[[x, y, z]] = true;
+                }
+            }
+        }
+        let cancel = CancellationToken::new();
+        let opened = morph(&morph(&mask, 1, false, &cancel).unwrap(), 1, true, &cancel).unwrap();
+        assert!(!opened[[0, 0, 0]]);
+        assert_eq!(opened.iter().filter(|&&v| v).count(), 27);
+    }
+
+    #[test]
+    fn components_are_numbered_largest_first_and_filtered_by_size() {
+        let mut data = Array3::zeros((8, 3, 3));
+        data[[0, 0, 0]] = 1.0;
+        for x in 4..8 {
+            data[[x, 1, 1]] = 1.0;
+        }
+        data[[2, 2, 2]] = 1.0;
+        data[[2, 2, 1]] = 1.0;
+        let mut request = request(ThresholdMethod::Value { value: 0.5 });
+        request.min_component_size = 2;
+
+        let result = segment(&data, &request, [1.0, 2.0, 0.5], &CancellationToken::new()).unwrap();
+        let counts: Vec<u64> = result.components.iter().map(|c| c.voxel_count).collect();
+        assert_eq!(counts, vec![4, 2]);
+        assert_eq!(result.components[0].physical_volume, 4.0);
+        assert_eq!(result.dtype, LabelDtype::U8);
+        assert_eq!(result.bytes.len(), data.len());
+
+        // Row-major like the data: the rod is label 1, the pair 2, the dropped voxel 0
+        let at = |x: usize, y: usize, z: usize| result.bytes[(x * 3 + y) * 3 + z];
+        assert_eq!(at(5, 1, 1), 1);
+        assert_eq!(at(2, 2, 2), 2);
+        assert_eq!(at(0, 0, 0), 0);
+    }
+}
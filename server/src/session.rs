@@ -0,0 +1,44 @@
+use axum::http::HeaderMap;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use tokio_util::sync::{CancellationToken, DropGuard};
+
+/// Header clients use to identify their session
+pub const SESSION_HEADER: &str = "x-session-id";
+
+/// Tracks the heavy request currently running for each client session, so a
+/// newer request (e.g. after switching volumes) cancels the one it replaces
+#[derive(Default)]
+pub struct Sessions {
+    active: Mutex<HashMap<String, CancellationToken>>,
+}
+
+/// Cancellation handle for one request. The token is cancelled when the
+/// guard is dropped (the client disconnected or the response finished) or
+/// when a newer request from the same session begins.
+pub struct RequestCancel {
+    pub token: CancellationToken,
+    _guard: DropGuard,
+}
+
+impl Sessions {
+    /// Start tracking a request, cancelling the previous one from the same session
+    pub fn begin(&self, headers: &HeaderMap) -> RequestCancel {
+        let token = CancellationToken::new();
+
+        if let Some(session) = headers.get(SESSION_HEADER).and_then(|v| v.to_str().ok()) {
+            let mut active = self.active.lock().unwrap();
+            // Finished requests have already cancelled their tokens
+            active.retain(|_, t| !t.is_cancelled());
+            if let Some(previous) = active.insert(session.to_string(), token.clone()) {
+                tracing::debug!(session, "Session started a new request, cancelling the previous one");
+                previous.cancel();
+            }
+        }
+
+        RequestCancel {
+            _guard: token.clone().drop_guard(),
+            token,
+        }
+    }
+}
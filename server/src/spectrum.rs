@@ -0,0 +1,321 @@
+//! Centred Fourier spectrum of a volume, served as two derived volumes: its
+//! log-magnitude and its phase
+
+use ndarray::{Array3, Axis};
+use num_complex::Complex32;
+use shared::VolumeInfo;
+use std::f32::consts::PI;
+use std::fmt;
+use std::str::FromStr;
+use tokio_util::sync::CancellationToken;
+
+use crate::fft;
+use crate::filters::check;
+use crate::hdf5_reader::HDF5Error;
+
+/// Which part of the spectrum a derived volume shows
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SpectrumPart {
+    /// `ln(1 + |F|)`, which keeps the centre of k-space and its periphery in one range
+    LogMagnitude,
+    /// `arg(F)` in radians, in [-pi, pi]
+    Phase,
+}
+
+impl SpectrumPart {
+    fn name(self) -> &'static str {
+        match self {
+            SpectrumPart::LogMagnitude => "logmag",
+            SpectrumPart::Phase => "phase",
+        }
+    }
+}
+
+/// Centred FFT of the whole volume, or of each slice perpendicular to `slice_axis`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Spectrum {
+    pub part: SpectrumPart,
+    pub slice_axis: Option<usize>,
+}
+
+impl fmt::Display for Spectrum {
+    /// Canonical form used in derived volume ids, e.g. `fft(logmag)` or `fft2(phase,0)`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.slice_axis {
+            None => write!(f, "fft({})", self.part.name()),
+            Some(axis) => write!(f, "fft2({},{})", self.part.name(), axis),
+        }
+    }
+}
+
+impl FromStr for Spectrum {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (name, args) = s
+            .strip_suffix(')')
+            .and_then(|rest| rest.split_once('('))
+            .ok_or_else(|| format!("Invalid spectrum '{}' (expected fft(part) or fft2(part,axis))", s))?;
+        let args: Vec<&str> = args.split(',').map(str::trim).collect();
+
+        let part = match args[0] {
+            "logmag" => SpectrumPart::LogMagnitude,
+            "phase" => SpectrumPart::Phase,
+            other => return Err(format!("Unknown spectrum part '{}' (logmag or phase)", other)),
+        };
+        let slice_axis = match (name, &args[1..]) {
+            ("fft", []) => None,
+            ("fft2", [axis]) => match axis.parse() {
+                Ok(axis @ 0..=2) => Some(axis),
+                _ => return Err(format!("Slice axis must be 0, 1 or 2, got '{}'", axis)),
+            },
+            _ => return Err(format!("Invalid spectrum '{}' (expected fft(part) or fft2(part,axis))", s)),
+        };
+        Ok(Spectrum { part, slice_axis })
+    }
+}
+
+impl Spectrum {
+    /// The other part of the same transform
+    pub fn sibling(&self) -> Spectrum {
+        let part = match self.part {
+            SpectrumPart::LogMagnitude => SpectrumPart::Phase,
+            SpectrumPart::Phase => SpectrumPart::LogMagnitude,
+        };
+        Spectrum { part, ..*self }
+    }
+
+    /// Range of this part, known before transforming: phase is in [-pi, pi],
+    /// and as an orthonormal transform of n voxels has |F| <= sqrt(n) max|x|,
+    /// the log-magnitude is at most ln(1 + sqrt(n) max|x|)
+    pub fn value_range(&self, source: &VolumeInfo) -> [f32; 2] {
+        match self.part {
+            SpectrumPart::Phase => [-PI, PI],
+            SpectrumPart::LogMagnitude => {
+                let dims = source.dimensions.map(|d| d as f32);
+                let voxels = match self.slice_axis {
+                    Some(axis) => dims.iter().product::<f32>() / dims[axis].max(1.0),
+                    None => dims.iter().product(),
+                };
+                let [min, max] = source.value_range;
+                let peak = min.abs().max(max.abs());
+                [0.0, (voxels.sqrt() * peak).ln_1p()]
+            }
+        }
+    }
+
+    /// This part and the sibling's of one transformed value
+    fn split(&self, value: Complex32) -> (f32, f32) {
+        let (log_magnitude, phase) = (value.norm().ln_1p(), value.arg());
+        match self.part {
+            SpectrumPart::LogMagnitude => (log_magnitude, phase),
+            SpectrumPart::Phase => (phase, log_magnitude),
+        }
+    }
+
+    /// Transform the volume once and split it into this spectrum's part,
+    /// written over `data`, and its sibling's, encoded as it is computed.
+    /// Checks `cancel` between slices of each pass.
+    pub fn apply(
+        &self,
+        mut data: Array3<f32>,
+        cancel: &CancellationToken,
+    ) -> Result<(Array3<f32>, EncodedPart), HDF5Error> {
+        let sibling = match self.slice_axis {
+            None => {
+                let values = data.iter().map(|&v| Complex32::new(v, 0.0)).collect();
+                let mut spectrum = Array3::from_shape_vec(data.raw_dim(), values)
+                    .map_err(|e| HDF5Error::ShapeMismatch(e.to_string()))?;
+                for axis in 0..3 {
+                    // Iterate over planes along another axis, each holding whole lanes along `axis`
+                    let outer = if axis == 0 { 1 } else { 0 };
+                    let lane_axis = if axis > outer { axis - 1 } else { axis };
+                    for mut plane in spectrum.axis_iter_mut(Axis(outer)) {
+                        check(cancel)?;
+                        fft::centered_axis(&mut plane, lane_axis, false);
+                    }
+                }
+
+                // Pack the sibling's values two to an element into the front
+                // of the transform, which has been read up to there, so it
+                // can shrink to half before they are encoded
+                let mut packed = spectrum.into_raw_vec();
+                for (i, value) in data.iter_mut().enumerate() {
+                    let (part, other) = self.split(packed[i]);
+                    *value = part;
+                    let slot = &mut packed[i / 2];
+                    if i % 2 == 0 {
+                        slot.re = other;
+                    } else {
+                        slot.im = other;
+                    }
+                }
+                packed.truncate(data.len().div_ceil(2));
+                packed.shrink_to_fit();
+                let mut sibling = EncodedPart::new(data.len());
+                let others = packed.iter().flat_map(|v| [v.re, v.im]).take(data.len());
+                for (i, other) in others.enumerate() {
+                    sibling.set(i, other);
+                }
+                sibling
+            }
+            Some(slice_axis) => {
+                // One slice's transform at a time
+                let mut sibling = EncodedPart::new(data.len());
+                let (_, ny, nz) = data.dim();
+                for index in 0..data.len_of(Axis(slice_axis)) {
+                    check(cancel)?;
+                    let mut slice = data.index_axis_mut(Axis(slice_axis), index);
+                    let mut plane = slice.mapv(|v| Complex32::new(v, 0.0));
+                    fft::centered_axis(&mut plane, 0, false);
+                    fft::centered_axis(&mut plane, 1, false);
+                    for (((p, q), &transformed), value) in plane.indexed_iter().zip(slice.iter_mut()) {
+                        let [x, y, z] = match slice_axis {
+                            0 => [index, p, q],
+                            1 => [p, index, q],
+                            _ => [p, q, index],
+                        };
+                        let (part, other) = self.split(transformed);
+                        *value = part;
+                        sibling.set((x * ny + y) * nz + z, other);
+                    }
+                }
+                sibling
+            }
+        };
+        Ok((data, sibling))
+    }
+}
+
+/// A part of a spectrum as little-endian `f32` bytes, ready for the cache
+pub struct EncodedPart {
+    pub bytes: Vec<u8>,
+    pub value_range: [f32; 2],
+}
+
+impl EncodedPart {
+    fn new(len: usize) -> Self {
+        Self {
+            bytes: vec![0; len * std::mem::size_of::<f32>()],
+            value_range: [f32::INFINITY, f32::NEG_INFINITY],
+        }
+    }
+
+    /// Store the value of voxel `index`, in standard layout order
+    fn set(&mut self, index: usize, value: f32) {
+        self.bytes[4 * index..4 * index + 4].copy_from_slice(&value.to_le_bytes());
+        self.value_range = [self.value_range[0].min(value), self.value_range[1].max(value)];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(dims: [u32; 3], value_range: [f32; 2]) -> VolumeInfo {
+        VolumeInfo {
+            id: "a".to_string(),
+            dimensions: dims,
+            value_range,
+            ..Default::default()
+        }
+    }
+
+    fn spectrum(spec: &str) -> Spectrum {
+        spec.parse().unwrap()
+    }
+
+    /// This part of `spec` and the decoded sibling, whose range must match its values
+    fn apply(spec: &str, data: &Array3<f32>) -> (Array3<f32>, Array3<f32>) {
+        let (part, sibling) = spectrum(spec).apply(data.clone(), &CancellationToken::new()).unwrap();
+        let values = sibling
+            .bytes
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect();
+        let decoded = Array3::from_shape_vec(data.raw_dim(), values).unwrap();
+        let min = decoded.iter().cloned().fold(f32::INFINITY, f32::min);
+        let max = decoded.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+        assert_eq!(sibling.value_range, [min, max]);
+        (part, decoded)
+    }
+
+    #[test]
+    fn parses_and_prints_ids() {
+        for spec in ["fft(logmag)", "fft(phase)", "fft2(logmag,0)", "fft2(phase,2)"] {
+            assert_eq!(spectrum(spec).to_string(), spec);
+        }
+        assert_eq!(spectrum(" fft2( phase , 1 )").to_string(), "fft2(phase,1)");
+        for spec in ["fft(real)", "fft(logmag,0)", "fft2(logmag)", "fft2(logmag,3)", "fft3(phase)", "fft"] {
+            assert!(spec.parse::<Spectrum>().is_err(), "{}", spec);
+        }
+        assert_eq!(spectrum("fft2(logmag,1)").sibling(), spectrum("fft2(phase,1)"));
+    }
+
+    #[test]
+    fn constant_volumes_peak_at_the_centre() {
+        let data = Array3::from_elem((4, 6, 5), 2.0);
+        let (log_magnitude, phase) = apply("fft(logmag)", &data);
+        // The bound is reached by the zero frequency of a constant
+        let bound = spectrum("fft(logmag)").value_range(&info([4, 6, 5], [0.0, 2.0]))[1];
+        assert!((log_magnitude[[2, 3, 2]] - bound).abs() < 1e-4);
+        assert!(log_magnitude.iter().enumerate().all(|(i, &v)| i == (2 * 6 + 3) * 5 + 2 || v < 1e-4));
+        assert!(phase[[2, 3, 2]].abs() < 1e-4);
+
+        // Per slice, each slice of a constant peaks at its own centre
+        let (phase, log_magnitude) = apply("fft2(phase,0)", &data);
+        let bound = spectrum("fft2(logmag,0)").value_range(&info([4, 6, 5], [0.0, 2.0]))[1];
+        for x in 0..4 {
+            assert!((log_magnitude[[x, 3, 2]] - bound).abs() < 1e-4);
+            assert!(phase[[x, 3, 2]].abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn known_ranges_hold() {
+        let data = Array3::from_shape_fn((5, 4, 7), |(x, y, z)| ((x * 7 + y * 3 + z) % 5) as f32 - 1.5);
+        let source = info([5, 4, 7], [-1.5, 2.5]);
+        for spec in ["fft(logmag)", "fft2(logmag,1)"] {
+            let (log_magnitude, phase) = apply(spec, &data);
+            let [min, max] = spectrum(spec).value_range(&source);
+            assert!(log_magnitude.iter().all(|&v| (min..=max).contains(&v)));
+            let [min, max] = spectrum(spec).sibling().value_range(&source);
+            assert!(phase.iter().all(|&v| (min..=max).contains(&v)));
+            assert!(phase.iter().any(|&v| v < -0.1) && phase.iter().any(|&v| v > 0.1));
+        }
+    }
+
+    #[test]
+    fn matches_transforming_each_axis() {
+        // An odd voxel count, so the packed sibling ends in half an element
+        let data = Array3::from_shape_fn((5, 3, 7), |(x, y, z)| ((x * 7 + y * 3 + z) % 5) as f32 + 0.1 * x as f32);
+        for spec in ["fft(logmag)", "fft(phase)", "fft2(logmag,0)", "fft2(phase,1)", "fft2(logmag,2)"] {
+            let spectrum = spectrum(spec);
+            let mut expected = data.mapv(|v| Complex32::new(v, 0.0));
+            for axis in (0..3).filter(|&axis| Some(axis) != spectrum.slice_axis) {
+                fft::centered_axis(&mut expected, axis, false);
+            }
+
+            let (part, sibling) = apply(spec, &data);
+            let (log_magnitude, phase) = match spectrum.part {
+                SpectrumPart::LogMagnitude => (part, sibling),
+                SpectrumPart::Phase => (sibling, part),
+            };
+            for ((&l, &p), e) in log_magnitude.iter().zip(&phase).zip(&expected) {
+                assert!((l - e.norm().ln_1p()).abs() < 1e-4, "{}", spec);
+                // Phases of near-zero values are noise; others may wrap around at pi
+                let turn = (p - e.arg()).abs();
+                assert!(e.norm() < 1e-3 || turn.min(2.0 * PI - turn) < 1e-3, "{}", spec);
+            }
+        }
+    }
+
+    #[test]
+    fn stops_when_cancelled() {
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        let result = spectrum("fft(phase)").apply(Array3::zeros((4, 4, 4)), &cancel);
+        assert!(matches!(result, Err(HDF5Error::Cancelled)));
+    }
+}
@@ -1,52 +1,590 @@
-use shared::VolumeInfo;
+use ndarray::Array3;
+use shared::{
+    CompareRequest, CompareResponse, FailedVolume, LabelVolumeInfo, SegmentRequest, SpectrumRequest, SpectrumResponse,
+    VolumeInfo,
+};
 use std::collections::HashMap;
-use std::path::Path;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Instant, SystemTime};
+use tokio_util::sync::CancellationToken;
 
-use crate::hdf5_reader::HDF5Volume;
+use crate::admission::{AdmissionController, HeavyRoute, Rejection};
+use crate::annotations::AnnotationStore;
+use crate::cache::{CacheKey, CacheLevel, CachedVolume, VolumeCache};
+use crate::catalog::{self, Location};
+use crate::compare;
+use crate::derived::{check_depth, parse_derived_id, DerivedRegistry, DerivedVolume, Operation, DERIVED_SEPARATOR};
+use crate::error::ApiError;
+use crate::fastmri;
+use crate::hdf5_reader::{join_error, version_hash, HDF5Error, HDF5Volume};
+use crate::labels::{LabelFile, LabelRegistry, LabelSource, LabelVolume};
+use crate::metrics::Metrics;
+use crate::phantom::{Phantom, PHANTOM_FOLDER};
+use crate::segment;
+use crate::session::Sessions;
+use crate::spectrum::{EncodedPart, Spectrum, SpectrumPart};
+use crate::storage::NpyObject;
+
+/// Most derived volumes kept registered at once
+const MAX_DERIVED_VOLUMES: usize = 256;
+
+/// The blocking part of computing a derived volume from its source data, and
+/// the encoded data of a sibling volume when the same work yields it
+type DerivedCompute =
+    Box<dyn FnOnce(Array3<f32>, &CancellationToken) -> Result<(Array3<f32>, Option<EncodedPart>), HDF5Error> + Send>;
+
+/// Most segmentations kept registered at once
+const MAX_SEGMENTATIONS: usize = 64;
+
+/// A volume served by the data routes
+#[derive(Clone)]
+pub enum VolumeRef<'a> {
+    /// Read from a file or an object in a bucket
+    File(&'a HDF5Volume),
+    /// Computed from another volume
+    Derived(Arc<DerivedVolume>),
+}
+
+impl VolumeRef<'_> {
+    pub fn info(&self) -> VolumeInfo {
+        match self {
+            VolumeRef::File(volume) => volume.info.clone(),
+            VolumeRef::Derived(derived) => derived.info(),
+        }
+    }
+
+    /// Modification time of the underlying file
+    pub fn modified(&self) -> SystemTime {
+        match self {
+            VolumeRef::File(volume) => volume.modified,
+            VolumeRef::Derived(derived) => derived.modified,
+        }
+    }
+}
 
 /// Application state shared across all request handlers
 pub struct AppState {
     /// Map of volume ID to volume data
     pub volumes: HashMap<String, HDF5Volume>,
+    /// Volume and label files that failed to load, sorted by id
+    pub failed: Vec<FailedVolume>,
+    /// Decoded and resampled volume data shared between requests
+    pub cache: VolumeCache,
+    /// Concurrency and memory limits for the heavy data routes
+    pub admission: Arc<AdmissionController>,
+    /// In-flight heavy requests per client session
+    pub sessions: Sessions,
+    /// Derived volumes registered so far, the most recently used
+    pub derived: DerivedRegistry,
+    /// Label volumes by id; their voxels live in `cache`
+    pub labels: LabelRegistry,
+    /// Landmarks, measurements and notes of the file volumes
+    pub annotations: AnnotationStore,
+    /// Request and load metrics for /metrics
+    pub metrics: Metrics,
 }
 
 impl AppState {
-    /// Create new app state by scanning a directory for H5 files
-    pub async fn new(samples_dir: &str) -> Self {
+    /// Create new app state by scanning the data roots recursively for volume
+    /// files, then generating the phantoms
+    pub async fn new(roots: &[String], phantoms: &[Phantom]) -> Self {
         let mut volumes = HashMap::new();
+        let mut label_files = Vec::new();
+        let mut failed = Vec::new();
+        let metrics = Metrics::default();
+
+        for entry in catalog::discover(roots).await {
+            let start = Instant::now();
+            let opened = open_volumes(&entry.location).await;
+            metrics.record_load("open", start.elapsed().as_secs_f64());
+            let opened = match opened {
+                Ok(opened) => opened,
+                Err(e) => {
+                    tracing::warn!(location = %entry.location, error = %e, "Failed to load volume");
+                    failed.push(FailedVolume {
+                        id: entry.id,
+                        file: entry.file,
+                        error: e.to_string(),
+                    });
+                    continue;
+                }
+            };
 
-        let path = Path::new(samples_dir);
-        if path.exists() && path.is_dir() {
-            if let Ok(entries) = std::fs::read_dir(path) {
-                for entry in entries.flatten() {
-                    let file_path = entry.path();
-                    if file_path.extension().map_or(false, |e| e == "h5") {
-                        match HDF5Volume::open(&file_path).await {
-                            Ok(volume) => {
-                                log::info!("Loaded volume: {} ({:?})", volume.info.name, volume.info.dimensions);
-                                volumes.insert(volume.info.id.clone(), volume);
-                            }
-                            Err(e) => {
-                                log::warn!("Failed to load {:?}: {}", file_path, e);
-                            }
-                        }
+            let sidecar = catalog::read_sidecar(&entry.location).await;
+            for mut volume in opened {
+                // Files served as several volumes become a folder of them
+                let (id, folder) = match volume.part() {
+                    Some(part) => (format!("{}/{}", entry.id, part), entry.id.clone()),
+                    None => (entry.id.clone(), entry.folder.clone()),
+                };
+                if volumes.contains_key(&id) {
+                    tracing::warn!(location = %entry.location, volume = %id, "Duplicate volume id");
+                    failed.push(FailedVolume {
+                        error: format!("Another file already has id '{}'", id),
+                        id,
+                        file: entry.file.clone(),
+                    });
+                    continue;
+                }
+
+                volume.info.id = id;
+                volume.info.folder = folder;
+                volume.info.attributes.extend(sidecar.attributes());
+                volume.info.tags.extend(sidecar.tags.iter().cloned());
+                volume.info.tags.sort();
+                volume.info.tags.dedup();
+                if let Some(range) = sidecar.value_range {
+                    volume.info.value_range = range;
+                }
+                tracing::info!(
+                    volume = %volume.info.id,
+                    dimensions = ?volume.info.dimensions,
+                    elapsed_ms = start.elapsed().as_millis() as u64,
+                    "Loaded volume"
+                );
+                // Label files are only looked up next to local files
+                let labels = match volume.path() {
+                    Some(path) => LabelFile::find(path, &volume.info).await,
+                    None => Ok(None),
+                };
+                match labels {
+                    Ok(Some(labels)) => label_files.push(labels),
+                    Ok(None) => {}
+                    Err(e) => {
+                        tracing::warn!(location = %entry.location, error = %e, "Failed to load labels");
+                        failed.push(FailedVolume {
+                            id: LabelFile::id_for(&volume.info.id),
+                            file: entry.file.clone(),
+                            error: e.to_string(),
+                        });
                     }
                 }
+                volumes.insert(volume.info.id.clone(), volume);
+            }
+        }
+
+        for phantom in phantoms {
+            let id = phantom.id();
+            if volumes.contains_key(&id) {
+                tracing::warn!(volume = %id, "Duplicate volume id");
+                failed.push(FailedVolume {
+                    error: format!("Another volume already has id '{}'", id),
+                    file: String::new(),
+                    id,
+                });
+                continue;
+            }
+
+            let start = Instant::now();
+            let generated = HDF5Volume::open_phantom(phantom.clone()).await;
+            metrics.record_load("phantom", start.elapsed().as_secs_f64());
+            match generated {
+                Ok(mut volume) => {
+                    volume.info.id = id;
+                    volume.info.folder = PHANTOM_FOLDER.to_string();
+                    tracing::info!(
+                        volume = %volume.info.id,
+                        elapsed_ms = start.elapsed().as_millis() as u64,
+                        "Generated phantom"
+                    );
+                    volumes.insert(volume.info.id.clone(), volume);
+                }
+                Err(e) => {
+                    tracing::warn!(volume = %id, error = %e, "Failed to generate phantom");
+                    failed.push(FailedVolume {
+                        id,
+                        file: String::new(),
+                        error: e.to_string(),
+                    });
+                }
             }
-        } else {
-            log::warn!("Samples directory not found: {}", samples_dir);
         }
 
-        Self { volumes }
+        failed.sort_by(|a, b| a.id.cmp(&b.id));
+
+        Self {
+            volumes,
+            failed,
+            cache: VolumeCache::from_env(),
+            admission: Arc::new(AdmissionController::from_env()),
+            sessions: Sessions::default(),
+            derived: DerivedRegistry::new(MAX_DERIVED_VOLUMES),
+            labels: LabelRegistry::new(label_files, MAX_SEGMENTATIONS),
+            annotations: AnnotationStore::default(),
+            metrics,
+        }
     }
 
     /// Get volume info list
     pub fn list_volumes(&self) -> Vec<VolumeInfo> {
-        self.volumes.values().map(|v| v.info.clone()).collect()
+        let mut volumes: Vec<VolumeInfo> = self.volumes.values().map(|v| v.info.clone()).collect();
+        volumes.extend(self.derived.infos());
+        volumes.into_iter().map(|info| self.with_labels(info)).collect()
+    }
+
+    /// Fill in the ids of the label volumes linked to a volume
+    pub fn with_labels(&self, mut info: VolumeInfo) -> VolumeInfo {
+        info.labels = self.labels.ids(&info.id);
+        info
+    }
+
+    /// Get a specific volume. Derived ids such as `target_087~gauss(1.5)` or
+    /// `recon_087~absdiff(target_087)` are registered on first use, in a
+    /// registry bounded to the most recently used.
+    pub fn get_volume(&self, id: &str) -> Option<VolumeRef<'_>> {
+        if let Some(volume) = self.volumes.get(id) {
+            return Some(VolumeRef::File(volume));
+        }
+        if let Some(derived) = self.derived.get(id) {
+            return Some(VolumeRef::Derived(derived));
+        }
+
+        let (source, operation) = parse_derived_id(id)?;
+        self.derive(source, operation.ok()?).ok().map(VolumeRef::Derived)
     }
 
-    /// Get a specific volume
-    pub fn get_volume(&self, id: &str) -> Option<&HDF5Volume> {
-        self.volumes.get(id)
+    /// Like `get_volume`, but derived ids chaining or nesting too many
+    /// operations are a bad request rather than not found
+    pub fn find_volume(&self, id: &str) -> Result<VolumeRef<'_>, ApiError> {
+        check_depth(id).map_err(ApiError::BadRequest)?;
+        self.get_volume(id).ok_or_else(|| ApiError::NotFound(id.to_string()))
+    }
+
+    /// Register the volume produced by applying `operation` to `source`.
+    /// Nothing is computed until its data is requested. Differences need a
+    /// reference with the same dimensions.
+    pub fn derive(&self, source: &str, operation: Operation) -> Result<Arc<DerivedVolume>, ApiError> {
+        check_depth(&format!("{}{}{}", source, DERIVED_SEPARATOR, operation)).map_err(ApiError::BadRequest)?;
+        let source = self.find_volume(source)?;
+        let info = source.info();
+        let mut modified = source.modified();
+        let reference = match &operation {
+            Operation::Filter(_) | Operation::Spectrum(_) => None,
+            Operation::AbsDiff(reference) => {
+                let reference = self.find_volume(reference)?;
+                let reference_dims = reference.info().dimensions;
+                if reference_dims != info.dimensions {
+                    return Err(ApiError::BadRequest(format!(
+                        "Dimensions differ: {:?} vs {:?}",
+                        info.dimensions, reference_dims
+                    )));
+                }
+                modified = modified.max(reference.modified());
+                Some(reference.info())
+            }
+        };
+        let derived = DerivedVolume::new(&info, modified, operation, reference.as_ref());
+        Ok(self.derived.insert(derived))
+    }
+
+    /// Full-res array of any volume, computing derived volumes as needed
+    pub fn read_array<'a>(
+        &'a self,
+        id: &'a str,
+        cancel: CancellationToken,
+    ) -> Pin<Box<dyn Future<Output = Result<Array3<f32>, ApiError>> + Send + 'a>> {
+        Box::pin(async move {
+            match self.find_volume(id)? {
+                VolumeRef::File(volume) => Ok(volume.read_array(cancel).await?),
+                VolumeRef::Derived(derived) => {
+                    let cached = self.derived_data(&derived, cancel).await?;
+                    let [x, y, z] = cached.dims;
+                    let values: Vec<f32> = cached
+                        .bytes
+                        .chunks_exact(4)
+                        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                        .collect();
+                    Array3::from_shape_vec([x as usize, y as usize, z as usize], values)
+                        .map_err(|e| HDF5Error::ShapeMismatch(e.to_string()).into())
+                }
+            }
+        })
+    }
+
+    /// Full-res bytes of a derived volume, computed once and kept in the cache
+    pub async fn derived_data(
+        &self,
+        derived: &DerivedVolume,
+        cancel: CancellationToken,
+    ) -> Result<CachedVolume, ApiError> {
+        // Results the cache cannot keep would be recomputed on every request
+        if derived.info().full_res_size > self.cache.budget_bytes() {
+            return Err(ApiError::Overloaded(Rejection::TooLarge));
+        }
+
+        let key = CacheKey::new(derived.id(), CacheLevel::Full);
+        self.cache
+            .get_or_compute(key, &cancel, |cancel| async move {
+                // Read the inputs first so chained filters never hold two permits
+                let source = self.read_array(&derived.source, cancel.clone()).await?;
+                let apply: DerivedCompute = match derived.operation.clone() {
+                    Operation::AbsDiff(reference) => {
+                        let reference = self.read_array(&reference, cancel.clone()).await?;
+                        Box::new(move |source, _| Ok(((&source - &reference).mapv(f32::abs), None)))
+                    }
+                    Operation::Filter(filter) => {
+                        Box::new(move |source, cancel| Ok((filter.apply(&source, cancel)?, None)))
+                    }
+                    Operation::Spectrum(spectrum) => Box::new(move |source, cancel| {
+                        let (part, sibling) = spectrum.apply(source, cancel)?;
+                        Ok((part, Some(sibling)))
+                    }),
+                };
+                let sibling = match &derived.operation {
+                    Operation::Spectrum(spectrum) => Some(Operation::Spectrum(spectrum.sibling())),
+                    Operation::Filter(_) | Operation::AbsDiff(_) => None,
+                };
+
+                let footprint = derived.operation.footprint(&derived.info());
+                let _permit = self.admission.admit(HeavyRoute::Filter, footprint).await?;
+                let span = tracing::info_span!("derive", volume = derived.id());
+                let compute = tokio::task::spawn_blocking(move || {
+                    let _span = span.enter();
+                    apply(source, &cancel)
+                });
+                let (filtered, sibling_data) = self
+                    .metrics
+                    .time_load("derived", async { compute.await.map_err(join_error)? })
+                    .await?;
+
+                let value_range = |data: &Array3<f32>| {
+                    let min = data.iter().cloned().fold(f32::INFINITY, f32::min);
+                    let max = data.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+                    [min, max]
+                };
+                derived.set_value_range(value_range(&filtered));
+
+                // Keep the sibling too, which would otherwise repeat the whole transform
+                if let Some(sibling) = sibling.and_then(|operation| self.derive(&derived.source, operation).ok()) {
+                    if let Some(data) = sibling_data {
+                        sibling.set_value_range(data.value_range);
+                        self.cache.put(
+                            CacheKey::new(sibling.id(), CacheLevel::Full),
+                            CachedVolume {
+                                bytes: data.bytes.into(),
+                                dims: sibling.info().dimensions,
+                            },
+                        );
+                    }
+                }
+
+                Ok(CachedVolume {
+                    bytes: HDF5Volume::to_bytes(&filtered).into(),
+                    dims: derived.info().dimensions,
+                })
+            })
+            .await
+    }
+
+    /// Label volumes belonging to an intensity volume, reading any not read yet
+    pub async fn list_labels(&self, volume_id: &str) -> Result<Vec<LabelVolumeInfo>, ApiError> {
+        let mut labels = Vec::new();
+        for id in self.labels.ids(volume_id) {
+            match self.get_labels(&id).await {
+                Ok(l) => labels.push(l.info.clone()),
+                // A segmentation forgotten since listing the ids
+                Err(ApiError::NotFound(_)) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(labels)
+    }
+
+    /// A label volume, reading shipped labels on first use
+    pub async fn get_labels(&self, id: &str) -> Result<Arc<LabelVolume>, ApiError> {
+        if let Some(labels) = self.labels.segmentation(id) {
+            return Ok(labels);
+        }
+        let file = self.labels.file(id).ok_or_else(|| ApiError::NotFound(id.to_string()))?;
+        let labels = file
+            .loaded
+            .get_or_try_init(|| async {
+                let _permit = self.admission.admit(HeavyRoute::Full, file.load_memory_estimate()).await?;
+                let (labels, bytes) = file.load().await?;
+                let value = CachedVolume {
+                    bytes: bytes.into(),
+                    dims: labels.info.dimensions,
+                };
+                self.cache.put(CacheKey::labels(id), value);
+                Ok::<_, ApiError>(Arc::new(labels))
+            })
+            .await?;
+        Ok(labels.clone())
+    }
+
+    /// Voxels of a label volume, read or segmented again if the cache evicted them
+    pub async fn label_data(&self, labels: &LabelVolume, cancel: CancellationToken) -> Result<CachedVolume, ApiError> {
+        let info = &labels.info;
+        self.cache
+            .get_or_compute(CacheKey::labels(&info.id), &cancel, |cancel| async move {
+                let bytes = match &labels.source {
+                    LabelSource::File { path, dataset } => labels.read_file(path, dataset).await?,
+                    LabelSource::Segmentation(request) => {
+                        self.run_segmentation(&info.volume_id, &info.id, request, cancel).await?.1
+                    }
+                };
+                Ok::<_, ApiError>(CachedVolume {
+                    bytes: bytes.into(),
+                    dims: info.dimensions,
+                })
+            })
+            .await
+    }
+
+    /// Segment a volume into connected components. The label volume's id is
+    /// derived from the volume version and the request, so repeating a
+    /// request returns the stored result while it is still registered.
+    pub async fn segment(
+        &self,
+        id: &str,
+        request: &SegmentRequest,
+        cancel: CancellationToken,
+    ) -> Result<Arc<LabelVolume>, ApiError> {
+        let volume = self.find_volume(id)?;
+        let info = volume.info();
+        let parameters = serde_json::to_string(request).unwrap();
+        let label_id = format!("{}~seg-{}", id, version_hash(&format!("{}{}", info.version, parameters)));
+        if let Some(labels) = self.labels.segmentation(&label_id) {
+            return Ok(labels);
+        }
+
+        let (labels, bytes) = self.run_segmentation(id, &label_id, request, cancel).await?;
+        let value = CachedVolume {
+            bytes: bytes.into(),
+            dims: labels.info.dimensions,
+        };
+        self.cache.put(CacheKey::labels(&label_id), value);
+        Ok(self.labels.insert(labels))
+    }
+
+    /// Segment volume `id` into the label volume `label_id` and its voxels
+    async fn run_segmentation(
+        &self,
+        id: &str,
+        label_id: &str,
+        request: &SegmentRequest,
+        cancel: CancellationToken,
+    ) -> Result<(LabelVolume, Vec<u8>), ApiError> {
+        let info = self.find_volume(id)?.info();
+        let data = self.read_array(id, cancel.clone()).await?;
+
+        // Mask and label arrays on top of the intensity data
+        let voxels = info.dimensions.iter().map(|&d| d as u64).product::<u64>();
+        let _permit = self.admission.admit(HeavyRoute::Filter, 6 * voxels).await?;
+        let task_request = request.clone();
+        let result = tokio::task::spawn_blocking(move || {
+            segment::segment(&data, &task_request, info.spacing, &cancel)
+        })
+        .await
+        .map_err(join_error)??;
+
+        tracing::info!(
+            volume = id,
+            components = result.components.len(),
+            threshold = result.threshold,
+            "Segmented volume"
+        );
+        let labels = LabelVolume {
+            info: LabelVolumeInfo {
+                id: label_id.to_string(),
+                volume_id: id.to_string(),
+                dimensions: info.dimensions,
+                dtype: result.dtype,
+                components: result.components,
+                table: Vec::new(),
+            },
+            source: LabelSource::Segmentation(request.clone()),
+            threshold: Some(result.threshold),
+        };
+        Ok((labels, result.bytes))
+    }
+
+    /// Register the log-magnitude and phase of a volume's centred FFT as
+    /// derived volumes. Nothing is computed until their data is requested.
+    pub fn spectrum(&self, id: &str, request: &SpectrumRequest) -> Result<SpectrumResponse, ApiError> {
+        if request.slice_axis.is_some_and(|axis| axis > 2) {
+            return Err(ApiError::BadRequest("Slice axis must be 0, 1 or 2".to_string()));
+        }
+        let derive = |part| {
+            let spectrum = Spectrum {
+                part,
+                slice_axis: request.slice_axis,
+            };
+            self.derive(id, Operation::Spectrum(spectrum))
+        };
+        Ok(SpectrumResponse {
+            volume_id: id.to_string(),
+            slice_axis: request.slice_axis,
+            log_magnitude: derive(SpectrumPart::LogMagnitude)?.info(),
+            phase: derive(SpectrumPart::Phase)?.info(),
+        })
+    }
+
+    /// Compare a volume against a reference of the same dimensions and
+    /// register their absolute difference as a derived volume
+    pub async fn compare(
+        &self,
+        id: &str,
+        request: &CompareRequest,
+        cancel: CancellationToken,
+    ) -> Result<CompareResponse, ApiError> {
+        let volume = self.find_volume(id)?;
+        let reference = self.find_volume(&request.reference)?;
+        let (dims, reference_dims) = (volume.info().dimensions, reference.info().dimensions);
+        if dims != reference_dims {
+            return Err(ApiError::BadRequest(format!(
+                "Dimensions differ: {:?} vs {:?}",
+                dims, reference_dims
+            )));
+        }
+        if request.axis > 2 {
+            return Err(ApiError::BadRequest("Axis must be 0, 1 or 2".to_string()));
+        }
+
+        let difference = self.derive(id, Operation::AbsDiff(request.reference.clone()))?;
+
+        let data = self.read_array(id, cancel.clone()).await?;
+        let reference_data = self.read_array(&request.reference, cancel.clone()).await?;
+
+        // Summed-area tables for one slice, on top of both volumes
+        let slice_voxels = dims.iter().map(|&d| d as u64).product::<u64>() / dims[request.axis].max(1) as u64;
+        let _permit = self.admission.admit(HeavyRoute::Filter, 40 * slice_voxels).await?;
+        let axis = request.axis;
+        let result = tokio::task::spawn_blocking(move || {
+            compare::compare(&data, &reference_data, axis, &cancel)
+        })
+        .await
+        .map_err(join_error)??;
+
+        // The difference data itself is only computed if it is requested
+        difference.set_value_range(result.difference_range);
+
+        Ok(CompareResponse {
+            volume_id: id.to_string(),
+            reference_id: request.reference.clone(),
+            axis,
+            data_range: result.data_range,
+            global: result.global,
+            slices: result.slices,
+            difference: difference.info(),
+        })
+    }
+}
+
+/// Open the volumes of a file: HDF5 on local disk, `.npy` in a bucket. A
+/// fastMRI file holds two, its stored and its zero-filled reconstruction.
+async fn open_volumes(location: &Location) -> Result<Vec<HDF5Volume>, HDF5Error> {
+    match location {
+        Location::File(path) if fastmri::is_fastmri(path) => HDF5Volume::open_fastmri(path).await,
+        Location::File(path) => Ok(vec![HDF5Volume::open(path).await?]),
+        Location::Object { bucket, key } if location.extension() == "npy" => {
+            Ok(vec![HDF5Volume::open_object(NpyObject::open(bucket.clone(), key.clone()).await?).await?])
+        }
+        Location::Object { .. } => Err(HDF5Error::Format(
+            "HDF5 files cannot be read from object storage in pieces; convert to .npy with `3dlab convert`"
+                .to_string(),
+        )),
     }
 }
@@ -0,0 +1,439 @@
+//! Data roots in S3-compatible object storage. Objects are listed once, their
+//! metadata is cached, and data is read piecemeal with ranged GETs; nothing is
+//! copied to local disk.
+
+use axum::body::Bytes;
+use ndarray::Array3;
+use object_store::aws::AmazonS3Builder;
+use object_store::path::Path as ObjectPath;
+use object_store::{GetOptions, GetRange, ObjectMeta, ObjectStore};
+use std::collections::HashMap;
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+use tokio_stream::StreamExt;
+
+use crate::hdf5_reader::HDF5Error;
+
+/// Scheme of data roots in object storage, e.g. `s3://scans/knee`
+pub const S3_SCHEME: &str = "s3://";
+
+/// Bytes read for the `.npy` header; longer headers are read again in full
+const HEADER_PROBE_BYTES: u64 = 4096;
+
+/// A bucket, or a prefix of one, used as a data root
+pub struct Bucket {
+    store: Box<dyn ObjectStore>,
+    /// `s3://bucket/prefix` as configured
+    url: String,
+    prefix: ObjectPath,
+    /// Metadata from the listing or a HEAD, by key
+    meta: Mutex<HashMap<ObjectPath, ObjectMeta>>,
+}
+
+impl Bucket {
+    /// Connect to `s3://bucket[/prefix]`. Credentials, region and endpoint come
+    /// from the usual `AWS_*` variables; set `AWS_ENDPOINT` and `AWS_ALLOW_HTTP=true`
+    /// for MinIO or another S3-compatible server.
+    pub fn from_url(url: &str) -> Result<Self, object_store::Error> {
+        let path = url.strip_prefix(S3_SCHEME).unwrap_or(url).trim_matches('/');
+        let (bucket, prefix) = path.split_once('/').unwrap_or((path, ""));
+        let store = AmazonS3Builder::from_env().with_bucket_name(bucket).build()?;
+        Ok(Self {
+            store: Box::new(store),
+            url: url.trim_end_matches('/').to_string(),
+            prefix: ObjectPath::from(prefix),
+            meta: Mutex::new(HashMap::new()),
+        })
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Last segment of the prefix, or the bucket name
+    pub fn name(&self) -> String {
+        match self.prefix.parts().last() {
+            Some(part) => part.as_ref().to_string(),
+            None => self.url.trim_start_matches(S3_SCHEME).to_string(),
+        }
+    }
+
+    /// `s3://` URL of an object
+    pub fn object_url(&self, key: &ObjectPath) -> String {
+        let bucket = self.url.trim_start_matches(S3_SCHEME).split('/').next().unwrap_or_default();
+        format!("{}{}/{}", S3_SCHEME, bucket, key)
+    }
+
+    /// Every object below the prefix with its key relative to the prefix,
+    /// sorted by key. Fills the metadata cache.
+    pub async fn list(&self) -> Result<Vec<(String, ObjectPath)>, object_store::Error> {
+        let prefix = (!self.prefix.as_ref().is_empty()).then_some(&self.prefix);
+        let listed: Vec<ObjectMeta> = self
+            .store
+            .list(prefix)
+            .collect::<Vec<_>>()
+            .await
+            .into_iter()
+            .collect::<Result<_, _>>()?;
+
+        let mut objects = Vec::with_capacity(listed.len());
+        let mut cache = self.meta.lock().unwrap();
+        for meta in listed {
+            let relative = match meta.location.prefix_match(&self.prefix) {
+                Some(parts) => parts.map(|p| p.as_ref().to_string()).collect::<Vec<_>>().join("/"),
+                None => continue,
+            };
+            objects.push((relative, meta.location.clone()));
+            cache.insert(meta.location.clone(), meta);
+        }
+        objects.sort();
+        Ok(objects)
+    }
+
+    /// Metadata of an object, from the cache when it has been listed before
+    pub async fn head(&self, key: &ObjectPath) -> Result<ObjectMeta, object_store::Error> {
+        if let Some(meta) = self.meta.lock().unwrap().get(key) {
+            return Ok(meta.clone());
+        }
+        let meta = self.store.head(key).await?;
+        self.meta.lock().unwrap().insert(key.clone(), meta.clone());
+        Ok(meta)
+    }
+
+    /// Whether the listing included `key`
+    pub fn is_listed(&self, key: &ObjectPath) -> bool {
+        self.meta.lock().unwrap().contains_key(key)
+    }
+
+    /// Bytes `range` of an object. If the object changed since its metadata
+    /// was cached, it is stat'ed again and the read retried when its size is
+    /// unchanged; otherwise this fails with a precondition error.
+    pub async fn get_range(&self, key: &ObjectPath, range: Range<u64>) -> Result<Bytes, object_store::Error> {
+        let meta = self.head(key).await?;
+        match self.get_matching(key, range.clone(), meta.e_tag).await {
+            Err(e @ object_store::Error::Precondition { .. }) => {
+                // Refresh the metadata so later reads do not keep failing on the old ETag
+                let fresh = self.store.head(key).await?;
+                self.meta.lock().unwrap().insert(key.clone(), fresh.clone());
+                // A different size means a different header, which was only read at startup
+                if fresh.size != meta.size {
+                    return Err(e);
+                }
+                self.get_matching(key, range, fresh.e_tag).await
+            }
+            result => result,
+        }
+    }
+
+    async fn get_matching(
+        &self,
+        key: &ObjectPath,
+        range: Range<u64>,
+        e_tag: Option<String>,
+    ) -> Result<Bytes, object_store::Error> {
+        let options = GetOptions {
+            range: Some(GetRange::Bounded(range)),
+            if_match: e_tag,
+            ..Default::default()
+        };
+        self.store.get_opts(key, options).await?.bytes().await
+    }
+
+    /// A whole object, for small ones such as sidecars
+    pub async fn get(&self, key: &ObjectPath) -> Result<Bytes, object_store::Error> {
+        self.store.get(key).await?.bytes().await
+    }
+}
+
+/// Element type of a `.npy` array
+#[derive(Debug, Clone, Copy)]
+enum NpyDtype {
+    F32,
+    F64,
+    U8,
+    I8,
+    U16,
+    I16,
+    U32,
+    I32,
+}
+
+impl NpyDtype {
+    /// From a little-endian `descr` such as `<f4`
+    fn parse(descr: &str) -> Option<Self> {
+        match descr.trim_start_matches(['<', '|', '=']) {
+            "f4" => Some(NpyDtype::F32),
+            "f8" => Some(NpyDtype::F64),
+            "u1" => Some(NpyDtype::U8),
+            "i1" => Some(NpyDtype::I8),
+            "u2" => Some(NpyDtype::U16),
+            "i2" => Some(NpyDtype::I16),
+            "u4" => Some(NpyDtype::U32),
+            "i4" => Some(NpyDtype::I32),
+            _ => None,
+        }
+    }
+
+    fn size(self) -> usize {
+        match self {
+            NpyDtype::U8 | NpyDtype::I8 => 1,
+            NpyDtype::U16 | NpyDtype::I16 => 2,
+            NpyDtype::F32 | NpyDtype::U32 | NpyDtype::I32 => 4,
+            NpyDtype::F64 => 8,
+        }
+    }
+
+    /// Name as in `VolumeInfo::dtype`
+    fn name(self) -> &'static str {
+        match self {
+            NpyDtype::F32 => "f32",
+            NpyDtype::F64 => "f64",
+            NpyDtype::U8 => "u8",
+            NpyDtype::I8 => "i8",
+            NpyDtype::U16 => "u16",
+            NpyDtype::I16 => "i16",
+            NpyDtype::U32 => "u32",
+            NpyDtype::I32 => "i32",
+        }
+    }
+
+    fn decode(self, bytes: &[u8]) -> Vec<f32> {
+        let chunks = bytes.chunks_exact(self.size());
+        match self {
+            NpyDtype::F32 => chunks.map(|b| f32::from_le_bytes(b.try_into().unwrap())).collect(),
+            NpyDtype::F64 => chunks.map(|b| f64::from_le_bytes(b.try_into().unwrap()) as f32).collect(),
+            NpyDtype::U8 => chunks.map(|b| b[0] as f32).collect(),
+            NpyDtype::I8 => chunks.map(|b| b[0] as i8 as f32).collect(),
+            NpyDtype::U16 => chunks.map(|b| u16::from_le_bytes(b.try_into().unwrap()) as f32).collect(),
+            NpyDtype::I16 => chunks.map(|b| i16::from_le_bytes(b.try_into().unwrap()) as f32).collect(),
+            NpyDtype::U32 => chunks.map(|b| u32::from_le_bytes(b.try_into().unwrap()) as f32).collect(),
+            NpyDtype::I32 => chunks.map(|b| i32::from_le_bytes(b.try_into().unwrap()) as f32).collect(),
+        }
+    }
+}
+
+/// A C-order NumPy array in a bucket, shaped `(x, y, z)` or `(t, x, y, z)`.
+/// The header is read once; X slabs are fetched with ranged GETs when needed.
+pub struct NpyObject {
+    bucket: Arc<Bucket>,
+    key: ObjectPath,
+    meta: ObjectMeta,
+    dtype: NpyDtype,
+    /// Byte offset of the data in the object
+    offset: u64,
+    frames: usize,
+    dims: [u32; 3],
+}
+
+impl NpyObject {
+    /// Read and check the header of `key`
+    pub async fn open(bucket: Arc<Bucket>, key: ObjectPath) -> Result<Self, HDF5Error> {
+        let meta = bucket.head(&key).await?;
+        let probe = bucket.get_range(&key, 0..HEADER_PROBE_BYTES.min(meta.size)).await?;
+        if probe.len() < 8 || &probe[..6] != b"\x93NUMPY" {
+            return Err(HDF5Error::Format("not a .npy array".to_string()));
+        }
+        // Version 1 stores the header length in 2 bytes, versions 2 and 3 in 4
+        let (header_len, header_start) = match (probe[6], probe.len()) {
+            (1, len) if len >= 10 => (u16::from_le_bytes([probe[8], probe[9]]) as u64, 10),
+            (2 | 3, len) if len >= 12 => (u32::from_le_bytes([probe[8], probe[9], probe[10], probe[11]]) as u64, 12),
+            (1..=3, _) => return Err(HDF5Error::Format("truncated .npy header".to_string())),
+            (version, _) => return Err(HDF5Error::Format(format!("unsupported .npy version {}", version))),
+        };
+        let offset = header_start + header_len;
+        let header = if offset as usize <= probe.len() {
+            probe.slice(header_start as usize..offset as usize)
+        } else {
+            bucket.get_range(&key, header_start..offset).await?
+        };
+        let header = String::from_utf8_lossy(&header);
+
+        let descr = header_value(&header, "descr")
+            .and_then(|v| v.split(['\'', '"']).nth(1))
+            .map(str::to_string)
+            .ok_or_else(|| HDF5Error::Format("missing descr".to_string()))?;
+        let dtype = NpyDtype::parse(&descr)
+            .ok_or_else(|| HDF5Error::Format(format!("unsupported dtype {} (need little-endian numbers)", descr)))?;
+        if header_value(&header, "fortran_order").is_some_and(|v| v.starts_with("True")) {
+            return Err(HDF5Error::Format(
+                "Fortran-order arrays cannot be read in slabs; save in C order".to_string(),
+            ));
+        }
+        let shape: Vec<usize> = header_value(&header, "shape")
+            .and_then(|v| v.split(')').next())
+            .map(|v| v.trim_start_matches('(').split(',').filter_map(|d| d.trim().parse().ok()).collect())
+            .unwrap_or_default();
+        let (frames, spatial) = match shape.len() {
+            3 => (1, &shape[..]),
+            4 => (shape[0], &shape[1..]),
+            _ => return Err(HDF5Error::Format(format!("need a 3D or 4D array, got shape {:?}", shape))),
+        };
+
+        let dims = [spatial[0] as u32, spatial[1] as u32, spatial[2] as u32];
+        let expected = [frames, dtype.size()]
+            .iter()
+            .chain(spatial)
+            .try_fold(1u64, |bytes, &n| bytes.checked_mul(n as u64))
+            .and_then(|bytes| bytes.checked_add(offset))
+            .ok_or_else(|| HDF5Error::Format(format!("shape {:?} is too large", shape)))?;
+        if meta.size < expected {
+            return Err(HDF5Error::Format(format!("object has {} bytes, shape needs {}", meta.size, expected)));
+        }
+
+        Ok(Self { bucket, key, meta, dtype, offset, frames, dims })
+    }
+
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+
+    pub fn dims(&self) -> [u32; 3] {
+        self.dims
+    }
+
+    pub fn dtype(&self) -> &'static str {
+        self.dtype.name()
+    }
+
+    pub fn meta(&self) -> &ObjectMeta {
+        &self.meta
+    }
+
+    pub fn url(&self) -> String {
+        self.bucket.object_url(&self.key)
+    }
+
+    /// File name of the object without `.npy`
+    pub fn name(&self) -> String {
+        let file = self.key.filename().unwrap_or_default();
+        file.strip_suffix(".npy").unwrap_or(file).to_string()
+    }
+
+    /// Read X slabs `x` of one frame with a single ranged GET. Blocks, so
+    /// call it from a blocking task.
+    pub fn read_slab(&self, frame: usize, x: Range<usize>) -> Result<Array3<f32>, HDF5Error> {
+        let [_, ny, nz] = self.dims.map(|d| d as usize);
+        let slab_bytes = (ny * nz * self.dtype.size()) as u64;
+        let frame_bytes = slab_bytes * self.dims[0] as u64;
+        let start = self.offset + frame as u64 * frame_bytes + x.start as u64 * slab_bytes;
+        let end = start + (x.end - x.start) as u64 * slab_bytes;
+
+        let bytes = tokio::runtime::Handle::current().block_on(self.bucket.get_range(&self.key, start..end))?;
+        let values = self.dtype.decode(&bytes);
+        Array3::from_shape_vec((x.end - x.start, ny, nz), values)
+            .map_err(|e| HDF5Error::ShapeMismatch(e.to_string()))
+    }
+}
+
+/// Raw value of `'key': value` in a `.npy` header dict
+fn header_value<'a>(header: &'a str, key: &str) -> Option<&'a str> {
+    let start = header.find(&format!("'{}'", key))? + key.len() + 2;
+    Some(header[start..].trim_start().strip_prefix(':')?.trim_start())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use object_store::memory::InMemory;
+
+    fn bucket() -> Arc<Bucket> {
+        Arc::new(Bucket {
+            store: Box::new(InMemory::new()),
+            url: "s3://scans".to_string(),
+            prefix: ObjectPath::from(""),
+            meta: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// A `.npy` file of the given version whose header dict is padded to 64 bytes
+    fn npy(version: u8, dict: &str, data: &[u8]) -> Vec<u8> {
+        let header_start = if version == 1 { 10 } else { 12 };
+        let mut dict = dict.to_string();
+        while !(header_start + dict.len() + 1).is_multiple_of(64) {
+            dict.push(' ');
+        }
+        dict.push('\n');
+
+        let mut bytes = b"\x93NUMPY".to_vec();
+        bytes.extend([version, 0]);
+        match version {
+            1 => bytes.extend((dict.len() as u16).to_le_bytes()),
+            _ => bytes.extend((dict.len() as u32).to_le_bytes()),
+        }
+        bytes.extend(dict.as_bytes());
+        bytes.extend(data);
+        bytes
+    }
+
+    async fn put(bucket: &Bucket, key: &ObjectPath, bytes: Vec<u8>) {
+        bucket.store.put(key, bytes.into()).await.unwrap();
+    }
+
+    async fn open(bytes: Vec<u8>) -> Result<NpyObject, HDF5Error> {
+        let bucket = bucket();
+        let key = ObjectPath::from("knee/case.npy");
+        put(&bucket, &key, bytes).await;
+        NpyObject::open(bucket, key).await
+    }
+
+    #[tokio::test]
+    async fn reads_headers() {
+        let dict = "{'descr': '<f4', 'fortran_order': False, 'shape': (2, 3, 4), }";
+        let object = open(npy(1, dict, &[0; 2 * 3 * 4 * 4])).await.unwrap();
+        assert_eq!(object.dims(), [2, 3, 4]);
+        assert_eq!(object.frames(), 1);
+        assert_eq!(object.dtype(), "f32");
+        assert_eq!(object.offset, 128);
+        assert_eq!(object.name(), "case");
+
+        // Version 2 has a 4-byte header length; 4D arrays are frames
+        let dict = "{'descr': '<u2', 'fortran_order': False, 'shape': (5, 2, 3, 4), }";
+        let object = open(npy(2, dict, &[0; 5 * 2 * 3 * 4 * 2])).await.unwrap();
+        assert_eq!((object.frames(), object.dims()), (5, [2, 3, 4]));
+        assert_eq!(object.dtype(), "u16");
+    }
+
+    #[tokio::test]
+    async fn rejects_bad_headers() {
+        let format_error = |result: Result<NpyObject, HDF5Error>| matches!(result, Err(HDF5Error::Format(_)));
+        let dict = |descr: &str, fortran: &str, shape: &str| {
+            format!("{{'descr': '{}', 'fortran_order': {}, 'shape': {}, }}", descr, fortran, shape)
+        };
+
+        // Too short for the header length of its version
+        let v2 = npy(2, &dict("<f4", "False", "(1, 1, 1)"), &[0; 4]);
+        assert!(format_error(open(v2[..11].to_vec()).await));
+        assert!(format_error(open(v2[..9].to_vec()).await));
+
+        let mut bad_magic = npy(1, &dict("<f4", "False", "(1, 1, 1)"), &[0; 4]);
+        bad_magic[1] = b'X';
+        assert!(format_error(open(bad_magic).await));
+        assert!(format_error(open(npy(4, &dict("<f4", "False", "(1, 1, 1)"), &[0; 4])).await));
+
+        assert!(format_error(open(npy(1, &dict(">f4", "False", "(1, 1, 1)"), &[0; 4])).await));
+        assert!(format_error(open(npy(1, &dict("<f4", "True", "(1, 1, 1)"), &[0; 4])).await));
+        assert!(format_error(open(npy(1, &dict("<f4", "False", "(4, 4)"), &[0; 64])).await));
+        // Data shorter than the shape, and shapes whose size overflows
+        assert!(format_error(open(npy(1, &dict("<f4", "False", "(2, 2, 2)"), &[0; 16])).await));
+        let huge = "(4294967296, 4294967296, 4294967296)";
+        assert!(format_error(open(npy(1, &dict("<f4", "False", huge), &[0; 4])).await));
+    }
+
+    #[tokio::test]
+    async fn restats_overwritten_objects() {
+        let bucket = bucket();
+        let key = ObjectPath::from("case.npy");
+        put(&bucket, &key, vec![1; 8]).await;
+        assert_eq!(bucket.get_range(&key, 0..4).await.unwrap().as_ref(), [1; 4]);
+
+        // Same size: the read is retried against the new version
+        put(&bucket, &key, vec![2; 8]).await;
+        assert_eq!(bucket.get_range(&key, 0..4).await.unwrap().as_ref(), [2; 4]);
+
+        // A new size fails once, but the refreshed metadata lets later reads through
+        put(&bucket, &key, vec![3; 16]).await;
+        let changed = bucket.get_range(&key, 0..4).await;
+        assert!(matches!(changed, Err(object_store::Error::Precondition { .. })));
+        assert_eq!(bucket.get_range(&key, 0..4).await.unwrap().as_ref(), [3; 4]);
+    }
+}
@@ -0,0 +1,332 @@
+//! Binary container of the volume data routes: a fixed-size header describing
+//! the voxels, followed by the voxels themselves
+
+use std::fmt;
+
+/// Little-endian voxel element type of a payload
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VoxelDtype {
+    U8 = 1,
+    U16 = 2,
+    F32 = 3,
+}
+
+impl VoxelDtype {
+    /// Bytes per voxel
+    pub fn size(self) -> usize {
+        match self {
+            VoxelDtype::U8 => 1,
+            VoxelDtype::U16 => 2,
+            VoxelDtype::F32 => 4,
+        }
+    }
+
+    fn from_code(code: u8) -> Option<Self> {
+        match code {
+            1 => Some(VoxelDtype::U8),
+            2 => Some(VoxelDtype::U16),
+            3 => Some(VoxelDtype::F32),
+            _ => None,
+        }
+    }
+}
+
+impl From<crate::LabelDtype> for VoxelDtype {
+    fn from(dtype: crate::LabelDtype) -> Self {
+        match dtype {
+            crate::LabelDtype::U8 => VoxelDtype::U8,
+            crate::LabelDtype::U16 => VoxelDtype::U16,
+        }
+    }
+}
+
+/// How the voxels after the header are stored
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VoxelEncoding {
+    /// Uncompressed, C order ([x, y, z], z fastest), frames one after another
+    Raw = 0,
+}
+
+impl VoxelEncoding {
+    fn from_code(code: u8) -> Option<Self> {
+        match code {
+            0 => Some(VoxelEncoding::Raw),
+            _ => None,
+        }
+    }
+}
+
+/// Why a payload could not be decoded
+#[derive(Debug, Clone, PartialEq)]
+pub enum ContainerError {
+    /// Fewer bytes than the header or the voxels it describes
+    Truncated { expected: u64, actual: u64 },
+    /// Does not start with `VolumeHeader::MAGIC`
+    BadMagic,
+    /// Written by a newer server, or version 0
+    UnsupportedVersion(u16),
+    UnknownDtype(u8),
+    UnknownEncoding(u8),
+    /// Describes more voxel bytes than a u64 can count
+    TooLarge,
+}
+
+impl fmt::Display for ContainerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ContainerError::Truncated { expected, actual } => {
+                write!(f, "Volume payload truncated: expected {} bytes, got {}", expected, actual)
+            }
+            ContainerError::BadMagic => write!(f, "Not a volume payload (bad magic)"),
+            ContainerError::UnsupportedVersion(version) => {
+                write!(f, "Unsupported volume payload version {}", version)
+            }
+            ContainerError::UnknownDtype(code) => write!(f, "Unknown voxel dtype {}", code),
+            ContainerError::UnknownEncoding(code) => write!(f, "Unknown voxel encoding {}", code),
+            ContainerError::TooLarge => write!(f, "Volume payload size overflows"),
+        }
+    }
+}
+
+impl std::error::Error for ContainerError {}
+
+/// Header prefixing every volume data payload (`/low`, `/full`, `/frames`,
+/// `/at/{resolution}` and label data), so the bytes describe themselves.
+///
+/// Layout, little-endian, `VolumeHeader::SIZE` bytes:
+///
+/// | Offset | Field |
+/// |--------|-------|
+/// | 0 | magic `3DLV` |
+/// | 4 | version (u16) |
+/// | 6 | header size (u16); decoders skip to it, so later versions can append fields |
+/// | 8 | dims (3 × u32) |
+/// | 20 | dtype (u8), encoding (u8), 2 reserved bytes |
+/// | 24 | value range (2 × f32) |
+/// | 32 | spacing in mm (3 × f32) |
+/// | 44 | brick origin in voxels (3 × u32) |
+/// | 56 | first frame (u32), frame count (u32) |
+#[derive(Debug, Clone, PartialEq)]
+pub struct VolumeHeader {
+    /// Dimensions [x, y, z] of the voxels in the payload
+    pub dims: [u32; 3],
+    pub dtype: VoxelDtype,
+    pub encoding: VoxelEncoding,
+    /// Value range [min, max] of the whole volume, for windowing
+    pub value_range: [f32; 2],
+    /// Voxel spacing in millimetres, scaled for resampled payloads
+    pub spacing: [f32; 3],
+    /// Voxel offset of the payload in the full-resolution volume; zero unless it is a brick
+    pub origin: [u32; 3],
+    /// Index of the first frame in the payload
+    pub frame: u32,
+    /// Number of frames in the payload
+    pub frames: u32,
+}
+
+impl VolumeHeader {
+    pub const MAGIC: [u8; 4] = *b"3DLV";
+    pub const VERSION: u16 = 1;
+    /// Size of the header this version writes
+    pub const SIZE: usize = 64;
+
+    /// Header of one frame of raw voxels at the volume's origin
+    pub fn new(dims: [u32; 3], dtype: VoxelDtype, value_range: [f32; 2], spacing: [f32; 3]) -> Self {
+        Self {
+            dims,
+            dtype,
+            encoding: VoxelEncoding::Raw,
+            value_range,
+            spacing,
+            origin: [0; 3],
+            frame: 0,
+            frames: 1,
+        }
+    }
+
+    /// The same header for `frames` frames starting at `frame`
+    pub fn with_frames(mut self, frame: u32, frames: u32) -> Self {
+        self.frame = frame;
+        self.frames = frames;
+        self
+    }
+
+    /// Bytes of the voxels after the header
+    pub fn payload_size(&self) -> Result<u64, ContainerError> {
+        self.dims
+            .iter()
+            .chain([&self.frames])
+            .try_fold(self.dtype.size() as u64, |bytes, &n| bytes.checked_mul(n as u64))
+            .ok_or(ContainerError::TooLarge)
+    }
+
+    pub fn encode(&self) -> [u8; Self::SIZE] {
+        let mut bytes = [0u8; Self::SIZE];
+        bytes[0..4].copy_from_slice(&Self::MAGIC);
+        bytes[4..6].copy_from_slice(&Self::VERSION.to_le_bytes());
+        bytes[6..8].copy_from_slice(&(Self::SIZE as u16).to_le_bytes());
+        put_u32s(&mut bytes[8..20], &self.dims);
+        bytes[20] = self.dtype as u8;
+        bytes[21] = self.encoding as u8;
+        put_f32s(&mut bytes[24..32], &self.value_range);
+        put_f32s(&mut bytes[32..44], &self.spacing);
+        put_u32s(&mut bytes[44..56], &self.origin);
+        put_u32s(&mut bytes[56..64], &[self.frame, self.frames]);
+        bytes
+    }
+
+    /// Split a payload into its header and voxel bytes, checking that all voxels are there
+    pub fn decode(bytes: &[u8]) -> Result<(Self, &[u8]), ContainerError> {
+        let truncated = |expected: u64| ContainerError::Truncated {
+            expected,
+            actual: bytes.len() as u64,
+        };
+        if bytes.len() < Self::SIZE {
+            return Err(truncated(Self::SIZE as u64));
+        }
+        if bytes[0..4] != Self::MAGIC {
+            return Err(ContainerError::BadMagic);
+        }
+        let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+        if version == 0 || version > Self::VERSION {
+            return Err(ContainerError::UnsupportedVersion(version));
+        }
+        let header_size = u16::from_le_bytes([bytes[6], bytes[7]]) as usize;
+        if bytes.len() < header_size {
+            return Err(truncated(header_size as u64));
+        }
+
+        let [frame, frames] = get_u32s(&bytes[56..64]);
+        let header = Self {
+            dims: get_u32s(&bytes[8..20]),
+            dtype: VoxelDtype::from_code(bytes[20]).ok_or(ContainerError::UnknownDtype(bytes[20]))?,
+            encoding: VoxelEncoding::from_code(bytes[21]).ok_or(ContainerError::UnknownEncoding(bytes[21]))?,
+            value_range: get_f32s(&bytes[24..32]),
+            spacing: get_f32s(&bytes[32..44]),
+            origin: get_u32s(&bytes[44..56]),
+            frame,
+            frames,
+        };
+
+        let payload = &bytes[header_size.max(Self::SIZE)..];
+        let payload_size = header.payload_size()?;
+        if (payload.len() as u64) < payload_size {
+            return Err(truncated(header_size as u64 + payload_size));
+        }
+        Ok((header, &payload[..payload_size as usize]))
+    }
+}
+
+/// Voxels of an `F32` payload
+pub fn decode_f32(payload: &[u8]) -> Vec<f32> {
+    payload
+        .chunks_exact(4)
+        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect()
+}
+
+/// Voxels of a `U8` or `U16` payload, widened to u16
+pub fn decode_u16(payload: &[u8], dtype: VoxelDtype) -> Vec<u16> {
+    match dtype {
+        VoxelDtype::U8 => payload.iter().map(|&b| b as u16).collect(),
+        _ => payload.chunks_exact(2).map(|b| u16::from_le_bytes([b[0], b[1]])).collect(),
+    }
+}
+
+fn put_u32s(bytes: &mut [u8], values: &[u32]) {
+    for (chunk, value) in bytes.chunks_exact_mut(4).zip(values) {
+        chunk.copy_from_slice(&value.to_le_bytes());
+    }
+}
+
+fn put_f32s(bytes: &mut [u8], values: &[f32]) {
+    for (chunk, value) in bytes.chunks_exact_mut(4).zip(values) {
+        chunk.copy_from_slice(&value.to_le_bytes());
+    }
+}
+
+fn get_u32s<const N: usize>(bytes: &[u8]) -> [u32; N] {
+    std::array::from_fn(|i| u32::from_le_bytes(bytes[i * 4..i * 4 + 4].try_into().unwrap()))
+}
+
+fn get_f32s<const N: usize>(bytes: &[u8]) -> [f32; N] {
+    std::array::from_fn(|i| f32::from_le_bytes(bytes[i * 4..i * 4 + 4].try_into().unwrap()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header() -> VolumeHeader {
+        VolumeHeader {
+            origin: [8, 0, 4],
+            ..VolumeHeader::new([2, 3, 4], VoxelDtype::U16, [-1.0, 2.5], [0.5, 0.5, 1.0]).with_frames(1, 2)
+        }
+    }
+
+    fn payload(header: &VolumeHeader) -> Vec<u8> {
+        let mut bytes = header.encode().to_vec();
+        bytes.extend((0..header.payload_size().unwrap()).map(|i| i as u8));
+        bytes
+    }
+
+    #[test]
+    fn round_trips() {
+        let header = header();
+        assert_eq!(header.payload_size(), Ok(2 * 3 * 4 * 2 * 2));
+        let mut bytes = payload(&header);
+        // Bytes past the voxels are not part of the payload
+        bytes.extend([0xff; 3]);
+
+        let (decoded, voxels) = VolumeHeader::decode(&bytes).unwrap();
+        assert_eq!(decoded, header);
+        assert_eq!(voxels, &bytes[VolumeHeader::SIZE..bytes.len() - 3]);
+        assert_eq!(decode_u16(&voxels[..4], VoxelDtype::U16), [0x0100, 0x0302]);
+    }
+
+    #[test]
+    fn skips_longer_headers() {
+        let header = header();
+        let mut bytes = header.encode().to_vec();
+        // A later version may append fields; this one only needs the size to skip them
+        bytes[6..8].copy_from_slice(&72u16.to_le_bytes());
+        bytes.extend([0; 8]);
+        bytes.extend(vec![7; header.payload_size().unwrap() as usize]);
+
+        let (decoded, voxels) = VolumeHeader::decode(&bytes).unwrap();
+        assert_eq!(decoded, header);
+        assert!(voxels.iter().all(|&b| b == 7));
+    }
+
+    #[test]
+    fn rejects_truncated_payloads() {
+        let bytes = payload(&header());
+        assert_eq!(
+            VolumeHeader::decode(&bytes[..10]),
+            Err(ContainerError::Truncated { expected: 64, actual: 10 })
+        );
+        let len = bytes.len() as u64;
+        assert_eq!(
+            VolumeHeader::decode(&bytes[..bytes.len() - 1]),
+            Err(ContainerError::Truncated { expected: len, actual: len - 1 })
+        );
+    }
+
+    #[test]
+    fn rejects_bad_headers() {
+        let bytes = payload(&header());
+        let with = |offset: usize, value: &[u8]| {
+            let mut bytes = bytes.clone();
+            bytes[offset..offset + value.len()].copy_from_slice(value);
+            VolumeHeader::decode(&bytes).map(|(header, _)| header)
+        };
+        assert_eq!(with(0, b"3DLX"), Err(ContainerError::BadMagic));
+        assert_eq!(with(4, &0u16.to_le_bytes()), Err(ContainerError::UnsupportedVersion(0)));
+        assert_eq!(with(4, &2u16.to_le_bytes()), Err(ContainerError::UnsupportedVersion(2)));
+        assert_eq!(with(20, &[9]), Err(ContainerError::UnknownDtype(9)));
+        assert_eq!(with(21, &[1]), Err(ContainerError::UnknownEncoding(1)));
+        // Sizes that do not fit in a u64 fail instead of overflowing
+        let huge = [u32::MAX.to_le_bytes(); 3].concat();
+        assert_eq!(with(8, &huge), Err(ContainerError::TooLarge));
+    }
+}
@@ -1,3 +1,5 @@
+mod container;
 mod types;
 
+pub use container::*;
 pub use types::*;
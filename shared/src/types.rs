@@ -1,7 +1,9 @@
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
 /// Information about a volume available on the server
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct VolumeInfo {
     /// Unique identifier for this volume
     pub id: String,
@@ -15,24 +17,196 @@ pub struct VolumeInfo {
     pub low_res_size: u64,
     /// Size of full resolution in bytes
     pub full_res_size: u64,
-    /// Value range [min, max]
+    /// Value range [min, max]. For time series, the first frame's range
+    /// widened by a low-res sample of each later frame.
     pub value_range: [f32; 2],
+    /// Voxel spacing [x, y, z] in millimetres (1.0 when the file does not say)
+    #[serde(default = "default_spacing")]
+    pub spacing: [f32; 3],
+    /// Content version derived from the source file identity.
+    /// Changes whenever the file changes; pass it as `?v=` to get immutable caching.
+    #[serde(default)]
+    pub version: String,
+    /// Ids of label volumes linked to this volume
+    #[serde(default)]
+    pub labels: Vec<String>,
+    /// Number of time frames (1 for static volumes). The sizes above describe
+    /// one frame. Only `/frames`, `/frames/{frame}` and `/export?frame=`
+    /// serve later frames; every other data route, derived volume, mesh,
+    /// segmentation and comparison uses the first.
+    #[serde(default = "default_frames")]
+    pub frames: u32,
+    /// Time between frames in seconds, if the file gives it
+    #[serde(default)]
+    pub frame_interval: Option<f32>,
+    /// Folder of the file below its data root, `/`-separated ("" at the top)
+    #[serde(default)]
+    pub folder: String,
+    /// User-defined tags from the file's `tags` attribute or its `.meta.json`/`.meta.yaml` sidecar
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Element type of the stored data, e.g. `f32` or `u16` (always `f32` for derived volumes)
+    #[serde(default)]
+    pub dtype: String,
+    /// Scalar attributes of the dataset and file root, and `attributes` from the sidecar
+    #[serde(default)]
+    pub attributes: BTreeMap<String, String>,
+}
+
+/// An empty volume with the same defaults as deserializing one: unit
+/// spacing and a single frame
+impl Default for VolumeInfo {
+    fn default() -> Self {
+        Self {
+            id: String::new(),
+            name: String::new(),
+            dimensions: [0; 3],
+            low_res_dimensions: [0; 3],
+            low_res_size: 0,
+            full_res_size: 0,
+            value_range: [0.0, 0.0],
+            spacing: default_spacing(),
+            version: String::new(),
+            labels: Vec::new(),
+            frames: default_frames(),
+            frame_interval: None,
+            folder: String::new(),
+            tags: Vec::new(),
+            dtype: String::new(),
+            attributes: BTreeMap::new(),
+        }
+    }
+}
+
+fn default_spacing() -> [f32; 3] {
+    [1.0, 1.0, 1.0]
+}
+
+fn default_frames() -> u32 {
+    1
+}
+
+/// A volume file that was found but could not be loaded
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct FailedVolume {
+    /// Id the volume would have had
+    pub id: String,
+    /// Path of the file relative to its data root
+    pub file: String,
+    /// Why loading failed
+    pub error: String,
 }
 
 /// Response for listing available volumes
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct VolumeListResponse {
     pub volumes: Vec<VolumeInfo>,
+    /// Files that failed to load; only sent for `?include=failed`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub failed: Vec<FailedVolume>,
+    /// File volumes grouped by folder, over the whole catalog: the filters
+    /// and pages only apply to `volumes`
+    #[serde(default)]
+    pub tree: CatalogFolder,
+    /// Every tag used by a file volume, sorted, over the whole catalog
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Volumes matching the filters, across all pages
+    #[serde(default)]
+    pub total: usize,
+    /// Pass as `cursor` to get the next page; absent on the last page
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+}
+
+/// Field the volume list is sorted by. Ties are broken by id.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum VolumeSort {
+    #[default]
+    Id,
+    Name,
+    Folder,
+    /// Full-resolution size in bytes
+    Size,
+    /// Number of voxels
+    Voxels,
+    Frames,
+    Dtype,
+}
+
+impl VolumeSort {
+    /// Name used in the `sort` query parameter
+    pub fn name(self) -> &'static str {
+        match self {
+            VolumeSort::Id => "id",
+            VolumeSort::Name => "name",
+            VolumeSort::Folder => "folder",
+            VolumeSort::Size => "size",
+            VolumeSort::Voxels => "voxels",
+            VolumeSort::Frames => "frames",
+            VolumeSort::Dtype => "dtype",
+        }
+    }
+}
+
+/// Direction of a sort
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+impl SortOrder {
+    /// Name used in the `order` query parameter
+    pub fn name(self) -> &'static str {
+        match self {
+            SortOrder::Asc => "asc",
+            SortOrder::Desc => "desc",
+        }
+    }
+}
+
+/// A folder of the volume catalog
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct CatalogFolder {
+    /// Folder name ("" for the top level)
+    pub name: String,
+    /// `/`-separated path below the data root, as in `VolumeInfo::folder`
+    pub path: String,
+    /// Ids of the volumes directly in this folder, sorted
+    pub volumes: Vec<String>,
+    /// Subfolders, sorted by name
+    #[cfg_attr(feature = "openapi", schema(no_recursion))]
+    pub folders: Vec<CatalogFolder>,
 }
 
 /// Response for volume metadata
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct VolumeMetadataResponse {
     pub info: VolumeInfo,
 }
 
+/// Request to create a derived (filtered) volume
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct DeriveRequest {
+    /// Filter expression, e.g. `gauss(1.5)`, `median(1)`, `diffusion(10,0.05)`,
+    /// `gradmag` or `laplacian`
+    pub filter: String,
+}
+
 /// Response for upload
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct UploadResponse {
     pub id: String,
     pub success: bool,
@@ -41,6 +215,7 @@ pub struct UploadResponse {
 
 /// Response for resampled volume data
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct ResampledVolumeResponse {
     /// Actual dimensions after resampling [x, y, z]
     pub dimensions: [u32; 3],
@@ -50,6 +225,562 @@ pub struct ResampledVolumeResponse {
 
 /// Error response
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct ErrorResponse {
     pub error: String,
 }
+
+/// Integer type of a label volume's voxels
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum LabelDtype {
+    U8,
+    U16,
+}
+
+impl LabelDtype {
+    /// Bytes per voxel
+    pub fn size(self) -> usize {
+        match self {
+            LabelDtype::U8 => 1,
+            LabelDtype::U16 => 2,
+        }
+    }
+
+    /// Short name, e.g. `u8`
+    pub fn name(self) -> &'static str {
+        match self {
+            LabelDtype::U8 => "u8",
+            LabelDtype::U16 => "u16",
+        }
+    }
+}
+
+/// How the foreground is selected before labelling
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(tag = "method", rename_all = "lowercase")]
+pub enum ThresholdMethod {
+    /// Otsu's method on a 256-bin histogram
+    Otsu,
+    /// Keep voxels above the given percentile (0-100)
+    Percentile { percentile: f32 },
+    /// Keep voxels above a fixed value
+    Value { value: f32 },
+}
+
+/// Request for automatic segmentation of an intensity volume
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct SegmentRequest {
+    pub threshold: ThresholdMethod,
+    /// Radius of the morphological opening (0 = none)
+    #[serde(default)]
+    pub open_radius: u32,
+    /// Radius of the morphological closing (0 = none)
+    #[serde(default)]
+    pub close_radius: u32,
+    /// Components smaller than this many voxels are discarded
+    #[serde(default)]
+    pub min_component_size: u64,
+    /// Use 26-connectivity instead of 6-connectivity
+    #[serde(default)]
+    pub full_connectivity: bool,
+}
+
+/// Statistics of one connected component
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ComponentStats {
+    /// Label value in the label volume (1 = largest component)
+    pub label: u32,
+    pub voxel_count: u64,
+    /// Inclusive voxel bounding box [x, y, z]
+    pub bbox_min: [u32; 3],
+    pub bbox_max: [u32; 3],
+    /// Voxel count times voxel volume, in cubic millimetres
+    pub physical_volume: f64,
+}
+
+/// Name and colour of one label value
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct LabelEntry {
+    pub value: u32,
+    pub name: String,
+    /// RGB colour; a default palette colour when the source does not give one
+    #[serde(default)]
+    pub color: Option<[u8; 3]>,
+}
+
+impl LabelEntry {
+    pub fn color(&self) -> [u8; 3] {
+        self.color.unwrap_or_else(|| default_label_color(self.value))
+    }
+}
+
+/// Distinct colour for a label value without an explicit one
+pub fn default_label_color(value: u32) -> [u8; 3] {
+    // Golden-ratio hue steps keep neighbouring labels apart
+    let hue = (value as f32 * 0.618_034).fract() * 6.0;
+    let x = 1.0 - (hue % 2.0 - 1.0).abs();
+    let (r, g, b) = match hue as u32 {
+        0 => (1.0, x, 0.0),
+        1 => (x, 1.0, 0.0),
+        2 => (0.0, 1.0, x),
+        3 => (0.0, x, 1.0),
+        4 => (x, 0.0, 1.0),
+        _ => (1.0, 0.0, x),
+    };
+    let channel = |c: f32| (64.0 + c * 191.0) as u8;
+    [channel(r), channel(g), channel(b)]
+}
+
+/// Information about a label volume linked to an intensity volume
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct LabelVolumeInfo {
+    pub id: String,
+    /// Id of the intensity volume the labels belong to
+    pub volume_id: String,
+    pub dimensions: [u32; 3],
+    pub dtype: LabelDtype,
+    /// Per-label statistics; for segmentations, one entry per connected component
+    pub components: Vec<ComponentStats>,
+    /// Names and colours of the label values, if the source provides them
+    #[serde(default)]
+    pub table: Vec<LabelEntry>,
+}
+
+/// Response for a segmentation request
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct SegmentResponse {
+    pub labels: LabelVolumeInfo,
+    /// Intensity threshold that was applied
+    pub threshold_value: f32,
+}
+
+/// Response for listing the label volumes of an intensity volume
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct LabelListResponse {
+    pub labels: Vec<LabelVolumeInfo>,
+}
+
+/// Request to compare a volume (e.g. a reconstruction) against a reference
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct CompareRequest {
+    /// Id of the reference (ground truth) volume; must have the same dimensions
+    pub reference: String,
+    /// Axis the per-slice metrics are computed along (0 = x, 1 = y, 2 = z)
+    #[serde(default)]
+    pub axis: usize,
+}
+
+/// Image quality metrics of a volume or slice against its reference
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct QualityMetrics {
+    /// Peak signal-to-noise ratio in dB; `None` if there is no error
+    pub psnr: Option<f64>,
+    /// Structural similarity (7x7 windows on 2D slices)
+    pub ssim: f64,
+    /// Squared error normalised by the reference energy; `None` if the reference is zero
+    pub nmse: Option<f64>,
+    /// Mean absolute error
+    pub mae: f64,
+}
+
+/// Response for a comparison request
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct CompareResponse {
+    pub volume_id: String,
+    pub reference_id: String,
+    pub axis: usize,
+    /// Reference max - min, used as the peak value for PSNR and SSIM
+    pub data_range: f32,
+    pub global: QualityMetrics,
+    /// One entry per slice along `axis`
+    pub slices: Vec<QualityMetrics>,
+    /// Derived absolute-difference volume, renderable like any other
+    pub difference: VolumeInfo,
+}
+
+/// Request to compute the centred FFT of a volume
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct SpectrumRequest {
+    /// Transform each slice perpendicular to this axis (0 = x, 1 = y, 2 = z)
+    /// in 2D; the whole volume is transformed in 3D when absent
+    #[serde(default)]
+    pub slice_axis: Option<usize>,
+}
+
+/// Response for a spectrum request. Both volumes are computed together when
+/// either's data is first requested; until then `value_range` is [-pi, pi]
+/// for the phase and an upper bound for the log-magnitude.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct SpectrumResponse {
+    pub volume_id: String,
+    pub slice_axis: Option<usize>,
+    /// Derived `ln(1 + |F|)` volume, id `{id}~fft(logmag)` or `{id}~fft2(logmag,{axis})`
+    pub log_magnitude: VolumeInfo,
+    /// Derived phase volume in radians, id `{id}~fft(phase)` or `{id}~fft2(phase,{axis})`
+    pub phase: VolumeInfo,
+}
+
+/// Geometry of an annotation. Coordinates are [x, y, z], in voxels or
+/// millimetres depending on where the shape appears.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum AnnotationShape {
+    /// A landmark
+    Point { position: [f32; 3] },
+    /// A distance measurement
+    Line { start: [f32; 3], end: [f32; 3] },
+    /// An axis-aligned region
+    Box { min: [f32; 3], max: [f32; 3] },
+    /// A free-text note, optionally pinned to a position
+    Text {
+        #[serde(default)]
+        position: Option<[f32; 3]>,
+    },
+}
+
+impl AnnotationShape {
+    /// Name of the shape type, as in the JSON `type` field
+    pub fn kind(&self) -> &'static str {
+        match self {
+            AnnotationShape::Point { .. } => "point",
+            AnnotationShape::Line { .. } => "line",
+            AnnotationShape::Box { .. } => "box",
+            AnnotationShape::Text { .. } => "text",
+        }
+    }
+
+    /// All coordinates of the shape
+    pub fn positions(&self) -> Vec<[f32; 3]> {
+        match *self {
+            AnnotationShape::Point { position } => vec![position],
+            AnnotationShape::Line { start, end } => vec![start, end],
+            AnnotationShape::Box { min, max } => vec![min, max],
+            AnnotationShape::Text { position } => position.into_iter().collect(),
+        }
+    }
+
+    /// The same shape with every coordinate passed through `f`
+    pub fn map(&self, f: impl Fn([f32; 3]) -> [f32; 3]) -> Self {
+        match *self {
+            AnnotationShape::Point { position } => AnnotationShape::Point { position: f(position) },
+            AnnotationShape::Line { start, end } => AnnotationShape::Line {
+                start: f(start),
+                end: f(end),
+            },
+            AnnotationShape::Box { min, max } => AnnotationShape::Box {
+                min: f(min),
+                max: f(max),
+            },
+            AnnotationShape::Text { position } => AnnotationShape::Text {
+                position: position.map(f),
+            },
+        }
+    }
+}
+
+/// Unit of the coordinates in an `AnnotationRequest`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum CoordinateUnits {
+    #[default]
+    Voxel,
+    /// Millimetres, i.e. voxel coordinates times the volume spacing
+    Mm,
+}
+
+/// Request to create or replace an annotation
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct AnnotationRequest {
+    pub shape: AnnotationShape,
+    #[serde(default)]
+    pub units: CoordinateUnits,
+    /// Short name shown in lists and next to the marker
+    #[serde(default)]
+    pub label: String,
+    /// Free-text note
+    #[serde(default)]
+    pub text: String,
+}
+
+/// An annotation stored for a volume
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct Annotation {
+    pub id: u64,
+    pub volume_id: String,
+    pub label: String,
+    pub text: String,
+    /// Shape in voxel coordinates
+    pub voxel: AnnotationShape,
+    /// Shape in millimetres
+    pub physical: AnnotationShape,
+    /// Line length in mm or box volume in mm³
+    #[serde(default)]
+    pub measurement: Option<f64>,
+    /// Creation and last modification time, in seconds since the Unix epoch
+    pub created: u64,
+    pub modified: u64,
+}
+
+/// Response for listing the annotations of a volume
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct AnnotationListResponse {
+    pub annotations: Vec<Annotation>,
+}
+
+/// Response for /api/health
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct HealthResponse {
+    /// `ok`, or `degraded` when some volume or label files failed to load
+    pub status: String,
+    /// Ids of the loaded volumes, sorted
+    pub available_samples: Vec<String>,
+    /// Number of volumes loaded
+    pub volumes: usize,
+    /// Volume and label files that failed to load
+    pub failed: Vec<FailedVolume>,
+}
+
+/// Response for /api/ready
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ReadinessResponse {
+    pub ready: bool,
+    /// Why the server is not ready
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+/// Cache counters exposed at /api/cache
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct CacheStats {
+    pub entries: usize,
+    pub used_bytes: u64,
+    pub budget_bytes: u64,
+    pub hits: u64,
+    pub misses: u64,
+    /// Requests that waited on an identical in-flight computation
+    pub coalesced: u64,
+}
+
+/// Per-route status reported at /api/status
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct RouteStatus {
+    pub route: String,
+    pub limit: usize,
+    pub in_flight: usize,
+    pub queued: usize,
+}
+
+/// Response for /api/status
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct AdmissionStatus {
+    pub routes: Vec<RouteStatus>,
+    pub in_flight_bytes: u64,
+    pub memory_budget_bytes: u64,
+    pub rejected: u64,
+}
+
+/// Query parameters for /volumes
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::IntoParams))]
+#[cfg_attr(feature = "openapi", into_params(parameter_in = Query))]
+pub struct VolumeListQuery {
+    /// `failed` to also list the files that failed to load
+    pub include: Option<String>,
+    /// Case-insensitive text to find in the id, name, tags or attributes
+    pub q: Option<String>,
+    /// Comma-separated tags the volumes must all have
+    pub tags: Option<String>,
+    /// Comma-separated dtypes the volumes may have, e.g. `f32,u16` (`float32`,
+    /// `uint16` and the other NumPy names also match)
+    pub dtype: Option<String>,
+    /// Only volumes in this folder or below it
+    pub folder: Option<String>,
+    /// Smallest allowed size of every dimension
+    pub min_dim: Option<u32>,
+    /// Largest allowed size of every dimension
+    pub max_dim: Option<u32>,
+    /// Field to sort by (default `id`)
+    #[cfg_attr(feature = "openapi", param(inline))]
+    pub sort: Option<VolumeSort>,
+    #[cfg_attr(feature = "openapi", param(inline))]
+    pub order: Option<SortOrder>,
+    /// Page size, at most 1000; all matching volumes when absent
+    pub limit: Option<usize>,
+    /// `next_cursor` of the previous page
+    pub cursor: Option<String>,
+}
+
+/// Query parameters accepted by the volume data routes
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::IntoParams))]
+#[cfg_attr(feature = "openapi", into_params(parameter_in = Query))]
+pub struct VersionQuery {
+    /// Content version the client expects (`VolumeInfo::version`)
+    pub v: Option<String>,
+}
+
+/// Query parameters for /volumes/{id}/frames
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::IntoParams))]
+#[cfg_attr(feature = "openapi", into_params(parameter_in = Query))]
+pub struct FrameRangeQuery {
+    /// First frame
+    #[serde(default)]
+    pub start: u32,
+    /// Number of frames, at most `MAX_FRAMES_PER_REQUEST`
+    #[serde(default = "default_frames")]
+    pub count: u32,
+}
+
+/// Download format of an extracted surface
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum MeshFormat {
+    /// Binary glTF
+    #[default]
+    Glb,
+    /// Wavefront OBJ (text)
+    Obj,
+    /// Binary STL
+    Stl,
+}
+
+impl MeshFormat {
+    pub fn name(self) -> &'static str {
+        match self {
+            MeshFormat::Glb => "glb",
+            MeshFormat::Obj => "obj",
+            MeshFormat::Stl => "stl",
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            MeshFormat::Glb => "model/gltf-binary",
+            MeshFormat::Obj => "model/obj",
+            MeshFormat::Stl => "model/stl",
+        }
+    }
+}
+
+/// Query parameters for /volumes/{id}/mesh
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::IntoParams))]
+#[cfg_attr(feature = "openapi", into_params(parameter_in = Query))]
+pub struct MeshQuery {
+    /// Intensity iso-value (defaults to the middle of the value range)
+    pub iso: Option<f32>,
+    /// Label value to extract instead of an intensity surface
+    pub label: Option<u32>,
+    /// Label volume holding `label` (defaults to the volume's first)
+    pub labels: Option<String>,
+    #[serde(default)]
+    #[cfg_attr(feature = "openapi", param(inline))]
+    pub format: MeshFormat,
+    /// Taubin smoothing iterations
+    #[serde(default)]
+    pub smooth: u32,
+    /// Vertex clustering cell size in voxels (0 or 1 = no decimation)
+    #[serde(default)]
+    pub decimate: u32,
+}
+
+/// Download format of an exported volume
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    /// Single-file NIfTI-1
+    #[default]
+    Nifti,
+    /// NumPy array, C order with shape (x, y, z)
+    Npy,
+    /// MetaImage with the data inline
+    Mha,
+    /// MetaImage header pointing at the `raw` export of the same volume
+    Mhd,
+    /// MetaImage data file: little-endian f32, x fastest
+    Raw,
+    /// HDF5 with a `data` dataset, readable by this server
+    Hdf5,
+}
+
+impl ExportFormat {
+    /// Format written to a file with extension `extension`
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        match extension.to_ascii_lowercase().as_str() {
+            "nii" => Some(ExportFormat::Nifti),
+            "npy" => Some(ExportFormat::Npy),
+            "mha" => Some(ExportFormat::Mha),
+            "mhd" => Some(ExportFormat::Mhd),
+            "raw" => Some(ExportFormat::Raw),
+            "h5" | "hdf5" => Some(ExportFormat::Hdf5),
+            _ => None,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Nifti => "nii",
+            ExportFormat::Npy => "npy",
+            ExportFormat::Mha => "mha",
+            ExportFormat::Mhd => "mhd",
+            ExportFormat::Raw => "raw",
+            ExportFormat::Hdf5 => "h5",
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            ExportFormat::Mhd => "text/plain",
+            ExportFormat::Hdf5 => "application/x-hdf5",
+            _ => "application/octet-stream",
+        }
+    }
+}
+
+/// Query parameters for /volumes/{id}/export
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::IntoParams))]
+#[cfg_attr(feature = "openapi", into_params(parameter_in = Query))]
+pub struct ExportQuery {
+    #[serde(default)]
+    #[cfg_attr(feature = "openapi", param(inline))]
+    pub format: ExportFormat,
+    /// Time frame of a time series
+    #[serde(default)]
+    pub frame: u32,
+    /// Voxel box `x0:x1,y0:y1,z0:z1`, half-open, in full-res coordinates
+    pub crop: Option<String>,
+    /// Target size of the largest dimension after cropping, as for /at/{resolution}
+    pub resolution: Option<usize>,
+    /// Keep only voxels with this label value; the rest become zero
+    pub label: Option<u32>,
+    /// Label volume holding `label` (defaults to the volume's first)
+    pub labels: Option<String>,
+}